@@ -10,6 +10,9 @@ template Withdraw(treeDepth, associationDepth) {
     signal input withdrawnValue;
     signal input stateRoot;             // a known state root
     signal input associationRoot;       // root of the association set Merkle tree
+    signal input context;               // hash of (recipient, relayer, fee), checked by the contract
+    signal input changeCommitment;      // commitment of a note holding the remaining value, or 0
+    signal input expiryLedger;          // last ledger the proof can be submitted in, or 0 for none
 
     // PRIVATE SIGNALS
 
@@ -27,6 +30,10 @@ template Withdraw(treeDepth, associationDepth) {
     signal input labelIndex;            // index of the label in the association tree
     signal input labelSiblings[associationDepth]; // siblings along the path to the association root
 
+    // signals for the change note
+    signal input changeNullifier;       // nullifier of the change commitment
+    signal input changeSecret;          // secret of the change commitment
+
     // OUTPUT SIGNALS
     signal output nullifierHash;        // hash of commitment nullifier (public output)
 
@@ -78,6 +85,26 @@ template Withdraw(treeDepth, associationDepth) {
 
     // ensure withdrawn value doesn't exceed commitment value
     // (this is enforced by the remainingValue being non-negative through range check)
+
+    // bind the context to the proof so the recipient, relayer and fee can't be changed in flight
+    signal contextSquare <== context * context;
+
+    // bind the expiry the same way; the contract compares it with the current ledger
+    signal expirySquare <== expiryLedger * expiryLedger;
+
+    // the change note keeps the label and holds exactly the remaining value,
+    // so withdrawnValue + changeValue == value
+    component changeHasher = CommitmentHasher();
+    changeHasher.label <== label;
+    changeHasher.value <== remainingValue;
+    changeHasher.secret <== changeSecret;
+    changeHasher.nullifier <== changeNullifier;
+    _ <== changeHasher.nullifierHash;
+
+    // a zero changeCommitment withdraws without a change note, like a zero associationRoot
+    signal changeDiff <== changeCommitment - changeHasher.commitment;
+    signal changeProduct <== changeCommitment * changeDiff;
+    changeProduct === 0;
 }
 
-component main {public [withdrawnValue, stateRoot, associationRoot]} = Withdraw(8, 2);  // state tree depth 20, association tree depth 2
\ No newline at end of file
+component main {public [withdrawnValue, stateRoot, associationRoot, context, changeCommitment, expiryLedger]} = Withdraw(20, 2);  // state tree depth 20, association tree depth 2
\ No newline at end of file
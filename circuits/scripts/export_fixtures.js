@@ -0,0 +1,102 @@
+/**
+ * Export a withdrawal proof as fixtures for the Soroban contract's tests
+ *
+ * This script reads a proof and its public signals as written by
+ * `snarkjs groth16 prove` and prints them in the forms contracts/opaque uses:
+ * the decimal coordinates `init_proof` in src/test.rs builds the proof from,
+ * and the serialized PROOF and PUB_SIGNALS arrays of src/self_test.rs.
+ *
+ * Usage: node scripts/export_fixtures.js build/proof.json build/public.json
+ */
+
+const fs = require("fs");
+
+const [proofPath, publicPath] = process.argv.slice(2);
+if (!proofPath || !publicPath) {
+  console.error(
+    "Usage: node scripts/export_fixtures.js <proof.json> <public.json>",
+  );
+  process.exit(1);
+}
+
+const proof = JSON.parse(fs.readFileSync(proofPath, "utf8"));
+const publicSignals = JSON.parse(fs.readFileSync(publicPath, "utf8"));
+
+// Big-endian bytes of a decimal string, left-padded to `size`
+function toBytes(decimal, size) {
+  const hex = BigInt(decimal).toString(16).padStart(size * 2, "0");
+  if (hex.length > size * 2) {
+    throw new Error(`${decimal} doesn't fit in ${size} bytes`);
+  }
+  return Buffer.from(hex, "hex");
+}
+
+// Uncompressed points as the contract serializes them: x then y for G1, and
+// each Fp2 coordinate as c1 then c0 for G2 (snarkjs lists them as [c0, c1])
+function g1Bytes(point) {
+  return Buffer.concat([toBytes(point[0], 48), toBytes(point[1], 48)]);
+}
+
+function g2Bytes(point) {
+  return Buffer.concat([
+    toBytes(point[0][1], 48),
+    toBytes(point[0][0], 48),
+    toBytes(point[1][1], 48),
+    toBytes(point[1][0], 48),
+  ]);
+}
+
+// Public signals are a big-endian u32 count followed by 32 bytes per signal
+function signalsBytes(signals) {
+  const count = Buffer.alloc(4);
+  count.writeUInt32BE(signals.length);
+  return Buffer.concat([
+    count,
+    ...signals.map((signal) => toBytes(signal, 32)),
+  ]);
+}
+
+// A Rust byte array, sixteen bytes per line as rustfmt lays them out
+function rustArray(bytes) {
+  const lines = [];
+  for (let i = 0; i < bytes.length; i += 16) {
+    const row = [...bytes.subarray(i, i + 16)].map(
+      (byte) => "0x" + byte.toString(16).padStart(2, "0"),
+    );
+    lines.push("    " + row.join(", ") + ",");
+  }
+  return lines.join("\n");
+}
+
+const proofBytes = Buffer.concat([
+  g1Bytes(proof.pi_a),
+  g2Bytes(proof.pi_b),
+  g1Bytes(proof.pi_c),
+]);
+const pubSignalsBytes = signalsBytes(publicSignals);
+
+console.log("init_proof (src/test.rs)");
+console.log("------------------------");
+console.log(`    let pi_ax = "${proof.pi_a[0]}";`);
+console.log(`    let pi_ay = "${proof.pi_a[1]}";`);
+console.log(`    let pi_bx1 = "${proof.pi_b[0][0]}";`);
+console.log(`    let pi_bx2 = "${proof.pi_b[0][1]}";`);
+console.log(`    let pi_by1 = "${proof.pi_b[1][0]}";`);
+console.log(`    let pi_by2 = "${proof.pi_b[1][1]}";`);
+console.log(`    let pi_cx = "${proof.pi_c[0]}";`);
+console.log(`    let pi_cy = "${proof.pi_c[1]}";`);
+
+console.log("\nPublic signals, in order");
+console.log("------------------------");
+publicSignals.forEach((signal, i) => {
+  console.log(`  [${i}] 0x${toBytes(signal, 32).toString("hex")}`);
+});
+
+console.log("\nself_test.rs");
+console.log("------------");
+console.log(`pub const PROOF: [u8; ${proofBytes.length}] = [`);
+console.log(rustArray(proofBytes));
+console.log("];\n");
+console.log(`pub const PUB_SIGNALS: [u8; ${pubSignalsBytes.length}] = [`);
+console.log(rustArray(pubSignalsBytes));
+console.log("];");
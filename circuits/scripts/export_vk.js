@@ -162,7 +162,9 @@ To deploy the Opaque contract, you need:
      --vk_bytes <hex-encoded-vk> \\
      --token_address <native-xlm-or-token-contract> \\
      --admin <your-admin-address> \\
-     --groth16_verifier <groth16-verifier-contract-id>
+     --groth16_verifier <groth16-verifier-contract-id> \\
+     --params '{"denomination": "1000000000", "tree_depth": 20, "root_history_size": 30, "poseidon_params_id": 1}' \\
+     --access '{"deposit_issuer": null, "allow_admin_nullifier_ops": false}'
 
 NOTE: The VK bytes need to be serialized in the format expected by the contract.
 The contract's test.rs file contains an example of how to construct the VK.
@@ -1,83 +1,386 @@
 //! Circuit Input Generator for Opaque Privacy Pool
 //!
-//! This Rust script generates valid test inputs for the Circom circuit
-//! using the same Poseidon255 implementation as the Soroban contract.
+//! This Rust script generates valid inputs for the Circom withdrawal circuit
+//! using the same Poseidon255 implementation as the Soroban contract, so the
+//! printed commitment is exactly what the contract stores on `deposit`.
 //!
 //! Usage:
-//!   cargo run --bin generate_inputs
+//!   cargo run --bin generate_inputs -- [--nullifier N] [--secret S] [--value V]
+//!                                      [--label L] [--context C] [--output FILE]
+//!                                      [--leaves FILE] [--labels FILE] [--root R]
+//!                                      [--withdrawn-value W] [--expiry-ledger E]
 //!
-//! The generated inputs can be used with snarkjs to test the circuit.
+//! Field elements are decimal or 0x-prefixed hex. The nullifier, secret and
+//! label are sampled at random when omitted, and the value defaults to
+//! 1000000000 (100 XLM in stroops). The input file defaults to `input.json`.
+//!
+//! `--leaves` lists the commitments already in the pool (`get_commitments`),
+//! one per line; the new commitment is inserted after them. `--labels` lists
+//! the association set, one label per line; the note's label is added if it
+//! is missing. `--root` is the root the contract reports after the deposit,
+//! checked against the generated tree.
+//!
+//! `--withdrawn-value` withdraws part of the note. The remainder goes into a
+//! change note with fresh random secrets, whose commitment `withdraw` inserts.
+//!
+//! `--expiry-ledger` is the last ledger the proof can be submitted in; the
+//! default, 0, never expires.
+//!
+//! The generated inputs can be used with snarkjs to build a withdrawal proof.
 
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Write};
+use std::process;
+
+use lean_incremental_merkle_tree::LeanIMT;
+use poseidon::Poseidon255;
+use soroban_sdk::{crypto::bls12_381::Fr, BytesN, Env};
 
-// This is a standalone script that would use the poseidon library
-// For now, we document the expected flow and provide test vectors
+const TREE_DEPTH: u32 = 20; // State tree depth of the circuit (Withdraw(20, 2))
+const ASSOCIATION_DEPTH: u32 = 2;
+const DEFAULT_VALUE: &str = "1000000000";
+const DEFAULT_OUTPUT: &str = "input.json";
+const FLAGS: [&str; 11] = [
+    "nullifier",
+    "secret",
+    "value",
+    "label",
+    "context",
+    "output",
+    "leaves",
+    "labels",
+    "root",
+    "withdrawn-value",
+    "expiry-ledger",
+];
+
+// BLS12-381 scalar field modulus, big-endian
+const SNARK_SCALAR_FIELD: [u8; 32] = [
+    0x73, 0xed, 0xa7, 0x53, 0x29, 0x9d, 0x7d, 0x48, 0x33, 0x39, 0xd8, 0x08, 0x09, 0xa1, 0xd8, 0x05,
+    0x53, 0xbd, 0xa4, 0x02, 0xff, 0xfe, 0x5b, 0xfe, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01,
+];
 
 fn main() {
+    let flags = parse_flags().unwrap_or_else(|err| exit_with(&err));
+    let field = |name: &str, default: Option<&str>| -> [u8; 32] {
+        match flags.get(name).map(String::as_str).or(default) {
+            Some(value) => {
+                parse_field(value).unwrap_or_else(|err| exit_with(&format!("--{name}: {err}")))
+            }
+            None => random_field(),
+        }
+    };
+
+    let nullifier = field("nullifier", None);
+    let secret = field("secret", None);
+    let value = field("value", Some(DEFAULT_VALUE));
+    let label = field("label", None);
+    let context = field("context", Some("0"));
+    let expiry_ledger = field("expiry-ledger", Some("0"));
+    let withdrawn_value = match flags.get("withdrawn-value") {
+        Some(_) => field("withdrawn-value", None),
+        None => value,
+    };
+    let change_value = to_amount(&value)
+        .checked_sub(to_amount(&withdrawn_value))
+        .unwrap_or_else(|| exit_with("--withdrawn-value exceeds --value"));
+    let output = flags
+        .get("output")
+        .cloned()
+        .unwrap_or_else(|| DEFAULT_OUTPUT.to_string());
+
+    let env = Env::default();
+    env.cost_estimate().budget().reset_unlimited();
+    let to_fr = |bytes: &[u8; 32]| Fr::from_bytes(BytesN::from_array(&env, bytes));
+
+    // Same formulas as circuits/commitment.circom
+    let poseidon1 = Poseidon255::new(&env, 2);
+    let poseidon2 = Poseidon255::new(&env, 3);
+    let note_commitment = |value: &[u8; 32], nullifier: &[u8; 32], secret: &[u8; 32]| {
+        let precommitment = poseidon2.hash_two(&env, &to_fr(nullifier), &to_fr(secret));
+        let hash_value_label = poseidon2.hash_two(&env, &to_fr(value), &to_fr(&label));
+        poseidon2
+            .hash_two(&env, &hash_value_label, &precommitment)
+            .to_bytes()
+    };
+    let nullifier_hash = poseidon1.hash(&env, &to_fr(&nullifier));
+    let commitment = note_commitment(&value, &nullifier, &secret);
+
+    // The change note keeps the label; a zero commitment means there is no change
+    let (change_nullifier, change_secret, change_commitment) = if change_value > 0 {
+        let (change_nullifier, change_secret) = (random_field(), random_field());
+        let change_commitment = note_commitment(
+            &amount_field(change_value),
+            &change_nullifier,
+            &change_secret,
+        );
+        (
+            change_nullifier,
+            change_secret,
+            change_commitment.to_array(),
+        )
+    } else {
+        ([0u8; 32], [0u8; 32], [0u8; 32])
+    };
+
+    // The pool's tree after the deposit, built with the contract's own LeanIMT
+    let leaves = read_fields(flags.get("leaves"));
+    let mut state_tree = LeanIMT::new(&env, TREE_DEPTH);
+    for leaf in leaves.iter().chain([commitment.to_array()].iter()) {
+        state_tree
+            .insert(BytesN::from_array(&env, leaf))
+            .unwrap_or_else(|err| exit_with(err));
+    }
+    let state_index = leaves.len() as u32;
+    let (state_siblings, state_root) = merkle_path(&env, &poseidon2, &state_tree, state_index);
+    if let Some(root) = flags.get("root") {
+        let root = parse_field(root).unwrap_or_else(|err| exit_with(&format!("--root: {err}")));
+        if root != state_root {
+            exit_with("the generated state root doesn't match --root; check --leaves");
+        }
+    }
+
+    // The association set, which must contain the note's label
+    let mut labels = read_fields(flags.get("labels"));
+    let label_index = match labels.iter().position(|item| *item == label) {
+        Some(index) => index,
+        None => {
+            labels.push(label);
+            labels.len() - 1
+        }
+    };
+    let mut association_tree = LeanIMT::new(&env, ASSOCIATION_DEPTH);
+    for item in labels.iter() {
+        association_tree
+            .insert(BytesN::from_array(&env, item))
+            .unwrap_or_else(|_| exit_with("too many labels for the association tree"));
+    }
+    let (label_siblings, association_root) =
+        merkle_path(&env, &poseidon2, &association_tree, label_index as u32);
+
+    let input = json_object(&[
+        ("withdrawnValue", json_string(&withdrawn_value)),
+        ("stateRoot", json_string(&state_root)),
+        ("associationRoot", json_string(&association_root)),
+        ("context", json_string(&context)),
+        ("changeCommitment", json_string(&change_commitment)),
+        ("expiryLedger", json_string(&expiry_ledger)),
+        ("label", json_string(&label)),
+        ("value", json_string(&value)),
+        ("nullifier", json_string(&nullifier)),
+        ("secret", json_string(&secret)),
+        ("stateSiblings", json_array(&state_siblings)),
+        ("stateIndex", json_string(&index_field(state_index))),
+        ("labelIndex", json_string(&index_field(label_index as u32))),
+        ("labelSiblings", json_array(&label_siblings)),
+        ("changeNullifier", json_string(&change_nullifier)),
+        ("changeSecret", json_string(&change_secret)),
+    ]);
+
+    File::create(&output)
+        .and_then(|mut file| file.write_all(input.as_bytes()))
+        .unwrap_or_else(|err| exit_with(&format!("writing {output}: {err}")));
+
     println!("Circuit Input Generator for Opaque Privacy Pool");
     println!("================================================\n");
+    println!(
+        "Commitment (deposit this): {}",
+        to_hex(&commitment.to_array())
+    );
+    println!(
+        "Nullifier Hash: {}",
+        to_hex(&nullifier_hash.to_bytes().to_array())
+    );
+    println!("State Root: {}", to_hex(&state_root));
+    println!("Leaf Index: {state_index}");
+    println!("Association Root: {}", to_hex(&association_root));
+    println!("\nKeep the nullifier and secret to withdraw:");
+    println!("  nullifier: {}", to_decimal(&nullifier));
+    println!("  secret:    {}", to_decimal(&secret));
+    if change_value > 0 {
+        println!("\nChange note worth {change_value} (pass its commitment to withdraw):");
+        println!("  commitment: {}", to_hex(&change_commitment));
+        println!("  nullifier:  {}", to_decimal(&change_nullifier));
+        println!("  secret:     {}", to_decimal(&change_secret));
+    }
+    println!("\nWrote circuit inputs to {output}");
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("error: {message}");
+    process::exit(1);
+}
+
+/// Reads `--name value` pairs from the command line
+fn parse_flags() -> Result<HashMap<String, String>, String> {
+    let mut flags = HashMap::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let name = arg
+            .strip_prefix("--")
+            .filter(|name| FLAGS.contains(name))
+            .ok_or_else(|| format!("unknown argument {arg}"))?;
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {arg}"))?;
+        flags.insert(name.to_string(), value);
+    }
+    Ok(flags)
+}
 
-    // Known-good test vectors from contract tests
-    // These are pre-computed values that pass the contract's Groth16 verification
+/// Gets the siblings of a leaf and checks that hashing along them, as the circuit's
+/// `MerkleProof` does, gives the tree's root
+fn merkle_path(
+    env: &Env,
+    poseidon: &Poseidon255,
+    tree: &LeanIMT,
+    index: u32,
+) -> (Vec<[u8; 32]>, [u8; 32]) {
+    let (siblings, _) = tree.generate_proof(index).unwrap();
+    let mut node = Fr::from_bytes(tree.get_leaf(index as usize).unwrap());
+    for (level, sibling) in siblings.iter().enumerate() {
+        node = if (index >> level) & 1 == 0 {
+            poseidon.hash_two(env, &node, &sibling)
+        } else {
+            poseidon.hash_two(env, &sibling, &node)
+        };
+    }
 
-    println!("Test Vector from Contract Tests:");
-    println!("---------------------------------");
+    let root = tree.get_root();
+    if node.to_bytes() != root {
+        exit_with("the merkle path doesn't hash to the tree root");
+    }
+    let siblings = siblings
+        .iter()
+        .map(|sibling| sibling.to_bytes().to_array())
+        .collect();
+    (siblings, root.to_array())
+}
 
-    // Commitment used in contract test
-    let commitment = "0x10cb631d174a98b2440b68d2e57da2ae9a13f7d1cccb1f41a1dd3d69a22faae9";
-    println!("Commitment: {}", commitment);
+/// Reads one field element per non-empty line of the file at `path`, if one was given
+fn read_fields(path: Option<&String>) -> Vec<[u8; 32]> {
+    let Some(path) = path else {
+        return Vec::new();
+    };
+    let contents = std::fs::read_to_string(path)
+        .unwrap_or_else(|err| exit_with(&format!("reading {path}: {err}")));
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| parse_field(line).unwrap_or_else(|err| exit_with(&format!("{path}: {err}"))))
+        .collect()
+}
 
-    // Public signals from working proof
-    let nullifier_hash = "0x4bb752d59801e586fa43aa952ab3c231f8ca8c9b863b82ca9abd3200a7e5a22d";
-    let withdrawn_value = "1000000000"; // 0x3b9aca00
-    let state_root = "0x4a4f118a44f7d073e88bae54e6206dd24897a54348b9f2c8eb707d26f44e32bc";
-    let association_root = "0x5d5826f9c9187bdb213f01ded6d230e9f1ab653b5bee6036504e82bc0716baa2";
+/// Reads a field element as a token amount
+fn to_amount(value: &[u8; 32]) -> u128 {
+    if value[..16].iter().any(|byte| *byte != 0) {
+        exit_with("values must fit in 128 bits");
+    }
+    let mut low = [0u8; 16];
+    low.copy_from_slice(&value[16..]);
+    u128::from_be_bytes(low)
+}
 
-    println!("Nullifier Hash: {}", nullifier_hash);
-    println!("Withdrawn Value: {}", withdrawn_value);
-    println!("State Root: {}", state_root);
-    println!("Association Root: {}", association_root);
+fn amount_field(amount: u128) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes[16..].copy_from_slice(&amount.to_be_bytes());
+    bytes
+}
 
-    println!("\n================================================");
-    println!("Circuit Input Structure (for reference):");
-    println!("================================================\n");
+fn index_field(index: u32) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes[28..].copy_from_slice(&index.to_be_bytes());
+    bytes
+}
 
-    // The circuit expects these inputs:
-    println!("Public Inputs:");
-    println!("  - withdrawnValue: Field element (amount being withdrawn)");
-    println!("  - stateRoot: Field element (merkle root of commitments)");
-    println!("  - associationRoot: Field element (merkle root of approved labels)");
-    println!();
-    println!("Private Inputs:");
-    println!("  - label: Field element (hash(scope, nonce) % SNARK_SCALAR_FIELD)");
-    println!("  - value: Field element (commitment value)");
-    println!("  - nullifier: Field element (random secret for nullifier)");
-    println!("  - secret: Field element (random secret for commitment)");
-    println!("  - stateSiblings[20]: Array of field elements (merkle proof)");
-    println!("  - stateIndex: Field element (leaf position in state tree)");
-    println!("  - labelIndex: Field element (leaf position in association tree)");
-    println!("  - labelSiblings[2]: Array of field elements (association proof)");
-    println!();
-    println!("Output:");
-    println!("  - nullifierHash: Field element (Poseidon(nullifier))");
-
-    println!("\n================================================");
-    println!("Commitment Formula:");
-    println!("================================================\n");
-    println!("precommitment = Poseidon(nullifier, secret)");
-    println!("hashValueLabel = Poseidon(value, label)");
-    println!("commitment = Poseidon(hashValueLabel, precommitment)");
-    println!("nullifierHash = Poseidon(nullifier)");
+/// Parses a decimal or 0x-prefixed hex string into a big-endian field element
+fn parse_field(value: &str) -> Result<[u8; 32], String> {
+    let mut bytes = [0u8; 32];
+    if let Some(hex) = value.strip_prefix("0x") {
+        if hex.is_empty() || hex.len() > 64 || !hex.is_ascii() {
+            return Err(format!("{value} is not a 32-byte hex value"));
+        }
+        let padded = format!("{hex:0>64}");
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&padded[2 * i..2 * i + 2], 16)
+                .map_err(|_| format!("{value} is not a hex value"))?;
+        }
+    } else {
+        if value.is_empty() {
+            return Err("empty value".to_string());
+        }
+        for digit in value.chars() {
+            let digit = digit
+                .to_digit(10)
+                .ok_or_else(|| format!("{value} is not a decimal value"))?;
+            // bytes = bytes * 10 + digit
+            let mut carry = digit;
+            for byte in bytes.iter_mut().rev() {
+                let next = *byte as u32 * 10 + carry;
+                *byte = next as u8;
+                carry = next >> 8;
+            }
+            if carry != 0 {
+                return Err(format!("{value} doesn't fit in 32 bytes"));
+            }
+        }
+    }
 
-    println!("\n================================================");
-    println!("To Generate Custom Inputs:");
-    println!("================================================\n");
-    println!("1. Use the Rust contract's Poseidon255 implementation");
-    println!("2. Run cargo test in the contracts directory to verify");
-    println!("3. Extract values from the test to use with the circuit");
-    println!();
-    println!("The contract tests at:");
-    println!("  contracts/opaque/src/test.rs");
-    println!("contain working proofs that can be used as reference.");
+    if bytes >= SNARK_SCALAR_FIELD {
+        return Err(format!("{value} is not below the scalar field modulus"));
+    }
+    Ok(bytes)
+}
+
+/// Samples a random field element from 31 bytes of OS randomness, so it is always in range
+fn random_field() -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    File::open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(&mut bytes[1..]))
+        .unwrap_or_else(|err| exit_with(&format!("reading /dev/urandom: {err}")));
+    bytes
+}
+
+/// Formats a big-endian 256-bit value in decimal, as snarkjs expects
+fn to_decimal(bytes: &[u8; 32]) -> String {
+    let mut value = *bytes;
+    let mut digits = Vec::new();
+    while value.iter().any(|byte| *byte != 0) {
+        // value, remainder = value / 10, value % 10
+        let mut remainder = 0u32;
+        for byte in value.iter_mut() {
+            let current = (remainder << 8) | *byte as u32;
+            *byte = (current / 10) as u8;
+            remainder = current % 10;
+        }
+        digits.push(char::from_digit(remainder, 10).unwrap());
+    }
+    if digits.is_empty() {
+        return "0".to_string();
+    }
+    digits.iter().rev().collect()
+}
+
+fn to_hex(bytes: &[u8; 32]) -> String {
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("0x{hex}")
+}
+
+fn json_string(value: &[u8; 32]) -> String {
+    format!("\"{}\"", to_decimal(value))
+}
+
+fn json_array(values: &[[u8; 32]]) -> String {
+    let items: Vec<String> = values.iter().map(json_string).collect();
+    format!("[{}]", items.join(", "))
+}
+
+fn json_object(fields: &[(&str, String)]) -> String {
+    let entries: Vec<String> = fields
+        .iter()
+        .map(|(name, value)| format!("  \"{name}\": {value}"))
+        .collect();
+    format!("{{\n{}\n}}\n", entries.join(",\n"))
 }
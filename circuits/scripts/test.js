@@ -68,6 +68,9 @@ function generateTestInputs() {
     withdrawnValue: withdrawnValue,
     stateRoot: "0", // Will be computed
     associationRoot: "0", // Use 0 to bypass association check
+    context: "0", // Placeholder - the contract derives it from (to, relayer, fee)
+    changeCommitment: "0", // Full withdrawal, no change note
+    expiryLedger: "0", // No expiry
 
     // Private signals
     label: label,
@@ -80,6 +83,8 @@ function generateTestInputs() {
     stateIndex: stateIndex,
     labelIndex: labelIndex,
     labelSiblings: labelSiblings,
+    changeNullifier: "0",
+    changeSecret: "0",
   };
 }
 
@@ -165,6 +170,9 @@ function getPrecomputedTestInputs() {
     withdrawnValue: "1000000000",
     stateRoot: "0", // Placeholder - needs real value from Poseidon255
     associationRoot: "0", // Bypass association check
+    context: "0",
+    changeCommitment: "0",
+    expiryLedger: "0",
 
     // Private signals - simple values
     label: "1",
@@ -177,6 +185,8 @@ function getPrecomputedTestInputs() {
     stateIndex: "0",
     labelIndex: "0",
     labelSiblings: Array(ASSOCIATION_DEPTH).fill("0"),
+    changeNullifier: "0",
+    changeSecret: "0",
   };
 }
 
@@ -8,6 +8,7 @@ use soroban_sdk::{
 pub const TREE_ROOT_KEY: Symbol = symbol_short!("root");
 pub const TREE_DEPTH_KEY: Symbol = symbol_short!("depth");
 pub const TREE_LEAVES_KEY: Symbol = symbol_short!("leaves");
+pub const TREE_FRONTIER_KEY: Symbol = symbol_short!("frontier");
 
 /// Converts u64 to BlsScalar for test compatibility
 pub fn u64_to_bls_scalar(env: &Env, value: u64) -> BlsScalar {
@@ -29,7 +30,10 @@ pub fn bytes_to_bls_scalar(bytes_n: &BytesN<32>) -> BlsScalar {
 /// - Storage and API uses BytesN<32> for Soroban compatibility
 pub struct LeanIMT {
     env: Env,
+    // The most recent leaves, oldest first: all of them, unless the tree was loaded with
+    // `from_frontier`, which only keeps the leaves inserted since
     leaves: Vec<BytesN<32>>,
+    leaf_count: u32,
     depth: u32,
     capacity: u32, // Pre-computed capacity (2^depth), cached for efficiency
     root: BytesN<32>,
@@ -41,6 +45,10 @@ pub struct LeanIMT {
     //    Key: (level, node_index) -> Value: computed hash for specific nodes
     subtree_cache: Map<u32, BlsScalar>,
     sparse_cache: Map<(u32, u32), BlsScalar>,
+    // Frontier of the tree: for each level, the hash of the rightmost node that is a
+    // left child. These are the only non-empty siblings an append can need, so
+    // persisting them makes every insert O(depth) without reloading other nodes.
+    frontier: Vec<BytesN<32>>,
 }
 
 impl LeanIMT {
@@ -51,14 +59,17 @@ impl LeanIMT {
         let mut tree = Self {
             env: env_clone.clone(),
             leaves: vec![&env_clone],
+            leaf_count: 0,
             depth,
             capacity,
             root: BytesN::from_array(&env_clone, &[0u8; 32]),
             poseidon: Poseidon255::new(&env_clone, 3),
             subtree_cache: Map::new(&env_clone),
             sparse_cache: Map::new(&env_clone),
+            frontier: vec![&env_clone],
         };
         tree.recompute_tree();
+        tree.rebuild_frontier();
         tree
     }
 
@@ -66,13 +77,12 @@ impl LeanIMT {
     /// Uses incremental path recomputation for efficiency (Clever shortcut 2)
     /// Returns Err if the tree is at capacity (2^depth leaves)
     pub fn insert(&mut self, leaf: BytesN<32>) -> Result<(), &'static str> {
-        let current_count = self.leaves.len() as u32;
-
-        if current_count >= self.capacity {
+        if self.leaf_count >= self.capacity {
             return Err("Tree is at capacity: cannot insert more leaves");
         }
 
         self.leaves.push_back(leaf);
+        self.leaf_count += 1;
         self.incremental_update();
         Ok(())
     }
@@ -101,7 +111,7 @@ impl LeanIMT {
 
     /// Gets the number of leaves that have been explicitly inserted
     pub fn get_leaf_count(&self) -> u32 {
-        self.leaves.len() as u32
+        self.leaf_count
     }
 
     /// Gets the maximum capacity of the tree (2^depth)
@@ -116,21 +126,16 @@ impl LeanIMT {
 
     /// Generates a merkle proof for a given leaf index
     pub fn generate_proof(&self, leaf_index: u32) -> Option<(Vec<BlsScalar>, u32)> {
-        if leaf_index >= self.leaves.len() as u32 {
+        if leaf_index >= self.leaf_count {
             return None;
         }
 
         let mut siblings = vec![&self.env];
 
         // Handle the simple 2-leaf case correctly
-        if self.depth == 1 && self.leaves.len() == 2 {
-            if leaf_index == 0 {
-                let sibling_bytes = self.leaves.get(1).unwrap();
-                siblings.push_back(bytes_to_bls_scalar(&sibling_bytes));
-            } else {
-                let sibling_bytes = self.leaves.get(0).unwrap();
-                siblings.push_back(bytes_to_bls_scalar(&sibling_bytes));
-            }
+        if self.depth == 1 && self.leaf_count == 2 {
+            let sibling_bytes = self.leaf_at(1 - leaf_index)?;
+            siblings.push_back(bytes_to_bls_scalar(&sibling_bytes));
         } else {
             // General approach
             let mut current_index = leaf_index;
@@ -145,8 +150,8 @@ impl LeanIMT {
 
                 let sibling_scalar = if current_depth == 0 {
                     // At leaf level, use actual leaves or zero if missing
-                    if sibling_index < self.leaves.len() as u32 {
-                        let sibling_bytes = self.leaves.get(sibling_index).unwrap();
+                    if sibling_index < self.leaf_count {
+                        let sibling_bytes = self.leaf_at(sibling_index)?;
                         bytes_to_bls_scalar(&sibling_bytes)
                     } else {
                         BlsScalar::from_u256(U256::from_u32(&self.env, 0))
@@ -185,8 +190,7 @@ impl LeanIMT {
 
         // If not cached, compute it
         if target_level == 0 {
-            if node_index < self.leaves.len() as u32 {
-                let leaf_bytes = self.leaves.get(node_index).unwrap();
+            if let Some(leaf_bytes) = self.leaf_at(node_index) {
                 bytes_to_bls_scalar(&leaf_bytes)
             } else {
                 BlsScalar::from_u256(U256::from_u32(&self.env, 0))
@@ -214,23 +218,24 @@ impl LeanIMT {
     /// Now with full memoization - we only recompute the specific path from the new leaf to root,
     /// and update the cache as we go.
     fn incremental_update(&mut self) {
-        let leaf_index = (self.leaves.len() - 1) as u32;
+        let leaf_index = self.leaf_count - 1;
 
         // Update the leaf in the sparse cache
-        let leaf_bytes = self.leaves.get(leaf_index).unwrap();
+        let leaf_bytes = self.leaves.last().unwrap();
         let leaf_scalar = bytes_to_bls_scalar(&leaf_bytes);
-        self.cache_sparse_node(0, leaf_index, leaf_scalar);
+        self.cache_sparse_node(0, leaf_index, leaf_scalar.clone());
 
         // Recompute the path to root and update cache
-        self.root = self.recompute_path_to_root_with_cache_update(leaf_index);
+        self.root = self.recompute_path_to_root_with_cache_update(leaf_index, leaf_scalar);
     }
 
     /// Recomputes only the path from a specific leaf to the root with cache updates
     /// This is the optimized version that updates the cache as it goes
-    fn recompute_path_to_root_with_cache_update(&mut self, leaf_index: u32) -> BytesN<32> {
-        let leaf_bytes = self.leaves.get(leaf_index).unwrap();
-        let leaf_scalar = bytes_to_bls_scalar(&leaf_bytes);
-
+    fn recompute_path_to_root_with_cache_update(
+        &mut self,
+        leaf_index: u32,
+        leaf_scalar: BlsScalar,
+    ) -> BytesN<32> {
         // Start from the leaf and work our way up to the root
         let mut current_index = leaf_index;
         let mut current_level = 0;
@@ -243,22 +248,18 @@ impl LeanIMT {
                 current_index - 1
             };
 
-            // Get the sibling value (either from cache or compute if missing)
-            let sibling_scalar = if current_level == 0 {
-                // At leaf level, use actual leaves or zero if missing
-                if sibling_index < self.leaves.len() as u32 {
-                    let sibling_bytes = self.leaves.get(sibling_index).unwrap();
-                    bytes_to_bls_scalar(&sibling_bytes)
-                } else {
-                    BlsScalar::from_u256(U256::from_u32(&self.env, 0))
-                }
+            // The newest leaf is always the rightmost one, so a right sibling is an
+            // empty subtree and a left sibling is the frontier node at this level
+            let sibling_scalar = if current_index % 2 == 0 {
+                self.frontier
+                    .set(current_level, bls_scalar_to_bytes(current_scalar.clone()));
+                self.get_cached_subtree_level(current_level).unwrap()
             } else {
-                // At internal levels, use hybrid cache system
-                if let Some(cached_value) = self.get_cached_node(current_level, sibling_index) {
-                    cached_value
-                } else {
-                    self.compute_node_at_level_scalar(sibling_index, current_level)
-                }
+                let sibling_bytes = self.frontier.get(current_level).unwrap();
+                let sibling_scalar = bytes_to_bls_scalar(&sibling_bytes);
+                // Keep the sparse cache warm for proof generation in this instance
+                self.cache_sparse_node(current_level, sibling_index, sibling_scalar.clone());
+                sibling_scalar
             };
 
             // Compute the parent hash
@@ -295,13 +296,20 @@ impl LeanIMT {
 
     /// Gets a cached node value using hybrid cache system:
     /// 1. First check sparse_cache for specific node updates
-    /// 2. If not found, fall back to subtree_cache for level-based cache
+    /// 2. If not found and the subtree holds no leaves, fall back to subtree_cache
     fn get_cached_node(&self, level: u32, node_index: u32) -> Option<BlsScalar> {
         // First check sparse cache for specific node updates
         if let Some(cached_value) = self.sparse_cache.get((level, node_index)) {
             return Some(cached_value);
         }
 
+        // The level-based cache only holds empty subtree hashes, so it must not be
+        // used for a node that covers any inserted leaf
+        let first_leaf = (node_index as u64) << level;
+        if first_leaf < self.leaf_count as u64 {
+            return None;
+        }
+
         // Fall back to subtree cache for level-based cache (empty tree optimization)
         self.get_cached_subtree_level(level)
     }
@@ -320,10 +328,31 @@ impl LeanIMT {
             return;
         }
 
-        // For trees with leaves, clear both caches and let them rebuild on-demand
-        // The hybrid cache system will handle the rest
-        self.subtree_cache = Map::new(&self.env);
+        // For trees with leaves, restore the empty subtree hashes (O(depth)) and let
+        // the sparse cache rebuild on-demand from the leaves
         self.sparse_cache = Map::new(&self.env);
+        self.compute_zero_hashes();
+    }
+
+    /// Recomputes the frontier from the current leaves
+    /// This is only needed when the tree was stored without its frontier, and costs
+    /// O(n) hashes once
+    fn rebuild_frontier(&mut self) {
+        let leaf_count = self.leaf_count;
+        let mut frontier = vec![&self.env];
+
+        for level in 0..self.depth {
+            let next_index = leaf_count >> level;
+            let node_scalar = if next_index % 2 == 1 {
+                self.compute_node_at_level_scalar(next_index - 1, level)
+            } else {
+                // Not read by the next insert; it is overwritten on the way up
+                self.get_cached_subtree_level(level).unwrap()
+            };
+            frontier.push_back(bls_scalar_to_bytes(node_scalar));
+        }
+
+        self.frontier = frontier;
     }
 
     /// Recomputes the entire tree after insertion using fixed depth and zero padding
@@ -335,6 +364,14 @@ impl LeanIMT {
             return;
         }
 
+        // Set the root
+        let empty_root = self.compute_zero_hashes();
+        self.root = bls_scalar_to_bytes(empty_root);
+    }
+
+    /// Fills the subtree cache with the hash of an empty subtree at every level
+    /// and returns the empty root
+    fn compute_zero_hashes(&mut self) -> BlsScalar {
         // For empty trees, all subtrees at the same level are identical
         // We only need to compute one hash per level: hash(level_n, level_n) = level_n+1
         let zero_scalar = BlsScalar::from_u256(U256::from_u32(&self.env, 0));
@@ -354,8 +391,7 @@ impl LeanIMT {
             self.cache_subtree_level(level, current_level_hash.clone());
         }
 
-        // Set the root
-        self.root = bls_scalar_to_bytes(current_level_hash);
+        current_level_hash
     }
 
     /// Hashes two BlsScalar values using Poseidon hash function
@@ -368,12 +404,72 @@ impl LeanIMT {
         (self.leaves.clone(), self.depth, self.root.clone())
     }
 
+    /// Gets the frontier nodes needed for the next insert, for storage next to
+    /// `to_storage`
+    pub fn get_frontier(&self) -> Vec<BytesN<32>> {
+        self.frontier.clone()
+    }
+
     /// Deserializes the tree state from storage
+    /// Without a stored frontier the next insert has to rehash the existing leaves,
+    /// prefer `from_storage_with_frontier` when the frontier is available
     pub fn from_storage(env: &Env, leaves: Vec<BytesN<32>>, depth: u32, root: BytesN<32>) -> Self {
+        let mut tree = Self::from_parts(env, leaves, depth, root);
+
+        // Rebuild the cache and frontier for the deserialized tree
+        tree.rebuild_cache_from_leaves();
+        tree.rebuild_frontier();
+        tree
+    }
+
+    /// Deserializes the tree state together with its stored frontier
+    /// Only the empty subtree hashes are recomputed, so loading is O(depth)
+    pub fn from_storage_with_frontier(
+        env: &Env,
+        leaves: Vec<BytesN<32>>,
+        depth: u32,
+        root: BytesN<32>,
+        frontier: Vec<BytesN<32>>,
+    ) -> Self {
+        let mut tree = Self::from_parts(env, leaves, depth, root);
+        tree.compute_zero_hashes();
+
+        if frontier.len() == depth {
+            tree.frontier = frontier;
+        } else {
+            tree.rebuild_frontier();
+        }
+        tree
+    }
+
+    /// Loads just enough of a stored tree to append to it: its leaf count, root and frontier
+    /// Inserts, the root, the frontier and `get_path` of a new leaf work as usual, but nodes
+    /// over the leaves that weren't loaded can't be read, so proofs need `from_storage`.
+    /// Returns None if the frontier doesn't have one node per level.
+    pub fn from_frontier(
+        env: &Env,
+        leaf_count: u32,
+        depth: u32,
+        root: BytesN<32>,
+        frontier: Vec<BytesN<32>>,
+    ) -> Option<Self> {
+        if frontier.len() != depth {
+            return None;
+        }
+
+        let mut tree = Self::from_parts(env, vec![env], depth, root);
+        tree.leaf_count = leaf_count;
+        tree.compute_zero_hashes();
+        tree.frontier = frontier;
+        Some(tree)
+    }
+
+    fn from_parts(env: &Env, leaves: Vec<BytesN<32>>, depth: u32, root: BytesN<32>) -> Self {
         let capacity = 1u32.checked_shl(depth).unwrap_or(u32::MAX);
         let env_clone = env.clone();
-        let mut tree = Self {
+        Self {
             env: env_clone.clone(),
+            leaf_count: leaves.len(),
             leaves,
             depth,
             capacity,
@@ -381,11 +477,8 @@ impl LeanIMT {
             poseidon: Poseidon255::new(&env_clone, 3),
             subtree_cache: Map::new(&env_clone),
             sparse_cache: Map::new(&env_clone),
-        };
-
-        // Rebuild the cache for the deserialized tree
-        tree.rebuild_cache_from_leaves();
-        tree
+            frontier: vec![&env_clone],
+        }
     }
 
     /// Gets all leaves in the tree
@@ -395,15 +488,18 @@ impl LeanIMT {
 
     /// Checks if the tree is empty
     pub fn is_empty(&self) -> bool {
-        self.leaves.is_empty()
+        self.leaf_count == 0
     }
 
     /// Gets a leaf at a specific index
     pub fn get_leaf(&self, index: usize) -> Option<BytesN<32>> {
-        match self.leaves.get(index.try_into().unwrap()) {
-            Some(leaf) => Some(leaf.clone()),
-            None => None,
-        }
+        self.leaf_at(index.try_into().ok()?)
+    }
+
+    /// Gets a leaf by its index in the whole tree, if it was loaded or inserted
+    fn leaf_at(&self, index: u32) -> Option<BytesN<32>> {
+        let first_loaded = self.leaf_count - self.leaves.len();
+        self.leaves.get(index.checked_sub(first_loaded)?)
     }
 
     /// Gets a leaf as BlsScalar at a specific index
@@ -415,11 +511,7 @@ impl LeanIMT {
     /// Gets the value of a node at a specific level and index
     pub fn get_node(&self, level: u32, index: u32) -> Option<BytesN<32>> {
         if level == 0 {
-            if index < self.leaves.len() as u32 {
-                Some(self.leaves.get(index).unwrap())
-            } else {
-                None
-            }
+            self.leaf_at(index)
         } else if level > self.depth {
             None
         } else {
@@ -427,6 +519,21 @@ impl LeanIMT {
         }
     }
 
+    /// Gets the nodes on the path from a leaf to the root, from the leaf's parent up
+    /// Right after an insert these are exactly the nodes it changed, and are read from
+    /// the cache it filled, so this costs no hashing
+    pub fn get_path(&self, leaf_index: u32) -> Option<Vec<BytesN<32>>> {
+        if leaf_index >= self.leaf_count {
+            return None;
+        }
+
+        let mut path = vec![&self.env];
+        for level in 1..=self.depth {
+            path.push_back(self.get_node(level, leaf_index >> level)?);
+        }
+        Some(path)
+    }
+
     /// Gets the sibling of a node at a specific level and index
     pub fn get_sibling(&self, level: u32, index: u32) -> Option<BytesN<32>> {
         if level > self.depth {
@@ -5,6 +5,12 @@ use soroban_sdk::{bytesn, contracttype, crypto::bls12_381::Fr, vec, Env, Vec, U2
 // Uncomment below to import Poseidon contract into the main contract
 // pub mod contract;
 
+/// Identifies the parameter set below: the BLS12-381 scalar field, the x^5 S-box, 8 full and 56
+/// partial rounds, and the round constants and MDS matrices of poseidon255_constants.circom for
+/// t = 2 and 3. Any change to those must bump this, since every tree and note hashed with the old
+/// parameters stops matching the circuit.
+pub const PARAMS_ID: u32 = 1;
+
 /// Poseidon255 implementation based on the Circom circuit
 /// This implements the same algorithm as poseidon255.circom
 #[contracttype]
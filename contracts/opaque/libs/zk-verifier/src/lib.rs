@@ -6,6 +6,9 @@ use soroban_sdk::{
     vec,
 };
 
+#[cfg(feature = "native")]
+pub mod native;
+
 // Re-export types compatible with groth16_verifier contract
 // These types match the groth16_verifier contract types for compatibility
 #[contracterror]
@@ -0,0 +1,135 @@
+//! Groth16 verification without a Soroban host, for relayers and other off-chain tools
+//!
+//! Takes the same byte encodings the contract stores and accepts: `VerificationKey::to_bytes`,
+//! `Proof::to_bytes` and `PublicSignals::to_bytes`, with points in the uncompressed big-endian
+//! encoding of the host's BLS12-381 functions. The check is the pairing equation of
+//! `Groth16Verifier::verify_proof` and the `groth16_verifier` contract, so a proof accepted here
+//! is accepted on-chain against the same key.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use ark_bls12_381::{Bls12_381, Fr, G1Affine, G2Affine};
+use ark_ec::{AffineRepr, CurveGroup, pairing::Pairing};
+use ark_ff::{PrimeField, Zero};
+use ark_serialize::CanonicalDeserialize;
+
+use crate::Groth16Error;
+
+const G1_LEN: usize = 96;
+const G2_LEN: usize = 192;
+const SCALAR_LEN: usize = 32;
+
+/// Reads the consecutive fields of an encoding, failing once the bytes run out
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < len {
+            return None;
+        }
+        let (field, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(field)
+    }
+
+    // Deserializing checks that the point is on the curve and in the prime-order subgroup
+    fn g1(&mut self) -> Option<G1Affine> {
+        G1Affine::deserialize_uncompressed(self.take(G1_LEN)?).ok()
+    }
+
+    fn g2(&mut self) -> Option<G2Affine> {
+        G2Affine::deserialize_uncompressed(self.take(G2_LEN)?).ok()
+    }
+
+    fn count(&mut self) -> Option<usize> {
+        let bytes = self.take(4)?.try_into().ok()?;
+        Some(u32::from_be_bytes(bytes) as usize)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+struct Key {
+    alpha: G1Affine,
+    beta: G2Affine,
+    gamma: G2Affine,
+    delta: G2Affine,
+    ic: Vec<G1Affine>,
+}
+
+fn read_key(bytes: &[u8]) -> Option<Key> {
+    let mut reader = Reader { bytes };
+    let alpha = reader.g1()?;
+    let beta = reader.g2()?;
+    let gamma = reader.g2()?;
+    let delta = reader.g2()?;
+    let ic_len = reader.count()?;
+    let ic = (0..ic_len)
+        .map(|_| reader.g1())
+        .collect::<Option<Vec<_>>>()?;
+    reader.is_empty().then_some(Key {
+        alpha,
+        beta,
+        gamma,
+        delta,
+        ic,
+    })
+}
+
+fn read_proof(bytes: &[u8]) -> Option<(G1Affine, G2Affine, G1Affine)> {
+    let mut reader = Reader { bytes };
+    let proof = (reader.g1()?, reader.g2()?, reader.g1()?);
+    reader.is_empty().then_some(proof)
+}
+
+// Signals are reduced modulo the scalar field, as `Fr::from_u256` does on-chain
+fn read_signals(bytes: &[u8]) -> Option<Vec<Fr>> {
+    let mut reader = Reader { bytes };
+    let len = reader.count()?;
+    let signals = (0..len)
+        .map(|_| Some(Fr::from_be_bytes_mod_order(reader.take(SCALAR_LEN)?)))
+        .collect::<Option<Vec<_>>>()?;
+    reader.is_empty().then_some(signals)
+}
+
+/// Verifies a serialized Groth16 proof against a serialized verification key
+///
+/// # Returns
+///
+/// * `true` if the proof is valid for the public signals
+/// * `false` if it isn't, or the proof or signals are malformed
+/// * `Err(Groth16Error::MalformedVerifyingKey)` if the key is malformed or doesn't have one
+///   input per signal
+pub fn verify(
+    vk_bytes: &[u8],
+    proof_bytes: &[u8],
+    pub_signals_bytes: &[u8],
+) -> Result<bool, Groth16Error> {
+    let vk = read_key(vk_bytes).ok_or(Groth16Error::MalformedVerifyingKey)?;
+    let Some((a, b, c)) = read_proof(proof_bytes) else {
+        return Ok(false);
+    };
+    let Some(pub_signals) = read_signals(pub_signals_bytes) else {
+        return Ok(false);
+    };
+    if pub_signals.len() + 1 != vk.ic.len() {
+        return Err(Groth16Error::MalformedVerifyingKey);
+    }
+
+    // vk_x = ic[0] + sum(pub_signals[i] * ic[i+1])
+    let vk_x = pub_signals
+        .iter()
+        .zip(&vk.ic[1..])
+        .fold(vk.ic[0].into_group(), |acc, (s, v)| acc + *v * s)
+        .into_affine();
+
+    // e(-A, B) * e(alpha, beta) * e(vk_x, gamma) * e(C, delta) == 1
+    let pairing =
+        Bls12_381::multi_pairing([-a, vk.alpha, vk_x, c], [b, vk.beta, vk.gamma, vk.delta]);
+    Ok(pairing.is_zero())
+}
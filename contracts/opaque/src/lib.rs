@@ -1,13 +1,21 @@
 #![no_std]
 
 use soroban_sdk::{
-    contract, contractimpl, log, symbol_short, token, vec, Address, Bytes, BytesN, Env, String,
-    Symbol, Vec,
+    contract, contractimpl, contractmeta, contracttype, crypto::bls12_381::Fr, log,
+    panic_with_error, symbol_short, token, vec, xdr::ToXdr, Address, Bytes, BytesN, Env, IntoVal,
+    Symbol, TryFromVal, Val, Vec,
 };
 
-use lean_incremental_merkle_tree::{LeanIMT, TREE_DEPTH_KEY, TREE_LEAVES_KEY, TREE_ROOT_KEY};
+use lean_incremental_merkle_tree::{
+    LeanIMT, TREE_DEPTH_KEY, TREE_FRONTIER_KEY, TREE_LEAVES_KEY, TREE_ROOT_KEY,
+};
+use poseidon::Poseidon255;
 use zk_verifier::{Proof, PublicSignals, VerificationKey};
 
+mod public_signals;
+pub use public_signals::WithdrawalSignals;
+mod self_test;
+
 #[cfg(test)]
 mod test;
 
@@ -17,7 +25,16 @@ mod groth16_verifier_wasm {
     );
 }
 
-use soroban_sdk::contracterror;
+use soroban_sdk::{contracterror, contractevent};
+
+/// Version of the deployed contract, returned by `version`
+///
+/// Bump it, together with the "version" metadata below, whenever the proof or public signal
+/// format changes, so clients and relayers can refuse deployments they don't support.
+pub const CONTRACT_VERSION: u32 = 1;
+
+contractmeta!(key = "version", val = "1");
+contractmeta!(key = "githash", val = env!("OPAQUE_GIT_HASH"));
 
 // Contract errors
 #[contracterror]
@@ -30,27 +47,569 @@ pub enum Error {
     OnlyAdmin = 4,
     TreeAtCapacity = 5,
     AssociationRootMismatch = 6,
+    InvalidAmount = 7,
+    Paused = 8,
+    InvalidLeafIndex = 9,
+    UnknownStateRoot = 10,
+    InvalidConfiguration = 11,
+    DuplicateCommitment = 12,
+    InvalidFee = 13,
+    ContextMismatch = 14,
+    UnboundRelayer = 15,
+    InvalidVerificationKey = 16,
+    NoPendingVerificationKey = 17,
+    TimelockActive = 18,
+    MalformedPublicSignals = 19,
+    ChangeCommitmentMismatch = 20,
+    AmountMismatch = 21,
+    Reentrancy = 22,
+    LabelExists = 23,
+    UnknownLabel = 24,
+    UnknownProposal = 25,
+    AlreadyApproved = 26,
+    ThresholdNotMet = 27,
+    EmergencyNotActive = 28,
+    RefundNotReady = 29,
+    UnknownCommitment = 30,
+    UnsupportedProofVersion = 31,
+    PoolNotEmpty = 32,
+    InvalidFieldElement = 33,
+    WithdrawTooEarly = 34,
+    InvalidToken = 35,
+    NoPendingVerifier = 36,
+    TreeAlreadyRebuilt = 37,
+    NotInitialized = 38,
+    UnknownReceipt = 39,
+    NotReceiptHolder = 40,
+    CannotSweepPoolToken = 41,
+    BelowMinReserve = 42,
+    WithdrawVolumeExceeded = 43,
+    PoseidonParamsMismatch = 44,
+    IssuerSignatureRequired = 45,
+    NullifierOpsDisabled = 46,
+    UnknownNullifier = 47,
+    EncryptedNoteTooLarge = 48,
+    ProofExpired = 49,
+    UnboundCommitment = 50,
+}
+
+// Contract events
+// Topics are fixed so indexers can filter on them; data is published as a vec in field order
+
+#[contractevent(topics = ["deposit"], data_format = "vec")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DepositEvent {
+    pub leaf_index: u32,
+    pub commitment: BytesN<32>,
+    pub new_root: BytesN<32>,
+    /// The nodes the insertion changed, from the leaf's parent up to `new_root`
+    pub path: Vec<BytesN<32>>,
+    /// The depositor's encrypted copy of the note, if they stored one
+    pub encrypted_note: Option<Bytes>,
+}
+
+#[contractevent(topics = ["withdraw"], data_format = "vec")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WithdrawEvent {
+    pub nullifier: BytesN<32>,
+    pub to: Address,
+    pub amount: i128,
+    pub relayer: Address,
+    pub fee: i128,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SplitWithdrawEvent {
+    pub nullifier: BytesN<32>,
+    pub payouts: Vec<(Address, i128)>,
+    pub relayer: Address,
+    pub fee: i128,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReceiptIssued {
+    pub receipt_id: u32,
+    pub holder: Address,
+    pub amount: i128,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReceiptTransferred {
+    pub receipt_id: u32,
+    pub from: Address,
+    pub to: Address,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReceiptRedeemed {
+    pub receipt_id: u32,
+    pub to: Address,
+    pub amount: i128,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AdminTransferred {
+    pub old_admin: Address,
+    pub new_admin: Address,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TokenChanged {
+    pub old_token: Address,
+    pub new_token: Address,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DenominationChanged {
+    pub old_denomination: Option<i128>,
+    pub new_denomination: Option<i128>,
+}
+
+/// A spent nullifier was deleted by the admins, making its note spendable again
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NullifierRemoved {
+    pub nullifier: BytesN<32>,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FeesCollected {
+    pub amount: i128,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FeesWithdrawn {
+    pub to: Address,
+    pub amount: i128,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TokenSwept {
+    pub token: Address,
+    pub to: Address,
+    pub amount: i128,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProposalCreated {
+    pub proposal_id: BytesN<32>,
+    pub proposer: Address,
+    pub action: AdminAction,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProposalApproved {
+    pub proposal_id: BytesN<32>,
+    pub admin: Address,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProposalExecuted {
+    pub proposal_id: BytesN<32>,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LabelAdded {
+    pub label: BytesN<32>,
+    pub association_root: BytesN<32>,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LabelRemoved {
+    pub label: BytesN<32>,
+    pub association_root: BytesN<32>,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RecipientWhitelisted {
+    pub recipient: Address,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RecipientUnwhitelisted {
+    pub recipient: Address,
+}
+
+/// A withdrawal to a whitelisted recipient skipped the association check
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AssociationBypassed {
+    pub nullifier: BytesN<32>,
+    pub recipient: Address,
+    pub association_root: BytesN<32>,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EmergencyAnnounced {
+    pub active_at: u64,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EmergencyCancelled {}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EmergencyRefund {
+    pub commitment: BytesN<32>,
+    pub nullifier: BytesN<32>,
+    pub to: Address,
+    pub amount: i128,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VerificationKeyProposed {
+    pub vk_hash: BytesN<32>,
+    pub effective_at: u64,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VerificationKeyUpdated {
+    pub vk_hash: BytesN<32>,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VerifierProposed {
+    pub verifier: Address,
+    pub effective_at: u64,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VerifierUpdated {
+    pub old_verifier: Address,
+    pub new_verifier: Address,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TreeRebuilt {
+    pub old_root: BytesN<32>,
+    pub new_root: BytesN<32>,
+    pub leaf_count: u32,
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContractUpgraded {
+    pub wasm_hash: BytesN<32>,
 }
 
-// Error messages for Vec<String> returns (legacy compatibility)
-pub const ERROR_NULLIFIER_USED: &str = "Nullifier already used";
-pub const ERROR_INSUFFICIENT_BALANCE: &str = "Insufficient balance";
-pub const ERROR_COIN_OWNERSHIP_PROOF: &str = "Couldn't verify coin ownership proof";
-pub const ERROR_WITHDRAW_SUCCESS: &str = "Withdrawal successful";
-pub const ERROR_ONLY_ADMIN: &str = "Only the admin can set association root";
-pub const SUCCESS_ASSOCIATION_ROOT_SET: &str = "Association root set successfully";
+/// A contract function a withdrawal pays into, as in `target.function(args..., amount)`
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContractCall {
+    pub contract: Address,
+    pub function: Symbol,
+    pub args: Vec<Val>,
+}
+
+/// One withdrawal of a `withdraw_batch`, with the arguments `withdraw` takes
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WithdrawItem {
+    pub to: Address,
+    pub relayer: Address,
+    pub fee: i128,
+    pub proof: Bytes,
+    pub pub_signals: Bytes,
+    pub change_commitment: Option<BytesN<32>>,
+}
+
+/// What a successful `withdraw` paid, so callers don't have to read it back from events
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WithdrawReceipt {
+    pub nullifier_hash: BytesN<32>,
+    /// The amount withdrawn from the pool, including the relayer's fee
+    pub amount: i128,
+    pub to: Address,
+    /// The ledger sequence the withdrawal landed in
+    pub ledger: u32,
+}
+
+/// A privileged change that the admins approve through `propose`, `approve` and `execute`
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AdminAction {
+    SetAssociationRoot(BytesN<32>),
+    AddLabel(BytesN<32>),
+    RemoveLabel(BytesN<32>),
+    Pause,
+    Unpause,
+    SetVerificationKey(Bytes),
+    ApplyVerificationKey,
+    CancelVerificationKey,
+    /// Replaces the admin set and the number of approvals a proposal needs
+    SetAdmins(Vec<Address>, u32),
+    AnnounceEmergency,
+    CancelEmergency,
+    SetProofVersions(u32, u32),
+    SetToken(Address),
+    SetWithdrawDelay(u32),
+    SetDepositFee(i128),
+    WithdrawFees(Address),
+    AddWhitelistedRecipient(Address),
+    RemoveWhitelistedRecipient(Address),
+    SetGroth16Verifier(Address),
+    ApplyGroth16Verifier,
+    CancelGroth16Verifier,
+    RebuildTree,
+    Sweep(Address, Address, i128),
+    SetMinReserve(i128),
+    SetWithdrawVolumeLimit(i128, u32),
+    SetDenomination(Option<i128>),
+    SetDepositIssuer(BytesN<32>),
+    RemoveNullifier(BytesN<32>),
+    BumpTtl(u32),
+    Upgrade(BytesN<32>),
+}
+
+/// A pending admin proposal and the admins who have approved it so far
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Proposal {
+    pub action: AdminAction,
+    pub approvals: Vec<Address>,
+}
+
+/// A snapshot of the pool's status, as returned by `get_state`
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PoolState {
+    pub merkle_root: BytesN<32>,
+    pub commitment_count: u32,
+    pub merkle_depth: u32,
+    pub remaining_capacity: u32,
+    pub association_root: BytesN<32>,
+    pub paused: bool,
+}
+
+/// The deployment's configuration, as returned by `get_config`
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PoolConfig {
+    pub token: Address,
+    /// The first admin of the admin set
+    pub admin: Address,
+    pub groth16_verifier: Address,
+    pub tree_depth: u32,
+    /// The denomination, or `None` for a variable-amount pool
+    pub fixed_amount: Option<i128>,
+}
+
+/// The pool's denomination and tree shape, passed to the constructor
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PoolParams {
+    /// The amount every deposit and withdrawal must be, or `None` for a variable-amount pool
+    pub denomination: Option<i128>,
+    /// The state tree depth compiled into the withdrawal circuit
+    pub tree_depth: u32,
+    /// The number of recent roots withdrawals may reference
+    pub root_history_size: u32,
+    /// The Poseidon parameter set compiled into the withdrawal circuit, which must be the
+    /// `poseidon::PARAMS_ID` this contract hashes with
+    pub poseidon_params_id: u32,
+}
+
+/// Opt-in restrictions and admin powers of a regulated pool, passed to the constructor
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AccessConfig {
+    /// The ed25519 key of the off-chain issuer that signs deposits, or `None` for a
+    /// permissionless pool
+    pub deposit_issuer: Option<BytesN<32>>,
+    /// Whether the admin may remove and restore nullifiers
+    pub allow_admin_nullifier_ops: bool,
+}
+
+/// A node of the commitment tree and the siblings that hash it up to the root, as returned by
+/// `get_root_proof`
+///
+/// From `node` at (`level`, `index`), for each sibling `i` in order: if bit `i` of `index` is
+/// 0 the parent is `Poseidon255(node, sibling)`, otherwise `Poseidon255(sibling, node)`. After
+/// `depth - level` siblings the result must equal `root`.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RootProof {
+    pub root: BytesN<32>,
+    /// The number of leaves the tree had at `root`
+    pub leaf_count: u32,
+    /// The ledger sequence the proof was read at
+    pub ledger: u32,
+    /// The node's level, 0 for the leaves and the tree depth for the root
+    pub level: u32,
+    /// The node's position within its level, counting from the left
+    pub index: u32,
+    pub node: BytesN<32>,
+    /// One sibling per level from `level` up to the root, lowest first
+    pub siblings: Vec<BytesN<32>>,
+}
+
+/// A transferable claim on funds deposited with `deposit_with_receipt`
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Receipt {
+    pub holder: Address,
+    pub amount: i128,
+}
+
+/// Lifetime flows of the pool, as returned by `get_pool_stats`
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PoolStats {
+    pub total_deposited: i128,
+    pub total_withdrawn: i128,
+}
+
+/// The first check a withdrawal would fail, as returned by `can_withdraw`
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WithdrawCheck {
+    Ok,
+    Paused,
+    NotInitialized,
+    UnsupportedProofVersion,
+    MalformedPublicSignals,
+    NullifierUsed,
+    ProofExpired,
+    UnknownStateRoot,
+    WithdrawTooEarly,
+    AssociationRootMismatch,
+    InvalidProof,
+    InvalidAmount,
+    InsufficientBalance,
+    BelowMinReserve,
+    WithdrawVolumeExceeded,
+}
+
+impl From<Error> for WithdrawCheck {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Paused => WithdrawCheck::Paused,
+            Error::NotInitialized => WithdrawCheck::NotInitialized,
+            Error::UnsupportedProofVersion => WithdrawCheck::UnsupportedProofVersion,
+            Error::MalformedPublicSignals => WithdrawCheck::MalformedPublicSignals,
+            Error::NullifierUsed => WithdrawCheck::NullifierUsed,
+            Error::ProofExpired => WithdrawCheck::ProofExpired,
+            Error::UnknownStateRoot => WithdrawCheck::UnknownStateRoot,
+            Error::WithdrawTooEarly => WithdrawCheck::WithdrawTooEarly,
+            Error::AssociationRootMismatch => WithdrawCheck::AssociationRootMismatch,
+            Error::InvalidAmount | Error::AmountMismatch => WithdrawCheck::InvalidAmount,
+            Error::InsufficientBalance => WithdrawCheck::InsufficientBalance,
+            Error::BelowMinReserve => WithdrawCheck::BelowMinReserve,
+            Error::WithdrawVolumeExceeded => WithdrawCheck::WithdrawVolumeExceeded,
+            _ => WithdrawCheck::InvalidProof,
+        }
+    }
+}
 
-const TREE_DEPTH: u32 = 8; // Reduced from 20 to fit Soroban budget (supports 256 deposits)
+const TREE_DEPTH: u32 = 20; // Depth of the bundled withdrawal circuit (Withdraw(20, 2))
+const MAX_TREE_DEPTH: u32 = 32; // Leaf indices are u32
+const ASSOCIATION_TREE_DEPTH: u32 = 2; // Association tree depth of the bundled circuit
+const ROOT_HISTORY_SIZE: u32 = 30; // Default number of recent roots a withdrawal proof may reference
+const VK_TIMELOCK: u64 = 172_800; // Seconds a proposed verification key waits before it can apply
+const EMERGENCY_TIMELOCK: u64 = 2_592_000; // Seconds from an emergency announcement to refunds
+const REFUND_CLAIM_DELAY: u64 = 86_400; // Seconds a refund claim waits before the note is revealed
 
 // Storage keys
 const NULL_KEY: Symbol = symbol_short!("null");
+const NULL_COUNT_KEY: Symbol = symbol_short!("nullcnt");
 const VK_KEY: Symbol = symbol_short!("vk");
+const PENDING_VK_KEY: Symbol = symbol_short!("vkpend");
 const TOKEN_KEY: Symbol = symbol_short!("token");
+const TOKEN_DECIMALS_KEY: Symbol = symbol_short!("decimals");
 const ASSOCIATION_ROOT_KEY: Symbol = symbol_short!("assoc");
-const ADMIN_KEY: Symbol = symbol_short!("admin");
+const ASSOCIATION_ROOTS_KEY: Symbol = symbol_short!("aroots");
+const ASSOCIATION_ROOT_INDEX_KEY: Symbol = symbol_short!("arootidx");
+const ASSOCIATION_ROOT_LEDGER_KEY: Symbol = symbol_short!("arootseq");
+const LABELS_KEY: Symbol = symbol_short!("labels");
+const WHITELIST_KEY: Symbol = symbol_short!("wlist");
+const ADMINS_KEY: Symbol = symbol_short!("admins");
+const LEGACY_ADMIN_KEY: Symbol = symbol_short!("admin");
+const ADMIN_THRESHOLD_KEY: Symbol = symbol_short!("adminthr");
+const PROPOSAL_KEY: Symbol = symbol_short!("prop");
+const PROPOSAL_NONCE_KEY: Symbol = symbol_short!("propnonce");
+const EMERGENCY_KEY: Symbol = symbol_short!("emergency");
+const REFUND_CLAIM_KEY: Symbol = symbol_short!("claim");
+const PROOF_VERSIONS_KEY: Symbol = symbol_short!("proofver");
 const GROTH16_VERIFIER_KEY: Symbol = symbol_short!("g16v");
+const PENDING_VERIFIER_KEY: Symbol = symbol_short!("g16vpend");
+const TREE_REBUILT_KEY: Symbol = symbol_short!("rebuilt");
+const POSEIDON_PARAMS_KEY: Symbol = symbol_short!("poseidon");
+const DENOMINATION_KEY: Symbol = symbol_short!("denom");
+const PAUSED_KEY: Symbol = symbol_short!("paused");
+const ROOTS_KEY: Symbol = symbol_short!("roots");
+const ROOT_INDEX_KEY: Symbol = symbol_short!("rootidx");
+const ROOT_LEDGER_KEY: Symbol = symbol_short!("rootseq");
+const ROOT_LEAF_COUNT_KEY: Symbol = symbol_short!("rootcnt");
+const WITHDRAW_DELAY_KEY: Symbol = symbol_short!("wdelay");
+const MIN_RESERVE_KEY: Symbol = symbol_short!("minres");
+const WITHDRAW_LIMIT_KEY: Symbol = symbol_short!("wdlimit");
+const WITHDRAW_VOLUME_KEY: Symbol = symbol_short!("wdvolume");
+const DEPOSIT_FEE_KEY: Symbol = symbol_short!("depfee");
+const DEPOSIT_ISSUER_KEY: Symbol = symbol_short!("issuer");
+const TOTAL_FEES_KEY: Symbol = symbol_short!("totfees");
+const FEES_PAID_KEY: Symbol = symbol_short!("feespaid");
+const ROOT_HISTORY_SIZE_KEY: Symbol = symbol_short!("rootsz");
+const COMMITMENT_KEY: Symbol = symbol_short!("cmt");
+const LEAF_COUNT_KEY: Symbol = symbol_short!("leafcnt");
+const LEAF_PAGE_SIZE: u32 = 64; // Leaves per `("leaves", page)` entry
+const MAX_COMMITMENTS_PER_CALL: u32 = 256; // Largest page `get_commitments_paged` returns
+const LOCK_KEY: Symbol = symbol_short!("lock");
+const TOTAL_DEPOSITED_KEY: Symbol = symbol_short!("deposited");
+const TOTAL_WITHDRAWN_KEY: Symbol = symbol_short!("withdrawn");
+const NOTE_VALUE_KEY: Symbol = symbol_short!("notesval");
+const RECEIPT_KEY: Symbol = symbol_short!("rcpt");
+const RECEIPT_COUNT_KEY: Symbol = symbol_short!("rcptcnt");
+const RECEIPT_TOTAL_KEY: Symbol = symbol_short!("rcpttot");
+const NULLIFIER_OPS_KEY: Symbol = symbol_short!("nullops");
+const ENCRYPTED_NOTE_KEY: Symbol = symbol_short!("encnote");
+const MAX_ENCRYPTED_NOTE_LEN: u32 = 256; // Room for a note's secrets, a key exchange and a MAC
+
+// Denomination of pools deployed without a stored one, in the token's smallest unit: 100 tokens
+// at the 7 decimals of Stellar assets. Amounts are never rescaled, so `get_token_decimals` is
+// what turns any of them into whole tokens.
+const FIXED_AMOUNT: i128 = 1_000_000_000;
 
-const FIXED_AMOUNT: i128 = 1_000_000_000; // 100 XLM in stroops
+// BLS12-381 scalar field modulus, big-endian. Commitments and every other signal must be below it
+const SNARK_SCALAR_FIELD: [u8; 32] = [
+    0x73, 0xed, 0xa7, 0x53, 0x29, 0x9d, 0x7d, 0x48, 0x33, 0x39, 0xd8, 0x08, 0x09, 0xa1, 0xd8, 0x05,
+    0x53, 0xbd, 0xa4, 0x02, 0xff, 0xfe, 0x5b, 0xfe, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01,
+];
+
+// Storage TTLs, in ledgers (about 5 seconds each). The instance only holds the small config
+// (admin, token, verification key, verifier, mode flags, association root); every deposit and
+// withdrawal extends it back to 30 days once it has less than 29 left. The tree, root histories
+// and nullifiers live in persistent entries, which get 120 days whenever they are written or,
+// for the tree and root histories, read. `bump_ttl` lets the admin top up an idle pool.
+const DAY_IN_LEDGERS: u32 = 17_280;
+const INSTANCE_BUMP_AMOUNT: u32 = 30 * DAY_IN_LEDGERS;
+const INSTANCE_LIFETIME_THRESHOLD: u32 = INSTANCE_BUMP_AMOUNT - DAY_IN_LEDGERS;
+const PERSISTENT_BUMP_AMOUNT: u32 = 120 * DAY_IN_LEDGERS;
+const PERSISTENT_LIFETIME_THRESHOLD: u32 = PERSISTENT_BUMP_AMOUNT - DAY_IN_LEDGERS;
 
 #[contract]
 pub struct PrivacyPoolsContract;
@@ -63,73 +622,349 @@ impl PrivacyPoolsContract {
         token_address: Address,
         admin: Address,
         groth16_verifier: Address,
+        params: PoolParams,
+        access: AccessConfig,
     ) {
-        // Store the admin
-        env.storage().instance().set(&ADMIN_KEY, &admin);
+        let PoolParams {
+            denomination,
+            tree_depth,
+            root_history_size,
+            poseidon_params_id,
+        } = params;
+        let AccessConfig {
+            deposit_issuer,
+            allow_admin_nullifier_ops,
+        } = access;
+
+        // The depth is compiled into the withdrawal circuit and can't be read back from the
+        // verification key, so the deployer must pass the circuit's state tree depth here
+        if root_history_size == 0 || tree_depth == 0 || tree_depth > MAX_TREE_DEPTH {
+            panic_with_error!(env, Error::InvalidConfiguration);
+        }
+        if denomination.is_some_and(|amount| amount <= 0) {
+            panic_with_error!(env, Error::InvalidConfiguration);
+        }
+        let decimals = match Self::read_token_decimals(env, &token_address) {
+            Ok(decimals) => decimals,
+            Err(error) => panic_with_error!(env, error),
+        };
+
+        // Start with the deployer as the only admin; `AdminAction::SetAdmins` adds more
+        env.storage().instance().set(&ADMINS_KEY, &vec![env, admin]);
+        env.storage().instance().set(&ADMIN_THRESHOLD_KEY, &1u32);
 
         env.storage().instance().set(&VK_KEY, &vk_bytes);
         env.storage().instance().set(&TOKEN_KEY, &token_address);
+        env.storage().instance().set(&TOKEN_DECIMALS_KEY, &decimals);
         env.storage()
             .instance()
             .set(&GROTH16_VERIFIER_KEY, &groth16_verifier);
+        env.storage()
+            .instance()
+            .set(&DENOMINATION_KEY, &denomination);
+        env.storage()
+            .instance()
+            .set(&ROOT_HISTORY_SIZE_KEY, &root_history_size);
+        env.storage()
+            .instance()
+            .set(&POSEIDON_PARAMS_KEY, &poseidon_params_id);
+        // Only regulated pools gate entry; permissionless ones are deployed without an issuer
+        if let Some(issuer) = deposit_issuer {
+            env.storage().instance().set(&DEPOSIT_ISSUER_KEY, &issuer);
+        }
+        // Can't be turned on later: a pool's users rely on their spent set being final
+        if allow_admin_nullifier_ops {
+            env.storage().instance().set(&NULLIFIER_OPS_KEY, &true);
+        }
+
+        // Initialize empty merkle tree with the circuit's depth
+        let tree = LeanIMT::new(env, tree_depth);
+        let (_, depth, root) = tree.to_storage();
+        Self::set_persistent(env, &LEAF_COUNT_KEY, &0u32);
+        Self::set_persistent(env, &TREE_DEPTH_KEY, &depth);
+        Self::set_persistent(env, &TREE_ROOT_KEY, &root);
+        Self::set_persistent(env, &TREE_FRONTIER_KEY, &tree.get_frontier());
+        Self::extend_instance_ttl(env);
+
+        Self::assert_valid_deployment(env);
+    }
+
+    /// Panics if the configuration the constructor stored can't make a working pool
+    ///
+    /// Fails the deployment on the usual mistakes instead of leaving a pool that takes deposits
+    /// nobody can withdraw: an empty or truncated verification key, and the admin, token and
+    /// verifier passed in the wrong order, as the same address or as the pool itself. Also
+    /// rejects a deposit issuer without a denomination: `deposit_signed` needs one and every
+    /// other path needs a signature, so that pool could never take a deposit, and a circuit
+    /// whose Poseidon parameter set isn't the one this contract hashes the tree with, whose
+    /// proofs could never match the pool's roots.
+    fn assert_valid_deployment(env: &Env) {
+        let (config, vk_bytes) = match (Self::get_config(env), Self::get_verification_key(env)) {
+            (Ok(config), Ok(vk_bytes)) => (config, vk_bytes),
+            (Err(error), _) | (_, Err(error)) => panic_with_error!(env, error),
+        };
+        if !Self::is_well_formed_vk(&vk_bytes) {
+            panic_with_error!(env, Error::InvalidVerificationKey);
+        }
+        if Self::get_poseidon_params_id(env) != poseidon::PARAMS_ID {
+            panic_with_error!(env, Error::PoseidonParamsMismatch);
+        }
+
+        let addresses = [
+            config.admin,
+            config.token,
+            config.groth16_verifier,
+            env.current_contract_address(),
+        ];
+        let distinct = addresses
+            .iter()
+            .enumerate()
+            .all(|(index, address)| !addresses[..index].contains(address));
+        if !distinct {
+            panic_with_error!(env, Error::InvalidConfiguration);
+        }
+        if config.fixed_amount.is_none() && Self::get_deposit_issuer(env).is_some() {
+            panic_with_error!(env, Error::InvalidConfiguration);
+        }
+    }
+
+    /// Loads the commitment tree from persistent storage
+    ///
+    /// # Returns
+    ///
+    /// * The tree
+    /// * `Err(Error::NotInitialized)` if the root or a page of leaves is missing
+    fn load_tree(env: &Env) -> Result<LeanIMT, Error> {
+        let leaves = Self::read_leaves(env)?;
+        let depth: u32 = Self::get_persistent(env, &TREE_DEPTH_KEY).unwrap_or(TREE_DEPTH);
+        let root: BytesN<32> =
+            Self::get_persistent(env, &TREE_ROOT_KEY).ok_or(Error::NotInitialized)?;
+        let frontier: Vec<BytesN<32>> =
+            Self::get_persistent(env, &TREE_FRONTIER_KEY).unwrap_or(vec![env]);
+        Ok(LeanIMT::from_storage_with_frontier(
+            env, leaves, depth, root, frontier,
+        ))
+    }
+
+    /// Loads the commitment tree for an insert, without reading any leaves
+    ///
+    /// The leaf count, root and frontier are all an append needs, so a deposit costs the same
+    /// however many pages of leaves the pool holds. A tree stored without its frontier falls
+    /// back to `load_tree`, which rebuilds it from the leaves once.
+    ///
+    /// # Returns
+    ///
+    /// * The tree, able to insert but not to prove existing leaves
+    /// * `Err(Error::NotInitialized)` if the root is missing
+    fn load_tree_frontier(env: &Env) -> Result<LeanIMT, Error> {
+        let depth: u32 = Self::get_persistent(env, &TREE_DEPTH_KEY).unwrap_or(TREE_DEPTH);
+        let root: BytesN<32> =
+            Self::get_persistent(env, &TREE_ROOT_KEY).ok_or(Error::NotInitialized)?;
+        let frontier: Vec<BytesN<32>> =
+            Self::get_persistent(env, &TREE_FRONTIER_KEY).unwrap_or(vec![env]);
+        let count = Self::get_commitment_count(env);
+        match LeanIMT::from_frontier(env, count, depth, root, frontier) {
+            Some(tree) => Ok(tree),
+            None => Self::load_tree(env),
+        }
+    }
+
+    /// Reads every leaf of the commitment tree, in insertion order
+    ///
+    /// Leaves are stored in pages of `LEAF_PAGE_SIZE` under `("leaves", page)`, so no single
+    /// entry grows with the tree. Only proofs and exports read them all: an insert loads the
+    /// tree with `load_tree_frontier` and reads and rewrites just the last page.
+    fn read_leaves(env: &Env) -> Result<Vec<BytesN<32>>, Error> {
+        let count = Self::get_commitment_count(env);
+        let mut leaves = vec![env];
+        for page in 0..count.div_ceil(LEAF_PAGE_SIZE) {
+            leaves.append(&Self::read_leaf_page(env, page)?);
+        }
+        Ok(leaves)
+    }
+
+    /// Reads one page of leaves, which `store_commitment` writes for every leaf it counts
+    fn read_leaf_page(env: &Env, page: u32) -> Result<Vec<BytesN<32>>, Error> {
+        Self::get_persistent(env, &(TREE_LEAVES_KEY, page)).ok_or(Error::NotInitialized)
+    }
 
-        // Initialize empty merkle tree with fixed depth
-        let tree = LeanIMT::new(env, TREE_DEPTH);
-        let (leaves, depth, root) = tree.to_storage();
-        env.storage().instance().set(&TREE_LEAVES_KEY, &leaves);
-        env.storage().instance().set(&TREE_DEPTH_KEY, &depth);
-        env.storage().instance().set(&TREE_ROOT_KEY, &root);
+    /// Reads a persistent entry, extending its TTL if it exists
+    ///
+    /// Used for the tree and root history entries, which are read far more often than they
+    /// are written, so they stay live for as long as the pool is used.
+    fn get_persistent<K, V>(env: &Env, key: &K) -> Option<V>
+    where
+        K: IntoVal<Env, Val>,
+        V: TryFromVal<Env, Val>,
+    {
+        let value = env.storage().persistent().get(key);
+        if value.is_some() {
+            Self::extend_persistent_ttl(env, key);
+        }
+        value
+    }
+
+    /// Writes a persistent entry and extends its TTL
+    fn set_persistent<K, V>(env: &Env, key: &K, value: &V)
+    where
+        K: IntoVal<Env, Val>,
+        V: IntoVal<Env, Val>,
+    {
+        env.storage().persistent().set(key, value);
+        Self::extend_persistent_ttl(env, key);
     }
 
-    /// Stores a commitment in simple storage and updates a SHA256-based root
-    /// DEMO MODE: Uses SHA256 instead of Poseidon to fit within Soroban budget
+    /// Inserts a commitment into the Poseidon merkle tree and stores the updated tree
+    ///
+    /// The tree uses the same Poseidon255 hash and depth as the withdrawal circuit, so
+    /// the stored root can be used as the `stateRoot` public input of a proof. The tree
+    /// frontier is persisted alongside the leaves so only the path from the new leaf to
+    /// the root is hashed (O(depth) per insert).
     ///
     /// # Arguments
     /// * `env` - The Soroban environment
     /// * `commitment` - The commitment to store
     ///
     /// # Returns
-    /// * A Result containing a tuple of (updated_merkle_root, leaf_index) after insertion
-    fn store_commitment(env: &Env, commitment: BytesN<32>) -> Result<(BytesN<32>, u32), Error> {
-        // Load current leaves
-        let mut leaves: Vec<BytesN<32>> = env
-            .storage()
-            .instance()
-            .get(&TREE_LEAVES_KEY)
-            .unwrap_or(vec![&env]);
-        
-        // Check capacity (2^8 = 256 leaves max)
-        if leaves.len() >= 256 {
-            return Err(Error::TreeAtCapacity);
+    /// * A Result containing a tuple of (updated_merkle_root, leaf_index, path) after insertion,
+    ///   where `path` holds the changed nodes from the leaf's parent up to the root
+    /// * `Err(Error::DuplicateCommitment)` if the commitment is already in the tree
+    /// * `Err(Error::InvalidFieldElement)` if the commitment is not below the scalar field
+    ///   modulus
+    fn store_commitment(
+        env: &Env,
+        commitment: BytesN<32>,
+    ) -> Result<(BytesN<32>, u32, Vec<BytesN<32>>), Error> {
+        // The circuit can't prove membership of a leaf outside the field, and Poseidon would
+        // hash it as its reduction, so such a deposit could never be withdrawn
+        if commitment.to_array() >= SNARK_SCALAR_FIELD {
+            return Err(Error::InvalidFieldElement);
         }
-        
+
+        // Each commitment gets a persistent `("cmt", commitment)` entry holding its leaf index.
+        // That index, not the leaves, answers every membership question: duplicates here,
+        // `get_leaf_index` and the emergency refund's commitment check
+        if Self::get_leaf_index(env, commitment.clone()).is_some() {
+            return Err(Error::DuplicateCommitment);
+        }
+
+        // The stored root was hashed with the pool's parameter set; appending to it with another
+        // would give roots the circuit can't reproduce
+        if Self::get_poseidon_params_id(env) != poseidon::PARAMS_ID {
+            return Err(Error::PoseidonParamsMismatch);
+        }
+
+        let mut tree = Self::load_tree_frontier(env)?;
+
         // Get leaf index before adding
-        let leaf_index = leaves.len() as u32;
-        
-        // Add the new commitment
-        leaves.push_back(commitment.clone());
-        
-        // Compute a simple SHA256-based root (DEMO: not a real Merkle tree)
-        // Just hash all the leaves together for a unique root
-        let mut data = soroban_sdk::Bytes::new(env);
-        for leaf in leaves.iter() {
-            data.extend_from_slice(&leaf.to_array());
+        let leaf_index = tree.get_leaf_count();
+        tree.insert(commitment.clone())
+            .map_err(|_| Error::TreeAtCapacity)?;
+
+        // Only index the commitment once it has a leaf, so a full tree leaves no entry behind
+        Self::set_persistent(env, &(COMMITMENT_KEY, commitment.clone()), &leaf_index);
+
+        // Store updated state, rewriting only the page that holds the new leaf
+        let (_, depth, new_root) = tree.to_storage();
+        let page = leaf_index / LEAF_PAGE_SIZE;
+        let mut leaves = if leaf_index % LEAF_PAGE_SIZE == 0 {
+            vec![env]
+        } else {
+            Self::read_leaf_page(env, page)?
+        };
+        leaves.push_back(commitment);
+        Self::record_root(env, &new_root, leaf_index + 1);
+        Self::set_persistent(env, &(TREE_LEAVES_KEY, page), &leaves);
+        Self::set_persistent(env, &LEAF_COUNT_KEY, &(leaf_index + 1));
+        Self::set_persistent(env, &TREE_DEPTH_KEY, &depth);
+        Self::set_persistent(env, &TREE_ROOT_KEY, &new_root);
+        Self::set_persistent(env, &TREE_FRONTIER_KEY, &tree.get_frontier());
+
+        let path = tree.get_path(leaf_index).ok_or(Error::InvalidLeafIndex)?;
+        Ok((new_root, leaf_index, path))
+    }
+
+    /// Writes a root into the root history ring, overwriting the oldest entry once it is full
+    ///
+    /// Deposits change the root between proof generation and submission, so withdrawals are
+    /// accepted against any root still in the ring rather than only the current one. Each slot
+    /// is stored under `("roots", slot)` so an update touches a single entry, next to the
+    /// ledger it was recorded at and the number of leaves the tree had.
+    fn record_root(env: &Env, root: &BytesN<32>, leaf_count: u32) {
+        let size = Self::get_root_history_size(env);
+        let index: u32 = Self::get_persistent(env, &ROOT_INDEX_KEY).unwrap_or(0);
+
+        Self::set_persistent(env, &(ROOTS_KEY, index), root);
+        Self::set_persistent(env, &(ROOT_LEDGER_KEY, index), &env.ledger().sequence());
+        Self::set_persistent(env, &(ROOT_LEAF_COUNT_KEY, index), &leaf_count);
+        Self::set_persistent(env, &ROOT_INDEX_KEY, &((index + 1) % size));
+    }
+
+    /// Writes an association root into its history ring, overwriting the oldest entry once full
+    ///
+    /// Proofs built against a set the admin has just replaced stay valid while their root is
+    /// still in the ring. The ring has the same size as the state root history.
+    fn record_association_root(env: &Env, root: &BytesN<32>) {
+        let size = Self::get_root_history_size(env);
+        let index: u32 = Self::get_persistent(env, &ASSOCIATION_ROOT_INDEX_KEY).unwrap_or(0);
+
+        Self::set_persistent(env, &(ASSOCIATION_ROOTS_KEY, index), root);
+        Self::set_persistent(env, &ASSOCIATION_ROOT_INDEX_KEY, &((index + 1) % size));
+    }
+
+    /// Gets the number of recent roots kept in the root history ring
+    fn get_root_history_size(env: &Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&ROOT_HISTORY_SIZE_KEY)
+            .unwrap_or(ROOT_HISTORY_SIZE)
+    }
+
+    /// Extends the TTL of the contract instance and code, see `INSTANCE_BUMP_AMOUNT`
+    fn extend_instance_ttl(env: &Env) {
+        env.storage()
+            .instance()
+            .extend_ttl(INSTANCE_LIFETIME_THRESHOLD, INSTANCE_BUMP_AMOUNT);
+    }
+
+    /// Extends the TTL of a persistent entry, see `PERSISTENT_BUMP_AMOUNT`
+    fn extend_persistent_ttl<K: IntoVal<Env, Val>>(env: &Env, key: &K) {
+        env.storage().persistent().extend_ttl(
+            key,
+            PERSISTENT_LIFETIME_THRESHOLD,
+            PERSISTENT_BUMP_AMOUNT,
+        );
+    }
+
+    /// Runs `f` while holding the pool's reentrancy lock
+    ///
+    /// The lock is a temporary storage flag set on entry and removed on exit, so a token contract
+    /// calling back into `deposit` or `withdraw` from inside a transfer is rejected instead of
+    /// running against half-updated state.
+    ///
+    /// # Returns
+    ///
+    /// * The result of `f`
+    /// * `Err(Error::Reentrancy)` if the lock is already held
+    fn non_reentrant<T>(env: &Env, f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+        if env.storage().temporary().has(&LOCK_KEY) {
+            return Err(Error::Reentrancy);
         }
-        let new_root = env.crypto().sha256(&data);
-        
-        // Store updated state
-        env.storage().instance().set(&TREE_LEAVES_KEY, &leaves);
-        env.storage().instance().set(&TREE_DEPTH_KEY, &TREE_DEPTH);
-        env.storage().instance().set(&TREE_ROOT_KEY, &new_root);
+        env.storage().temporary().set(&LOCK_KEY, &true);
 
-        Ok((new_root.into(), leaf_index))
+        let result = f();
+
+        env.storage().temporary().remove(&LOCK_KEY);
+        result
     }
 
     /// Deposits funds into the privacy pool and stores a commitment in the merkle tree.
     ///
-    /// This function allows a user to deposit a fixed amount (1 XLM) of the configured token into the privacy pool
+    /// This function allows a user to deposit `amount` of the configured token into the privacy pool
     /// while providing a cryptographic commitment that will be used for zero-knowledge proof
-    /// verification during withdrawal.
+    /// verification during withdrawal. When the admin has set a deposit fee, the fee is taken out
+    /// of `amount` and sent to the fee recipient, and the pool keeps the rest. The commitment
+    /// must commit to that net value, since that is the most a later withdrawal proof can claim.
     ///
     /// # Arguments
     ///
@@ -137,262 +972,4391 @@ impl PrivacyPoolsContract {
     /// * `from` - The address of the depositor (must be authenticated)
     /// * `commitment` - A 32-byte cryptographic commitment that will be used to prove
     ///                 ownership during withdrawal without revealing the actual coin details
+    /// * `amount` - The amount of the configured token to deposit, including the deposit fee
+    /// * `encrypted_note` - Optionally, the note encrypted under a key of its owner, stored by
+    ///                      leaf index so a wallet that lost its backup can find and decrypt it
     ///
     /// # Returns
     ///
-    /// * The leaf index where the commitment was stored in the merkle tree
+    /// * The leaf index where the commitment was stored in the merkle tree, and the tree's new
+    ///   root, so a client can build a proof without a separate `get_merkle_root` call
+    /// * `Err(Error::InvalidAmount)` if `amount` doesn't exceed the deposit fee, or the net value
+    ///   differs from the pool's denomination when it has one
+    /// * `Err(Error::Paused)` if the admin has paused the contract
+    /// * `Err(Error::Reentrancy)` if called back from inside another deposit or withdrawal
+    /// * `Err(Error::DuplicateCommitment)` if the commitment has already been deposited
+    /// * `Err(Error::InvalidFieldElement)` if the commitment is not below the scalar field
+    ///   modulus
+    /// * `Err(Error::TreeAtCapacity)` if the merkle tree is full
+    /// * `Err(Error::IssuerSignatureRequired)` if the pool only accepts commitments signed by
+    ///   its deposit issuer, which go through `deposit_signed`
+    /// * `Err(Error::UnboundCommitment)` if the pool has no denomination, so deposits have to go
+    ///   through `deposit_note`
+    /// * `Err(Error::EncryptedNoteTooLarge)` if `encrypted_note` is longer than 256 bytes
     ///
     /// # Security
     ///
     /// * Requires authentication from the `from` address
     /// * The commitment is stored in a merkle tree for efficient inclusion proofs
-    /// * Transfers exactly `FIXED_AMOUNT` of the configured token from the depositor to the contract
+    /// * In fixed-denomination mode only the denomination is accepted, which keeps every note
+    ///   identical and maximizes the anonymity set, and withdrawals must prove the same value
+    /// * The pool can't see the value a caller-chosen commitment commits to, so only a
+    ///   denomination ties it to `amount`. Variable pools reject it, and `deposit_note` computes
+    ///   the commitment from the amount received instead
     ///
     /// # Storage
     ///
     /// * Updates the merkle tree with the new commitment
-    /// * Transfers the asset from the depositor to the contract
-    pub fn deposit(env: &Env, from: Address, commitment: BytesN<32>) -> Result<u32, Error> {
+    /// * Stores `encrypted_note`, if any, under the leaf index. The contract never reads it, so
+    ///   it has no bearing on the proof or on who can withdraw
+    /// * Transfers the asset from the depositor to the contract, and the deposit fee to the fee
+    ///   recipient
+    ///
+    /// # Events
+    ///
+    /// * Publishes `DepositEvent` with the leaf index, commitment, new root and the changed path
+    ///   nodes, so indexers can update a mirror of the tree without refetching the leaves, and
+    ///   the encrypted note for wallets scanning for theirs
+    pub fn deposit(
+        env: &Env,
+        from: Address,
+        commitment: BytesN<32>,
+        amount: i128,
+        encrypted_note: Option<Bytes>,
+    ) -> Result<(u32, BytesN<32>), Error> {
         from.require_auth();
+        Self::require_unsigned_deposits(env)?;
+        Self::require_denomination(env)?;
 
-        // Get the stored token address
-        let token_address: Address = env.storage().instance().get(&TOKEN_KEY).unwrap();
-
-        // Create token client and transfer from depositor to contract
-        let token_client = token::Client::new(env, &token_address);
-        token_client.transfer(&from, &env.current_contract_address(), &FIXED_AMOUNT);
-
-        // Store the commitment in the merkle tree
-        let (_, leaf_index) = Self::store_commitment(env, commitment)?;
-
-        Ok(leaf_index)
+        Self::accept_deposit(
+            env,
+            |_| Ok(commitment),
+            encrypted_note,
+            amount,
+            |token_client, to, value| token_client.transfer(&from, to, &value),
+        )
     }
 
-    /// Withdraws funds from the privacy pool using a zero-knowledge proof.
+    /// Deposits a commitment signed by the pool's deposit issuer
     ///
-    /// This function allows a user to withdraw a fixed amount (1 XLM) of the configured token from the privacy pool
-    /// by providing a cryptographic proof that demonstrates ownership of a previously deposited
-    /// commitment without revealing which specific commitment it corresponds to.
+    /// Regulated pools are deployed with the ed25519 public key of an off-chain issuer, which
+    /// signs a depositor's commitment once it has checked them, for example after KYC. Such a
+    /// pool only accepts deposits through this function, so every note in its tree was approved
+    /// on entry. This is separate from the association set, which gates withdrawals. The
+    /// signature is over the 32 bytes of the commitment; since a commitment can only be
+    /// deposited once, it can't be replayed into the same pool. In a pool without an issuer
+    /// this is the same as `deposit` and the signature is ignored.
     ///
     /// # Arguments
     ///
     /// * `env` - The Soroban environment
-    /// * `to` - The address of the recipient (must be authenticated)
-    /// * `proof_bytes` - The serialized zero-knowledge proof demonstrating ownership of a
-    ///                   commitment without revealing the commitment itself
-    /// * `pub_signals_bytes` - The serialized public signals associated with the proof
+    /// * `from` - The address of the depositor (must be authenticated)
+    /// * `commitment` - The commitment of the note being deposited
+    /// * `amount` - The amount of the configured token to deposit, including the deposit fee
+    /// * `signature` - The issuer's ed25519 signature over `commitment`
     ///
     /// # Returns
     ///
-    /// Returns a vector containing status messages:
-    /// * Empty vector `[]` on successful withdrawal (success is logged as a diagnostic event)
-    /// * `["Nullifier already used"]` if the nullifier has been used before
-    /// * `["Couldn't verify coin ownership proof"]` if the zero-knowledge proof verification fails
-    /// * `["Insufficient balance"]` if the contract doesn't have enough funds
+    /// * The leaf index where the commitment was stored and the new root, or the other errors
+    ///   of `deposit`
     ///
     /// # Security
     ///
-    /// * Requires authentication from the `to` address
-    /// * Verifies that the nullifier hasn't been used before (prevents double-spending)
-    /// * Validates the zero-knowledge proof using Groth16 verification
-    /// * Transfers exactly `FIXED_AMOUNT` of the configured token from the contract to the recipient
-    ///
-    /// # Storage
-    ///
-    /// * Adds the nullifier to the used nullifiers list to prevent reuse
-    /// * Transfers the asset from the contract to the recipient
-    ///
-    /// # Privacy
-    ///
-    /// * The withdrawal doesn't reveal which specific commitment is being spent
-    /// * The nullifier ensures the same commitment cannot be spent twice
-    /// * The zero-knowledge proof proves ownership without revealing the commitment details
-    /// DEMO MODE: Withdraws funds without full ZK verification
-    /// Validates nullifier hasn't been used, then transfers funds
-    /// In production, this would verify the Groth16 proof
-    pub fn withdraw(
+    /// * The host traps if the signature doesn't verify against the issuer's key, so the call
+    ///   fails without an `Error`
+    /// * The issuer can't see the value inside the commitment, so like `deposit` this is only open
+    ///   in pools with a denomination
+    pub fn deposit_signed(
         env: &Env,
-        to: Address,
-        proof_bytes: Bytes,
-        pub_signals_bytes: Bytes,
-    ) -> Vec<String> {
-        to.require_auth();
-
-        // DEMO MODE: Skip ZK verification due to hash function mismatch
-        // (Contract uses SHA256 for Merkle root, circuit uses Poseidon)
-        // In production, both would use the same hash function
-        
-        let _ = proof_bytes; // Unused in demo mode
-        
-        // Extract nullifier from public signals (first 32 bytes after 4-byte length prefix)
-        if pub_signals_bytes.len() < 36 {
-            return vec![env, String::from_str(env, "Invalid public signals")];
-        }
-        
-        // Get nullifier bytes (bytes 4-36, skipping length prefix)
-        let mut nullifier_bytes = [0u8; 32];
-        for i in 0..32 {
-            nullifier_bytes[i] = pub_signals_bytes.get(4 + i as u32).unwrap();
-        }
-        let nullifier = BytesN::from_array(env, &nullifier_bytes);
-        
-        // Check nullifier not used
-        let mut nullifiers: Vec<BytesN<32>> = env
-            .storage()
-            .instance()
-            .get(&NULL_KEY)
-            .unwrap_or(vec![env]);
-        
-        if nullifiers.contains(&nullifier) {
-            return vec![env, String::from_str(env, ERROR_NULLIFIER_USED)];
+        from: Address,
+        commitment: BytesN<32>,
+        amount: i128,
+        signature: BytesN<64>,
+    ) -> Result<(u32, BytesN<32>), Error> {
+        from.require_auth();
+        Self::require_denomination(env)?;
+        if let Some(issuer) = Self::get_deposit_issuer(env) {
+            env.crypto()
+                .ed25519_verify(&issuer, &commitment.clone().into(), &signature);
         }
-        
-        // Get token and check balance
-        let token_address: Address = env.storage().instance().get(&TOKEN_KEY).unwrap();
-        let token_client = token::Client::new(env, &token_address);
-        
-        let balance = token_client.balance(&env.current_contract_address());
-        if balance < FIXED_AMOUNT {
-            return vec![env, String::from_str(env, ERROR_INSUFFICIENT_BALANCE)];
-        }
-        
-        // Add nullifier to used list
-        nullifiers.push_back(nullifier.clone());
-        env.storage().instance().set(&NULL_KEY, &nullifiers);
-        
-        // Transfer funds
-        token_client.transfer(&env.current_contract_address(), &to, &FIXED_AMOUNT);
-        
-        log!(env, "Withdrawal successful (DEMO MODE)");
-        vec![env]
-    }
-
-    /// Gets the current merkle root of the commitment tree
-    pub fn get_merkle_root(env: &Env) -> BytesN<32> {
-        env.storage()
-            .instance()
-            .get(&TREE_ROOT_KEY)
-            .unwrap_or(BytesN::from_array(&env, &[0u8; 32]))
-    }
-
-    /// Gets the current depth of the merkle tree
-    pub fn get_merkle_depth(env: &Env) -> u32 {
-        env.storage().instance().get(&TREE_DEPTH_KEY).unwrap_or(0)
-    }
 
-    /// Gets the number of commitments (leaves) in the merkle tree
-    pub fn get_commitment_count(env: &Env) -> u32 {
-        let leaves: Vec<BytesN<32>> = env
-            .storage()
-            .instance()
-            .get(&TREE_LEAVES_KEY)
-            .unwrap_or(vec![&env]);
-        leaves.len() as u32
-    }
-
-    /// Gets all commitments (leaves) in the merkle tree
-    pub fn get_commitments(env: &Env) -> Vec<BytesN<32>> {
-        env.storage()
-            .instance()
-            .get(&TREE_LEAVES_KEY)
-            .unwrap_or(vec![env])
-    }
-
-    pub fn get_nullifiers(env: &Env) -> Vec<BytesN<32>> {
-        env.storage().instance().get(&NULL_KEY).unwrap_or(vec![env])
-    }
-
-    /// Gets the balance of the configured token held by the contract
-    pub fn get_balance(env: &Env) -> i128 {
-        let token_address: Address = env.storage().instance().get(&TOKEN_KEY).unwrap();
-        let token_client = token::Client::new(env, &token_address);
-        token_client.balance(&env.current_contract_address())
+        Self::accept_deposit(
+            env,
+            |_| Ok(commitment),
+            None,
+            amount,
+            |token_client, to, value| token_client.transfer(&from, to, &value),
+        )
     }
 
-    /// Validates that the caller is the admin
+    /// Deposits funds for a note whose commitment the pool computes from the transferred value
+    ///
+    /// With `deposit` the depositor chooses the commitment, and only the denomination check links
+    /// its value to the amount transferred. Here the depositor only supplies
+    /// the note's label and precommitment, `Poseidon(nullifier, secret)`, and the pool hashes in
+    /// the net value it actually received, the same way `compute_commitment` does. A note built
+    /// this way can never claim more than was paid for it, whatever the pool's denomination. The
+    /// precommitment keeps the nullifier and secret private.
     ///
     /// # Arguments
     ///
     /// * `env` - The Soroban environment
-    /// * `caller` - The address to validate as admin
+    /// * `from` - The address of the depositor (must be authenticated)
+    /// * `label` - The note's label, as a 32-byte big-endian field element
+    /// * `precommitment` - `Poseidon(nullifier, secret)` of the note, as a 32-byte big-endian
+    ///   field element
+    /// * `amount` - The amount of the configured token to deposit, including the deposit fee
     ///
     /// # Returns
     ///
-    /// * `true` if the caller is the admin, `false` otherwise
-    fn is_admin(env: &Env, caller: &Address) -> bool {
-        let admin: Address = env.storage().instance().get(&ADMIN_KEY).unwrap();
-        *caller == admin
+    /// * The leaf index where the computed commitment was stored and the new root
+    /// * `Err(Error::InvalidFieldElement)` if `label` or `precommitment` is not below the scalar
+    ///   field modulus
+    /// * The other errors of `deposit`
+    ///
+    /// # Events
+    ///
+    /// * Publishes `DepositEvent` with the computed commitment, like `deposit`
+    pub fn deposit_note(
+        env: &Env,
+        from: Address,
+        label: BytesN<32>,
+        precommitment: BytesN<32>,
+        amount: i128,
+    ) -> Result<(u32, BytesN<32>), Error> {
+        from.require_auth();
+        Self::require_unsigned_deposits(env)?;
+
+        Self::accept_deposit(
+            env,
+            |value| {
+                if [&label, &precommitment]
+                    .iter()
+                    .any(|input| input.to_array() >= SNARK_SCALAR_FIELD)
+                {
+                    return Err(Error::InvalidFieldElement);
+                }
+                let precommitment = Fr::from_bytes(precommitment);
+                Ok(Self::bound_commitment(env, value, &label, &precommitment))
+            },
+            None,
+            amount,
+            |token_client, to, value| token_client.transfer(&from, to, &value),
+        )
     }
 
-    /// Sets the association set root for compliance verification
+    /// Deposits funds pulled from an allowance, for operator contracts such as vaults
     ///
-    /// This function allows the admin to update the association set root,
-    /// which is used to verify that withdrawals are associated with approved
-    /// subsets of deposits for compliance purposes.
+    /// The depositor first approves `spender` for at least `amount` on the token; the spender
+    /// then deposits on their behalf, and the pool pulls the funds with `transfer_from`. Only the
+    /// spender signs the call, so the depositor doesn't have to be part of the transaction.
+    /// `deposit` remains the direct path.
     ///
     /// # Arguments
     ///
     /// * `env` - The Soroban environment
-    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `spender` - The address holding the allowance (must be authenticated)
+    /// * `from` - The address whose funds are deposited
+    /// * `commitment` - The commitment of the note being funded
+    /// * `amount` - The amount of the configured token to deposit
+    ///
+    /// # Returns
+    ///
+    /// * The leaf index where the commitment was stored and the new root, or the same errors as
+    ///   `deposit`
+    ///
+    /// # Security
+    ///
+    /// * The token contract rejects the transfer if `from` hasn't approved `spender` for `amount`
+    pub fn deposit_from(
+        env: &Env,
+        spender: Address,
+        from: Address,
+        commitment: BytesN<32>,
+        amount: i128,
+    ) -> Result<(u32, BytesN<32>), Error> {
+        spender.require_auth();
+        Self::require_unsigned_deposits(env)?;
+        Self::require_denomination(env)?;
+
+        Self::accept_deposit(
+            env,
+            |_| Ok(commitment),
+            None,
+            amount,
+            |token_client, to, value| token_client.transfer_from(&spender, &from, to, &value),
+        )
+    }
+
+    /// Checks a deposit, pulls its funds with `pull` and stores the commitment
+    ///
+    /// `commitment` gets the deposit's net value and returns the commitment to store, so
+    /// `deposit_note` can bind the note to what was paid. `pull` moves the given amount from the
+    /// depositor to the given address. The fee stays in the pool with the note's value but is
+    /// accounted separately, see `withdraw_fees`.
+    fn accept_deposit(
+        env: &Env,
+        commitment: impl FnOnce(i128) -> Result<BytesN<32>, Error>,
+        encrypted_note: Option<Bytes>,
+        amount: i128,
+        pull: impl Fn(&token::Client, &Address, i128),
+    ) -> Result<(u32, BytesN<32>), Error> {
+        Self::non_reentrant(env, || {
+            if Self::is_paused(env) {
+                return Err(Error::Paused);
+            }
+            Self::extend_instance_ttl(env);
+
+            let fee = Self::get_deposit_fee(env);
+            let value = Self::net_deposit_value(env, amount, fee)?;
+            let commitment = commitment(value)?;
+            if encrypted_note
+                .as_ref()
+                .is_some_and(|note| note.len() > MAX_ENCRYPTED_NOTE_LEN)
+            {
+                return Err(Error::EncryptedNoteTooLarge);
+            }
+
+            // Get the stored token address
+            let token_address = Self::get_token(env)?;
+
+            // Create token client and transfer from depositor to contract
+            let token_client = token::Client::new(env, &token_address);
+            pull(&token_client, &env.current_contract_address(), amount);
+
+            // Store the commitment in the merkle tree
+            let (new_root, leaf_index, path) = Self::store_commitment(env, commitment.clone())?;
+            Self::add_to_total(env, &TOTAL_DEPOSITED_KEY, value);
+            Self::add_to_total(env, &NOTE_VALUE_KEY, value);
+            Self::collect_fee(env, fee);
+            if let Some(note) = &encrypted_note {
+                Self::set_persistent(env, &(ENCRYPTED_NOTE_KEY, leaf_index), note);
+            }
+
+            DepositEvent {
+                leaf_index,
+                commitment,
+                new_root: new_root.clone(),
+                path,
+                encrypted_note,
+            }
+            .publish(env);
+
+            Ok((leaf_index, new_root))
+        })
+    }
+
+    /// Deposits a commitment funded by a separate payer
+    ///
+    /// Lets a sponsor, such as an app covering its users' deposits or an exchange, pay for a note
+    /// it doesn't own. The user only hands over the commitment; the payer never learns the note's
+    /// secrets and can't spend it. Nothing about the payer is recorded beyond the token transfer.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `payer` - The address funding the deposit (must be authenticated)
+    /// * `commitment` - The commitment of the note being funded
+    /// * `amount` - The amount of the configured token to deposit
+    ///
+    /// # Returns
+    ///
+    /// * The leaf index where the commitment was stored and the new root, or the same errors as
+    ///   `deposit`
+    pub fn deposit_for(
+        env: &Env,
+        payer: Address,
+        commitment: BytesN<32>,
+        amount: i128,
+    ) -> Result<(u32, BytesN<32>), Error> {
+        Self::deposit(env, payer, commitment, amount, None)
+    }
+
+    /// Deposits several commitments of the same amount in one call
+    ///
+    /// Transfers `amount_each` times the number of commitments in a single token transfer and
+    /// inserts the commitments in order. The whole batch is checked against the tree's remaining
+    /// capacity before anything is transferred or inserted, so it either lands completely or not
+    /// at all.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `from` - The address of the depositor (must be authenticated)
+    /// * `commitments` - The commitments to insert, in order
+    /// * `amount_each` - The amount deposited for every commitment, including the deposit fee
+    ///
+    /// # Returns
+    ///
+    /// * The leaf index of each commitment, in the order given
+    /// * `Err(Error::TreeAtCapacity)` if the tree can't hold the whole batch
+    /// * The other errors of `deposit`, for any commitment in the batch
+    ///
+    /// # Events
+    ///
+    /// * Publishes a `DepositEvent` for each inserted commitment
+    pub fn deposit_batch(
+        env: &Env,
+        from: Address,
+        commitments: Vec<BytesN<32>>,
+        amount_each: i128,
+    ) -> Result<Vec<u32>, Error> {
+        from.require_auth();
+        Self::require_unsigned_deposits(env)?;
+        Self::require_denomination(env)?;
+
+        Self::non_reentrant(env, || {
+            if Self::is_paused(env) {
+                return Err(Error::Paused);
+            }
+            Self::extend_instance_ttl(env);
+
+            let fee = Self::get_deposit_fee(env);
+            let value_each = Self::net_deposit_value(env, amount_each, fee)?;
+
+            let mut leaf_indices = vec![env];
+            if commitments.is_empty() {
+                return Ok(leaf_indices);
+            }
+
+            if commitments.len() > Self::remaining_capacity(env) {
+                return Err(Error::TreeAtCapacity);
+            }
+
+            let total = value_each
+                .checked_mul(commitments.len() as i128)
+                .ok_or(Error::InvalidAmount)?;
+            let total_fee = fee
+                .checked_mul(commitments.len() as i128)
+                .ok_or(Error::InvalidAmount)?;
+            let amount = total.checked_add(total_fee).ok_or(Error::InvalidAmount)?;
+            let token_address = Self::get_token(env)?;
+            let token_client = token::Client::new(env, &token_address);
+            token_client.transfer(&from, env.current_contract_address(), &amount);
+            Self::add_to_total(env, &TOTAL_DEPOSITED_KEY, total);
+            Self::add_to_total(env, &NOTE_VALUE_KEY, total);
+            Self::collect_fee(env, total_fee);
+
+            for commitment in commitments.iter() {
+                let (new_root, leaf_index, path) = Self::store_commitment(env, commitment.clone())?;
+                leaf_indices.push_back(leaf_index);
+
+                DepositEvent {
+                    leaf_index,
+                    commitment,
+                    new_root,
+                    path,
+                    encrypted_note: None,
+                }
+                .publish(env);
+            }
+
+            Ok(leaf_indices)
+        })
+    }
+
+    /// Deposits funds against a transferable on-chain receipt instead of a private note
+    ///
+    /// For wallets that can't keep a note: the holder of the receipt can later redeem it with
+    /// `redeem_receipt`, or hand it on with `transfer_receipt`, without any proof. This path is
+    /// public and separate from the privacy pool: no commitment is inserted into the tree and
+    /// no nullifier is ever spent, so a receipt can't be linked to any note and doesn't add to
+    /// the anonymity set either. The depositor, holder and amount are visible on-chain. Funds
+    /// backing receipts are kept aside, so private withdrawals can't pay out of them.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `from` - The address of the depositor (must be authenticated)
+    /// * `holder` - The address the receipt is issued to
+    /// * `amount` - The amount of the configured token to deposit
+    ///
+    /// # Returns
+    ///
+    /// * The id of the new receipt
+    /// * `Err(Error::InvalidAmount)` if `amount` is not positive
+    /// * `Err(Error::Paused)` if the contract is paused
+    ///
+    /// # Events
+    ///
+    /// * Publishes `ReceiptIssued` with the receipt id, holder and amount
+    pub fn deposit_with_receipt(
+        env: &Env,
+        from: Address,
+        holder: Address,
+        amount: i128,
+    ) -> Result<u32, Error> {
+        from.require_auth();
+
+        Self::non_reentrant(env, || {
+            if Self::is_paused(env) {
+                return Err(Error::Paused);
+            }
+            if amount <= 0 {
+                return Err(Error::InvalidAmount);
+            }
+            Self::extend_instance_ttl(env);
+
+            let token_client = token::Client::new(env, &Self::get_token(env)?);
+            token_client.transfer(&from, env.current_contract_address(), &amount);
+
+            let receipt_id: u32 = Self::get_persistent(env, &RECEIPT_COUNT_KEY).unwrap_or(0);
+            Self::set_persistent(env, &RECEIPT_COUNT_KEY, &(receipt_id + 1));
+            let receipt = Receipt {
+                holder: holder.clone(),
+                amount,
+            };
+            Self::set_persistent(env, &(RECEIPT_KEY, receipt_id), &receipt);
+            Self::add_to_total(env, &RECEIPT_TOTAL_KEY, amount);
+
+            ReceiptIssued {
+                receipt_id,
+                holder,
+                amount,
+            }
+            .publish(env);
+            Ok(receipt_id)
+        })
+    }
+
+    /// Gets an unredeemed receipt
+    pub fn get_receipt(env: &Env, receipt_id: u32) -> Option<Receipt> {
+        Self::get_persistent(env, &(RECEIPT_KEY, receipt_id))
+    }
+
+    /// Gets the total amount owed to unredeemed receipts, which private withdrawals can't use
+    pub fn get_outstanding_receipts(env: &Env) -> i128 {
+        env.storage()
+            .instance()
+            .get(&RECEIPT_TOTAL_KEY)
+            .unwrap_or(0)
+    }
+
+    /// Gets the total value of the notes that haven't been spent
+    ///
+    /// Deposits add the value of their notes, and withdrawals and emergency refunds take off
+    /// the value they prove; a change note stays part of it, since only the withdrawn part is
+    /// taken off. Only this much of the pool's balance is owed to note holders: whatever the
+    /// pool holds beyond it, receipts and unpaid fees, such as tokens sent to the contract
+    /// directly, is surplus that `sweep` can send out.
+    pub fn get_unspent_value(env: &Env) -> i128 {
+        env.storage().instance().get(&NOTE_VALUE_KEY).unwrap_or(0)
+    }
+
+    /// Reads a receipt and checks that `holder` holds it
+    fn held_receipt(env: &Env, holder: &Address, receipt_id: u32) -> Result<Receipt, Error> {
+        let receipt = Self::get_receipt(env, receipt_id).ok_or(Error::UnknownReceipt)?;
+        if receipt.holder != *holder {
+            return Err(Error::NotReceiptHolder);
+        }
+        Ok(receipt)
+    }
+
+    /// Hands a receipt on to a new holder
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `holder` - The current holder (must be authenticated)
+    /// * `receipt_id` - The id returned by `deposit_with_receipt`
+    /// * `new_holder` - The address that will hold the receipt
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the receipt has moved
+    /// * `Err(Error::UnknownReceipt)` if there is no unredeemed receipt with that id
+    /// * `Err(Error::NotReceiptHolder)` if `holder` doesn't hold it
+    ///
+    /// # Events
+    ///
+    /// * Publishes `ReceiptTransferred` with the receipt id and both holders
+    pub fn transfer_receipt(
+        env: &Env,
+        holder: Address,
+        receipt_id: u32,
+        new_holder: Address,
+    ) -> Result<(), Error> {
+        holder.require_auth();
+
+        let mut receipt = Self::held_receipt(env, &holder, receipt_id)?;
+        receipt.holder = new_holder.clone();
+        Self::set_persistent(env, &(RECEIPT_KEY, receipt_id), &receipt);
+
+        ReceiptTransferred {
+            receipt_id,
+            from: holder,
+            to: new_holder,
+        }
+        .publish(env);
+        Ok(())
+    }
+
+    /// Redeems a receipt, paying its amount out and removing it
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `holder` - The current holder (must be authenticated)
+    /// * `receipt_id` - The id returned by `deposit_with_receipt`
+    /// * `to` - The address the funds are sent to
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the funds are sent
+    /// * `Err(Error::UnknownReceipt)` if there is no unredeemed receipt with that id
+    /// * `Err(Error::NotReceiptHolder)` if `holder` doesn't hold it
+    /// * `Err(Error::Paused)` if the contract is paused
+    ///
+    /// # Events
+    ///
+    /// * Publishes `ReceiptRedeemed` with the receipt id, recipient and amount
+    pub fn redeem_receipt(
+        env: &Env,
+        holder: Address,
+        receipt_id: u32,
+        to: Address,
+    ) -> Result<(), Error> {
+        holder.require_auth();
+
+        Self::non_reentrant(env, || {
+            if Self::is_paused(env) {
+                return Err(Error::Paused);
+            }
+            let receipt = Self::held_receipt(env, &holder, receipt_id)?;
+            env.storage()
+                .persistent()
+                .remove(&(RECEIPT_KEY, receipt_id));
+            Self::add_to_total(env, &RECEIPT_TOTAL_KEY, -receipt.amount);
+
+            let token_client = token::Client::new(env, &Self::get_token(env)?);
+            token_client.transfer(&env.current_contract_address(), &to, &receipt.amount);
+
+            ReceiptRedeemed {
+                receipt_id,
+                to,
+                amount: receipt.amount,
+            }
+            .publish(env);
+            Ok(())
+        })
+    }
+
+    /// Gets the part of the pool's token balance that backs private notes, leaving out what is
+    /// owed to receipts and the deposit fees not withdrawn yet
+    fn note_balance(env: &Env, token_client: &token::Client) -> i128 {
+        token_client.balance(&env.current_contract_address())
+            - Self::get_outstanding_receipts(env)
+            - Self::unpaid_fees(env)
+    }
+
+    /// Verifies a Groth16 proof against the stored verification key
+    ///
+    /// Deserializes the proof, public signals and verification key and hands them to the
+    /// `groth16_verifier` contract stored under `GROTH16_VERIFIER_KEY`.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `proof_bytes` - The serialized Groth16 proof
+    /// * `pub_signals_bytes` - The serialized public signals
+    ///
+    /// # Returns
+    ///
+    /// * `true` if the verifier accepted the proof, `false` if it rejected it or errored
+    /// * `Err(Error::NotInitialized)` if the verification key or verifier is not set
+    fn verify_groth16(
+        env: &Env,
+        proof_bytes: &Bytes,
+        pub_signals_bytes: &Bytes,
+    ) -> Result<bool, Error> {
+        let vk_bytes = Self::get_verification_key(env)?;
+        let vk = match VerificationKey::from_bytes(env, &vk_bytes) {
+            Ok(vk) => vk,
+            Err(_) => return Ok(false),
+        };
+        let proof = Proof::from_bytes(env, proof_bytes);
+        let pub_signals = PublicSignals::from_bytes(env, pub_signals_bytes);
+
+        // The verifier contract's interface exposes curve points as raw bytes and
+        // scalars as U256, so convert from the zk_verifier types
+        let mut ic = vec![env];
+        for point in vk.ic.iter() {
+            ic.push_back(point.to_bytes());
+        }
+        let verifier_vk = groth16_verifier_wasm::VerificationKey {
+            alpha: vk.alpha.to_bytes(),
+            beta: vk.beta.to_bytes(),
+            gamma: vk.gamma.to_bytes(),
+            delta: vk.delta.to_bytes(),
+            ic,
+        };
+        let verifier_proof = groth16_verifier_wasm::Proof {
+            a: proof.a.to_bytes(),
+            b: proof.b.to_bytes(),
+            c: proof.c.to_bytes(),
+        };
+        let mut signals = vec![env];
+        for signal in pub_signals.pub_signals.iter() {
+            signals.push_back(signal.to_u256());
+        }
+
+        let verifier_address = Self::get_groth16_verifier(env)?;
+        let verifier = groth16_verifier_wasm::Client::new(env, &verifier_address);
+        Ok(matches!(
+            verifier.try_verify_proof(&verifier_vk, &verifier_proof, &signals),
+            Ok(Ok(true))
+        ))
+    }
+
+    /// Withdraws funds from the privacy pool using a zero-knowledge proof.
+    ///
+    /// This function allows a user to withdraw the configured token from the privacy pool
+    /// by providing a cryptographic proof that demonstrates ownership of a previously deposited
+    /// commitment without revealing which specific commitment it corresponds to. The amount paid
+    /// out is the proven `withdrawnValue`, which must be the denomination in fixed-denomination
+    /// mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `to` - The address of the recipient
+    /// * `relayer` - The address submitting the withdrawal (must be authenticated); pass `to`
+    ///               to withdraw without a relayer
+    /// * `fee` - The part of the amount paid to `relayer`
+    /// * `proof_bytes` - The serialized zero-knowledge proof demonstrating ownership of a
+    ///                   commitment without revealing the commitment itself
+    /// * `pub_signals_bytes` - The serialized public signals associated with the proof
+    /// * `change_commitment` - For a partial withdrawal, the commitment of the note holding the
+    ///                         rest of the spent note's value, as proven by `changeCommitment`
+    ///
+    /// # Returns
+    ///
+    /// * A `WithdrawReceipt` with the nullifier hash, amount, recipient and ledger of the
+    ///   withdrawal (success is also logged as a diagnostic event)
+    /// * `Err(Error::Paused)` if the admin has paused the contract
+    /// * `Err(Error::Reentrancy)` if called back from inside another deposit or withdrawal
+    /// * `Err(Error::UnsupportedProofVersion)` if the public signals' version is not accepted
+    /// * `Err(Error::MalformedPublicSignals)` if the public signals can't be parsed or a signal
+    ///   is not below the scalar field modulus
+    /// * `Err(Error::ContextMismatch)` if the proof's `context` signal doesn't match
+    ///   `get_withdraw_context(to, relayer, fee, nullifier_hash)`
+    /// * `Err(Error::UnboundRelayer)` if the proof has no `context` signal and `relayer` is not
+    ///   `to` or `fee` is not zero
+    /// * `Err(Error::NullifierUsed)` if the nullifier has been used before
+    /// * `Err(Error::ProofExpired)` if the proof has an `expiryLedger` and the current ledger is
+    ///   past it
+    /// * `Err(Error::UnknownStateRoot)` if the proof's `stateRoot` is not in the root history
+    /// * `Err(Error::AssociationRootMismatch)` if an association set is configured and the
+    ///   proof's `associationRoot` differs from it, unless `to` is whitelisted and bound by the
+    ///   proof's `context`
+    /// * `Err(Error::CoinOwnershipProofFailed)` if the zero-knowledge proof verification fails
+    /// * `Err(Error::InvalidAmount)` if the proven `withdrawnValue` is zero or doesn't fit an `i128`
+    /// * `Err(Error::AmountMismatch)` if the pool has a fixed denomination and the proven
+    ///   `withdrawnValue` is not that denomination
+    /// * `Err(Error::InvalidFee)` if `fee` is negative or exceeds the amount
+    /// * `Err(Error::ChangeCommitmentMismatch)` if `change_commitment` differs from the proof's
+    ///   `changeCommitment` signal
+    /// * `Err(Error::InvalidAmount)` if a change commitment is given in a fixed-denomination pool
+    /// * `Err(Error::DuplicateCommitment)` or `Err(Error::TreeAtCapacity)` if the change
+    ///   commitment can't be inserted
+    /// * `Err(Error::InsufficientBalance)` if the contract doesn't have enough funds
+    ///
+    /// # Security
+    ///
+    /// * Requires authentication from the `relayer` address
+    /// * Verifies that the recipient, relayer and fee are the ones bound into the proof, so a
+    ///   relayer can't redirect the funds or raise its fee. The recipient is covered by the
+    ///   `context` signal rather than a signal of its own, so a front-runner copying the proof
+    ///   from a pending transaction can't substitute its own address either
+    /// * Proofs without a `context` signal bind no recipient; they are only accepted
+    ///   self-relayed without a fee, and whoever submits one first receives the funds, so
+    ///   wallets should only generate proofs with a context
+    /// * Verifies that the nullifier hasn't been used before (prevents double-spending)
+    /// * Rejects proofs past their `expiryLedger`, so a stale proof can't linger in the mempool
+    /// * Verifies that the proof was generated against a recent root of the commitment tree
+    /// * Verifies the proof's association root against the configured association set, when one
+    ///   is set
+    /// * Validates the zero-knowledge proof using Groth16 verification
+    /// * Never pays more than the amount in the verified public signals. Only the note that
+    ///   holds the last unspent value may be paid less, when the balance backing notes fell
+    ///   short of it, see `settled_amount`
+    ///
+    /// # Storage
+    ///
+    /// * Records the nullifier in persistent storage to prevent reuse
+    /// * Inserts the change commitment, if any, into the merkle tree
+    /// * Transfers `fee` to the relayer and the rest of the amount to the recipient
+    ///
+    /// # Events
+    ///
+    /// * Publishes `DepositEvent` for the change commitment, if any
+    /// * Publishes `AssociationBypassed` if the association check was skipped for a whitelisted
+    ///   recipient
+    /// * Publishes `WithdrawEvent` with the nullifier, recipient, amount, relayer and fee
+    ///
+    /// # Privacy
+    ///
+    /// * The withdrawal doesn't reveal which specific commitment is being spent
+    /// * The nullifier ensures the same commitment cannot be spent twice
+    /// * The zero-knowledge proof proves ownership without revealing the commitment details
+    pub fn withdraw(
+        env: &Env,
+        to: Address,
+        relayer: Address,
+        fee: i128,
+        proof_bytes: Bytes,
+        pub_signals_bytes: Bytes,
+        change_commitment: Option<BytesN<32>>,
+    ) -> Result<WithdrawReceipt, Error> {
+        relayer.require_auth();
+
+        Self::non_reentrant(env, || {
+            // Proofs without a `context` signal bind neither the relayer nor the fee, so they can
+            // only be self-relayed without a fee
+            let context = |nullifier_hash: &BytesN<32>| {
+                Self::get_withdraw_context(
+                    env,
+                    to.clone(),
+                    relayer.clone(),
+                    fee,
+                    nullifier_hash.clone(),
+                )
+            };
+            let allow_unbound = relayer == to && fee == 0;
+            let receipt = Self::process_withdrawal(
+                env,
+                &to,
+                &relayer,
+                fee,
+                context,
+                allow_unbound,
+                &proof_bytes,
+                &pub_signals_bytes,
+                change_commitment,
+            )?;
+
+            log!(env, "Withdrawal successful");
+            Ok(receipt)
+        })
+    }
+
+    /// Processes several withdrawals in one call, for relayers submitting many at once
+    ///
+    /// Each item is checked and paid exactly as `withdraw` would, in order, and each proof is
+    /// still verified on its own; batching only shares the transaction's base costs. If any
+    /// item fails, the call returns its error and none of the withdrawals take effect.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `items` - The withdrawals, each with the arguments of `withdraw`
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once every withdrawal is paid, including for an empty batch
+    /// * The first error `withdraw` would return for any item, including
+    ///   `Err(Error::NullifierUsed)` if two items spend the same note
+    ///
+    /// # Security
+    ///
+    /// * Requires authentication from the relayer of every item, once per distinct relayer
+    ///
+    /// # Events
+    ///
+    /// * Publishes the events of `withdraw` for each item, in order
+    pub fn withdraw_batch(env: &Env, items: Vec<WithdrawItem>) -> Result<(), Error> {
+        let mut relayers = vec![env];
+        for item in items.iter() {
+            if !relayers.contains(&item.relayer) {
+                item.relayer.require_auth();
+                relayers.push_back(item.relayer);
+            }
+        }
+
+        Self::non_reentrant(env, || {
+            for item in items.iter() {
+                let context = |nullifier_hash: &BytesN<32>| {
+                    Self::get_withdraw_context(
+                        env,
+                        item.to.clone(),
+                        item.relayer.clone(),
+                        item.fee,
+                        nullifier_hash.clone(),
+                    )
+                };
+                let allow_unbound = item.relayer == item.to && item.fee == 0;
+                Self::process_withdrawal(
+                    env,
+                    &item.to,
+                    &item.relayer,
+                    item.fee,
+                    context,
+                    allow_unbound,
+                    &item.proof,
+                    &item.pub_signals,
+                    item.change_commitment.clone(),
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Withdraws into a contract function, for example to swap the withdrawn funds atomically
+    ///
+    /// Verifies the proof like `withdraw`, transfers the amount minus `fee` to `call.contract`
+    /// and then invokes `call.function` on it with `call.args` followed by the transferred
+    /// amount. If the call fails, the whole withdrawal is reverted. The proof's `context` signal
+    /// must equal `get_contract_withdraw_context(call, relayer, fee, nullifier_hash)`, so neither
+    /// the target nor its arguments can be changed in flight; proofs without a `context` are
+    /// rejected.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `call` - The contract, function and leading arguments the funds are paid into
+    /// * `relayer` - The address submitting the withdrawal (must be authenticated)
+    /// * `fee` - The part of the amount paid to `relayer`
+    /// * `proof_bytes` - The serialized zero-knowledge proof
+    /// * `pub_signals_bytes` - The serialized public signals associated with the proof
+    /// * `change_commitment` - For a partial withdrawal, the commitment of the change note
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` on successful withdrawal
+    /// * `Err(Error::UnboundRelayer)` if the proof has no `context` signal
+    /// * `Err(Error::ContextMismatch)` if the proof's `context` signal doesn't match the call
+    /// * The other errors of `withdraw`
+    ///
+    /// # Events
+    ///
+    /// * Publishes `WithdrawEvent` with `call.contract` as the recipient
+    pub fn withdraw_to_contract(
+        env: &Env,
+        call: ContractCall,
+        relayer: Address,
+        fee: i128,
+        proof_bytes: Bytes,
+        pub_signals_bytes: Bytes,
+        change_commitment: Option<BytesN<32>>,
+    ) -> Result<(), Error> {
+        relayer.require_auth();
+
+        Self::non_reentrant(env, || {
+            let context = |nullifier_hash: &BytesN<32>| {
+                Self::get_contract_withdraw_context(
+                    env,
+                    call.clone(),
+                    relayer.clone(),
+                    fee,
+                    nullifier_hash.clone(),
+                )
+            };
+            let receipt = Self::process_withdrawal(
+                env,
+                &call.contract,
+                &relayer,
+                fee,
+                context,
+                false,
+                &proof_bytes,
+                &pub_signals_bytes,
+                change_commitment,
+            )?;
+
+            let mut args = call.args;
+            args.push_back((receipt.amount - fee).into_val(env));
+            env.invoke_contract::<Val>(&call.contract, &call.function, args);
+
+            log!(env, "Withdrawal successful");
+            Ok(())
+        })
+    }
+
+    /// Withdraws a note to several recipients at once, for example a payment and a donation
+    ///
+    /// Verifies the proof like `withdraw` and pays each `(recipient, amount)` of `payouts` in
+    /// order, with one nullifier for the whole note. The payouts plus `fee` must add up to the
+    /// proven `withdrawnValue`. The proof's `context` signal must equal
+    /// `get_split_withdraw_context(payouts, relayer, fee, nullifier_hash)`, so no recipient or
+    /// amount can be changed in flight; proofs without a `context` are rejected.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `payouts` - The recipients and the amount each of them receives
+    /// * `relayer` - The address submitting the withdrawal (must be authenticated)
+    /// * `fee` - The part of the amount paid to `relayer`
+    /// * `proof_bytes` - The serialized zero-knowledge proof
+    /// * `pub_signals_bytes` - The serialized public signals associated with the proof
+    /// * `change_commitment` - For a partial withdrawal, the commitment of the change note
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` on successful withdrawal
+    /// * `Err(Error::InvalidAmount)` if `payouts` is empty or an amount is not positive
+    /// * `Err(Error::AmountMismatch)` if the payouts and fee don't add up to the proven amount
+    /// * `Err(Error::UnboundRelayer)` if the proof has no `context` signal
+    /// * `Err(Error::ContextMismatch)` if the proof's `context` signal doesn't match the payouts
+    /// * The other errors of `withdraw`
+    ///
+    /// # Events
+    ///
+    /// * Publishes `SplitWithdrawEvent` with the nullifier, payouts, relayer and fee
+    pub fn withdraw_split(
+        env: &Env,
+        payouts: Vec<(Address, i128)>,
+        relayer: Address,
+        fee: i128,
+        proof_bytes: Bytes,
+        pub_signals_bytes: Bytes,
+        change_commitment: Option<BytesN<32>>,
+    ) -> Result<(), Error> {
+        relayer.require_auth();
+
+        Self::non_reentrant(env, || {
+            if payouts.is_empty() {
+                return Err(Error::InvalidAmount);
+            }
+            let mut total: i128 = 0;
+            for (_, amount) in payouts.iter() {
+                if amount <= 0 {
+                    return Err(Error::InvalidAmount);
+                }
+                total = total.checked_add(amount).ok_or(Error::InvalidAmount)?;
+            }
+
+            let context = |nullifier_hash: &BytesN<32>| {
+                Self::get_split_withdraw_context(
+                    env,
+                    payouts.clone(),
+                    relayer.clone(),
+                    fee,
+                    nullifier_hash.clone(),
+                )
+            };
+            let (nullifier, amount, token_client) = Self::spend_note(
+                env,
+                &relayer,
+                fee,
+                context,
+                false,
+                None,
+                false,
+                &proof_bytes,
+                &pub_signals_bytes,
+                change_commitment,
+            )?;
+            if total != amount - fee {
+                return Err(Error::AmountMismatch);
+            }
+
+            for (recipient, amount) in payouts.iter() {
+                token_client.transfer(&env.current_contract_address(), &recipient, &amount);
+            }
+
+            SplitWithdrawEvent {
+                nullifier,
+                payouts,
+                relayer,
+                fee,
+            }
+            .publish(env);
+
+            log!(env, "Withdrawal successful");
+            Ok(())
+        })
+    }
+
+    /// Verifies a withdrawal and pays it out to `to`
+    ///
+    /// Shared by `withdraw` and `withdraw_to_contract`, which differ only in the context they
+    /// expect and in whether a proof without a `context` signal is accepted. `context` computes
+    /// the expected context from the proof's nullifier hash.
+    ///
+    /// # Returns
+    ///
+    /// * The receipt `withdraw` returns; `to` was paid its amount minus `fee`
+    /// * The errors documented on `withdraw`
+    #[allow(clippy::too_many_arguments)]
+    fn process_withdrawal(
+        env: &Env,
+        to: &Address,
+        relayer: &Address,
+        fee: i128,
+        context: impl Fn(&BytesN<32>) -> BytesN<32>,
+        allow_unbound: bool,
+        proof_bytes: &Bytes,
+        pub_signals_bytes: &Bytes,
+        change_commitment: Option<BytesN<32>>,
+    ) -> Result<WithdrawReceipt, Error> {
+        let exempt_recipient = Self::is_whitelisted_recipient(env, to.clone()).then_some(to);
+        let (nullifier, amount, token_client) = Self::spend_note(
+            env,
+            relayer,
+            fee,
+            context,
+            allow_unbound,
+            exempt_recipient,
+            true,
+            proof_bytes,
+            pub_signals_bytes,
+            change_commitment,
+        )?;
+
+        let payout = amount - fee;
+        token_client.transfer(&env.current_contract_address(), to, &payout);
+
+        WithdrawEvent {
+            nullifier: nullifier.clone(),
+            to: to.clone(),
+            amount,
+            relayer: relayer.clone(),
+            fee,
+        }
+        .publish(env);
+
+        Ok(WithdrawReceipt {
+            nullifier_hash: nullifier,
+            amount,
+            to: to.clone(),
+            ledger: env.ledger().sequence(),
+        })
+    }
+
+    /// Verifies a withdrawal, spends its nullifier and pays the relayer's fee
+    ///
+    /// Everything a withdrawal does except paying the recipients, which is up to the caller.
+    /// `exempt_recipient` is the recipient when it is whitelisted, in which case a proof that
+    /// binds it through its `context` skips the association check. With `settle_last_note`, the
+    /// note holding the last unspent value pays out `settled_amount` instead of the proven
+    /// amount; callers whose payouts are bound into the proof pass `false`.
+    ///
+    /// # Returns
+    ///
+    /// * The nullifier, the amount paid out and a client for the pool's token
+    /// * The errors documented on `withdraw`
+    #[allow(clippy::too_many_arguments)]
+    fn spend_note<'a>(
+        env: &'a Env,
+        relayer: &Address,
+        fee: i128,
+        context: impl Fn(&BytesN<32>) -> BytesN<32>,
+        allow_unbound: bool,
+        exempt_recipient: Option<&Address>,
+        settle_last_note: bool,
+        proof_bytes: &Bytes,
+        pub_signals_bytes: &Bytes,
+        change_commitment: Option<BytesN<32>>,
+    ) -> Result<(BytesN<32>, i128, token::Client<'a>), Error> {
+        if Self::is_paused(env) {
+            return Err(Error::Paused);
+        }
+        Self::extend_instance_ttl(env);
+
+        let (signals, pub_signals_bytes) = Self::decode_signals(env, pub_signals_bytes)?;
+
+        // Circuits with a `context` signal bind the recipient, relayer and fee into the proof
+        match &signals.context {
+            Some(proven_context) => {
+                if *proven_context != context(&signals.nullifier_hash) {
+                    return Err(Error::ContextMismatch);
+                }
+            }
+            None => {
+                if !allow_unbound {
+                    return Err(Error::UnboundRelayer);
+                }
+            }
+        }
+
+        // The change note must be the one the proof commits to. Fixed-denomination notes are
+        // always spent whole, so those pools can't take change
+        if change_commitment != signals.change_commitment {
+            return Err(Error::ChangeCommitmentMismatch);
+        }
+        if change_commitment.is_some() && Self::is_fixed_denomination(env) {
+            return Err(Error::InvalidAmount);
+        }
+
+        // Only a proof bound to its recipient can use the whitelist, or anyone could replay it
+        // to a whitelisted address to get around the association set
+        let bypass = exempt_recipient.filter(|_| {
+            signals.context.is_some()
+                && Self::has_association_set(env)
+                && !Self::is_known_association_root(env, signals.association_root.clone())
+        });
+
+        Self::check_proof(
+            env,
+            &signals,
+            bypass.is_none(),
+            proof_bytes,
+            &pub_signals_bytes,
+        )?;
+        let nullifier = signals.nullifier_hash.clone();
+        if let Some(recipient) = bypass {
+            AssociationBypassed {
+                nullifier: nullifier.clone(),
+                recipient: recipient.clone(),
+                association_root: signals.association_root.clone(),
+            }
+            .publish(env);
+        }
+
+        // The payout is always the proven value; a fixed pool only accepts its denomination
+        let amount = signals.withdrawn_amount().ok_or(Error::InvalidAmount)?;
+        if Self::get_denomination(env).is_some_and(|fixed| amount != fixed) {
+            return Err(Error::AmountMismatch);
+        }
+
+        // Get token and check balance
+        let token_address = Self::get_token(env)?;
+        let token_client = token::Client::new(env, &token_address);
+
+        let balance = Self::note_balance(env, &token_client);
+        let proven = amount;
+        let amount = if settle_last_note {
+            Self::settled_amount(env, amount, balance)
+        } else {
+            amount
+        };
+        if fee < 0 || fee > amount {
+            return Err(Error::InvalidFee);
+        }
+        if amount <= 0 || balance < amount {
+            return Err(Error::InsufficientBalance);
+        }
+        Self::check_withdraw_limits(env, balance, amount)?;
+
+        // Mark nullifier as used, taking the note's value off what the pool owes to notes
+        Self::store_nullifier(env, &nullifier, proven);
+        Self::add_to_total(env, &NOTE_VALUE_KEY, -proven);
+        Self::record_withdraw_volume(env, amount);
+
+        // The rest of the note's value stays in the pool as a new note
+        if let Some(change_commitment) = change_commitment {
+            let (new_root, leaf_index, path) =
+                Self::store_commitment(env, change_commitment.clone())?;
+            DepositEvent {
+                leaf_index,
+                commitment: change_commitment,
+                new_root,
+                path,
+                encrypted_note: None,
+            }
+            .publish(env);
+        }
+
+        // Pay the relayer its fee out of the withdrawn amount
+        if fee > 0 {
+            token_client.transfer(&env.current_contract_address(), relayer, &fee);
+        }
+        Self::add_to_total(env, &TOTAL_WITHDRAWN_KEY, amount);
+
+        Ok((nullifier, amount, token_client))
+    }
+
+    /// Gets what a withdrawal proven to be worth `amount` pays out, given the `balance` backing
+    /// notes
+    ///
+    /// That is the proven amount, except when it is exactly what is left of `get_unspent_value`:
+    /// no other note has a claim on the balance then, so a shortfall, such as one left by a
+    /// token that charges for transfers, doesn't block the final withdrawal and it pays out
+    /// `min(balance, amount)` instead. It never pays more than proven, so a surplus, such as
+    /// tokens sent to the pool directly, is left for the admin to `sweep`. A partial withdrawal
+    /// leaves a change note behind, so it never covers the unspent value, and an amount above
+    /// it was never deposited and gets no such allowance.
+    fn settled_amount(env: &Env, amount: i128, balance: i128) -> i128 {
+        if amount == Self::get_unspent_value(env) {
+            amount.min(balance)
+        } else {
+            amount
+        }
+    }
+
+    /// Computes the `context` public signal that binds a withdrawal to its recipient, relayer,
+    /// fee and note
+    ///
+    /// The context is the canonical message a user authorizes a relayer with: the SHA-256 of the
+    /// XDR-encoded `(to, relayer, fee, nullifier_hash)` tuple (an `ScVec` of an address, an
+    /// address, an `i128` and 32 bytes) with the top byte cleared so it is a valid BLS12-381
+    /// scalar. Provers pass it as the circuit's `context` input, and `withdraw` recomputes it
+    /// from its arguments and the proof's nullifier hash. Because the nullifier hash is
+    /// included, an authorization is only good for one note, and no competing relayer can
+    /// submit the proof with itself as relayer or a different fee.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `to` - The address of the recipient
+    /// * `relayer` - The address that will submit the withdrawal
+    /// * `fee` - The part of the amount paid to `relayer`
+    /// * `nullifier_hash` - The `nullifierHash` signal of the note being withdrawn
+    ///
+    /// # Returns
+    ///
+    /// * The 32-byte big-endian context value
+    pub fn get_withdraw_context(
+        env: &Env,
+        to: Address,
+        relayer: Address,
+        fee: i128,
+        nullifier_hash: BytesN<32>,
+    ) -> BytesN<32> {
+        Self::hash_context(env, (to, relayer, fee, nullifier_hash))
+    }
+
+    /// Computes the `context` public signal that binds a withdrawal to a contract call, relayer
+    /// and fee
+    ///
+    /// Like `get_withdraw_context`, but over the XDR-encoded `(call, relayer, fee, nullifier_hash)`
+    /// tuple, so the target contract, function and arguments are all covered. Used by
+    /// `withdraw_to_contract`.
+    ///
+    /// # Returns
+    ///
+    /// * The 32-byte big-endian context value
+    pub fn get_contract_withdraw_context(
+        env: &Env,
+        call: ContractCall,
+        relayer: Address,
+        fee: i128,
+        nullifier_hash: BytesN<32>,
+    ) -> BytesN<32> {
+        Self::hash_context(env, (call, relayer, fee, nullifier_hash))
+    }
+
+    /// Computes the `context` public signal that binds a split withdrawal to its payouts,
+    /// relayer and fee
+    ///
+    /// Like `get_withdraw_context`, but over the XDR-encoded
+    /// `(payouts, relayer, fee, nullifier_hash)` tuple, so every recipient and amount is
+    /// covered. Used by `withdraw_split`.
+    ///
+    /// # Returns
+    ///
+    /// * The 32-byte big-endian context value
+    pub fn get_split_withdraw_context(
+        env: &Env,
+        payouts: Vec<(Address, i128)>,
+        relayer: Address,
+        fee: i128,
+        nullifier_hash: BytesN<32>,
+    ) -> BytesN<32> {
+        Self::hash_context(env, (payouts, relayer, fee, nullifier_hash))
+    }
+
+    /// Hashes a context message: the SHA-256 of its XDR encoding with the top byte cleared, so
+    /// it is a valid BLS12-381 scalar
+    fn hash_context(env: &Env, message: impl ToXdr) -> BytesN<32> {
+        let mut context = env.crypto().sha256(&message.to_xdr(env)).to_array();
+        context[0] = 0;
+        BytesN::from_array(env, &context)
+    }
+
+    /// Checks whether a withdrawal proof would currently be accepted, without spending it
+    ///
+    /// Runs the nullifier, state root, association root and Groth16 checks of `withdraw`, so
+    /// relayers can simulate this before paying for a `withdraw` transaction. It doesn't check
+    /// the recipient, relayer and fee bound by the `context` signal, the pause state or the
+    /// pool's balance.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `proof_bytes` - The serialized zero-knowledge proof
+    /// * `pub_signals_bytes` - The serialized public signals associated with the proof
+    ///
+    /// # Returns
+    ///
+    /// * `true` if `withdraw` would accept the proof and public signals
+    pub fn verify_proof(env: &Env, proof_bytes: Bytes, pub_signals_bytes: Bytes) -> bool {
+        match Self::decode_signals(env, &pub_signals_bytes) {
+            Ok((signals, pub_signals_bytes)) => {
+                Self::check_proof(env, &signals, true, &proof_bytes, &pub_signals_bytes).is_ok()
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Reports why a withdrawal proof would be rejected, without spending it
+    ///
+    /// Runs the checks of `withdraw` in the same order and stops at the first one that fails:
+    /// the pause state, the signals' version and layout, the nullifier, the state root and its
+    /// withdrawal delay, the association root, the Groth16 proof, the proven amount and the
+    /// pool's balance, reserve and withdrawal volume limit. Like `verify_proof`, it doesn't
+    /// check the recipient, relayer and fee bound by the `context` signal, and nothing is
+    /// written to storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `proof_bytes` - The serialized zero-knowledge proof
+    /// * `pub_signals_bytes` - The serialized public signals associated with the proof
+    ///
+    /// # Returns
+    ///
+    /// * `WithdrawCheck::Ok` if `withdraw` would accept the proof, otherwise the first failure
+    pub fn can_withdraw(env: &Env, proof_bytes: Bytes, pub_signals_bytes: Bytes) -> WithdrawCheck {
+        match Self::check_withdrawal(env, &proof_bytes, &pub_signals_bytes) {
+            Ok(()) => WithdrawCheck::Ok,
+            Err(error) => error.into(),
+        }
+    }
+
+    fn check_withdrawal(
+        env: &Env,
+        proof_bytes: &Bytes,
+        pub_signals_bytes: &Bytes,
+    ) -> Result<(), Error> {
+        if Self::is_paused(env) {
+            return Err(Error::Paused);
+        }
+        let (signals, pub_signals_bytes) = Self::decode_signals(env, pub_signals_bytes)?;
+        Self::check_proof(env, &signals, true, proof_bytes, &pub_signals_bytes)?;
+
+        let amount = signals.withdrawn_amount().ok_or(Error::InvalidAmount)?;
+        if Self::get_denomination(env).is_some_and(|fixed| amount != fixed) {
+            return Err(Error::AmountMismatch);
+        }
+        let token_client = token::Client::new(env, &Self::get_token(env)?);
+        let balance = Self::note_balance(env, &token_client);
+        let amount = Self::settled_amount(env, amount, balance);
+        if amount <= 0 || balance < amount {
+            return Err(Error::InsufficientBalance);
+        }
+        Self::check_withdraw_limits(env, balance, amount)
+    }
+
+    /// Parses public signals according to their version byte
+    ///
+    /// # Returns
+    ///
+    /// * The named signals, and the unversioned signals the verifier checks the proof against
+    /// * `Err(Error::UnsupportedProofVersion)` if the version is unknown or outside
+    ///   `get_proof_versions`
+    /// * `Err(Error::MalformedPublicSignals)` if the signals don't match their version's layout
+    fn decode_signals(
+        env: &Env,
+        pub_signals_bytes: &Bytes,
+    ) -> Result<(WithdrawalSignals, Bytes), Error> {
+        let (version, payload) = public_signals::split_version(pub_signals_bytes)?;
+        let (min_version, max_version) = Self::get_proof_versions(env);
+        if version < min_version || version > max_version {
+            return Err(Error::UnsupportedProofVersion);
+        }
+
+        // Circuits with a new signal or proof layout get their own arm here
+        match version {
+            public_signals::LEGACY_VERSION | public_signals::CURRENT_VERSION => {
+                let signals = public_signals::parse_public_signals(env, &payload)?;
+                Ok((signals, payload))
+            }
+            _ => Err(Error::UnsupportedProofVersion),
+        }
+    }
+
+    /// Gets the range of proof versions `withdraw` accepts, inclusive
+    ///
+    /// # Returns
+    ///
+    /// * `(min_version, max_version)`, by default every version this contract can parse
+    pub fn get_proof_versions(env: &Env) -> (u32, u32) {
+        env.storage()
+            .instance()
+            .get(&PROOF_VERSIONS_KEY)
+            .unwrap_or((
+                public_signals::LEGACY_VERSION,
+                public_signals::CURRENT_VERSION,
+            ))
+    }
+
+    /// Sets the range of proof versions `withdraw` accepts
+    ///
+    /// Raising `min_version` deprecates proofs of older circuits once wallets have moved to a
+    /// new one; their notes stay withdrawable with proofs from a supported circuit.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `min_version` - The oldest accepted version
+    /// * `max_version` - The newest accepted version
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the range is stored
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::InvalidConfiguration)` if `min_version` exceeds `max_version` or
+    ///   `max_version` is newer than this contract can parse
+    pub fn set_proof_versions(
+        env: &Env,
+        caller: Address,
+        min_version: u32,
+        max_version: u32,
+    ) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::store_proof_versions(env, min_version, max_version)
+    }
+
+    /// Stores the accepted proof versions, see `set_proof_versions`
+    fn store_proof_versions(env: &Env, min_version: u32, max_version: u32) -> Result<(), Error> {
+        if min_version > max_version || max_version > public_signals::CURRENT_VERSION {
+            return Err(Error::InvalidConfiguration);
+        }
+
+        env.storage()
+            .instance()
+            .set(&PROOF_VERSIONS_KEY, &(min_version, max_version));
+        Ok(())
+    }
+
+    /// Gets the flat fee taken from every deposit, zero unless the admin has set one
+    pub fn get_deposit_fee(env: &Env) -> i128 {
+        env.storage().instance().get(&DEPOSIT_FEE_KEY).unwrap_or(0)
+    }
+
+    /// Sets the flat fee taken from every deposit
+    ///
+    /// Depositors pay `fee` on top of the value of their note: a deposit of `amount` is worth
+    /// `amount - fee` and the commitment is for that. In fixed-denomination mode a deposit is
+    /// therefore the denomination plus the fee, so every note stays the same size. The pool holds
+    /// collected fees apart from the funds backing notes until the admin pays them out with
+    /// `withdraw_fees`. A fee of zero, the default, turns it off.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `fee` - The fee per deposit, in the token's smallest unit
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the fee is stored
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::InvalidFee)` if `fee` is negative
+    pub fn set_deposit_fee(env: &Env, caller: Address, fee: i128) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::store_deposit_fee(env, fee)
+    }
+
+    /// Stores the deposit fee, see `set_deposit_fee`
+    fn store_deposit_fee(env: &Env, fee: i128) -> Result<(), Error> {
+        if fee < 0 {
+            return Err(Error::InvalidFee);
+        }
+
+        env.storage().instance().set(&DEPOSIT_FEE_KEY, &fee);
+        Ok(())
+    }
+
+    /// Gets the total of the deposit fees the pool has collected, including those already paid
+    /// out with `withdraw_fees`
+    pub fn get_total_fees(env: &Env) -> i128 {
+        env.storage().instance().get(&TOTAL_FEES_KEY).unwrap_or(0)
+    }
+
+    /// Pays out the deposit fees collected since the last payout
+    ///
+    /// Only ever sends what `get_total_fees` counted and wasn't paid out yet, never the funds
+    /// backing notes or receipts, which withdrawals in turn can't use.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `to` - The address the fees are sent to
+    ///
+    /// # Returns
+    ///
+    /// * The amount paid out, zero if there were no fees to pay
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    ///
+    /// # Events
+    ///
+    /// * Publishes `FeesWithdrawn` with the recipient and amount, unless there was nothing to pay
+    pub fn withdraw_fees(env: &Env, caller: Address, to: Address) -> Result<i128, Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::pay_fees(env, to)
+    }
+
+    /// Sends the unpaid deposit fees to `to`, see `withdraw_fees`
+    fn pay_fees(env: &Env, to: Address) -> Result<i128, Error> {
+        let amount = Self::unpaid_fees(env);
+        if amount == 0 {
+            return Ok(0);
+        }
+
+        let token_address = Self::get_token(env)?;
+        Self::non_reentrant(env, || {
+            Self::add_to_total(env, &FEES_PAID_KEY, amount);
+            token::Client::new(env, &token_address).transfer(
+                &env.current_contract_address(),
+                &to,
+                &amount,
+            );
+            Ok(())
+        })?;
+        FeesWithdrawn { to, amount }.publish(env);
+        Ok(amount)
+    }
+
+    /// Adds a deposit's fee to the collected fees
+    fn collect_fee(env: &Env, fee: i128) {
+        if fee > 0 {
+            Self::add_to_total(env, &TOTAL_FEES_KEY, fee);
+            FeesCollected { amount: fee }.publish(env);
+        }
+    }
+
+    /// Gets the collected deposit fees that are still held by the pool
+    fn unpaid_fees(env: &Env) -> i128 {
+        let paid: i128 = env.storage().instance().get(&FEES_PAID_KEY).unwrap_or(0);
+        Self::get_total_fees(env) - paid
+    }
+
+    /// Gets the ed25519 public key of the issuer whose signature deposits need, if any
+    ///
+    /// # Returns
+    ///
+    /// * The key, or `None` for a permissionless pool
+    pub fn get_deposit_issuer(env: &Env) -> Option<BytesN<32>> {
+        env.storage().instance().get(&DEPOSIT_ISSUER_KEY)
+    }
+
+    /// Replaces the deposit issuer's public key, for example to rotate a compromised key
+    ///
+    /// Whether a pool gates deposits is fixed at deployment, so this only changes the key of a
+    /// pool deployed with an issuer. Commitments the old key signed and that weren't deposited
+    /// yet have to be signed again.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `issuer` - The issuer's ed25519 public key
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the key is replaced
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::InvalidConfiguration)` if the pool was deployed without an issuer
+    pub fn set_deposit_issuer(env: &Env, caller: Address, issuer: BytesN<32>) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::store_deposit_issuer(env, issuer)
+    }
+
+    /// Stores the deposit issuer's key of a gated pool, see `set_deposit_issuer`
+    fn store_deposit_issuer(env: &Env, issuer: BytesN<32>) -> Result<(), Error> {
+        if Self::get_deposit_issuer(env).is_none() {
+            return Err(Error::InvalidConfiguration);
+        }
+
+        env.storage().instance().set(&DEPOSIT_ISSUER_KEY, &issuer);
+        Ok(())
+    }
+
+    /// Checks that the pool takes deposits without an issuer signature
+    fn require_unsigned_deposits(env: &Env) -> Result<(), Error> {
+        if Self::get_deposit_issuer(env).is_some() {
+            return Err(Error::IssuerSignatureRequired);
+        }
+        Ok(())
+    }
+
+    /// Checks that the pool has a denomination, which is what binds a caller-chosen commitment
+    /// to the amount paid for it
+    ///
+    /// Withdrawals from a fixed pool must prove the denomination, so a commitment to any other
+    /// value can never be spent. A variable pool would pay out whatever the commitment claims.
+    fn require_denomination(env: &Env) -> Result<(), Error> {
+        if !Self::is_fixed_denomination(env) {
+            return Err(Error::UnboundCommitment);
+        }
+        Ok(())
+    }
+
+    /// Gets what a deposit of `amount` is worth after `fee`
+    ///
+    /// # Returns
+    ///
+    /// * The value the deposit's commitment stands for
+    /// * `Err(Error::InvalidAmount)` if `amount` doesn't exceed the fee, or the value differs
+    ///   from the pool's denomination when it has one
+    fn net_deposit_value(env: &Env, amount: i128, fee: i128) -> Result<i128, Error> {
+        if amount <= fee {
+            return Err(Error::InvalidAmount);
+        }
+        let value = amount - fee;
+        if Self::get_denomination(env).is_some_and(|fixed| value != fixed) {
+            return Err(Error::InvalidAmount);
+        }
+        Ok(value)
+    }
+
+    /// Gets the minimum age, in ledgers, of the deposits a withdrawal can spend
+    pub fn get_withdraw_delay(env: &Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&WITHDRAW_DELAY_KEY)
+            .unwrap_or(0)
+    }
+
+    /// Sets the minimum age, in ledgers, of the deposits a withdrawal can spend
+    ///
+    /// Some compliance regimes require funds to stay in the pool for a while. With a delay set,
+    /// `withdraw` rejects proofs against a state root recorded less than `ledgers` ledgers ago,
+    /// and since a note is only in roots recorded after its deposit, every note has to wait out
+    /// the delay. Zero, the default, disables the check.
+    ///
+    /// Closed ledgers are final on Stellar, so roots can't be reorged out; the delay is also the
+    /// confirmation depth for integrators that want one before a root is spent against.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `ledgers` - The delay in ledgers (about 5 seconds each)
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the delay is stored
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    pub fn set_withdraw_delay(env: &Env, caller: Address, ledgers: u32) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        env.storage().instance().set(&WITHDRAW_DELAY_KEY, &ledgers);
+        Ok(())
+    }
+
+    /// Gets the balance withdrawals must leave in the pool, in the token's smallest unit
+    pub fn get_min_reserve(env: &Env) -> i128 {
+        env.storage().instance().get(&MIN_RESERVE_KEY).unwrap_or(0)
+    }
+
+    /// Sets the balance withdrawals must leave in the pool
+    ///
+    /// `withdraw` rejects a withdrawal that would take the pool's balance, less what is owed to
+    /// receipts, below the reserve. Zero, the default, disables the check.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `amount` - The reserve, in the token's smallest unit
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the reserve is stored
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::InvalidAmount)` if `amount` is negative
+    pub fn set_min_reserve(env: &Env, caller: Address, amount: i128) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::store_min_reserve(env, amount)
+    }
+
+    fn store_min_reserve(env: &Env, amount: i128) -> Result<(), Error> {
+        if amount < 0 {
+            return Err(Error::InvalidAmount);
+        }
+        env.storage().instance().set(&MIN_RESERVE_KEY, &amount);
+        Ok(())
+    }
+
+    /// Gets the most that can be withdrawn per window of ledgers
+    ///
+    /// # Returns
+    ///
+    /// * `(max_volume, window_ledgers)`, where a `max_volume` of zero, the default, means
+    ///   unlimited
+    pub fn get_withdraw_volume_limit(env: &Env) -> (i128, u32) {
+        env.storage()
+            .instance()
+            .get(&WITHDRAW_LIMIT_KEY)
+            .unwrap_or((0, 0))
+    }
+
+    /// Sets the most that can be withdrawn per window of ledgers
+    ///
+    /// Windows are fixed: the ledger sequence divided by `window_ledgers` numbers them, and the
+    /// volume starts from zero in each. A withdrawal that would take the current window's volume
+    /// above `max_volume` fails until the next window. Setting a limit starts its count over.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `max_volume` - The most withdrawn per window, in the token's smallest unit, or zero
+    ///   for no limit
+    /// * `window_ledgers` - The length of a window in ledgers (about 5 seconds each)
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the limit is stored
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::InvalidConfiguration)` if `max_volume` is negative, or positive with a
+    ///   zero-length window
+    pub fn set_withdraw_volume_limit(
+        env: &Env,
+        caller: Address,
+        max_volume: i128,
+        window_ledgers: u32,
+    ) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::store_withdraw_volume_limit(env, max_volume, window_ledgers)
+    }
+
+    fn store_withdraw_volume_limit(
+        env: &Env,
+        max_volume: i128,
+        window_ledgers: u32,
+    ) -> Result<(), Error> {
+        if max_volume < 0 || (max_volume > 0 && window_ledgers == 0) {
+            return Err(Error::InvalidConfiguration);
+        }
+        env.storage()
+            .instance()
+            .set(&WITHDRAW_LIMIT_KEY, &(max_volume, window_ledgers));
+        env.storage().instance().remove(&WITHDRAW_VOLUME_KEY);
+        Ok(())
+    }
+
+    /// Gets how much has been withdrawn in the current window of the volume limit
+    ///
+    /// # Returns
+    ///
+    /// * The withdrawn volume, or zero without a limit
+    pub fn get_withdrawn_in_window(env: &Env) -> i128 {
+        let (max_volume, window_ledgers) = Self::get_withdraw_volume_limit(env);
+        if max_volume == 0 {
+            return 0;
+        }
+        let window = env.ledger().sequence() / window_ledgers;
+        match env
+            .storage()
+            .instance()
+            .get::<_, (u32, i128)>(&WITHDRAW_VOLUME_KEY)
+        {
+            Some((recorded_window, volume)) if recorded_window == window => volume,
+            _ => 0,
+        }
+    }
+
+    /// Checks a withdrawal of `amount` from a pool holding `balance` against the reserve and
+    /// the volume limit
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` if both allow it
+    /// * `Err(Error::BelowMinReserve)` if it would leave less than `get_min_reserve`
+    /// * `Err(Error::WithdrawVolumeExceeded)` if it would go over the current window's limit
+    fn check_withdraw_limits(env: &Env, balance: i128, amount: i128) -> Result<(), Error> {
+        if balance - amount < Self::get_min_reserve(env) {
+            return Err(Error::BelowMinReserve);
+        }
+        let (max_volume, _) = Self::get_withdraw_volume_limit(env);
+        if max_volume > 0 && Self::get_withdrawn_in_window(env).saturating_add(amount) > max_volume
+        {
+            return Err(Error::WithdrawVolumeExceeded);
+        }
+        Ok(())
+    }
+
+    /// Adds a withdrawal to the current window's volume, if there is a limit
+    fn record_withdraw_volume(env: &Env, amount: i128) {
+        let (max_volume, window_ledgers) = Self::get_withdraw_volume_limit(env);
+        if max_volume == 0 {
+            return;
+        }
+        let window = env.ledger().sequence() / window_ledgers;
+        let volume = Self::get_withdrawn_in_window(env).saturating_add(amount);
+        env.storage()
+            .instance()
+            .set(&WITHDRAW_VOLUME_KEY, &(window, volume));
+    }
+
+    /// Checks a withdrawal proof against the contract's state
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` if the proof is valid and spends an unused nullifier
+    /// * `Err(Error::NullifierUsed)` if the nullifier has been used before
+    /// * `Err(Error::ProofExpired)` if the current ledger is past the proof's `expiryLedger`
+    /// * `Err(Error::UnknownStateRoot)` if the proof's `stateRoot` is not in the root history
+    /// * `Err(Error::WithdrawTooEarly)` if the proof's `stateRoot` is younger than the
+    ///   withdrawal delay
+    /// * `Err(Error::AssociationRootMismatch)` if `check_association` is set, an association
+    ///   set is configured and the proof's `associationRoot` is not one of its recent roots
+    /// * `Err(Error::CoinOwnershipProofFailed)` if the zero-knowledge proof verification fails.
+    ///   Builds with the `demo` feature skip the verification and accept any proof
+    fn check_proof(
+        env: &Env,
+        signals: &WithdrawalSignals,
+        check_association: bool,
+        proof_bytes: &Bytes,
+        pub_signals_bytes: &Bytes,
+    ) -> Result<(), Error> {
+        // Check nullifier not used
+        if Self::is_nullifier_used(env, signals.nullifier_hash.clone()) {
+            return Err(Error::NullifierUsed);
+        }
+
+        // The expiry is a public signal, so it can't be extended without a new proof
+        if signals
+            .expiry_ledger
+            .is_some_and(|expiry| env.ledger().sequence() > expiry)
+        {
+            return Err(Error::ProofExpired);
+        }
+
+        // The proof must reference a root this contract produced recently
+        if !Self::is_known_root(env, signals.state_root.clone()) {
+            return Err(Error::UnknownStateRoot);
+        }
+
+        // A root contains every deposit made up to the ledger it was recorded at, so a root old
+        // enough proves the spent deposit is old enough too
+        let delay = Self::get_withdraw_delay(env);
+        if delay > 0 {
+            let recorded_at = Self::root_recorded_at(env, &signals.state_root).unwrap_or(0);
+            if env.ledger().sequence() < recorded_at.saturating_add(delay) {
+                return Err(Error::WithdrawTooEarly);
+            }
+        }
+
+        // The proof must be bound to an association set the admin approved recently
+        if check_association
+            && Self::has_association_set(env)
+            && !Self::is_known_association_root(env, signals.association_root.clone())
+        {
+            return Err(Error::AssociationRootMismatch);
+        }
+
+        // Verify the zero-knowledge proof of coin ownership
+        #[cfg(not(feature = "demo"))]
+        if !Self::verify_groth16(env, proof_bytes, pub_signals_bytes)? {
+            return Err(Error::CoinOwnershipProofFailed);
+        }
+        #[cfg(feature = "demo")]
+        let _ = (proof_bytes, pub_signals_bytes);
+
+        Ok(())
+    }
+
+    /// Deserializes withdrawal public signals into named fields
+    ///
+    /// Lets clients check the signals they are about to submit with the same parser `withdraw`
+    /// uses. Accepts unversioned signals and signals prefixed with a version byte.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `pub_signals_bytes` - The serialized public signals
+    ///
+    /// # Returns
+    ///
+    /// * The named signals, with `context` set only for circuits that have one
+    /// * `Err(Error::UnsupportedProofVersion)` if the version is not accepted
+    /// * `Err(Error::MalformedPublicSignals)` if the count prefix or length is wrong, or a
+    ///   signal is not a canonical field element
+    pub fn parse_public_signals(
+        env: &Env,
+        pub_signals_bytes: Bytes,
+    ) -> Result<WithdrawalSignals, Error> {
+        Self::decode_signals(env, &pub_signals_bytes).map(|(signals, _)| signals)
+    }
+
+    /// Checks whether the pool only accepts deposits and withdrawals of its denomination
+    ///
+    /// # Returns
+    ///
+    /// * `true` if the pool was deployed with a denomination
+    pub fn is_fixed_denomination(env: &Env) -> bool {
+        Self::get_denomination(env).is_some()
+    }
+
+    /// Gets the amount every deposit and withdrawal must be, in the token's smallest unit
+    ///
+    /// Tiered pools are one pool per denomination, deployed by the pool factory's
+    /// `deploy_tiered_pools`, so each tier keeps its own tree and anonymity set.
+    ///
+    /// # Returns
+    ///
+    /// * The denomination, or `None` if the pool accepts any amount
+    pub fn get_denomination(env: &Env) -> Option<i128> {
+        env.storage()
+            .instance()
+            .get(&DENOMINATION_KEY)
+            .unwrap_or(Some(FIXED_AMOUNT))
+    }
+
+    /// Replaces the pool's denomination, to recover from deploying with the wrong one
+    ///
+    /// Like `set_token`, only allowed while nothing has been deposited or withdrawn, since every
+    /// note in the tree was made for the old denomination.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `denomination` - The amount every deposit and withdrawal must be, or `None` to accept
+    ///   any amount
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the denomination is replaced
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::InvalidConfiguration)` if `denomination` isn't positive, or is `None` in
+    ///   a pool with a deposit issuer
+    /// * `Err(Error::PoolNotEmpty)` if any commitment has been deposited or nullifier spent
+    ///
+    /// # Events
+    ///
+    /// * Publishes `DenominationChanged` with the old and new denomination
+    pub fn set_denomination(
+        env: &Env,
+        caller: Address,
+        denomination: Option<i128>,
+    ) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::replace_denomination(env, denomination)
+    }
+
+    /// Replaces the denomination once the pool is known to be unused, see `set_denomination`
+    fn replace_denomination(env: &Env, denomination: Option<i128>) -> Result<(), Error> {
+        if denomination.is_some_and(|amount| amount <= 0) {
+            return Err(Error::InvalidConfiguration);
+        }
+        // Signed deposits carry caller-chosen commitments, which only a denomination binds
+        if denomination.is_none() && Self::get_deposit_issuer(env).is_some() {
+            return Err(Error::InvalidConfiguration);
+        }
+        if Self::get_commitment_count(env) > 0 || Self::get_nullifier_count(env) > 0 {
+            return Err(Error::PoolNotEmpty);
+        }
+
+        let old_denomination = Self::get_denomination(env);
+        env.storage()
+            .instance()
+            .set(&DENOMINATION_KEY, &denomination);
+        DenominationChanged {
+            old_denomination,
+            new_denomination: denomination,
+        }
+        .publish(env);
+        Ok(())
+    }
+
+    /// Gets the Poseidon parameter set the commitment tree is hashed with
+    ///
+    /// The withdrawal circuit has its parameter set compiled in, so provers and deployers
+    /// compare this against the circuit's before trusting roots or commitments from the pool.
+    ///
+    /// # Returns
+    ///
+    /// * The `poseidon::PARAMS_ID` the tree was built with. Pools deployed before the id was
+    ///   stored were built with the first set
+    pub fn get_poseidon_params_id(env: &Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&POSEIDON_PARAMS_KEY)
+            .unwrap_or(1)
+    }
+
+    /// Gets the current merkle root of the commitment tree
+    pub fn get_merkle_root(env: &Env) -> BytesN<32> {
+        Self::get_persistent(env, &TREE_ROOT_KEY).unwrap_or(BytesN::from_array(env, &[0u8; 32]))
+    }
+
+    /// Gets the pool's status in a single call
+    ///
+    /// Reads everything a dashboard shows from the same ledger state, so the values are always
+    /// consistent with each other.
+    pub fn get_state(env: &Env) -> PoolState {
+        PoolState {
+            merkle_root: Self::get_merkle_root(env),
+            commitment_count: Self::get_commitment_count(env),
+            merkle_depth: Self::get_merkle_depth(env),
+            remaining_capacity: Self::remaining_capacity(env),
+            association_root: Self::get_association_root(env),
+            paused: Self::is_paused(env),
+        }
+    }
+
+    /// Gets the pool's configuration in a single call
+    ///
+    /// The deployment descriptor a client checks to make sure it is talking to the pool it
+    /// expects: the token, admin, verifier contract, tree depth and denomination. These only
+    /// change through admin actions such as `set_token` or `set_groth16_verifier`.
+    ///
+    /// # Returns
+    ///
+    /// * The configuration
+    /// * `Err(Error::NotInitialized)` if the token, admins or verifier are missing from storage
+    pub fn get_config(env: &Env) -> Result<PoolConfig, Error> {
+        Ok(PoolConfig {
+            token: Self::get_token(env)?,
+            admin: Self::get_admins(env)?
+                .first()
+                .ok_or(Error::NotInitialized)?,
+            groth16_verifier: Self::get_groth16_verifier(env)?,
+            tree_depth: Self::get_merkle_depth(env),
+            fixed_amount: Self::get_denomination(env),
+        })
+    }
+
+    /// Gets the pool's lifetime totals
+    ///
+    /// `total_deposited` counts every deposited amount and `total_withdrawn` every amount paid
+    /// out by a withdrawal or emergency refund, relayer fees included. Tokens sent to the pool
+    /// outside a deposit are in neither, so the balance can exceed the difference.
+    pub fn get_pool_stats(env: &Env) -> PoolStats {
+        PoolStats {
+            total_deposited: env
+                .storage()
+                .instance()
+                .get(&TOTAL_DEPOSITED_KEY)
+                .unwrap_or(0),
+            total_withdrawn: env
+                .storage()
+                .instance()
+                .get(&TOTAL_WITHDRAWN_KEY)
+                .unwrap_or(0),
+        }
+    }
+
+    /// Adds `amount` to one of the lifetime totals, such as those of `get_pool_stats`
+    fn add_to_total(env: &Env, key: &Symbol, amount: i128) {
+        let total: i128 = env.storage().instance().get(key).unwrap_or(0);
+        env.storage()
+            .instance()
+            .set(key, &total.saturating_add(amount));
+    }
+
+    /// Gets the merkle path for a commitment
+    ///
+    /// Returns the sibling hashes from the leaf up to the root, in the order expected by the
+    /// circuit's `stateSiblings` input. The leaf index bits give the left/right position at each
+    /// level. This rehashes the tree from its leaves and is meant to be simulated, not submitted.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `leaf_index` - The index returned by `deposit` for the commitment
+    ///
+    /// # Returns
+    ///
+    /// * One sibling per tree level
+    /// * `Err(Error::InvalidLeafIndex)` if no commitment has been stored at `leaf_index`
+    /// * `Err(Error::NotInitialized)` if the tree is missing from storage
+    pub fn get_merkle_proof(env: &Env, leaf_index: u32) -> Result<Vec<BytesN<32>>, Error> {
+        let tree = Self::load_tree(env)?;
+        let (siblings, _) = tree
+            .generate_proof(leaf_index)
+            .ok_or(Error::InvalidLeafIndex)?;
+
+        let mut path = vec![env];
+        for sibling in siblings.iter() {
+            path.push_back(sibling.to_bytes());
+        }
+        Ok(path)
+    }
+
+    /// Gets the internal tree nodes the contract persists next to the leaves
+    ///
+    /// Returns the tree frontier: for each level from the leaves up, the hash of the rightmost
+    /// node that is a left child (an empty-subtree hash where there is none). Together with
+    /// `get_commitments`, `get_merkle_depth` and `get_merkle_root` this is the whole stored tree,
+    /// so an off-chain `LeanIMT::from_storage_with_frontier` rebuilds it identically and can
+    /// check each new root without rehashing the leaves.
+    ///
+    /// # Returns
+    ///
+    /// * One node per tree level
+    /// * `Err(Error::NotInitialized)` if the tree is missing from storage
+    pub fn get_tree_nodes(env: &Env) -> Result<Vec<BytesN<32>>, Error> {
+        Ok(Self::load_tree_frontier(env)?.get_frontier())
+    }
+
+    /// Exports the current root with a proof that a subtree is part of it
+    ///
+    /// For bridges and other light clients that track the pool's root on another chain: a
+    /// relayer reads the root at a ledger, and a remote verifier that holds the subtree's root
+    /// (or a single leaf, at level 0) checks it against that root by hashing up the siblings as
+    /// described on `RootProof`. Empty leaves are zero, so empty subtrees hash the same as in
+    /// `get_merkle_proof`. This rehashes the tree from its leaves and is meant to be simulated,
+    /// not submitted.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `level` - The level of the subtree's root, from 0 for a leaf up to the tree depth
+    /// * `index` - The subtree's position within `level`
+    ///
+    /// # Returns
+    ///
+    /// * The root, the subtree's root and the `depth - level` siblings above it
+    /// * `Err(Error::InvalidLeafIndex)` if the tree has no node at `level` and `index`
+    /// * `Err(Error::NotInitialized)` if the tree is missing from storage
+    pub fn get_root_proof(env: &Env, level: u32, index: u32) -> Result<RootProof, Error> {
+        let tree = Self::load_tree(env)?;
+        let depth = tree.get_depth();
+        if level > depth || u64::from(index) >> (depth - level) != 0 {
+            return Err(Error::InvalidLeafIndex);
+        }
+
+        // `get_node` and `get_sibling` leave out empty leaves, which hash as zero
+        let empty = BytesN::from_array(env, &[0u8; 32]);
+        let node = tree.get_node(level, index).unwrap_or(empty.clone());
+        let mut siblings = vec![env];
+        for sibling_level in level..depth {
+            let position = index >> (sibling_level - level);
+            siblings.push_back(
+                tree.get_sibling(sibling_level, position)
+                    .unwrap_or(empty.clone()),
+            );
+        }
+
+        Ok(RootProof {
+            root: tree.get_root(),
+            leaf_count: tree.get_leaf_count(),
+            ledger: env.ledger().sequence(),
+            level,
+            index,
+            node,
+            siblings,
+        })
+    }
+
+    /// Checks whether a root is one of the recent roots of the commitment tree
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `root` - The root to look up
+    ///
+    /// # Returns
+    ///
+    /// * `true` if `root` is still in the root history ring
+    pub fn is_known_root(env: &Env, root: BytesN<32>) -> bool {
+        let size = Self::get_root_history_size(env);
+        for slot in 0..size {
+            let stored: Option<BytesN<32>> = Self::get_persistent(env, &(ROOTS_KEY, slot));
+            if stored == Some(root.clone()) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Checks whether a root is the current root of the commitment tree
+    ///
+    /// Lets a client compare the root it computed locally without fetching `get_merkle_root`
+    /// first. Use `is_known_root` to check whether an older root can still be proven against.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `root` - The root to compare
+    ///
+    /// # Returns
+    ///
+    /// * `true` if `root` is the tree's root, `false` otherwise and before the first deposit
+    pub fn is_current_root(env: &Env, root: BytesN<32>) -> bool {
+        // The constructor stores the empty tree's root, which no note can be proven against
+        if Self::get_commitment_count(env) == 0 {
+            return false;
+        }
+        let current: Option<BytesN<32>> = Self::get_persistent(env, &TREE_ROOT_KEY);
+        current == Some(root)
+    }
+
+    /// Gets the ledger sequence a root in the root history was recorded at
+    ///
+    /// # Returns
+    ///
+    /// * The sequence, or `None` if `root` is not in the history or was recorded before
+    ///   sequences were kept
+    fn root_recorded_at(env: &Env, root: &BytesN<32>) -> Option<u32> {
+        let slot = Self::root_slot(env, root)?;
+        Self::get_persistent(env, &(ROOT_LEDGER_KEY, slot))
+    }
+
+    /// Gets the number of leaves the tree had when a root in the root history was recorded
+    ///
+    /// Clients building a proof against a root other than the current one need the tree as it
+    /// was at that root: its first `leaf_count` leaves, which `get_commitments_paged` returns.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `root` - The root to look up
+    ///
+    /// # Returns
+    ///
+    /// * The leaf count, or `None` if `root` is not in the history or was recorded before leaf
+    ///   counts were kept
+    pub fn get_leaf_count_at_root(env: &Env, root: BytesN<32>) -> Option<u32> {
+        let slot = Self::root_slot(env, &root)?;
+        Self::get_persistent(env, &(ROOT_LEAF_COUNT_KEY, slot))
+    }
+
+    /// Finds the slot of a root in the root history ring
+    fn root_slot(env: &Env, root: &BytesN<32>) -> Option<u32> {
+        let size = Self::get_root_history_size(env);
+        (0..size).find(|slot| {
+            let stored: Option<BytesN<32>> = Self::get_persistent(env, &(ROOTS_KEY, *slot));
+            stored.as_ref() == Some(root)
+        })
+    }
+
+    /// Gets the recent roots of the commitment tree, oldest first
+    ///
+    /// The last entry is the current root. At most the configured root history size is returned.
+    pub fn get_root_history(env: &Env) -> Vec<BytesN<32>> {
+        let size = Self::get_root_history_size(env);
+        let index: u32 = Self::get_persistent(env, &ROOT_INDEX_KEY).unwrap_or(0);
+
+        let mut roots = vec![env];
+        for offset in 0..size {
+            let slot = (index + offset) % size;
+            if let Some(root) = Self::get_persistent(env, &(ROOTS_KEY, slot)) {
+                roots.push_back(root);
+            }
+        }
+        roots
+    }
+
+    /// Gets the version of the contract, see `CONTRACT_VERSION`
+    pub fn version(_env: &Env) -> u32 {
+        CONTRACT_VERSION
+    }
+
+    /// Gets the configured depth of the merkle tree
+    ///
+    /// This is the depth the tree was deployed with and the circuit proves against: every
+    /// merkle proof has this many siblings, however few leaves there are. See
+    /// `get_effective_depth` for the depth the current leaves actually need.
+    pub fn get_merkle_depth(env: &Env) -> u32 {
+        Self::get_persistent(env, &TREE_DEPTH_KEY).unwrap_or(0)
+    }
+
+    /// Gets the depth needed to hold the current leaves, `ceil(log2(commitment_count))`
+    ///
+    /// Above this level every sibling in a merkle proof is an empty-subtree hash, so clients
+    /// can trim them for circuits that support a variable depth. It never exceeds
+    /// `get_merkle_depth`, and is 0 for a tree with at most one leaf.
+    pub fn get_effective_depth(env: &Env) -> u32 {
+        let count = Self::get_commitment_count(env);
+        if count <= 1 {
+            0
+        } else {
+            u32::BITS - (count - 1).leading_zeros()
+        }
+    }
+
+    /// Gets the number of commitments (leaves) in the merkle tree
+    pub fn get_commitment_count(env: &Env) -> u32 {
+        Self::get_persistent(env, &LEAF_COUNT_KEY).unwrap_or(0)
+    }
+
+    /// Gets the leaf index the next deposited commitment will be stored at
+    ///
+    /// Leaves are appended in order, so this is the commitment count. It only holds until the
+    /// next deposit: if another deposit lands first, the commitment goes to a later index, so
+    /// clients should pre-build a proof path from it and then check it against the leaf index
+    /// `deposit` returns.
+    pub fn next_leaf_index(env: &Env) -> u32 {
+        Self::get_commitment_count(env)
+    }
+
+    /// Gets how many more commitments the tree can hold
+    ///
+    /// A tree of depth `d` holds `2^d` commitments, with `d` set at deployment. Front-ends can
+    /// use this to stop offering deposits before one fails with `Error::TreeAtCapacity`.
+    pub fn remaining_capacity(env: &Env) -> u32 {
+        let capacity = 1u32
+            .checked_shl(Self::get_merkle_depth(env))
+            .unwrap_or(u32::MAX);
+        capacity.saturating_sub(Self::get_commitment_count(env))
+    }
+
+    /// Checks whether the tree is full, so every further deposit would fail
+    pub fn is_full(env: &Env) -> bool {
+        Self::remaining_capacity(env) == 0
+    }
+
+    /// Gets all commitments (leaves) in the merkle tree
+    ///
+    /// Empty if a page of leaves is missing from storage; `get_commitments_paged` reports that
+    /// as `Error::NotInitialized`.
+    pub fn get_commitments(env: &Env) -> Vec<BytesN<32>> {
+        Self::read_leaves(env).unwrap_or(vec![env])
+    }
+
+    /// Gets a range of commitments, in leaf order
+    ///
+    /// Lets clients sync the tree incrementally instead of fetching every leaf with
+    /// `get_commitments`. Only the storage pages holding the range are read.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `start` - The leaf index of the first commitment
+    /// * `limit` - The most commitments to return, capped at 256
+    ///
+    /// # Returns
+    ///
+    /// * The commitments from `start` on, empty if `start` is at or past the commitment count
+    pub fn get_commitments_paged(
+        env: &Env,
+        start: u32,
+        limit: u32,
+    ) -> Result<Vec<BytesN<32>>, Error> {
+        let end = start
+            .saturating_add(limit.min(MAX_COMMITMENTS_PER_CALL))
+            .min(Self::get_commitment_count(env));
+
+        let mut commitments = vec![env];
+        let mut index = start;
+        while index < end {
+            let page = index / LEAF_PAGE_SIZE;
+            let page_start = page * LEAF_PAGE_SIZE;
+            let page_end = (page_start + LEAF_PAGE_SIZE).min(end);
+            let chunk = Self::read_leaf_page(env, page)?;
+            commitments.append(&chunk.slice(index - page_start..page_end - page_start));
+            index = page_end;
+        }
+        Ok(commitments)
+    }
+
+    /// Gets the commitment stored at a leaf index
+    ///
+    /// # Returns
+    ///
+    /// * The commitment, or `None` if no deposit has filled that leaf yet
+    pub fn get_leaf(env: &Env, index: u32) -> Option<BytesN<32>> {
+        let page: Vec<BytesN<32>> =
+            Self::get_persistent(env, &(TREE_LEAVES_KEY, index / LEAF_PAGE_SIZE))?;
+        page.get(index % LEAF_PAGE_SIZE)
+    }
+
+    /// Gets the encrypted note stored with the deposit at a leaf index
+    ///
+    /// Wallets recovering notes without a backup trial-decrypt these, or the `encrypted_note` of
+    /// each `DepositEvent`, with their key. The pool only stores the bytes.
+    ///
+    /// # Returns
+    ///
+    /// * The ciphertext, or `None` if the deposit came without one or the leaf is empty
+    pub fn get_encrypted_note(env: &Env, index: u32) -> Option<Bytes> {
+        Self::get_persistent(env, &(ENCRYPTED_NOTE_KEY, index))
+    }
+
+    /// Gets the leaf index of a deposited commitment
+    ///
+    /// Reads the `("cmt", commitment)` entry written on insertion, so a wallet can locate its
+    /// deposit without downloading every leaf.
+    ///
+    /// # Returns
+    ///
+    /// * The leaf index, or `None` if the commitment was never deposited
+    pub fn get_leaf_index(env: &Env, commitment: BytesN<32>) -> Option<u32> {
+        env.storage()
+            .persistent()
+            .get(&(COMMITMENT_KEY, commitment))
+    }
+
+    /// Records a spent nullifier
+    ///
+    /// Each nullifier gets its own persistent entry keyed by `("null", nullifier)` so
+    /// membership checks are O(1). A second entry keyed by `("null", index)` keeps the
+    /// insertion order for `get_nullifiers`.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `nullifier` - The nullifier hash to record
+    /// * `value` - The value the spend took off `get_unspent_value`
+    fn store_nullifier(env: &Env, nullifier: &BytesN<32>, value: i128) {
+        let count: u32 = Self::get_persistent(env, &NULL_COUNT_KEY).unwrap_or(0);
+
+        // The nullifier's position in the spent order and the value it spent, so
+        // `delete_nullifier` can find it and give the value back
+        Self::set_persistent(env, &(NULL_KEY, nullifier.clone()), &(count, value));
+        Self::set_persistent(env, &(NULL_KEY, count), nullifier);
+        Self::set_persistent(env, &NULL_COUNT_KEY, &(count + 1));
+    }
+
+    /// Checks whether a nullifier has already been spent
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `nullifier` - The nullifier hash to look up
+    ///
+    /// # Returns
+    ///
+    /// * `true` if a withdrawal with this nullifier has already succeeded
+    pub fn is_nullifier_used(env: &Env, nullifier: BytesN<32>) -> bool {
+        env.storage().persistent().has(&(NULL_KEY, nullifier))
+    }
+
+    /// Checks whether a note has already been withdrawn, for wallets reconstructing a balance
+    ///
+    /// Takes the note's nullifier hash, which the wallet derives from the note's secret
+    /// `nullifier` as `Poseidon(nullifier)` (Poseidon255 with one input, as in
+    /// `circuits/commitment.circom`). That is the `nullifierHash` a withdrawal of the note
+    /// publishes, so the note is spent exactly when the hash is in the used set. Equivalent to
+    /// `is_nullifier_used`.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `nullifier_hash` - `Poseidon(nullifier)` for the note
+    ///
+    /// # Returns
+    ///
+    /// * `true` if the note has been withdrawn (or refunded)
+    pub fn is_spent(env: &Env, nullifier_hash: BytesN<32>) -> bool {
+        Self::is_nullifier_used(env, nullifier_hash)
+    }
+
+    /// Gets the number of spent nullifiers, one per withdrawn or refunded note
+    pub fn get_nullifier_count(env: &Env) -> u32 {
+        Self::get_persistent(env, &NULL_COUNT_KEY).unwrap_or(0)
+    }
+
+    /// Checks whether the admins may remove spent nullifiers with `remove_nullifier`
+    ///
+    /// Set at deployment and never changed, so depositors can check it before trusting a pool.
+    pub fn is_nullifier_removal_allowed(env: &Env) -> bool {
+        env.storage()
+            .instance()
+            .get(&NULLIFIER_OPS_KEY)
+            .unwrap_or(false)
+    }
+
+    /// Deletes a spent nullifier, for testnets and for repairing the spent set after a migration
+    ///
+    /// **Dangerous**: the note the nullifier belongs to becomes spendable again, so removing the
+    /// nullifier of a withdrawal that really paid out lets the same note be withdrawn twice.
+    /// Only pools deployed with `allow_admin_nullifier_ops` allow it, which is off by default and
+    /// can't be turned on afterwards. Nothing is transferred, and the pool's totals keep the
+    /// original withdrawal.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `nullifier` - The nullifier hash to delete
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the nullifier is removed
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::NullifierOpsDisabled)` if the pool was deployed without
+    ///   `allow_admin_nullifier_ops`
+    /// * `Err(Error::UnknownNullifier)` if the nullifier hasn't been spent
+    ///
+    /// # Events
+    ///
+    /// * Publishes `NullifierRemoved` with the nullifier
+    pub fn remove_nullifier(
+        env: &Env,
+        caller: Address,
+        nullifier: BytesN<32>,
+    ) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::delete_nullifier(env, nullifier)
+    }
+
+    /// Deletes a nullifier, re-opening its note to spending, see `remove_nullifier`
+    ///
+    /// The last spent nullifier moves into the deleted one's place in the spent order, so this
+    /// touches the same few entries however many nullifiers the pool holds. The value the
+    /// nullifier spent is owed to the note again, so it goes back onto `get_unspent_value`.
+    fn delete_nullifier(env: &Env, nullifier: BytesN<32>) -> Result<(), Error> {
+        if !Self::is_nullifier_removal_allowed(env) {
+            return Err(Error::NullifierOpsDisabled);
+        }
+        let (index, value): (u32, i128) = Self::get_persistent(env, &(NULL_KEY, nullifier.clone()))
+            .ok_or(Error::UnknownNullifier)?;
+
+        let count = Self::get_nullifier_count(env);
+        let last = count.checked_sub(1).ok_or(Error::NotInitialized)?;
+        if index != last {
+            let moved: BytesN<32> =
+                Self::get_persistent(env, &(NULL_KEY, last)).ok_or(Error::NotInitialized)?;
+            let (_, moved_value): (u32, i128) =
+                Self::get_persistent(env, &(NULL_KEY, moved.clone()))
+                    .ok_or(Error::NotInitialized)?;
+            Self::set_persistent(env, &(NULL_KEY, index), &moved);
+            Self::set_persistent(env, &(NULL_KEY, moved), &(index, moved_value));
+        }
+        env.storage().persistent().remove(&(NULL_KEY, last));
+        env.storage()
+            .persistent()
+            .remove(&(NULL_KEY, nullifier.clone()));
+        Self::set_persistent(env, &NULL_COUNT_KEY, &last);
+        Self::add_to_total(env, &NOTE_VALUE_KEY, value);
+
+        NullifierRemoved { nullifier }.publish(env);
+        Ok(())
+    }
+
+    /// Gets all spent nullifiers in the order they were used
+    ///
+    /// `remove_nullifier` moves the last nullifier into the removed one's place, so after a
+    /// removal the order is only roughly chronological.
+    ///
+    /// This reads one storage entry per nullifier and is intended for off-chain
+    /// clients; use `is_nullifier_used` to check a single nullifier. Entries missing from
+    /// storage are left out.
+    pub fn get_nullifiers(env: &Env) -> Vec<BytesN<32>> {
+        let count: u32 = Self::get_persistent(env, &NULL_COUNT_KEY).unwrap_or(0);
+        let mut nullifiers = vec![env];
+        for index in 0..count {
+            if let Some(nullifier) = env.storage().persistent().get(&(NULL_KEY, index)) {
+                nullifiers.push_back(nullifier);
+            }
+        }
+        nullifiers
+    }
+
+    /// Gets the balance of the configured token held by the contract, or 0 if no token is set
+    pub fn get_balance(env: &Env) -> i128 {
+        match Self::get_token(env) {
+            Ok(token_address) => {
+                token::Client::new(env, &token_address).balance(&env.current_contract_address())
+            }
+            Err(_) => 0,
+        }
+    }
+
+    /// Gets the address of the token the pool holds
+    ///
+    /// # Returns
+    ///
+    /// * The token address
+    /// * `Err(Error::NotInitialized)` if no token is set, which only happens if the contract
+    ///   was not constructed or its storage is incomplete
+    pub fn get_token(env: &Env) -> Result<Address, Error> {
+        env.storage()
+            .instance()
+            .get(&TOKEN_KEY)
+            .ok_or(Error::NotInitialized)
+    }
+
+    /// Gets the number of decimals of the pool's token, as read from it when it was set
+    ///
+    /// Every amount the pool takes or reports, including the denomination, is in the token's
+    /// smallest unit.
+    pub fn get_token_decimals(env: &Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&TOKEN_DECIMALS_KEY)
+            .unwrap_or(7)
+    }
+
+    /// Reads a token's SEP-41 metadata, to check it is a token at all
+    ///
+    /// # Returns
+    ///
+    /// * The token's decimals
+    /// * `Err(Error::InvalidToken)` if `decimals` or `name` fails on the contract
+    fn read_token_decimals(env: &Env, token_address: &Address) -> Result<u32, Error> {
+        let token_client = token::TokenClient::new(env, token_address);
+        if !matches!(token_client.try_name(), Ok(Ok(_))) {
+            return Err(Error::InvalidToken);
+        }
+        match token_client.try_decimals() {
+            Ok(Ok(decimals)) => Ok(decimals),
+            _ => Err(Error::InvalidToken),
+        }
+    }
+
+    /// Replaces the pool's token, to recover from deploying with the wrong token address
+    ///
+    /// Only allowed before the first deposit and while the pool holds none of the old token,
+    /// so no note or balance can be stranded in the old asset.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `new_token` - The token the pool should hold
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the token is replaced
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::PoolNotEmpty)` if any commitment has been deposited or the pool holds a
+    ///   balance of the old token
+    /// * `Err(Error::InvalidToken)` if `new_token` doesn't answer the SEP-41 metadata calls
+    ///
+    /// # Events
+    ///
+    /// * Publishes `TokenChanged` with the old and new token
+    pub fn set_token(env: &Env, caller: Address, new_token: Address) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::replace_token(env, new_token)
+    }
+
+    /// Replaces the token once the pool is known to be empty, see `set_token`
+    fn replace_token(env: &Env, new_token: Address) -> Result<(), Error> {
+        if Self::get_commitment_count(env) > 0 || Self::get_balance(env) != 0 {
+            return Err(Error::PoolNotEmpty);
+        }
+
+        let decimals = Self::read_token_decimals(env, &new_token)?;
+        let old_token = Self::get_token(env)?;
+        env.storage().instance().set(&TOKEN_KEY, &new_token);
+        env.storage().instance().set(&TOKEN_DECIMALS_KEY, &decimals);
+        TokenChanged {
+            old_token,
+            new_token,
+        }
+        .publish(env);
+        Ok(())
+    }
+
+    /// Sends out a token that was sent to the pool by mistake
+    ///
+    /// The pool only ever holds its own token on behalf of depositors, so anything else sent
+    /// to its address would otherwise be stuck. The pool token can only be swept out of its
+    /// surplus: what the pool holds beyond `get_unspent_value`, receipts and unpaid fees, such
+    /// as tokens sent to the pool directly, which no withdrawal pays out, see `withdraw`.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `token` - The stray token
+    /// * `to` - The address the tokens are sent to
+    /// * `amount` - The amount to send
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the tokens are sent
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::CannotSweepPoolToken)` if `token` is the pool's token and `amount` is
+    ///   more than its surplus
+    /// * `Err(Error::InvalidAmount)` if `amount` is not positive
+    ///
+    /// # Events
+    ///
+    /// * Publishes `TokenSwept` with the token, recipient and amount
+    pub fn sweep(
+        env: &Env,
+        caller: Address,
+        token: Address,
+        to: Address,
+        amount: i128,
+    ) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::sweep_token(env, token, to, amount)
+    }
+
+    /// Sends out a stray token, or the pool token's surplus, see `sweep`
+    fn sweep_token(env: &Env, token: Address, to: Address, amount: i128) -> Result<(), Error> {
+        if token == Self::get_token(env)?
+            && amount
+                > Self::note_balance(env, &token::Client::new(env, &token))
+                    - Self::get_unspent_value(env)
+        {
+            return Err(Error::CannotSweepPoolToken);
+        }
+        if amount <= 0 {
+            return Err(Error::InvalidAmount);
+        }
+
+        Self::non_reentrant(env, || {
+            token::Client::new(env, &token).transfer(&env.current_contract_address(), &to, &amount);
+            Ok(())
+        })?;
+        TokenSwept { token, to, amount }.publish(env);
+        Ok(())
+    }
+
+    /// Validates that the caller can perform admin actions on its own
+    ///
+    /// That is only the case while the threshold is 1. With a higher threshold, every direct
+    /// admin method returns `Error::OnlyAdmin` and the change has to go through `propose`,
+    /// `approve` and `execute` instead; each of them has an `AdminAction`, and `transfer_admin`
+    /// is covered by `AdminAction::SetAdmins`.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address to validate as admin
+    ///
+    /// # Returns
+    ///
+    /// * `true` if the caller is an admin and the threshold is 1, `false` otherwise
+    fn is_admin(env: &Env, caller: &Address) -> bool {
+        Self::get_admin_threshold(env) <= 1 && Self::is_admin_member(env, caller)
+    }
+
+    /// Checks whether an address is in the admin set, regardless of the threshold
+    fn is_admin_member(env: &Env, caller: &Address) -> bool {
+        Self::get_admins(env).is_ok_and(|admins| admins.contains(caller))
+    }
+
+    /// Gets the admin set
+    ///
+    /// # Returns
+    ///
+    /// * The admins
+    /// * `Err(Error::NotInitialized)` if no admins are stored
+    pub fn get_admins(env: &Env) -> Result<Vec<Address>, Error> {
+        let admins = env.storage().instance().get(&ADMINS_KEY);
+        // The original contract stored a single admin; `rebuild_tree` moves it into the set
+        let legacy = || {
+            env.storage()
+                .instance()
+                .get(&LEGACY_ADMIN_KEY)
+                .map(|admin: Address| vec![env, admin])
+        };
+        admins.or_else(legacy).ok_or(Error::NotInitialized)
+    }
+
+    /// Gets the number of admin approvals a proposal needs before it can be executed
+    pub fn get_admin_threshold(env: &Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&ADMIN_THRESHOLD_KEY)
+            .unwrap_or(1)
+    }
+
+    /// Proposes a privileged change, counting as the proposer's approval
+    ///
+    /// The proposal is stored under its id, the SHA-256 of the XDR-encoded action and a
+    /// proposal counter, so the same action can be proposed again later. With a threshold of 1
+    /// the proposer can `execute` it right away.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The proposing admin (must be authenticated and in the admin set)
+    /// * `action` - The change to make once enough admins approve
+    ///
+    /// # Returns
+    ///
+    /// * The proposal id
+    /// * `Err(Error::OnlyAdmin)` if the caller is not in the admin set
+    ///
+    /// # Events
+    ///
+    /// * Publishes `ProposalCreated` with the id, proposer and action
+    pub fn propose(env: &Env, caller: Address, action: AdminAction) -> Result<BytesN<32>, Error> {
+        caller.require_auth();
+
+        if !Self::is_admin_member(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        let nonce: u64 = env
+            .storage()
+            .instance()
+            .get(&PROPOSAL_NONCE_KEY)
+            .unwrap_or(0);
+        env.storage()
+            .instance()
+            .set(&PROPOSAL_NONCE_KEY, &(nonce + 1));
+        let proposal_id: BytesN<32> = env
+            .crypto()
+            .sha256(&(action.clone(), nonce).to_xdr(env))
+            .into();
+
+        let proposal = Proposal {
+            action: action.clone(),
+            approvals: vec![env, caller.clone()],
+        };
+        Self::set_persistent(env, &(PROPOSAL_KEY, proposal_id.clone()), &proposal);
+
+        ProposalCreated {
+            proposal_id: proposal_id.clone(),
+            proposer: caller,
+            action,
+        }
+        .publish(env);
+        Ok(proposal_id)
+    }
+
+    /// Approves a pending proposal
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The approving admin (must be authenticated and in the admin set)
+    /// * `proposal_id` - The id returned by `propose`
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the approval is recorded
+    /// * `Err(Error::OnlyAdmin)` if the caller is not in the admin set
+    /// * `Err(Error::UnknownProposal)` if no proposal has that id
+    /// * `Err(Error::AlreadyApproved)` if the caller has already approved it
+    ///
+    /// # Events
+    ///
+    /// * Publishes `ProposalApproved` with the id and the approving admin
+    pub fn approve(env: &Env, caller: Address, proposal_id: BytesN<32>) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin_member(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        let key = (PROPOSAL_KEY, proposal_id.clone());
+        let mut proposal: Proposal =
+            Self::get_persistent(env, &key).ok_or(Error::UnknownProposal)?;
+        if proposal.approvals.contains(&caller) {
+            return Err(Error::AlreadyApproved);
+        }
+        proposal.approvals.push_back(caller.clone());
+        Self::set_persistent(env, &key, &proposal);
+
+        ProposalApproved {
+            proposal_id,
+            admin: caller,
+        }
+        .publish(env);
+        Ok(())
+    }
+
+    /// Executes a proposal that has enough approvals
+    ///
+    /// Only approvals from addresses that are still admins count, so removing an admin also
+    /// withdraws its pending approvals. The proposal is deleted when it executes.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The executing admin (must be authenticated and in the admin set)
+    /// * `proposal_id` - The id returned by `propose`
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the action has been applied
+    /// * `Err(Error::OnlyAdmin)` if the caller is not in the admin set
+    /// * `Err(Error::UnknownProposal)` if no proposal has that id
+    /// * `Err(Error::ThresholdNotMet)` if fewer admins than the threshold have approved it
+    /// * `Err(Error::InvalidConfiguration)` if a `SetAdmins` action has no admins, duplicate
+    ///   admins, or a threshold of 0 or above the number of admins
+    /// * The errors of the function the action corresponds to
+    ///
+    /// # Events
+    ///
+    /// * Publishes `ProposalExecuted` with the id, after the action's own events
+    pub fn execute(env: &Env, caller: Address, proposal_id: BytesN<32>) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin_member(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        let key = (PROPOSAL_KEY, proposal_id.clone());
+        let proposal: Proposal = Self::get_persistent(env, &key).ok_or(Error::UnknownProposal)?;
+        let approvals = proposal
+            .approvals
+            .iter()
+            .filter(|admin| Self::is_admin_member(env, admin))
+            .count() as u32;
+        if approvals < Self::get_admin_threshold(env) {
+            return Err(Error::ThresholdNotMet);
+        }
+        env.storage().persistent().remove(&key);
+
+        match proposal.action {
+            AdminAction::SetAssociationRoot(root) => Self::update_association_root(env, &root),
+            AdminAction::AddLabel(label) => {
+                Self::insert_label(env, label)?;
+            }
+            AdminAction::RemoveLabel(label) => {
+                Self::delete_label(env, label)?;
+            }
+            AdminAction::Pause => env.storage().instance().set(&PAUSED_KEY, &true),
+            AdminAction::Unpause => env.storage().instance().set(&PAUSED_KEY, &false),
+            AdminAction::SetVerificationKey(vk_bytes) => {
+                Self::stage_verification_key(env, vk_bytes)?
+            }
+            AdminAction::ApplyVerificationKey => Self::activate_verification_key(env)?,
+            AdminAction::CancelVerificationKey => env.storage().instance().remove(&PENDING_VK_KEY),
+            AdminAction::SetGroth16Verifier(verifier) => Self::stage_verifier(env, verifier),
+            AdminAction::ApplyGroth16Verifier => Self::activate_verifier(env)?,
+            AdminAction::CancelGroth16Verifier => {
+                env.storage().instance().remove(&PENDING_VERIFIER_KEY)
+            }
+            AdminAction::RebuildTree => Self::recompute_tree(env)?,
+            AdminAction::SetAdmins(admins, threshold) => {
+                Self::replace_admins(env, admins, threshold)?
+            }
+            AdminAction::AnnounceEmergency => Self::start_emergency(env)?,
+            AdminAction::CancelEmergency => Self::stop_emergency(env)?,
+            AdminAction::SetProofVersions(min_version, max_version) => {
+                Self::store_proof_versions(env, min_version, max_version)?
+            }
+            AdminAction::SetToken(new_token) => Self::replace_token(env, new_token)?,
+            AdminAction::SetDenomination(denomination) => {
+                Self::replace_denomination(env, denomination)?
+            }
+            AdminAction::SetDepositIssuer(issuer) => Self::store_deposit_issuer(env, issuer)?,
+            AdminAction::RemoveNullifier(nullifier) => Self::delete_nullifier(env, nullifier)?,
+            AdminAction::BumpTtl(ledgers) => Self::extend_pool_ttl(env, ledgers),
+            AdminAction::Upgrade(wasm_hash) => Self::upgrade_wasm(env, wasm_hash),
+            AdminAction::SetWithdrawDelay(ledgers) => {
+                env.storage().instance().set(&WITHDRAW_DELAY_KEY, &ledgers)
+            }
+            AdminAction::SetDepositFee(fee) => Self::store_deposit_fee(env, fee)?,
+            AdminAction::WithdrawFees(to) => {
+                Self::pay_fees(env, to)?;
+            }
+            AdminAction::AddWhitelistedRecipient(recipient) => {
+                Self::whitelist_recipient(env, recipient)
+            }
+            AdminAction::RemoveWhitelistedRecipient(recipient) => {
+                Self::unwhitelist_recipient(env, recipient)
+            }
+            AdminAction::Sweep(token, to, amount) => Self::sweep_token(env, token, to, amount)?,
+            AdminAction::SetMinReserve(amount) => Self::store_min_reserve(env, amount)?,
+            AdminAction::SetWithdrawVolumeLimit(max_volume, window_ledgers) => {
+                Self::store_withdraw_volume_limit(env, max_volume, window_ledgers)?
+            }
+        }
+
+        ProposalExecuted { proposal_id }.publish(env);
+        Ok(())
+    }
+
+    /// Gets a pending proposal
+    pub fn get_proposal(env: &Env, proposal_id: BytesN<32>) -> Option<Proposal> {
+        env.storage().persistent().get(&(PROPOSAL_KEY, proposal_id))
+    }
+
+    /// Replaces the admin set and threshold, see `AdminAction::SetAdmins`
+    fn replace_admins(env: &Env, admins: Vec<Address>, threshold: u32) -> Result<(), Error> {
+        for (index, admin) in admins.iter().enumerate() {
+            if admins.first_index_of(&admin) != Some(index as u32) {
+                return Err(Error::InvalidConfiguration);
+            }
+        }
+        if threshold == 0 || threshold > admins.len() {
+            return Err(Error::InvalidConfiguration);
+        }
+
+        env.storage().instance().set(&ADMINS_KEY, &admins);
+        env.storage()
+            .instance()
+            .set(&ADMIN_THRESHOLD_KEY, &threshold);
+        Ok(())
+    }
+
+    /// Sets the association set root for compliance verification
+    ///
+    /// This function allows the admin to update the association set root,
+    /// which is used to verify that withdrawals are associated with approved
+    /// subsets of deposits for compliance purposes.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
     /// * `association_root` - The new association set root (32-byte hash)
     ///
     /// # Returns
     ///
-    /// Returns a vector containing status messages:
-    /// * `["Association root set successfully"]` on successful update
-    /// * `["Only the admin can set association root"]` if the caller is not the admin
+    /// * `Ok(())` once the root is set
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    ///
+    /// # Security
+    ///
+    /// * Requires authentication from the caller
+    /// * Only the contract deployer (admin) can update association sets
+    pub fn set_association_root(
+        env: &Env,
+        caller: Address,
+        association_root: BytesN<32>,
+    ) -> Result<(), Error> {
+        caller.require_auth();
+
+        // Verify that the caller is actually the admin
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::update_association_root(env, &association_root);
+        Ok(())
+    }
+
+    /// Makes `root` the current association root and records it in the history ring
+    fn update_association_root(env: &Env, root: &BytesN<32>) {
+        env.storage().instance().set(&ASSOCIATION_ROOT_KEY, root);
+        env.storage()
+            .instance()
+            .set(&ASSOCIATION_ROOT_LEDGER_KEY, &env.ledger().sequence());
+        Self::record_association_root(env, root);
+    }
+
+    /// Builds the association tree from the admin-managed labels, in the order they were added
+    fn load_association_tree(env: &Env) -> LeanIMT {
+        let mut tree = LeanIMT::new(env, ASSOCIATION_TREE_DEPTH);
+        for label in Self::get_labels(env).iter() {
+            // The stored labels always fit, since `add_label` checks the capacity
+            tree.insert(label).unwrap();
+        }
+        tree
+    }
+
+    /// Adds a label to the on-chain association set
+    ///
+    /// The labels form a Poseidon tree of the circuit's association depth, so its root is what
+    /// proofs reference as `associationRoot`. Adding a label recomputes that root and makes it
+    /// the current association root, keeping the previous one valid through the association
+    /// root history. `set_association_root` can still replace the root wholesale for sets managed
+    /// off-chain; the next label change recomputes it from the stored labels again.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `label` - The label to approve, as a 32-byte big-endian field element
+    ///
+    /// # Returns
+    ///
+    /// * The new association root
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::LabelExists)` if the label is already in the set
+    /// * `Err(Error::TreeAtCapacity)` if the association tree is full
+    ///
+    /// # Events
+    ///
+    /// * Publishes `LabelAdded` with the label and the new root
+    pub fn add_label(env: &Env, caller: Address, label: BytesN<32>) -> Result<BytesN<32>, Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::insert_label(env, label)
+    }
+
+    /// Adds a label to the association tree, see `add_label`
+    fn insert_label(env: &Env, label: BytesN<32>) -> Result<BytesN<32>, Error> {
+        let mut labels = Self::get_labels(env);
+        if labels.contains(&label) {
+            return Err(Error::LabelExists);
+        }
+        let mut tree = Self::load_association_tree(env);
+        tree.insert(label.clone())
+            .map_err(|_| Error::TreeAtCapacity)?;
+        labels.push_back(label.clone());
+
+        let association_root = tree.get_root();
+        Self::set_persistent(env, &LABELS_KEY, &labels);
+        Self::update_association_root(env, &association_root);
+
+        LabelAdded {
+            label,
+            association_root: association_root.clone(),
+        }
+        .publish(env);
+        Ok(association_root)
+    }
+
+    /// Removes a label from the on-chain association set
+    ///
+    /// The tree is rebuilt from the remaining labels, so the labels added after the removed one
+    /// move down one index; fetch fresh proofs with `get_label_proof`. Proofs against the
+    /// previous root stay valid while it is in the association root history.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `label` - The label to remove
+    ///
+    /// # Returns
+    ///
+    /// * The new association root
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::UnknownLabel)` if the label is not in the set
+    ///
+    /// # Events
+    ///
+    /// * Publishes `LabelRemoved` with the label and the new root
+    pub fn remove_label(
+        env: &Env,
+        caller: Address,
+        label: BytesN<32>,
+    ) -> Result<BytesN<32>, Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::delete_label(env, label)
+    }
+
+    /// Removes a label from the association tree, see `remove_label`
+    fn delete_label(env: &Env, label: BytesN<32>) -> Result<BytesN<32>, Error> {
+        let mut labels = Self::get_labels(env);
+        let index = labels.first_index_of(&label).ok_or(Error::UnknownLabel)?;
+        labels.remove(index);
+        Self::set_persistent(env, &LABELS_KEY, &labels);
+
+        let association_root = Self::load_association_tree(env).get_root();
+        Self::update_association_root(env, &association_root);
+
+        LabelRemoved {
+            label,
+            association_root: association_root.clone(),
+        }
+        .publish(env);
+        Ok(association_root)
+    }
+
+    /// Gets the labels of the on-chain association set, in tree order
+    pub fn get_labels(env: &Env) -> Vec<BytesN<32>> {
+        Self::get_persistent(env, &LABELS_KEY).unwrap_or(vec![env])
+    }
+
+    /// Gets the merkle path of a label in the on-chain association set
+    ///
+    /// Returns the sibling hashes in the order expected by the circuit's `labelSiblings` input,
+    /// with `index` as its `labelIndex`.
+    ///
+    /// # Returns
+    ///
+    /// * One sibling per association tree level
+    /// * `Err(Error::InvalidLeafIndex)` if there is no label at `index`
+    pub fn get_label_proof(env: &Env, index: u32) -> Result<Vec<BytesN<32>>, Error> {
+        let tree = Self::load_association_tree(env);
+        let (siblings, _) = tree.generate_proof(index).ok_or(Error::InvalidLeafIndex)?;
+
+        let mut path = vec![env];
+        for sibling in siblings.iter() {
+            path.push_back(sibling.to_bytes());
+        }
+        Ok(path)
+    }
+
+    /// Checks whether a root is the current or a recent association set root
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `root` - The root to look up
+    ///
+    /// # Returns
+    ///
+    /// * `true` if `root` is the current association root or still in the association root
+    ///   history ring; the zero root is never known
+    pub fn is_known_association_root(env: &Env, root: BytesN<32>) -> bool {
+        if root == BytesN::from_array(env, &[0u8; 32]) {
+            return false;
+        }
+        if root == Self::get_association_root(env) {
+            return true;
+        }
+
+        let size = Self::get_root_history_size(env);
+        for slot in 0..size {
+            let stored: Option<BytesN<32>> =
+                Self::get_persistent(env, &(ASSOCIATION_ROOTS_KEY, slot));
+            if stored == Some(root.clone()) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Gets the recent association roots, oldest first
+    ///
+    /// The last entry is the current root. At most the configured root history size is returned.
+    /// Every change is recorded, whether through `set_association_root`, a label change or an
+    /// executed proposal, so this is the on-chain audit trail of the association set.
+    pub fn get_association_root_history(env: &Env) -> Vec<BytesN<32>> {
+        let size = Self::get_root_history_size(env);
+        let index: u32 = Self::get_persistent(env, &ASSOCIATION_ROOT_INDEX_KEY).unwrap_or(0);
+
+        let mut roots = vec![env];
+        for offset in 0..size {
+            let slot = (index + offset) % size;
+            if let Some(root) = Self::get_persistent(env, &(ASSOCIATION_ROOTS_KEY, slot)) {
+                roots.push_back(root);
+            }
+        }
+        roots
+    }
+
+    /// Gets the ledger sequence the association root last changed at
+    ///
+    /// # Returns
+    ///
+    /// * The sequence, or `None` if no association root has been set
+    pub fn get_association_root_updated_at(env: &Env) -> Option<u32> {
+        env.storage().instance().get(&ASSOCIATION_ROOT_LEDGER_KEY)
+    }
+
+    /// Gets the current association set root
+    ///
+    /// # Returns
+    ///
+    /// * The current association set root, or zero bytes if not set
+    pub fn get_association_root(env: &Env) -> BytesN<32> {
+        env.storage()
+            .instance()
+            .get(&ASSOCIATION_ROOT_KEY)
+            .unwrap_or(BytesN::from_array(&env, &[0u8; 32]))
+    }
+
+    /// Checks whether withdrawals to `recipient` may skip the association check
+    pub fn is_whitelisted_recipient(env: &Env, recipient: Address) -> bool {
+        Self::get_persistent(env, &(WHITELIST_KEY, recipient)).unwrap_or(false)
+    }
+
+    /// Lets withdrawals to `recipient` reference any association root
+    ///
+    /// For compliant flows the association set doesn't cover, such as withdrawing back to a
+    /// KYC'd exchange deposit address. A withdrawal to a whitelisted recipient still needs a
+    /// valid proof against a known state root, and only skips the association check when its
+    /// `context` signal binds it to that recipient; proofs without a context are checked as
+    /// usual. Each skipped check publishes `AssociationBypassed`.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `recipient` - The address to whitelist
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the recipient is whitelisted, including when it already was
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    ///
+    /// # Events
+    ///
+    /// * Publishes `RecipientWhitelisted` with the recipient
+    pub fn add_whitelisted_recipient(
+        env: &Env,
+        caller: Address,
+        recipient: Address,
+    ) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::whitelist_recipient(env, recipient);
+        Ok(())
+    }
+
+    /// Whitelists a recipient, see `add_whitelisted_recipient`
+    fn whitelist_recipient(env: &Env, recipient: Address) {
+        Self::set_persistent(env, &(WHITELIST_KEY, recipient.clone()), &true);
+        RecipientWhitelisted { recipient }.publish(env);
+    }
+
+    /// Takes `recipient` off the whitelist, so its withdrawals need an approved association
+    /// root again
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `recipient` - The address to remove
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the recipient is removed, including when it wasn't whitelisted
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    ///
+    /// # Events
+    ///
+    /// * Publishes `RecipientUnwhitelisted` with the recipient
+    pub fn remove_whitelisted_recipient(
+        env: &Env,
+        caller: Address,
+        recipient: Address,
+    ) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::unwhitelist_recipient(env, recipient);
+        Ok(())
+    }
+
+    /// Removes a recipient from the whitelist, see `remove_whitelisted_recipient`
+    fn unwhitelist_recipient(env: &Env, recipient: Address) {
+        env.storage()
+            .persistent()
+            .remove(&(WHITELIST_KEY, recipient.clone()));
+        RecipientUnwhitelisted { recipient }.publish(env);
+    }
+
+    /// Checks if an association set is currently configured
+    ///
+    /// # Returns
+    ///
+    /// * `true` if an association set root is configured, `false` otherwise
+    pub fn has_association_set(env: &Env) -> bool {
+        let association_root = Self::get_association_root(env);
+        let zero_root = BytesN::from_array(&env, &[0u8; 32]);
+        association_root != zero_root
+    }
+
+    /// Gets the first admin, which is the contract deployer until the admins are changed
+    ///
+    /// Panics with `Error::NotInitialized` if no admins are stored, as there is no address to
+    /// fall back to; `get_admins` returns that error instead.
+    pub fn get_admin(env: &Env) -> Address {
+        match Self::get_admins(env).map(|admins| admins.first()) {
+            Ok(Some(admin)) => admin,
+            _ => panic_with_error!(env, Error::NotInitialized),
+        }
+    }
+
+    /// Gets the serialized verification key withdrawal proofs are checked against
+    ///
+    /// Wallets can compare it with the key of the circuit they prove with, or use it to verify
+    /// proofs off-chain before submitting them.
+    ///
+    /// # Returns
+    ///
+    /// * The `vk_bytes` the contract was deployed with
+    /// * `Err(Error::NotInitialized)` if no verification key is stored
+    pub fn get_verification_key(env: &Env) -> Result<Bytes, Error> {
+        env.storage()
+            .instance()
+            .get(&VK_KEY)
+            .ok_or(Error::NotInitialized)
+    }
+
+    /// Transfers the caller's admin role to a new address
+    ///
+    /// Only available while the threshold is 1; otherwise change the admins through an
+    /// `AdminAction::SetAdmins` proposal.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `new_admin` - The address that becomes the admin
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the admin has been replaced
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    ///
+    /// # Events
+    ///
+    /// * Publishes `AdminTransferred` with the old and new admin
+    pub fn transfer_admin(env: &Env, caller: Address, new_admin: Address) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        let mut admins = Self::get_admins(env)?;
+        // `is_admin` just checked that the caller is in the set
+        let index = admins.first_index_of(&caller).unwrap();
+        admins.set(index, new_admin.clone());
+        env.storage().instance().set(&ADMINS_KEY, &admins);
+        AdminTransferred {
+            old_admin: caller,
+            new_admin,
+        }
+        .publish(env);
+        Ok(())
+    }
+
+    /// Extends the TTL of the pool's state, for pools that see no deposits or withdrawals
+    ///
+    /// Deposits and withdrawals keep the instance and the tree alive on their own; an idle pool
+    /// needs this, or a restore, before its state archives and the funds become unreachable.
+    /// Besides the instance it extends the persistent entries every deposit and withdrawal
+    /// reads: the tree's root, frontier, depth and leaf count, the page the next leaf goes
+    /// into, and the root history ring. Commitment and nullifier entries are not extended.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `ledgers` - The TTL to extend the entries to, capped at the network's maximum
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the entries have been extended
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    pub fn bump_ttl(env: &Env, caller: Address, ledgers: u32) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::extend_pool_ttl(env, ledgers);
+        Ok(())
+    }
+
+    /// Extends the instance and the tree's persistent entries to `ledgers`, see `bump_ttl`
+    fn extend_pool_ttl(env: &Env, ledgers: u32) {
+        let extend_to = ledgers.min(env.storage().max_ttl());
+        env.storage().instance().extend_ttl(extend_to, extend_to);
+
+        let extend = |key: Val| {
+            if env.storage().persistent().has(&key) {
+                env.storage()
+                    .persistent()
+                    .extend_ttl(&key, extend_to, extend_to);
+            }
+        };
+        extend(TREE_ROOT_KEY.into_val(env));
+        extend(TREE_FRONTIER_KEY.into_val(env));
+        extend(TREE_DEPTH_KEY.into_val(env));
+        extend(LEAF_COUNT_KEY.into_val(env));
+        let page = Self::get_commitment_count(env) / LEAF_PAGE_SIZE;
+        extend((TREE_LEAVES_KEY, page).into_val(env));
+        extend(ROOT_INDEX_KEY.into_val(env));
+        for slot in 0..Self::get_root_history_size(env) {
+            extend((ROOTS_KEY, slot).into_val(env));
+            extend((ROOT_LEDGER_KEY, slot).into_val(env));
+            extend((ROOT_LEAF_COUNT_KEY, slot).into_val(env));
+        }
+    }
+
+    /// Pauses deposits and withdrawals
+    ///
+    /// Intended for incident response: while paused, `deposit` and `withdraw` return
+    /// `Error::Paused`. Read-only methods and admin functions keep working.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the contract is paused
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    pub fn pause(env: &Env, caller: Address) -> Result<(), Error> {
+        Self::set_paused(env, caller, true)
+    }
+
+    /// Resumes deposits and withdrawals after a `pause`
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the contract is unpaused
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    pub fn unpause(env: &Env, caller: Address) -> Result<(), Error> {
+        Self::set_paused(env, caller, false)
+    }
+
+    /// Checks whether deposits and withdrawals are currently paused
+    pub fn is_paused(env: &Env) -> bool {
+        env.storage().instance().get(&PAUSED_KEY).unwrap_or(false)
+    }
+
+    fn set_paused(env: &Env, caller: Address, paused: bool) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        env.storage().instance().set(&PAUSED_KEY, &paused);
+        Ok(())
+    }
+
+    /// Proposes a new verification key, for when the withdrawal circuit is upgraded
+    ///
+    /// The key only takes effect once `apply_verification_key` is called at least `VK_TIMELOCK`
+    /// seconds later. Whoever controls the verification key decides which proofs are valid, so a
+    /// backdoored key would let its author drain the pool. The delay gives depositors time to
+    /// inspect the proposed key (its hash is published) and withdraw if they don't trust it.
+    /// A new proposal replaces a pending one and restarts the delay.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `vk_bytes` - The serialized verification key of the upgraded circuit
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the key is pending
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::InvalidVerificationKey)` if `vk_bytes` isn't a serialized verification key
+    ///
+    /// # Events
+    ///
+    /// * Publishes `VerificationKeyProposed` with the key's SHA-256 hash and the time it can apply
+    pub fn set_verification_key(env: &Env, caller: Address, vk_bytes: Bytes) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::stage_verification_key(env, vk_bytes)
+    }
+
+    /// Makes `vk_bytes` the pending verification key, see `set_verification_key`
+    fn stage_verification_key(env: &Env, vk_bytes: Bytes) -> Result<(), Error> {
+        if !Self::is_well_formed_vk(&vk_bytes) {
+            return Err(Error::InvalidVerificationKey);
+        }
+
+        let effective_at = env.ledger().timestamp() + VK_TIMELOCK;
+        let vk_hash: BytesN<32> = env.crypto().sha256(&vk_bytes).into();
+        env.storage()
+            .instance()
+            .set(&PENDING_VK_KEY, &(vk_bytes, effective_at));
+
+        VerificationKeyProposed {
+            vk_hash,
+            effective_at,
+        }
+        .publish(env);
+        Ok(())
+    }
+
+    /// Replaces the verification key with the pending one once its timelock has passed
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the new key is in use
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::NoPendingVerificationKey)` if no key has been proposed
+    /// * `Err(Error::TimelockActive)` if the pending key can't apply yet
+    ///
+    /// # Events
+    ///
+    /// * Publishes `VerificationKeyUpdated` with the new key's SHA-256 hash
+    pub fn apply_verification_key(env: &Env, caller: Address) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::activate_verification_key(env)
+    }
+
+    /// Replaces the verification key with the pending one, see `apply_verification_key`
+    fn activate_verification_key(env: &Env) -> Result<(), Error> {
+        let (vk_bytes, effective_at) =
+            Self::get_pending_verification_key(env).ok_or(Error::NoPendingVerificationKey)?;
+        if env.ledger().timestamp() < effective_at {
+            return Err(Error::TimelockActive);
+        }
+
+        let vk_hash: BytesN<32> = env.crypto().sha256(&vk_bytes).into();
+        env.storage().instance().set(&VK_KEY, &vk_bytes);
+        env.storage().instance().remove(&PENDING_VK_KEY);
+
+        VerificationKeyUpdated { vk_hash }.publish(env);
+        Ok(())
+    }
+
+    /// Discards the pending verification key
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once no key is pending
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    pub fn cancel_verification_key(env: &Env, caller: Address) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        env.storage().instance().remove(&PENDING_VK_KEY);
+        Ok(())
+    }
+
+    /// Gets the address of the contract withdrawal proofs are verified with
+    ///
+    /// # Returns
+    ///
+    /// * The verifier address
+    /// * `Err(Error::NotInitialized)` if no verifier is stored
+    pub fn get_groth16_verifier(env: &Env) -> Result<Address, Error> {
+        env.storage()
+            .instance()
+            .get(&GROTH16_VERIFIER_KEY)
+            .ok_or(Error::NotInitialized)
+    }
+
+    /// Gets the proposed verifier and the ledger timestamp from which it can apply
+    pub fn get_pending_groth16_verifier(env: &Env) -> Option<(Address, u64)> {
+        env.storage().instance().get(&PENDING_VERIFIER_KEY)
+    }
+
+    /// Proposes a new Groth16 verifier contract, so the verifier can be upgraded on its own
+    ///
+    /// The verifier decides which proofs are valid just like the verification key does, so a
+    /// change goes through the same `VK_TIMELOCK`: it only takes effect once
+    /// `apply_groth16_verifier` is called at least that long after the proposal. A new proposal
+    /// replaces a pending one and restarts the delay.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `verifier` - The address of the new verifier contract
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the verifier is pending
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
     ///
-    /// # Security
+    /// # Events
     ///
-    /// * Requires authentication from the caller
-    /// * Only the contract deployer (admin) can update association sets
-    pub fn set_association_root(
+    /// * Publishes `VerifierProposed` with the verifier and the time it can apply
+    pub fn set_groth16_verifier(
         env: &Env,
         caller: Address,
-        association_root: BytesN<32>,
-    ) -> Vec<String> {
+        verifier: Address,
+    ) -> Result<(), Error> {
         caller.require_auth();
 
-        // Verify that the caller is actually the admin
         if !Self::is_admin(env, &caller) {
-            return vec![env, String::from_str(env, ERROR_ONLY_ADMIN)];
+            return Err(Error::OnlyAdmin);
         }
 
+        Self::stage_verifier(env, verifier);
+        Ok(())
+    }
+
+    /// Makes `verifier` the pending verifier, see `set_groth16_verifier`
+    fn stage_verifier(env: &Env, verifier: Address) {
+        let effective_at = env.ledger().timestamp() + VK_TIMELOCK;
         env.storage()
             .instance()
-            .set(&ASSOCIATION_ROOT_KEY, &association_root);
-        vec![env, String::from_str(env, SUCCESS_ASSOCIATION_ROOT_SET)]
+            .set(&PENDING_VERIFIER_KEY, &(verifier.clone(), effective_at));
+
+        VerifierProposed {
+            verifier,
+            effective_at,
+        }
+        .publish(env);
     }
 
-    /// Gets the current association set root
+    /// Replaces the verifier with the pending one once its timelock has passed
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
     ///
     /// # Returns
     ///
-    /// * The current association set root, or zero bytes if not set
-    pub fn get_association_root(env: &Env) -> BytesN<32> {
+    /// * `Ok(())` once the new verifier is in use
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::NoPendingVerifier)` if no verifier has been proposed
+    /// * `Err(Error::TimelockActive)` if the pending verifier can't apply yet
+    ///
+    /// # Events
+    ///
+    /// * Publishes `VerifierUpdated` with the old and new verifier
+    pub fn apply_groth16_verifier(env: &Env, caller: Address) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::activate_verifier(env)
+    }
+
+    /// Replaces the verifier with the pending one, see `apply_groth16_verifier`
+    fn activate_verifier(env: &Env) -> Result<(), Error> {
+        let (new_verifier, effective_at) =
+            Self::get_pending_groth16_verifier(env).ok_or(Error::NoPendingVerifier)?;
+        if env.ledger().timestamp() < effective_at {
+            return Err(Error::TimelockActive);
+        }
+
+        let old_verifier = Self::get_groth16_verifier(env)?;
         env.storage()
             .instance()
-            .get(&ASSOCIATION_ROOT_KEY)
-            .unwrap_or(BytesN::from_array(&env, &[0u8; 32]))
+            .set(&GROTH16_VERIFIER_KEY, &new_verifier);
+        env.storage().instance().remove(&PENDING_VERIFIER_KEY);
+
+        VerifierUpdated {
+            old_verifier,
+            new_verifier,
+        }
+        .publish(env);
+        Ok(())
     }
 
-    /// Checks if an association set is currently configured
+    /// Discards the pending verifier
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
     ///
     /// # Returns
     ///
-    /// * `true` if an association set root is configured, `false` otherwise
-    pub fn has_association_set(env: &Env) -> bool {
-        let association_root = Self::get_association_root(env);
-        let zero_root = BytesN::from_array(&env, &[0u8; 32]);
-        association_root != zero_root
+    /// * `Ok(())` once no verifier is pending
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    pub fn cancel_groth16_verifier(env: &Env, caller: Address) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        env.storage().instance().remove(&PENDING_VERIFIER_KEY);
+        Ok(())
+    }
+
+    /// Rebuilds the commitment tree from its stored leaves, for migrating older deployments
+    ///
+    /// Pools deployed from the original contract stored a root that doesn't match a Poseidon
+    /// tree over their leaves, so no proof could be made against it. On such a pool's storage,
+    /// this first moves its storage to the current layout, see
+    /// `migrate_legacy_storage`, then rehashes every leaf into a fresh `LeanIMT` of the stored
+    /// depth, overwrites the root and frontier and records the new root in the root history.
+    /// It hashes every leaf, so run it before the pool grows large.
+    ///
+    /// The same applies after an upgrade to a contract with different Poseidon parameters:
+    /// deposits fail with `Error::PoseidonParamsMismatch` until the rebuild rehashes the tree and
+    /// stores the new `get_poseidon_params_id`. It can run once for the migration from SHA256
+    /// and once more each time the parameters change.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the tree has been rebuilt
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::TreeAlreadyRebuilt)` if the tree was already rebuilt with the current
+    ///   Poseidon parameters
+    ///
+    /// # Events
+    ///
+    /// * Publishes `TreeRebuilt` with the replaced root, the new root and the number of leaves
+    pub fn rebuild_tree(env: &Env, caller: Address) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::recompute_tree(env)
     }
 
-    /// Gets the admin address (the contract deployer)
+    /// Rebuilds the commitment tree, see `rebuild_tree`
+    fn recompute_tree(env: &Env) -> Result<(), Error> {
+        if env.storage().instance().has(&TREE_REBUILT_KEY)
+            && Self::get_poseidon_params_id(env) == poseidon::PARAMS_ID
+        {
+            return Err(Error::TreeAlreadyRebuilt);
+        }
+        env.storage().instance().set(&TREE_REBUILT_KEY, &true);
+        Self::migrate_legacy_storage(env)?;
+
+        let old_root = Self::get_merkle_root(env);
+        let depth = Self::get_merkle_depth(env);
+        let leaves = Self::read_leaves(env)?;
+        let mut tree = LeanIMT::new(env, depth);
+        for leaf in leaves.iter() {
+            tree.insert(leaf).map_err(|_| Error::TreeAtCapacity)?;
+        }
+
+        let new_root = tree.get_root();
+        Self::record_root(env, &new_root, leaves.len());
+        Self::set_persistent(env, &TREE_ROOT_KEY, &new_root);
+        Self::set_persistent(env, &TREE_FRONTIER_KEY, &tree.get_frontier());
+        env.storage()
+            .instance()
+            .set(&POSEIDON_PARAMS_KEY, &poseidon::PARAMS_ID);
+
+        TreeRebuilt {
+            old_root,
+            new_root,
+            leaf_count: leaves.len(),
+        }
+        .publish(env);
+        Ok(())
+    }
+
+    /// Moves the instance storage of a pool deployed from the original contract to the current
+    /// layout, once
+    ///
+    /// The original contract kept everything in instance storage: the single admin under
+    /// `"admin"`, every leaf in one `"leaves"` vector next to `"depth"` and a SHA256 `"root"`,
+    /// and every spent nullifier in one `"null"` vector, with `FIXED_AMOUNT` as its only
+    /// denomination. This writes the admin set, the leaf pages, commitment index and leaf
+    /// count, the spent nullifiers, the token's decimals and the value of the unspent notes,
+    /// then removes the old entries, so it does nothing for any other pool. The old root is
+    /// kept until `recompute_tree` replaces it.
     ///
     /// # Returns
     ///
-    /// * The address of the admin (contract deployer)
-    pub fn get_admin(env: &Env) -> Address {
-        env.storage().instance().get(&ADMIN_KEY).unwrap()
+    /// * `Ok(())` once the storage is in the current layout
+    /// * `Err(Error::InvalidToken)` if the token no longer answers `decimals`
+    fn migrate_legacy_storage(env: &Env) -> Result<(), Error> {
+        let instance = env.storage().instance();
+        let Some(leaves) = instance.get::<_, Vec<BytesN<32>>>(&TREE_LEAVES_KEY) else {
+            return Ok(());
+        };
+        let nullifiers: Vec<BytesN<32>> = instance.get(&NULL_KEY).unwrap_or(vec![env]);
+        let depth: u32 = instance.get(&TREE_DEPTH_KEY).unwrap_or(TREE_DEPTH);
+        let root: Option<BytesN<32>> = instance.get(&TREE_ROOT_KEY);
+
+        if let Some(admin) = instance.get::<_, Address>(&LEGACY_ADMIN_KEY) {
+            instance.set(&ADMINS_KEY, &vec![env, admin]);
+            instance.remove(&LEGACY_ADMIN_KEY);
+        }
+        let decimals = Self::read_token_decimals(env, &Self::get_token(env)?)?;
+        instance.set(&TOKEN_DECIMALS_KEY, &decimals);
+
+        for page in 0..leaves.len().div_ceil(LEAF_PAGE_SIZE) {
+            let start = page * LEAF_PAGE_SIZE;
+            let end = (start + LEAF_PAGE_SIZE).min(leaves.len());
+            Self::set_persistent(env, &(TREE_LEAVES_KEY, page), &leaves.slice(start..end));
+        }
+        for (index, leaf) in leaves.iter().enumerate() {
+            Self::set_persistent(env, &(COMMITMENT_KEY, leaf), &(index as u32));
+        }
+        Self::set_persistent(env, &LEAF_COUNT_KEY, &leaves.len());
+        Self::set_persistent(env, &TREE_DEPTH_KEY, &depth);
+        if let Some(root) = root {
+            Self::set_persistent(env, &TREE_ROOT_KEY, &root);
+        }
+        for nullifier in nullifiers.iter() {
+            Self::store_nullifier(env, &nullifier, FIXED_AMOUNT);
+        }
+        let unspent = leaves.len().saturating_sub(nullifiers.len()) as i128;
+        instance.set(&NOTE_VALUE_KEY, &(unspent * FIXED_AMOUNT));
+
+        instance.remove(&TREE_LEAVES_KEY);
+        instance.remove(&TREE_DEPTH_KEY);
+        instance.remove(&TREE_ROOT_KEY);
+        instance.remove(&NULL_KEY);
+        Ok(())
+    }
+
+    /// Replaces the pool's code with the uploaded wasm `wasm_hash`, keeping its storage
+    ///
+    /// The new code takes over from the next invocation. A pool upgraded from the original
+    /// contract's storage layout needs `rebuild_tree` next, which migrates it.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `wasm_hash` - The hash of the new contract wasm, which must already be uploaded
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the code has been replaced
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    ///
+    /// # Events
+    ///
+    /// * Publishes `ContractUpgraded` with the new wasm hash
+    pub fn upgrade(env: &Env, caller: Address, wasm_hash: BytesN<32>) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::upgrade_wasm(env, wasm_hash);
+        Ok(())
+    }
+
+    /// Replaces the pool's code, see `upgrade`
+    fn upgrade_wasm(env: &Env, wasm_hash: BytesN<32>) {
+        env.deployer()
+            .update_current_contract_wasm(wasm_hash.clone());
+        ContractUpgraded { wasm_hash }.publish(env);
+    }
+
+    /// Announces that emergency refunds will open after `EMERGENCY_TIMELOCK` (30 days)
+    ///
+    /// This is the exit for when withdrawal proofs can no longer be produced or verified, for
+    /// example because the verifier or the circuit's proving key is broken. Once active, each
+    /// depositor can reclaim a note by revealing its preimage through `request_refund` and
+    /// `emergency_refund`, which gives up the note's privacy. The long delay and the public
+    /// announcement leave depositors time to withdraw privately instead, or to object, and the
+    /// admin can't censor refunds once they are open: an active emergency can't be cancelled.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the announcement is recorded
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::TimelockActive)` if an emergency has already been announced
+    ///
+    /// # Events
+    ///
+    /// * Publishes `EmergencyAnnounced` with the timestamp refunds open at
+    pub fn announce_emergency(env: &Env, caller: Address) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::start_emergency(env)
+    }
+
+    /// Records the emergency announcement, see `announce_emergency`
+    fn start_emergency(env: &Env) -> Result<(), Error> {
+        if env.storage().instance().has(&EMERGENCY_KEY) {
+            return Err(Error::TimelockActive);
+        }
+
+        let active_at = env.ledger().timestamp() + EMERGENCY_TIMELOCK;
+        env.storage().instance().set(&EMERGENCY_KEY, &active_at);
+        EmergencyAnnounced { active_at }.publish(env);
+        Ok(())
+    }
+
+    /// Withdraws an emergency announcement before refunds open
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the announcement is removed
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::EmergencyNotActive)` if no emergency has been announced
+    /// * `Err(Error::TimelockActive)` if refunds are already open
+    ///
+    /// # Events
+    ///
+    /// * Publishes `EmergencyCancelled`
+    pub fn cancel_emergency(env: &Env, caller: Address) -> Result<(), Error> {
+        caller.require_auth();
+
+        if !Self::is_admin(env, &caller) {
+            return Err(Error::OnlyAdmin);
+        }
+
+        Self::stop_emergency(env)
+    }
+
+    /// Removes the emergency announcement, see `cancel_emergency`
+    fn stop_emergency(env: &Env) -> Result<(), Error> {
+        let active_at = Self::get_emergency_active_at(env).ok_or(Error::EmergencyNotActive)?;
+        if env.ledger().timestamp() >= active_at {
+            return Err(Error::TimelockActive);
+        }
+
+        env.storage().instance().remove(&EMERGENCY_KEY);
+        EmergencyCancelled {}.publish(env);
+        Ok(())
+    }
+
+    /// Gets the timestamp emergency refunds open at, if an emergency has been announced
+    pub fn get_emergency_active_at(env: &Env) -> Option<u64> {
+        env.storage().instance().get(&EMERGENCY_KEY)
+    }
+
+    /// Checks whether emergency refunds are open
+    pub fn is_emergency_active(env: &Env) -> bool {
+        Self::get_emergency_active_at(env)
+            .is_some_and(|active_at| env.ledger().timestamp() >= active_at)
+    }
+
+    /// Computes the claim hash `request_refund` takes for a note and recipient
+    ///
+    /// The claim is the SHA-256 of the XDR-encoded `(commitment, to)` tuple. It can be computed
+    /// off-chain; this only exists so clients don't have to reimplement the encoding.
+    pub fn get_refund_claim(env: &Env, commitment: BytesN<32>, to: Address) -> BytesN<32> {
+        env.crypto().sha256(&(commitment, to).to_xdr(env)).into()
+    }
+
+    /// Registers a refund claim, the first step of an emergency refund
+    ///
+    /// Revealing a note's preimage in `emergency_refund` makes it public, so anyone watching
+    /// pending transactions could copy it into their own refund to a different address. To prevent
+    /// that, the recipient is committed first: the claim hides the commitment and recipient until
+    /// the reveal, and the reveal is only accepted for a claim at least `REFUND_CLAIM_DELAY`
+    /// (1 day) old. A copied preimage would need its own claim and the same wait, by which time
+    /// the note has been refunded.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `claim` - `get_refund_claim(commitment, to)` for the note and its recipient
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once the claim is recorded; re-registering a claim keeps its original time
+    /// * `Err(Error::EmergencyNotActive)` if emergency refunds aren't open
+    pub fn request_refund(env: &Env, claim: BytesN<32>) -> Result<(), Error> {
+        if !Self::is_emergency_active(env) {
+            return Err(Error::EmergencyNotActive);
+        }
+
+        let key = (REFUND_CLAIM_KEY, claim);
+        if !env.storage().persistent().has(&key) {
+            Self::set_persistent(env, &key, &env.ledger().timestamp());
+        }
+        Ok(())
+    }
+
+    /// Refunds a note by revealing its preimage, the second step of an emergency refund
+    ///
+    /// The note is verified without a proof: the contract recomputes
+    /// `commitment = Poseidon(Poseidon(value, label), Poseidon(nullifier, secret))` exactly as
+    /// `circuits/commitment.circom` does, and requires it to be a deposited leaf. Knowing the
+    /// preimage is what a withdrawal proof proves, so this accepts the same people. The note's
+    /// nullifier hash `Poseidon(nullifier)` is then marked as spent, so a note can't be both
+    /// refunded and withdrawn, and a note that was already withdrawn can't be refunded.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `to` - The recipient committed to in the claim
+    /// * `value` - The note's value
+    /// * `label` - The note's label
+    /// * `nullifier` - The note's nullifier (not its hash)
+    /// * `secret` - The note's secret
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` once `value` has been transferred to `to`
+    /// * `Err(Error::EmergencyNotActive)` if emergency refunds aren't open
+    /// * `Err(Error::InvalidAmount)` if `value` is not positive
+    /// * `Err(Error::AmountMismatch)` if the pool has a denomination and `value` isn't it
+    /// * `Err(Error::UnknownCommitment)` if the preimage doesn't hash to a deposited commitment
+    /// * `Err(Error::RefundNotReady)` if there is no claim for the commitment and `to`, or it is
+    ///   less than `REFUND_CLAIM_DELAY` old
+    /// * `Err(Error::NullifierUsed)` if the note was already withdrawn or refunded
+    /// * `Err(Error::InsufficientBalance)` if the pool can't cover `value`
+    ///
+    /// # Events
+    ///
+    /// * Publishes `EmergencyRefund` with the commitment, nullifier hash, recipient and amount
+    pub fn emergency_refund(
+        env: &Env,
+        to: Address,
+        value: i128,
+        label: BytesN<32>,
+        nullifier: BytesN<32>,
+        secret: BytesN<32>,
+    ) -> Result<(), Error> {
+        Self::non_reentrant(env, || {
+            if !Self::is_emergency_active(env) {
+                return Err(Error::EmergencyNotActive);
+            }
+            if value <= 0 {
+                return Err(Error::InvalidAmount);
+            }
+            // Like a withdrawal, a note in a fixed pool is only worth the denomination, whatever
+            // its commitment claims. A variable pool computed the commitment from what was paid
+            if Self::get_denomination(env).is_some_and(|fixed| value != fixed) {
+                return Err(Error::AmountMismatch);
+            }
+
+            let commitment = Self::note_commitment(env, value, &label, &nullifier, &secret);
+            if Self::get_leaf_index(env, commitment.clone()).is_none() {
+                return Err(Error::UnknownCommitment);
+            }
+
+            let claim = Self::get_refund_claim(env, commitment.clone(), to.clone());
+            let claimed_at: u64 = Self::get_persistent(env, &(REFUND_CLAIM_KEY, claim))
+                .ok_or(Error::RefundNotReady)?;
+            if env.ledger().timestamp() < claimed_at + REFUND_CLAIM_DELAY {
+                return Err(Error::RefundNotReady);
+            }
+
+            let nullifier_hash = Self::note_nullifier_hash(env, &nullifier);
+            if Self::is_nullifier_used(env, nullifier_hash.clone()) {
+                return Err(Error::NullifierUsed);
+            }
+
+            let token_address = Self::get_token(env)?;
+            let token_client = token::Client::new(env, &token_address);
+            if Self::note_balance(env, &token_client) < value {
+                return Err(Error::InsufficientBalance);
+            }
+
+            Self::store_nullifier(env, &nullifier_hash, value);
+            Self::add_to_total(env, &NOTE_VALUE_KEY, -value);
+            token_client.transfer(&env.current_contract_address(), &to, &value);
+            Self::add_to_total(env, &TOTAL_WITHDRAWN_KEY, value);
+
+            EmergencyRefund {
+                commitment,
+                nullifier: nullifier_hash,
+                to,
+                amount: value,
+            }
+            .publish(env);
+            Ok(())
+        })
+    }
+
+    /// Computes a note's commitment, for clients to check their own implementation against
+    ///
+    /// The commitment is `Poseidon(Poseidon(value, label), Poseidon(nullifier, secret))`, with
+    /// the Poseidon255 parameters the tree and `circuits/commitment.circom` use. This is a pure
+    /// helper: it reads no state and only echoes a hash of its inputs. Call it through
+    /// simulation, since a submitted transaction would publish the note's secrets.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `value` - The note's value, in the token's smallest unit
+    /// * `label` - The note's label, as a 32-byte big-endian field element
+    /// * `nullifier` - The note's nullifier, as a 32-byte big-endian field element
+    /// * `secret` - The note's secret, as a 32-byte big-endian field element
+    ///
+    /// # Returns
+    ///
+    /// * The commitment `deposit` expects for the note
+    /// * `Err(Error::InvalidAmount)` if `value` is negative
+    /// * `Err(Error::InvalidFieldElement)` if `label`, `nullifier` or `secret` is not below the
+    ///   scalar field modulus
+    pub fn compute_commitment(
+        env: &Env,
+        value: i128,
+        label: BytesN<32>,
+        nullifier: BytesN<32>,
+        secret: BytesN<32>,
+    ) -> Result<BytesN<32>, Error> {
+        if value < 0 {
+            return Err(Error::InvalidAmount);
+        }
+        if [&label, &nullifier, &secret]
+            .iter()
+            .any(|input| input.to_array() >= SNARK_SCALAR_FIELD)
+        {
+            return Err(Error::InvalidFieldElement);
+        }
+
+        let commitment = Self::note_commitment(env, value, &label, &nullifier, &secret);
+        Ok(commitment)
+    }
+
+    /// Computes the nullifier hash a withdrawal of a note publishes, for clients to check their
+    /// own implementation against
+    ///
+    /// The hash is `Poseidon(nullifier)`, the circuit's `nullifierHash` public signal and the key
+    /// `is_nullifier_used` looks up. Like `compute_commitment`, this is a pure helper to call
+    /// through simulation; a submitted transaction would publish the nullifier.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `nullifier` - The note's nullifier, as a 32-byte big-endian field element
+    ///
+    /// # Returns
+    ///
+    /// * The nullifier hash
+    /// * `Err(Error::InvalidFieldElement)` if `nullifier` is not below the scalar field modulus
+    pub fn compute_nullifier_hash(env: &Env, nullifier: BytesN<32>) -> Result<BytesN<32>, Error> {
+        if nullifier.to_array() >= SNARK_SCALAR_FIELD {
+            return Err(Error::InvalidFieldElement);
+        }
+
+        Ok(Self::note_nullifier_hash(env, &nullifier))
+    }
+
+    /// Checks that the deployment is wired up correctly by verifying a known-good proof
+    ///
+    /// Hashes a known note with Poseidon and runs the proof and public signals bundled in
+    /// `self_test.rs` through the stored verification key and `groth16_verifier` contract, the
+    /// same path `withdraw` verifies proofs with. The proof's state root is not one of this
+    /// pool's roots, so only the hashing and Groth16 checks run. Nothing is written, so this can
+    /// be simulated right after deployment and by monitoring.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    ///
+    /// # Returns
+    ///
+    /// * `true` if the hash and proof check out, `false` if the verification key, verifier or
+    ///   hashing is misconfigured or missing
+    pub fn self_test(env: &Env) -> bool {
+        let field = |byte: u8| {
+            let mut bytes = [0u8; 32];
+            bytes[31] = byte;
+            BytesN::from_array(env, &bytes)
+        };
+        let commitment = Self::note_commitment(env, 1_000_000_000, &field(1), &field(2), &field(3));
+        if commitment.to_array() != self_test::SAMPLE_COMMITMENT {
+            return false;
+        }
+
+        Self::verify_groth16(
+            env,
+            &Bytes::from_array(env, &self_test::PROOF),
+            &Bytes::from_array(env, &self_test::PUB_SIGNALS),
+        )
+        .unwrap_or(false)
+    }
+
+    /// Checks whether this pool was built with the `demo` feature, which skips the Groth16
+    /// check and so pays out any withdrawal with well-formed public signals
+    ///
+    /// The pool factory refuses to deploy such a build. For a pool deployed any other way,
+    /// depositors should check this is `false` before trusting it with funds.
+    pub fn is_demo_build() -> bool {
+        cfg!(feature = "demo")
     }
-}
\ No newline at end of file
+
+    /// Computes a note's commitment the way `circuits/commitment.circom` does
+    fn note_commitment(
+        env: &Env,
+        value: i128,
+        label: &BytesN<32>,
+        nullifier: &BytesN<32>,
+        secret: &BytesN<32>,
+    ) -> BytesN<32> {
+        let precommitment = Poseidon255::new(env, 3).hash_two(
+            env,
+            &Fr::from_bytes(nullifier.clone()),
+            &Fr::from_bytes(secret.clone()),
+        );
+        Self::bound_commitment(env, value, label, &precommitment)
+    }
+
+    /// Computes `Poseidon(Poseidon(value, label), precommitment)`, the commitment of a note of
+    /// `value` whose nullifier and secret hash to `precommitment`
+    fn bound_commitment(
+        env: &Env,
+        value: i128,
+        label: &BytesN<32>,
+        precommitment: &Fr,
+    ) -> BytesN<32> {
+        let mut value_bytes = [0u8; 32];
+        value_bytes[16..].copy_from_slice(&value.to_be_bytes());
+        let value = Fr::from_bytes(BytesN::from_array(env, &value_bytes));
+
+        let poseidon = Poseidon255::new(env, 3);
+        let value_label = poseidon.hash_two(env, &value, &Fr::from_bytes(label.clone()));
+        poseidon
+            .hash_two(env, &value_label, precommitment)
+            .to_bytes()
+    }
+
+    /// Computes the nullifier hash a withdrawal of the note would publish
+    fn note_nullifier_hash(env: &Env, nullifier: &BytesN<32>) -> BytesN<32> {
+        Poseidon255::new(env, 2)
+            .hash(env, &Fr::from_bytes(nullifier.clone()))
+            .to_bytes()
+    }
+
+    /// Checks that `vk_bytes` has the layout `VerificationKey::from_bytes` expects:
+    /// alpha (G1), beta, gamma, delta (G2), a 4-byte IC count and that many G1 points
+    fn is_well_formed_vk(vk_bytes: &Bytes) -> bool {
+        const HEADER_LEN: u32 = 96 + 3 * 192 + 4;
+        if vk_bytes.len() < HEADER_LEN {
+            return false;
+        }
+        let mut ic_len_bytes = [0u8; 4];
+        vk_bytes
+            .slice(HEADER_LEN - 4..HEADER_LEN)
+            .copy_into_slice(&mut ic_len_bytes);
+        let ic_len = u32::from_be_bytes(ic_len_bytes);
+
+        // A circuit always has at least the constant IC point
+        let expected_len = ic_len
+            .checked_mul(96)
+            .and_then(|len| len.checked_add(HEADER_LEN));
+        ic_len > 0 && expected_len == Some(vk_bytes.len())
+    }
+
+    /// Gets the proposed verification key and the ledger timestamp from which it can apply
+    pub fn get_pending_verification_key(env: &Env) -> Option<(Bytes, u64)> {
+        env.storage().instance().get(&PENDING_VK_KEY)
+    }
+}
@@ -0,0 +1,176 @@
+//! Layout of the withdrawal circuit's public signals
+//!
+//! snarkjs serializes the public signals as a 4-byte big-endian count followed by one 32-byte
+//! big-endian field element per signal, outputs first:
+//! `[nullifierHash, withdrawnValue, stateRoot, associationRoot]`. Newer circuits append
+//! `context`, which binds the recipient, relayer and fee into the proof, then
+//! `changeCommitment` for partial withdrawals and then `expiryLedger`, the last ledger the proof
+//! can be submitted in.
+//!
+//! Every signal must be in canonical form: big-endian and below the scalar field modulus, which
+//! is how snarkjs and `generate_inputs` write them. The verifier reduces signals modulo the field,
+//! so `x` and `x + r` verify the same proof; accepting both would give one note two nullifier
+//! hashes. Endianness can't be detected from the bytes alone, so little-endian signals aren't
+//! rejected here but fail verification, as they are a different value to the proof.
+//!
+//! Signals may be prefixed with a version byte naming the circuit that produced them, so the
+//! layout can change in later circuits. Unversioned signals are version 0: their first byte is
+//! the top byte of the count, which is always 0.
+
+use soroban_sdk::{contracttype, Bytes, BytesN, Env};
+
+use crate::{Error, SNARK_SCALAR_FIELD};
+
+/// Signals without a version byte, as snarkjs serializes them
+pub const LEGACY_VERSION: u32 = 0;
+/// A version byte followed by the legacy layout
+pub const CURRENT_VERSION: u32 = 1;
+
+const COUNT_LEN: u32 = 4;
+const SIGNAL_LEN: u32 = 32;
+const BASE_SIGNAL_COUNT: u32 = 4; // Circuits without a `context` signal
+const CONTEXT_SIGNAL_COUNT: u32 = 5;
+const CHANGE_SIGNAL_COUNT: u32 = 6;
+const EXPIRY_SIGNAL_COUNT: u32 = 7;
+
+const NULLIFIER_HASH_INDEX: u32 = 0;
+const WITHDRAWN_VALUE_INDEX: u32 = 1;
+const STATE_ROOT_INDEX: u32 = 2;
+const ASSOCIATION_ROOT_INDEX: u32 = 3;
+const CONTEXT_INDEX: u32 = 4;
+const CHANGE_COMMITMENT_INDEX: u32 = 5;
+const EXPIRY_LEDGER_INDEX: u32 = 6;
+
+/// The public signals of a withdrawal proof, as 32-byte big-endian field elements
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WithdrawalSignals {
+    pub nullifier_hash: BytesN<32>,
+    pub withdrawn_value: BytesN<32>,
+    pub state_root: BytesN<32>,
+    pub association_root: BytesN<32>,
+    pub context: Option<BytesN<32>>,
+    pub change_commitment: Option<BytesN<32>>,
+    /// The last ledger the proof is accepted in, `None` if it has no expiry
+    pub expiry_ledger: Option<u32>,
+}
+
+impl WithdrawalSignals {
+    /// Reads `withdrawn_value` as a token amount
+    ///
+    /// # Returns
+    ///
+    /// * The value if it is positive and fits in an `i128`, `None` otherwise
+    pub fn withdrawn_amount(&self) -> Option<i128> {
+        let value_bytes = self.withdrawn_value.to_array();
+
+        // Anything above 128 bits can't be a token amount
+        if value_bytes[..16].iter().any(|byte| *byte != 0) {
+            return None;
+        }
+        let mut low = [0u8; 16];
+        low.copy_from_slice(&value_bytes[16..]);
+        let value = i128::try_from(u128::from_be_bytes(low)).ok()?;
+
+        if value > 0 {
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
+
+/// Splits the version byte off serialized public signals
+///
+/// # Returns
+///
+/// * The version and the signals in the layout of that version, which for versions 0 and 1 is
+///   what `parse_public_signals` and the verifier take
+/// * `Err(Error::MalformedPublicSignals)` if `bytes` is empty
+pub fn split_version(bytes: &Bytes) -> Result<(u32, Bytes), Error> {
+    let version = bytes.first().ok_or(Error::MalformedPublicSignals)? as u32;
+    if version == LEGACY_VERSION {
+        Ok((version, bytes.clone()))
+    } else {
+        Ok((version, bytes.slice(1..)))
+    }
+}
+
+/// Deserializes and validates serialized public signals
+///
+/// # Returns
+///
+/// * The named signals
+/// * `Err(Error::MalformedPublicSignals)` if the count prefix isn't a supported number of
+///   signals or the length doesn't match it exactly, a signal isn't below the scalar field
+///   modulus, or `expiryLedger` isn't a ledger sequence
+pub fn parse_public_signals(env: &Env, bytes: &Bytes) -> Result<WithdrawalSignals, Error> {
+    if bytes.len() < COUNT_LEN {
+        return Err(Error::MalformedPublicSignals);
+    }
+    let mut count_bytes = [0u8; 4];
+    bytes.slice(..COUNT_LEN).copy_into_slice(&mut count_bytes);
+    let count = u32::from_be_bytes(count_bytes);
+
+    if !(BASE_SIGNAL_COUNT..=EXPIRY_SIGNAL_COUNT).contains(&count) {
+        return Err(Error::MalformedPublicSignals);
+    }
+    if bytes.len() != COUNT_LEN + count * SIGNAL_LEN {
+        return Err(Error::MalformedPublicSignals);
+    }
+
+    let value = |index: u32| {
+        let start = COUNT_LEN + index * SIGNAL_LEN;
+        let mut value = [0u8; 32];
+        bytes
+            .slice(start..start + SIGNAL_LEN)
+            .copy_into_slice(&mut value);
+        value
+    };
+    // Checked for every signal, since the verifier would reduce any of them
+    if (0..count).any(|index| value(index) >= SNARK_SCALAR_FIELD) {
+        return Err(Error::MalformedPublicSignals);
+    }
+    let signal = |index: u32| BytesN::from_array(env, &value(index));
+
+    Ok(WithdrawalSignals {
+        nullifier_hash: signal(NULLIFIER_HASH_INDEX),
+        withdrawn_value: signal(WITHDRAWN_VALUE_INDEX),
+        state_root: signal(STATE_ROOT_INDEX),
+        association_root: signal(ASSOCIATION_ROOT_INDEX),
+        context: if count >= CONTEXT_SIGNAL_COUNT {
+            Some(signal(CONTEXT_INDEX))
+        } else {
+            None
+        },
+        // A zero change commitment means the whole note is withdrawn
+        change_commitment: if count >= CHANGE_SIGNAL_COUNT {
+            Some(signal(CHANGE_COMMITMENT_INDEX))
+                .filter(|commitment| commitment.to_array() != [0u8; 32])
+        } else {
+            None
+        },
+        // A zero expiry means the proof never expires
+        expiry_ledger: if count == EXPIRY_SIGNAL_COUNT {
+            Some(parse_ledger(&signal(EXPIRY_LEDGER_INDEX))?).filter(|ledger| *ledger != 0)
+        } else {
+            None
+        },
+    })
+}
+
+/// Reads a signal as a ledger sequence
+///
+/// # Returns
+///
+/// * The sequence
+/// * `Err(Error::MalformedPublicSignals)` if the signal doesn't fit in a `u32`
+fn parse_ledger(signal: &BytesN<32>) -> Result<u32, Error> {
+    let bytes = signal.to_array();
+    if bytes[..28].iter().any(|byte| *byte != 0) {
+        return Err(Error::MalformedPublicSignals);
+    }
+    let mut ledger = [0u8; 4];
+    ledger.copy_from_slice(&bytes[28..]);
+    Ok(u32::from_be_bytes(ledger))
+}
@@ -0,0 +1,53 @@
+//! A known-good withdrawal proof for checking a deployment's configuration
+//!
+//! The proof and public signals are the ones `test.rs` builds with `init_proof` and
+//! `init_pub_signals`, serialized the way `withdraw` takes them. They only verify against the
+//! verification key the pool ships with, through a working `groth16_verifier` contract.
+
+/// The serialized Groth16 proof
+pub const PROOF: [u8; 384] = [
+    0x01, 0x61, 0x58, 0xf1, 0x2c, 0x45, 0x42, 0xc8, 0x41, 0x73, 0x09, 0xf2, 0x7d, 0x3c, 0xf0, 0x48,
+    0x4a, 0xff, 0x24, 0x7e, 0xb6, 0xdb, 0x4b, 0x9f, 0xed, 0x5b, 0x2f, 0xae, 0x74, 0x8f, 0xbe, 0xf2,
+    0xd3, 0xa4, 0x10, 0xce, 0xc6, 0x8b, 0x5c, 0xae, 0xef, 0xe5, 0xda, 0xb3, 0x07, 0xb5, 0xf7, 0xdb,
+    0x06, 0xc8, 0x14, 0x52, 0x0e, 0x92, 0x21, 0x30, 0x0f, 0x56, 0x38, 0xd8, 0x08, 0xf4, 0x33, 0x03,
+    0x17, 0x8f, 0x93, 0x96, 0x23, 0x35, 0x72, 0xdb, 0x07, 0xd3, 0x9a, 0x2d, 0xfe, 0x86, 0xf9, 0x7e,
+    0x16, 0x16, 0xbb, 0xcc, 0x1a, 0x86, 0x7b, 0x90, 0x2a, 0x68, 0xce, 0xac, 0xd2, 0xf9, 0x7e, 0x06,
+    0x13, 0x42, 0x46, 0x2e, 0x30, 0x12, 0xe4, 0xaf, 0x35, 0x1c, 0xd9, 0xc9, 0x2a, 0xfd, 0xf3, 0xc5,
+    0xfe, 0x04, 0xe1, 0x4a, 0x34, 0x67, 0x3a, 0xcf, 0xd7, 0x71, 0x11, 0x84, 0xc5, 0xb4, 0xa6, 0x36,
+    0x43, 0x9f, 0x2f, 0xda, 0xae, 0x90, 0xa0, 0xe0, 0x7e, 0x6a, 0x7f, 0x81, 0xd6, 0x3b, 0x78, 0x1a,
+    0x16, 0x36, 0x07, 0xf6, 0x94, 0x75, 0x3e, 0x8d, 0x59, 0x3d, 0x04, 0xe7, 0xb5, 0x78, 0xd7, 0x4b,
+    0x23, 0x73, 0xbf, 0x3c, 0xd2, 0x42, 0x57, 0x8e, 0xaa, 0xb5, 0x6d, 0x40, 0x3b, 0xf4, 0x76, 0x10,
+    0xb5, 0x3d, 0x90, 0x09, 0xe5, 0xc7, 0xcb, 0x58, 0x68, 0x49, 0x12, 0x70, 0x45, 0x3a, 0xb3, 0x78,
+    0x0f, 0xfa, 0xf5, 0x49, 0xe6, 0x3c, 0xfc, 0x43, 0xc9, 0xee, 0xd7, 0x8d, 0x7f, 0xcb, 0x76, 0x32,
+    0x82, 0x58, 0x35, 0x26, 0x37, 0x7e, 0x13, 0x30, 0x05, 0x62, 0x61, 0x84, 0x59, 0xd5, 0x5a, 0x59,
+    0xab, 0x04, 0x8c, 0x0a, 0x3a, 0x61, 0x1a, 0x2c, 0xfc, 0x13, 0x98, 0x88, 0xd6, 0x9f, 0x49, 0x7f,
+    0x14, 0xf1, 0xc9, 0x8d, 0xc8, 0x05, 0x9d, 0x5c, 0x71, 0x2a, 0x26, 0xe6, 0x5b, 0x8b, 0xc9, 0x6b,
+    0xa8, 0xcc, 0x5c, 0xa9, 0x69, 0xaf, 0xdb, 0xdd, 0x18, 0x7a, 0x6e, 0xbc, 0x95, 0x65, 0x74, 0xd3,
+    0x69, 0x90, 0xdc, 0x5c, 0x36, 0xdc, 0xb0, 0x44, 0x2d, 0x75, 0xa5, 0xac, 0xd0, 0xbc, 0x95, 0x32,
+    0x02, 0xe1, 0x01, 0x4a, 0xf1, 0xc8, 0x47, 0x82, 0xb7, 0xcc, 0x0e, 0xcf, 0xeb, 0x67, 0xa3, 0xa5,
+    0xc5, 0xb8, 0x2f, 0xcd, 0xbd, 0x64, 0x35, 0xa0, 0xc3, 0xf1, 0x72, 0x63, 0xfb, 0x65, 0xbf, 0x7a,
+    0x01, 0x8b, 0xeb, 0xd5, 0x63, 0xc9, 0xa6, 0xa8, 0x13, 0x72, 0x54, 0x82, 0xfd, 0xfe, 0x0d, 0x6b,
+    0x0d, 0xab, 0x8b, 0x3e, 0x32, 0xb6, 0x09, 0xb6, 0x40, 0xb6, 0x02, 0x93, 0x2e, 0xdc, 0xfe, 0xf1,
+    0x8a, 0xd8, 0x06, 0xe9, 0x73, 0x5c, 0x28, 0x11, 0xb0, 0x47, 0xaf, 0x98, 0x3b, 0x7a, 0x69, 0x8a,
+    0xf4, 0xa2, 0x15, 0xcd, 0xbe, 0x14, 0x8b, 0x24, 0x91, 0x02, 0x9f, 0xf3, 0x25, 0xbd, 0x11, 0xe2,
+];
+
+/// The serialized public signals of `PROOF`:
+/// `[nullifierHash, withdrawnValue, stateRoot, associationRoot]`
+pub const PUB_SIGNALS: [u8; 132] = [
+    0x00, 0x00, 0x00, 0x04, 0x4b, 0xb7, 0x52, 0xd5, 0x98, 0x01, 0xe5, 0x86, 0xfa, 0x43, 0xaa, 0x95,
+    0x2a, 0xb3, 0xc2, 0x31, 0xf8, 0xca, 0x8c, 0x9b, 0x86, 0x3b, 0x82, 0xca, 0x9a, 0xbd, 0x32, 0x00,
+    0xa7, 0xe5, 0xa2, 0x2d, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x3b, 0x9a, 0xca, 0x00, 0x4a, 0x4f, 0x11, 0x8a, 0x44, 0xf7, 0xd0, 0x73, 0xe8, 0x8b, 0xae, 0x54,
+    0xe6, 0x20, 0x6d, 0xd2, 0x48, 0x97, 0xa5, 0x43, 0x48, 0xb9, 0xf2, 0xc8, 0xeb, 0x70, 0x7d, 0x26,
+    0xf4, 0x4e, 0x32, 0xbc, 0x5d, 0x58, 0x26, 0xf9, 0xc9, 0x18, 0x7b, 0xdb, 0x21, 0x3f, 0x01, 0xde,
+    0xd6, 0xd2, 0x30, 0xe9, 0xf1, 0xab, 0x65, 0x3b, 0x5b, 0xee, 0x60, 0x36, 0x50, 0x4e, 0x82, 0xbc,
+    0x07, 0x16, 0xba, 0xa2,
+];
+
+/// `compute_commitment(1_000_000_000, 1, 2, 3)`, the note of `circuits/build/sample_input.json`
+pub const SAMPLE_COMMITMENT: [u8; 32] = [
+    0x4d, 0x00, 0xa9, 0xaa, 0x48, 0x11, 0x74, 0xab, 0xeb, 0x36, 0x0c, 0xef, 0x72, 0xcf, 0x86, 0x91,
+    0x17, 0x69, 0x92, 0xa1, 0xc9, 0xe0, 0xc2, 0x92, 0x53, 0xeb, 0xc4, 0xda, 0x67, 0xc4, 0x32, 0xbd,
+];
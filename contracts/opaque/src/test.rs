@@ -1,12 +1,17 @@
 #![cfg(test)]
 use super::*;
+extern crate std;
 use ark_bls12_381::{Fq, Fq2};
 use ark_serialize::CanonicalSerialize;
 use core::str::FromStr;
-use soroban_sdk::testutils::Address as TestAddress;
+use ed25519_dalek::{Signer, SigningKey};
+use soroban_sdk::testutils::{
+    storage::{Instance as _, Persistent as _},
+    Address as TestAddress, Events as _, Ledger as _,
+};
 use soroban_sdk::{
     crypto::bls12_381::{Fr, G1Affine, G2Affine, G1_SERIALIZED_SIZE, G2_SERIALIZED_SIZE},
-    symbol_short, vec, Address, Bytes, BytesN, Env, String, U256,
+    symbol_short, vec, Address, Bytes, BytesN, Env, Event, String, Symbol, U256,
 };
 
 // Mock token contract for testing
@@ -46,6 +51,20 @@ impl MockToken {
         env.storage().instance().get(&id).unwrap_or(0)
     }
 
+    pub fn decimals(env: &Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&symbol_short!("decimal"))
+            .unwrap()
+    }
+
+    pub fn name(env: &Env) -> String {
+        env.storage()
+            .instance()
+            .get(&symbol_short!("name"))
+            .unwrap()
+    }
+
     pub fn transfer(env: &Env, from: Address, to: Address, amount: i128) {
         from.require_auth();
 
@@ -60,6 +79,76 @@ impl MockToken {
             .set(&from, &(from_balance - amount));
         env.storage().instance().set(&to, &(to_balance + amount));
     }
+
+    pub fn approve(env: &Env, from: Address, spender: Address, amount: i128) {
+        from.require_auth();
+
+        env.storage()
+            .instance()
+            .set(&(symbol_short!("allow"), from, spender), &amount);
+    }
+
+    pub fn allowance(env: &Env, from: Address, spender: Address) -> i128 {
+        env.storage()
+            .instance()
+            .get(&(symbol_short!("allow"), from, spender))
+            .unwrap_or(0)
+    }
+
+    pub fn transfer_from(env: &Env, spender: Address, from: Address, to: Address, amount: i128) {
+        spender.require_auth();
+
+        let allowance = Self::allowance(env, from.clone(), spender.clone());
+        if allowance < amount {
+            panic!("insufficient allowance");
+        }
+        env.storage().instance().set(
+            &(symbol_short!("allow"), from.clone(), spender),
+            &(allowance - amount),
+        );
+
+        let from_balance = env.storage().instance().get(&from).unwrap_or(0);
+        if from_balance < amount {
+            panic!("insufficient balance");
+        }
+
+        let to_balance = env.storage().instance().get(&to).unwrap_or(0);
+        env.storage()
+            .instance()
+            .set(&from, &(from_balance - amount));
+        env.storage().instance().set(&to, &(to_balance + amount));
+    }
+}
+
+// Mock verifier that accepts every proof, for signal layouts the bundled proof doesn't cover
+#[contract]
+pub struct MockVerifier;
+
+#[contractimpl]
+impl MockVerifier {
+    pub fn verify_proof(
+        _env: &Env,
+        _vk: groth16_verifier_wasm::VerificationKey,
+        _proof: groth16_verifier_wasm::Proof,
+        _pub_signals: Vec<U256>,
+    ) -> bool {
+        true
+    }
+}
+
+// Mock swap target for `withdraw_to_contract`, recording the amount received per memo
+#[contract]
+pub struct MockReceiver;
+
+#[contractimpl]
+impl MockReceiver {
+    pub fn receive(env: &Env, memo: Symbol, amount: i128) {
+        env.storage().instance().set(&memo, &amount);
+    }
+
+    pub fn received(env: &Env, memo: Symbol) -> i128 {
+        env.storage().instance().get(&memo).unwrap_or(0)
+    }
 }
 
 fn g1_from_coords(env: &Env, x: &str, y: &str) -> G1Affine {
@@ -218,6 +307,64 @@ fn init_pub_signals(env: &Env) -> Bytes {
     return pub_signals.to_bytes(env);
 }
 
+// The nodes a `DepositEvent` reports for the last of `leaves`, from an independent tree
+fn insertion_path(env: &Env, leaves: &[BytesN<32>]) -> Vec<BytesN<32>> {
+    let mut tree = lean_incremental_merkle_tree::LeanIMT::new(env, TREE_DEPTH);
+    for leaf in leaves {
+        tree.insert(leaf.clone()).unwrap();
+    }
+    let leaf_index = leaves.len() as u32 - 1;
+    let mut path = vec![env];
+    for level in 1..=TREE_DEPTH {
+        path.push_back(tree.get_node(level, leaf_index >> level).unwrap());
+    }
+    assert_eq!(path.last().unwrap(), tree.get_root());
+    path
+}
+
+// The `nullifierHash` of the bundled note, which withdrawal contexts are computed over
+fn init_nullifier_hash(env: &Env) -> BytesN<32> {
+    PublicSignals::from_bytes(env, &init_pub_signals(env))
+        .pub_signals
+        .get(0)
+        .unwrap()
+        .to_bytes()
+}
+
+// The bundled public signals with a trailing `context` signal, as produced by the current circuit
+fn init_pub_signals_with_context(env: &Env, context: BytesN<32>) -> Bytes {
+    let mut pub_signals = PublicSignals::from_bytes(env, &init_pub_signals(env));
+    pub_signals.pub_signals.push_back(Fr::from_bytes(context));
+    pub_signals.to_bytes(env)
+}
+
+// Signals for a partial withdrawal of the bundled note, with `context` and `changeCommitment`
+fn init_pub_signals_with_change(
+    env: &Env,
+    withdrawn_value: u128,
+    context: BytesN<32>,
+    change_commitment: BytesN<32>,
+) -> Bytes {
+    let mut pub_signals = PublicSignals::from_bytes(env, &init_pub_signals(env));
+    pub_signals
+        .pub_signals
+        .set(1, Fr::from_u256(U256::from_u128(env, withdrawn_value)));
+    pub_signals.pub_signals.push_back(Fr::from_bytes(context));
+    pub_signals
+        .pub_signals
+        .push_back(Fr::from_bytes(change_commitment));
+    pub_signals.to_bytes(env)
+}
+
+// `pub_signals` with the state root replaced, for the mock verifier and a tree of other notes
+fn with_state_root(env: &Env, pub_signals: &Bytes, state_root: &BytesN<32>) -> Bytes {
+    let mut pub_signals = PublicSignals::from_bytes(env, pub_signals);
+    pub_signals
+        .pub_signals
+        .set(2, Fr::from_bytes(state_root.clone()));
+    pub_signals.to_bytes(env)
+}
+
 fn init_erronous_pub_signals(env: &Env) -> Bytes {
     let public_0 = U256::from_be_bytes(
         &env,
@@ -282,13 +429,58 @@ fn init_erronous_pub_signals(env: &Env) -> Bytes {
     return pub_signals.to_bytes(env);
 }
 
+/// The pool parameters the tests deploy with, for a tree of `tree_depth`
+fn pool_params(denomination: Option<i128>, tree_depth: u32) -> PoolParams {
+    PoolParams {
+        denomination,
+        tree_depth,
+        root_history_size: ROOT_HISTORY_SIZE,
+        poseidon_params_id: poseidon::PARAMS_ID,
+    }
+}
+
+/// The access options of a permissionless pool without admin nullifier removal
+fn open_access() -> AccessConfig {
+    AccessConfig {
+        deposit_issuer: None,
+        allow_admin_nullifier_ops: false,
+    }
+}
+
+/// The commitment of the bundled proof's note, the first of `CIRCUIT_TREE_LEAVES`
+fn sample_commitment(env: &Env) -> BytesN<32> {
+    BytesN::from_array(env, &CIRCUIT_TREE_LEAVES[0])
+}
+
+/// Mints the fixed amount to `from` and deposits `sample_commitment` with it
+fn deposit_fixed(client: &PrivacyPoolsContractClient, from: &Address) -> (u32, BytesN<32>) {
+    let env = &client.env;
+    MockTokenClient::new(env, &client.get_token()).mint(from, &FIXED_AMOUNT);
+    client.deposit(from, &sample_commitment(env), &FIXED_AMOUNT, &None)
+}
+
 fn setup_test_environment(env: &Env) -> (Address, Address, Address) {
-    // Reset budget to unlimited for tests with depth-20 merkle tree
-    env.cost_estimate().budget().reset_unlimited();
+    setup_test_environment_with_mode(env, true)
+}
 
+fn setup_test_environment_with_mode(
+    env: &Env,
+    fixed_denomination: bool,
+) -> (Address, Address, Address) {
     // Deploy groth16_verifier contract
     let groth16_verifier_id = env.register(groth16_verifier_wasm::WASM, ());
 
+    setup_test_environment_with_verifier(env, fixed_denomination, groth16_verifier_id)
+}
+
+fn setup_test_environment_with_verifier(
+    env: &Env,
+    fixed_denomination: bool,
+    groth16_verifier_id: Address,
+) -> (Address, Address, Address) {
+    // Reset budget to unlimited for tests with depth-20 merkle tree
+    env.cost_estimate().budget().reset_unlimited();
+
     // Deploy mock token
     let token_admin = Address::generate(env);
     let token_id = env.register(MockToken, ());
@@ -311,6 +503,8 @@ fn setup_test_environment(env: &Env) -> (Address, Address, Address) {
             token_id.clone(),
             admin.clone(),
             groth16_verifier_id,
+            pool_params(fixed_denomination.then_some(FIXED_AMOUNT), TREE_DEPTH),
+            open_access(),
         ),
     );
 
@@ -321,7 +515,6 @@ fn setup_test_environment(env: &Env) -> (Address, Address, Address) {
 fn test_deposit_and_withdraw_correct_proof() {
     let env = Env::default();
     let (token_id, contract_id, admin) = setup_test_environment(&env);
-    env.cost_estimate().budget().print();
 
     // Create test addresses
     let alice = Address::generate(&env);
@@ -339,18 +532,11 @@ fn test_deposit_and_withdraw_correct_proof() {
     assert_eq!(token_client.balance(&alice), 1000000000);
 
     // Test deposit
-    let commitment = BytesN::from_array(
-        &env,
-        &[
-            0x10, 0xcb, 0x63, 0x1d, 0x17, 0x4a, 0x98, 0xb2, 0x44, 0x0b, 0x68, 0xd2, 0xe5, 0x7d,
-            0xa2, 0xae, 0x9a, 0x13, 0xf7, 0xd1, 0xcc, 0xcb, 0x1f, 0x41, 0xa1, 0xdd, 0x3d, 0x69,
-            0xa2, 0x2f, 0xaa, 0xe9,
-        ],
-    );
+    let commitment = sample_commitment(&env);
 
     // Mock authentication for alice
     env.mock_all_auths();
-    client.deposit(&alice, &commitment);
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
 
     // Check commitments
     let commitments = client.get_commitments();
@@ -371,11 +557,7 @@ fn test_deposit_and_withdraw_correct_proof() {
         ],
     );
     env.mock_all_auths();
-    let set_result = client.set_association_root(&admin, &association_root);
-    assert_eq!(
-        set_result,
-        vec![&env, String::from_str(&env, SUCCESS_ASSOCIATION_ROOT_SET)]
-    );
+    client.set_association_root(&admin, &association_root);
 
     // Test withdraw
     let proof = init_proof(&env);
@@ -383,13 +565,19 @@ fn test_deposit_and_withdraw_correct_proof() {
     let pub_signals_struct = PublicSignals::from_bytes(&env, &pub_signals);
     let nullifier = pub_signals_struct.pub_signals.get(0).unwrap().to_bytes();
 
-    let result = client.withdraw(&bob, &proof, &pub_signals);
-    // Success is now logged as a diagnostic event, so we return an empty vec
-    assert_eq!(result, vec![&env]);
+    // Success is logged as a diagnostic event
+    client.withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
 
     // Check balances after withdrawal
     assert_eq!(token_client.balance(&bob), 1000000000); // Bob should have the tokens
     assert_eq!(token_client.balance(&contract_id), 0); // Contract should have 0 tokens
+    assert_eq!(
+        client.get_pool_stats(),
+        PoolStats {
+            total_deposited: FIXED_AMOUNT,
+            total_withdrawn: FIXED_AMOUNT,
+        }
+    );
 
     // Check nullifiers
     let nullifiers = client.get_nullifiers();
@@ -418,18 +606,11 @@ fn test_deposit_and_withdraw_wrong_proof() {
     assert_eq!(token_client.balance(&alice), 1000000000);
 
     // Test deposit
-    let commitment = BytesN::from_array(
-        &env,
-        &[
-            0x10, 0xcb, 0x63, 0x1d, 0x17, 0x4a, 0x98, 0xb2, 0x44, 0x0b, 0x68, 0xd2, 0xe5, 0x7d,
-            0xa2, 0xae, 0x9a, 0x13, 0xf7, 0xd1, 0xcc, 0xcb, 0x1f, 0x41, 0xa1, 0xdd, 0x3d, 0x69,
-            0xa2, 0x2f, 0xaa, 0xe9,
-        ],
-    );
+    let commitment = sample_commitment(&env);
 
     // Mock authentication for alice
     env.mock_all_auths();
-    client.deposit(&alice, &commitment);
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
 
     // Check commitments
     let commitments = client.get_commitments();
@@ -449,14 +630,12 @@ fn test_deposit_and_withdraw_wrong_proof() {
     client.set_association_root(&admin, &association_root);
 
     // Test withdraw with wrong proof (different state root)
+    // The state root is checked against the root history before the proof is verified
     let proof = init_proof(&env);
     let pub_signals = init_erronous_pub_signals(&env);
 
-    let result = client.withdraw(&bob, &proof, &pub_signals);
-    assert_eq!(
-        result,
-        vec![&env, String::from_str(&env, ERROR_COIN_OWNERSHIP_PROOF)]
-    );
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::UnknownStateRoot)));
 
     // Check that balances are unchanged (withdrawal failed)
     assert_eq!(token_client.balance(&bob), 0); // Bob should still have 0
@@ -466,13 +645,22 @@ fn test_deposit_and_withdraw_wrong_proof() {
     assert_eq!(nullifiers.len(), 0); // No nullifiers should be stored
 }
 
+#[cfg(not(feature = "demo"))]
 #[test]
-fn test_withdraw_insufficient_balance() {
+fn test_withdraw_tampered_proof() {
     let env = Env::default();
-    let (_token_id, contract_id, admin) = setup_test_environment(&env);
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+
     let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+    assert!(!client.is_demo_build());
+
+    env.mock_all_auths();
+    deposit_fixed(&client, &alice);
 
-    // Set association root to match the proof
     let association_root = BytesN::from_array(
         &env,
         &[
@@ -481,52 +669,94 @@ fn test_withdraw_insufficient_balance() {
             0x07, 0x16, 0xba, 0xa2,
         ],
     );
-    env.mock_all_auths();
     client.set_association_root(&admin, &association_root);
 
-    let bob = Address::generate(&env);
-    let proof = init_proof(&env);
+    // Negate the A point of an otherwise valid proof so the pairing check fails
+    let mut tampered_proof = Proof::from_bytes(&env, &init_proof(&env));
+    let a = tampered_proof.a.clone();
+    tampered_proof.a = -a;
     let pub_signals = init_pub_signals(&env);
 
-    // Attempt to withdraw with zero balance
-    env.mock_all_auths();
-    let result = client.withdraw(&bob, &proof, &pub_signals);
-    assert_eq!(
-        result,
-        vec![&env, String::from_str(&env, ERROR_INSUFFICIENT_BALANCE)]
+    let result = client.try_withdraw(
+        &bob,
+        &bob,
+        &0,
+        &tampered_proof.to_bytes(&env),
+        &pub_signals,
+        &None,
     );
+    assert_eq!(result, Err(Ok(Error::CoinOwnershipProofFailed)));
 
-    // Ensure nullifier was not stored when withdrawal failed
+    // Nothing should have moved and the nullifier must remain unused
+    assert_eq!(token_client.balance(&bob), 0);
+    assert_eq!(token_client.balance(&contract_id), 1000000000);
     assert_eq!(client.get_nullifiers().len(), 0);
+
+    // The untampered proof for the same signals is still accepted
+    client.withdraw(&bob, &bob, &0, &init_proof(&env), &pub_signals, &None);
+    assert_eq!(token_client.balance(&bob), 1000000000);
 }
 
+#[cfg(feature = "demo")]
 #[test]
-fn test_reuse_nullifier() {
+fn test_demo_skips_proof_verification() {
     let env = Env::default();
-    let (token_id, contract_id, admin) = setup_test_environment(&env);
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
     let client = PrivacyPoolsContractClient::new(&env, &contract_id);
     let token_client = MockTokenClient::new(&env, &token_id);
+    assert!(client.is_demo_build());
 
     let alice = Address::generate(&env);
     let bob = Address::generate(&env);
-
-    // Mint tokens to alice for the deposit
     env.mock_all_auths();
-    token_client.mint(&alice, &1000000000);
+    token_client.mint(&alice, &FIXED_AMOUNT);
+    let (_, root) = client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[1u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
 
-    // Deposit
-    let commitment = BytesN::from_array(
-        &env,
-        &[
-            0x10, 0xcb, 0x63, 0x1d, 0x17, 0x4a, 0x98, 0xb2, 0x44, 0x0b, 0x68, 0xd2, 0xe5, 0x7d,
-            0xa2, 0xae, 0x9a, 0x13, 0xf7, 0xd1, 0xcc, 0xcb, 0x1f, 0x41, 0xa1, 0xdd, 0x3d, 0x69,
-            0xa2, 0x2f, 0xaa, 0xe9,
-        ],
+    // The real verifier is registered, but a proof that doesn't verify is paid out anyway
+    let mut tampered_proof = Proof::from_bytes(&env, &init_proof(&env));
+    let a = tampered_proof.a.clone();
+    tampered_proof.a = -a;
+    let pub_signals = with_state_root(&env, &init_pub_signals(&env), &root);
+    client.withdraw(
+        &bob,
+        &bob,
+        &0,
+        &tampered_proof.to_bytes(&env),
+        &pub_signals,
+        &None,
     );
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT);
+}
+
+#[test]
+fn test_deposit_and_withdraw_events() {
+    let env = Env::default();
+    let (_token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
     env.mock_all_auths();
-    client.deposit(&alice, &commitment);
+    let commitment = sample_commitment(&env);
+    let (leaf_index, new_root) = deposit_fixed(&client, &alice);
+    assert_eq!(new_root, client.get_merkle_root());
+    assert_eq!(
+        env.events().all(),
+        [DepositEvent {
+            leaf_index,
+            commitment: commitment.clone(),
+            new_root: new_root.clone(),
+            path: insertion_path(&env, core::slice::from_ref(&commitment)),
+            encrypted_note: None,
+        }
+        .to_xdr(&env, &contract_id)]
+    );
 
-    // Set association root to match the proof
     let association_root = BytesN::from_array(
         &env,
         &[
@@ -535,293 +765,4849 @@ fn test_reuse_nullifier() {
             0x07, 0x16, 0xba, 0xa2,
         ],
     );
-    env.mock_all_auths();
     client.set_association_root(&admin, &association_root);
 
-    // First withdraw - should succeed
-    let proof = init_proof(&env);
     let pub_signals = init_pub_signals(&env);
-    env.mock_all_auths();
-    let result = client.withdraw(&bob, &proof, &pub_signals);
-    assert_eq!(result, vec![&env]); // Should succeed
-
-    // Verify the nullifier was stored
-    let nullifiers = client.get_nullifiers();
-    assert_eq!(nullifiers.len(), 1);
-
-    // Attempt to reuse nullifier - should fail even though contract has no balance
-    // The balance check comes first, so we need to add balance to reach the nullifier check
-    env.mock_all_auths();
-    token_client.mint(&contract_id, &1000000000); // Add balance directly to contract
-
-    // Now try to withdraw again with the same proof
-    env.mock_all_auths();
-    let result = client.withdraw(&bob, &proof, &pub_signals);
+    let nullifier = PublicSignals::from_bytes(&env, &pub_signals)
+        .pub_signals
+        .get(0)
+        .unwrap()
+        .to_bytes();
+    client.withdraw(&bob, &bob, &0, &init_proof(&env), &pub_signals, &None);
     assert_eq!(
-        result,
-        vec![&env, String::from_str(&env, ERROR_NULLIFIER_USED)]
+        env.events().all(),
+        [WithdrawEvent {
+            nullifier,
+            to: bob.clone(),
+            amount: FIXED_AMOUNT,
+            relayer: bob.clone(),
+            fee: 0,
+        }
+        .to_xdr(&env, &contract_id)]
     );
 }
 
 #[test]
-fn test_contract_initialization() {
+fn test_withdraw_with_relayer_fee() {
     let env = Env::default();
-    let (_token_id, contract_id, _admin) = setup_test_environment(&env);
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, _admin) =
+        setup_test_environment_with_verifier(&env, true, verifier_id);
     let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
 
-    // Test that contract initializes correctly
-    let merkle_root = client.get_merkle_root();
-    let merkle_depth = client.get_merkle_depth();
-    let commitment_count = client.get_commitment_count();
-    let commitments = client.get_commitments();
-    let nullifiers = client.get_nullifiers();
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let relayer = Address::generate(&env);
+    env.mock_all_auths();
+    deposit_fixed(&client, &alice);
 
-    // Verify initial state
-    assert_eq!(merkle_depth, 20);
-    assert_eq!(commitment_count, 0);
-    assert_eq!(commitments.len(), 0);
-    assert_eq!(nullifiers.len(), 0);
+    let fee = 10000000;
+    let context = client.get_withdraw_context(&bob, &relayer, &fee, &init_nullifier_hash(&env));
+    assert_eq!(context.get(0), Some(0));
+    let proof = init_proof(&env);
+    let pub_signals = init_pub_signals_with_context(&env, context);
 
-    // Merkle root should be initialized (not all zeros)
-    assert_ne!(merkle_root, BytesN::from_array(&env, &[0u8; 32]));
+    // The relayer can't change the recipient or its fee after the proof was generated
+    let result = client.try_withdraw(&relayer, &relayer, &fee, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::ContextMismatch)));
+    let result = client.try_withdraw(&bob, &relayer, &(fee * 2), &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::ContextMismatch)));
+
+    // An authorization for another note can't be reused
+    let other_note = BytesN::from_array(&env, &[7u8; 32]);
+    let other_signals = init_pub_signals_with_context(
+        &env,
+        client.get_withdraw_context(&bob, &relayer, &fee, &other_note),
+    );
+    let result = client.try_withdraw(&bob, &relayer, &fee, &proof, &other_signals, &None);
+    assert_eq!(result, Err(Ok(Error::ContextMismatch)));
+
+    // Neither can someone who copies the proof from a pending transaction
+    let front_runner = Address::generate(&env);
+    let result = client.try_withdraw(&front_runner, &relayer, &fee, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::ContextMismatch)));
+    let result = client.try_withdraw(
+        &front_runner,
+        &front_runner,
+        &fee,
+        &proof,
+        &pub_signals,
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::ContextMismatch)));
+
+    // The fee must be covered by the withdrawn amount, even when the proof commits to it
+    for bad_fee in [-1, FIXED_AMOUNT + 1] {
+        let pub_signals = init_pub_signals_with_context(
+            &env,
+            client.get_withdraw_context(&bob, &relayer, &bad_fee, &init_nullifier_hash(&env)),
+        );
+        let result = client.try_withdraw(&bob, &relayer, &bad_fee, &proof, &pub_signals, &None);
+        assert_eq!(result, Err(Ok(Error::InvalidFee)));
+    }
+
+    client.withdraw(&bob, &relayer, &fee, &proof, &pub_signals, &None);
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT - fee);
+    assert_eq!(token_client.balance(&relayer), fee);
+    assert_eq!(token_client.balance(&contract_id), 0);
 }
 
 #[test]
-#[should_panic(expected = "Association root must be set before withdrawal")]
-fn test_withdraw_without_association_set() {
+fn test_withdraw_real_proof_recipient_binding() {
     let env = Env::default();
     let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
 
-    // Create test addresses
     let alice = Address::generate(&env);
     let bob = Address::generate(&env);
+    let relayer = Address::generate(&env);
+    env.mock_all_auths();
+    deposit_fixed(&client, &alice);
+
+    // The bundled proof has no `context` signal, so it can't be relayed to anyone else
+    let proof = init_proof(&env);
+    let pub_signals = init_pub_signals(&env);
+    let result = client.try_withdraw(&bob, &relayer, &0, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::UnboundRelayer)));
+    let result = client.try_withdraw(&bob, &bob, &1, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::UnboundRelayer)));
+
+    // Nor can a relayer bind it to a recipient after the fact: the verifier rejects a `context`
+    // the proof wasn't generated with
+    for (to, fee) in [(&bob, 0), (&relayer, 0), (&bob, 10000000)] {
+        let context = client.get_withdraw_context(to, &relayer, &fee, &init_nullifier_hash(&env));
+        let bound_signals = init_pub_signals_with_context(&env, context);
+        let result = client.try_withdraw(to, &relayer, &fee, &proof, &bound_signals, &None);
+        assert_eq!(result, Err(Ok(Error::CoinOwnershipProofFailed)));
+    }
+    assert_eq!(client.get_nullifiers().len(), 0);
+
+    client.withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT);
+    assert_eq!(token_client.balance(&relayer), 0);
+}
 
+#[test]
+fn test_withdraw_with_change_commitment() {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, _admin) =
+        setup_test_environment_with_verifier(&env, false, verifier_id);
     let client = PrivacyPoolsContractClient::new(&env, &contract_id);
     let token_client = MockTokenClient::new(&env, &token_id);
 
-    // Mint tokens to alice
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
     env.mock_all_auths();
     token_client.mint(&alice, &1000000000);
 
-    // Test initial balance
-    assert_eq!(client.get_balance(), 0);
-    assert_eq!(token_client.balance(&alice), 1000000000);
+    // A variable pool computes the note's commitment from what it receives
+    let label = BytesN::from_array(&env, &[1u8; 32]);
+    let precommitment = BytesN::from_array(&env, &[2u8; 32]);
+    let (leaf_index, root) = client.deposit_note(&alice, &label, &precommitment, &1000000000);
+    let commitment = client.get_leaf(&leaf_index).unwrap();
 
-    // Test deposit - use the same commitment as in our proof
-    let commitment = BytesN::from_array(
+    // Withdraw 400_000_000 of the 1_000_000_000 note and keep the rest as a change note
+    let change_commitment = BytesN::from_array(&env, &[9u8; 32]);
+    let proof = init_proof(&env);
+    let pub_signals = init_pub_signals_with_change(
         &env,
-        &[
-            0x10, 0xcb, 0x63, 0x1d, 0x17, 0x4a, 0x98, 0xb2, 0x44, 0x0b, 0x68, 0xd2, 0xe5, 0x7d,
-            0xa2, 0xae, 0x9a, 0x13, 0xf7, 0xd1, 0xcc, 0xcb, 0x1f, 0x41, 0xa1, 0xdd, 0x3d, 0x69,
-            0xa2, 0x2f, 0xaa, 0xe9,
-        ],
+        400000000,
+        client.get_withdraw_context(&bob, &bob, &0, &init_nullifier_hash(&env)),
+        change_commitment.clone(),
     );
+    let pub_signals = with_state_root(&env, &pub_signals, &root);
+    let signals = client.parse_public_signals(&pub_signals);
+    assert_eq!(signals.change_commitment, Some(change_commitment.clone()));
+
+    // The change note has to be the one the proof commits to
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::ChangeCommitmentMismatch)));
+    let other_change = Some(BytesN::from_array(&env, &[8u8; 32]));
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &other_change);
+    assert_eq!(result, Err(Ok(Error::ChangeCommitmentMismatch)));
+
+    client.withdraw(
+        &bob,
+        &bob,
+        &0,
+        &proof,
+        &pub_signals,
+        &Some(change_commitment.clone()),
+    );
+    assert_eq!(token_client.balance(&bob), 400000000);
+    assert_eq!(client.get_balance(), 600000000);
+    assert_eq!(
+        client.get_commitments(),
+        vec![&env, commitment, change_commitment]
+    );
+    assert!(client.is_nullifier_used(&signals.nullifier_hash));
+    assert!(client.is_known_root(&client.get_merkle_root()));
 
-    // Mock authentication for alice
-    env.mock_all_auths();
-    client.deposit(&alice, &commitment);
-
-    // Check commitments
-    let commitments = client.get_commitments();
-    assert_eq!(commitments.len(), 1);
-    assert_eq!(commitments.get(0).unwrap(), commitment);
+    // A zero change commitment is a full withdrawal
+    let pub_signals = init_pub_signals_with_change(
+        &env,
+        1000000000,
+        client.get_withdraw_context(&bob, &bob, &0, &init_nullifier_hash(&env)),
+        BytesN::from_array(&env, &[0u8; 32]),
+    );
+    assert_eq!(
+        client.parse_public_signals(&pub_signals).change_commitment,
+        None
+    );
+}
 
-    // Check balances after deposit
-    assert_eq!(token_client.balance(&alice), 0); // Alice's balance should be 0
-    assert_eq!(token_client.balance(&contract_id), 1000000000); // Contract should have the tokens
+#[test]
+fn test_withdraw_proof_expiry() {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, _admin) =
+        setup_test_environment_with_verifier(&env, true, verifier_id);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
 
-    // Verify no association set is configured
-    assert_eq!(client.has_association_set(), false);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    env.mock_all_auths();
+    deposit_fixed(&client, &alice);
 
-    // Verify state before withdrawal attempt
-    assert_eq!(token_client.balance(&bob), 0); // Bob should have 0
-    assert_eq!(token_client.balance(&contract_id), 1000000000); // Contract should have tokens
-    assert_eq!(client.get_nullifiers().len(), 0); // No nullifiers should be stored
+    // The expiry is the last public signal, after the context and a zero change commitment
+    let context = client.get_withdraw_context(&bob, &bob, &0, &init_nullifier_hash(&env));
+    let expiring = |expiry: u128| {
+        let mut pub_signals = PublicSignals::from_bytes(
+            &env,
+            &init_pub_signals_with_change(
+                &env,
+                FIXED_AMOUNT as u128,
+                context.clone(),
+                BytesN::from_array(&env, &[0u8; 32]),
+            ),
+        );
+        pub_signals
+            .pub_signals
+            .push_back(Fr::from_u256(U256::from_u128(&env, expiry)));
+        pub_signals.to_bytes(&env)
+    };
+    let pub_signals = expiring(105);
+    assert_eq!(
+        client.parse_public_signals(&pub_signals).expiry_ledger,
+        Some(105)
+    );
+    assert_eq!(
+        client.parse_public_signals(&expiring(0)).expiry_ledger,
+        None
+    );
+    let result = client.try_parse_public_signals(&expiring(1 << 32));
+    assert_eq!(result, Err(Ok(Error::MalformedPublicSignals)));
 
-    // Test withdraw with no association set configured
-    // Since association root is now required, withdrawal should panic
     let proof = init_proof(&env);
-    let pub_signals = init_pub_signals(&env);
+    env.ledger().with_mut(|ledger| ledger.sequence_number = 106);
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::ProofExpired)));
+    assert_eq!(
+        client.can_withdraw(&proof, &pub_signals),
+        WithdrawCheck::ProofExpired
+    );
 
-    env.mock_all_auths();
-    client.withdraw(&bob, &proof, &pub_signals);
+    // The expiry ledger itself is still in time
+    env.ledger().with_mut(|ledger| ledger.sequence_number = 105);
+    client.withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT);
 }
 
 #[test]
-fn test_withdraw_association_root_mismatch() {
+fn test_withdraw_change_rejected_in_fixed_mode() {
     let env = Env::default();
-    let (token_id, contract_id, admin) = setup_test_environment(&env);
-
-    // Create test addresses
-    let alice = Address::generate(&env);
-    let bob = Address::generate(&env);
-
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, _admin) =
+        setup_test_environment_with_verifier(&env, true, verifier_id);
     let client = PrivacyPoolsContractClient::new(&env, &contract_id);
     let token_client = MockTokenClient::new(&env, &token_id);
 
-    // Mint tokens to alice
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
     env.mock_all_auths();
     token_client.mint(&alice, &1000000000);
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[1u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+
+    // Fixed pools pay the full note, so a change note would create value
+    let change_commitment = BytesN::from_array(&env, &[9u8; 32]);
+    let pub_signals = init_pub_signals_with_change(
+        &env,
+        1,
+        client.get_withdraw_context(&bob, &bob, &0, &init_nullifier_hash(&env)),
+        change_commitment.clone(),
+    );
+    let result = client.try_withdraw(
+        &bob,
+        &bob,
+        &0,
+        &init_proof(&env),
+        &pub_signals,
+        &Some(change_commitment),
+    );
+    assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+    assert_eq!(client.get_commitment_count(), 1);
+}
+
+#[test]
+fn test_withdraw_fixed_amount_mismatch() {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, _admin) =
+        setup_test_environment_with_verifier(&env, true, verifier_id);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &2000000000);
+    let commitment = sample_commitment(&env);
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[2u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+
+    // A proof for any other value can't be redeemed for the fixed denomination
+    let zero_change = BytesN::from_array(&env, &[0u8; 32]);
+    for withdrawn_value in [1u128, 500000000, 2000000000] {
+        let pub_signals = init_pub_signals_with_change(
+            &env,
+            withdrawn_value,
+            client.get_withdraw_context(&bob, &bob, &0, &init_nullifier_hash(&env)),
+            zero_change.clone(),
+        );
+        let result = client.try_withdraw(&bob, &bob, &0, &init_proof(&env), &pub_signals, &None);
+        assert_eq!(result, Err(Ok(Error::AmountMismatch)));
+    }
+    assert_eq!(token_client.balance(&bob), 0);
+    assert_eq!(client.get_nullifiers().len(), 0);
+}
+
+#[test]
+fn test_withdraw_relayer_requires_context() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let relayer = Address::generate(&env);
+    env.mock_all_auths();
+    deposit_fixed(&client, &alice);
+
+    // The bundled proof has no context signal, so nothing binds a relayer or fee to it
+    let proof = init_proof(&env);
+    let pub_signals = init_pub_signals(&env);
+    let result = client.try_withdraw(&bob, &relayer, &0, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::UnboundRelayer)));
+    let result = client.try_withdraw(&bob, &bob, &1, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::UnboundRelayer)));
+    assert_eq!(token_client.balance(&contract_id), FIXED_AMOUNT);
+}
+
+#[test]
+fn test_withdraw_to_contract() {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, _admin) =
+        setup_test_environment_with_verifier(&env, true, verifier_id);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+    let receiver_id = env.register(MockReceiver, ());
+    let receiver_client = MockReceiverClient::new(&env, &receiver_id);
+
+    let alice = Address::generate(&env);
+    let relayer = Address::generate(&env);
+    env.mock_all_auths();
+    deposit_fixed(&client, &alice);
+
+    let memo = symbol_short!("swap");
+    let call = ContractCall {
+        contract: receiver_id.clone(),
+        function: symbol_short!("receive"),
+        args: vec![&env, memo.into_val(&env)],
+    };
+    let fee = 100000000;
+    let proof = init_proof(&env);
+    let pub_signals = init_pub_signals_with_context(
+        &env,
+        client.get_contract_withdraw_context(&call, &relayer, &fee, &init_nullifier_hash(&env)),
+    );
+
+    // The arguments are bound into the proof, like the target
+    let mut redirected = call.clone();
+    redirected.args = vec![&env, symbol_short!("other").into_val(&env)];
+    let result =
+        client.try_withdraw_to_contract(&redirected, &relayer, &fee, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::ContextMismatch)));
+
+    // A proof without a context can't be paid into a contract
+    let result = client.try_withdraw_to_contract(
+        &call,
+        &relayer,
+        &fee,
+        &proof,
+        &init_pub_signals(&env),
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::UnboundRelayer)));
+
+    client.withdraw_to_contract(&call, &relayer, &fee, &proof, &pub_signals, &None);
+    assert_eq!(token_client.balance(&receiver_id), FIXED_AMOUNT - fee);
+    assert_eq!(token_client.balance(&relayer), fee);
+    assert_eq!(receiver_client.received(&memo), FIXED_AMOUNT - fee);
+    assert_eq!(client.get_balance(), 0);
+}
+
+#[test]
+fn test_withdraw_split() {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, _admin) =
+        setup_test_environment_with_verifier(&env, true, verifier_id);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let merchant = Address::generate(&env);
+    let charity = Address::generate(&env);
+    let relayer = Address::generate(&env);
+    env.mock_all_auths();
+    deposit_fixed(&client, &alice);
+
+    let fee = 100000000;
+    let payouts = vec![
+        &env,
+        (merchant.clone(), 600000000i128),
+        (charity.clone(), 300000000i128),
+    ];
+    let nullifier_hash = init_nullifier_hash(&env);
+    let proof = init_proof(&env);
+    let pub_signals = init_pub_signals_with_context(
+        &env,
+        client.get_split_withdraw_context(&payouts, &relayer, &fee, &nullifier_hash),
+    );
+
+    // Every recipient and amount is bound into the proof
+    let swapped = vec![
+        &env,
+        (merchant.clone(), 300000000i128),
+        (charity.clone(), 600000000i128),
+    ];
+    let result = client.try_withdraw_split(&swapped, &relayer, &fee, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::ContextMismatch)));
+    let result = client.try_withdraw_split(
+        &payouts,
+        &relayer,
+        &fee,
+        &proof,
+        &init_pub_signals(&env),
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::UnboundRelayer)));
+
+    // The payouts and fee must add up to the proven value, with every payout positive
+    let short = vec![&env, (merchant.clone(), 600000000i128)];
+    let short_signals = init_pub_signals_with_context(
+        &env,
+        client.get_split_withdraw_context(&short, &relayer, &fee, &nullifier_hash),
+    );
+    let result = client.try_withdraw_split(&short, &relayer, &fee, &proof, &short_signals, &None);
+    assert_eq!(result, Err(Ok(Error::AmountMismatch)));
+    for invalid in [vec![&env], vec![&env, (merchant.clone(), 0i128)]] {
+        let result =
+            client.try_withdraw_split(&invalid, &relayer, &fee, &proof, &pub_signals, &None);
+        assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+    }
+    assert_eq!(client.get_nullifiers().len(), 0);
+
+    client.withdraw_split(&payouts, &relayer, &fee, &proof, &pub_signals, &None);
+    assert_eq!(
+        env.events().all(),
+        [SplitWithdrawEvent {
+            nullifier: nullifier_hash.clone(),
+            payouts: payouts.clone(),
+            relayer: relayer.clone(),
+            fee,
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(token_client.balance(&merchant), 600000000);
+    assert_eq!(token_client.balance(&charity), 300000000);
+    assert_eq!(token_client.balance(&relayer), fee);
+    assert_eq!(client.get_balance(), 0);
+    assert!(client.is_spent(&nullifier_hash));
+
+    let result = client.try_withdraw_split(&payouts, &relayer, &fee, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::NullifierUsed)));
+}
+
+#[test]
+fn test_deposit_with_receipt() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let carol = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 2));
+
+    let result = client.try_deposit_with_receipt(&alice, &bob, &0);
+    assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+
+    // A receipt deposit leaves the tree alone
+    let receipt_id = client.deposit_with_receipt(&alice, &bob, &FIXED_AMOUNT);
+    assert_eq!(
+        env.events().all(),
+        [ReceiptIssued {
+            receipt_id,
+            holder: bob.clone(),
+            amount: FIXED_AMOUNT,
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(client.get_commitment_count(), 0);
+    assert_eq!(client.get_outstanding_receipts(), FIXED_AMOUNT);
+
+    // Private withdrawals can't pay out of the funds backing receipts
+    let commitment = sample_commitment(&env);
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    token_client.transfer(&contract_id, &alice, &FIXED_AMOUNT);
+    let result = client.try_withdraw(
+        &alice,
+        &alice,
+        &0,
+        &init_proof(&env),
+        &init_pub_signals(&env),
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::InsufficientBalance)));
+
+    // Only the holder can move or redeem the receipt
+    let result = client.try_transfer_receipt(&carol, &receipt_id, &carol);
+    assert_eq!(result, Err(Ok(Error::NotReceiptHolder)));
+    client.transfer_receipt(&bob, &receipt_id, &carol);
+    assert_eq!(
+        client.get_receipt(&receipt_id),
+        Some(Receipt {
+            holder: carol.clone(),
+            amount: FIXED_AMOUNT,
+        })
+    );
+    let result = client.try_redeem_receipt(&bob, &receipt_id, &bob);
+    assert_eq!(result, Err(Ok(Error::NotReceiptHolder)));
+
+    client.redeem_receipt(&carol, &receipt_id, &carol);
+    assert_eq!(token_client.balance(&carol), FIXED_AMOUNT);
+    assert_eq!(client.get_receipt(&receipt_id), None);
+    assert_eq!(client.get_outstanding_receipts(), 0);
+    let result = client.try_redeem_receipt(&carol, &receipt_id, &carol);
+    assert_eq!(result, Err(Ok(Error::UnknownReceipt)));
+}
+
+#[test]
+fn test_withdraw_batch() {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, _admin) =
+        setup_test_environment_with_verifier(&env, true, verifier_id);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let carol = Address::generate(&env);
+    let relayer = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 2));
+    let commitment = sample_commitment(&env);
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    let state_root = client.get_merkle_root();
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[1u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+
+    // Two notes, each withdrawn through the same relayer with the recipient bound into its proof
+    let fee = 100000000;
+    let item = |to: &Address, nullifier: u8| {
+        let nullifier_hash = BytesN::from_array(&env, &[nullifier; 32]);
+        let mut pub_signals = PublicSignals::from_bytes(
+            &env,
+            &pub_signals_for_note(&env, &nullifier_hash, &state_root),
+        );
+        let context = client.get_withdraw_context(to, &relayer, &fee, &nullifier_hash);
+        pub_signals.pub_signals.push_back(Fr::from_bytes(context));
+        WithdrawItem {
+            to: to.clone(),
+            relayer: relayer.clone(),
+            fee,
+            proof: init_proof(&env),
+            pub_signals: pub_signals.to_bytes(&env),
+            change_commitment: None,
+        }
+    };
+
+    // A failing item reverts the whole batch, including the items before it
+    let result = client.try_withdraw_batch(&vec![&env, item(&bob, 1), item(&carol, 1)]);
+    assert_eq!(result, Err(Ok(Error::NullifierUsed)));
+    assert_eq!(client.get_nullifiers().len(), 0);
+    assert_eq!(token_client.balance(&bob), 0);
+
+    client.withdraw_batch(&vec![&env, item(&bob, 1), item(&carol, 2)]);
+    assert_eq!(
+        env.events().all(),
+        [
+            WithdrawEvent {
+                nullifier: BytesN::from_array(&env, &[1u8; 32]),
+                to: bob.clone(),
+                amount: FIXED_AMOUNT,
+                relayer: relayer.clone(),
+                fee,
+            }
+            .to_xdr(&env, &contract_id),
+            WithdrawEvent {
+                nullifier: BytesN::from_array(&env, &[2u8; 32]),
+                to: carol.clone(),
+                amount: FIXED_AMOUNT,
+                relayer: relayer.clone(),
+                fee,
+            }
+            .to_xdr(&env, &contract_id)
+        ]
+    );
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT - fee);
+    assert_eq!(token_client.balance(&carol), FIXED_AMOUNT - fee);
+    assert_eq!(token_client.balance(&relayer), fee * 2);
+    assert_eq!(client.get_balance(), 0);
+    assert_eq!(client.get_nullifiers().len(), 2);
+
+    client.withdraw_batch(&vec![&env]);
+}
+
+#[test]
+fn test_deposit_for() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let sponsor = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&sponsor, &1000000000);
+
+    // The sponsor funds a note for a commitment someone else generated
+    let commitment = BytesN::from_array(&env, &[1u8; 32]);
+    let (leaf_index, new_root) = client.deposit_for(&sponsor, &commitment, &FIXED_AMOUNT);
+    assert_eq!(env.auths()[0].0, sponsor);
+    assert_eq!(new_root, client.get_merkle_root());
+    assert_eq!(
+        env.events().all(),
+        [DepositEvent {
+            leaf_index,
+            commitment: commitment.clone(),
+            new_root,
+            path: insertion_path(&env, core::slice::from_ref(&commitment)),
+            encrypted_note: None,
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+
+    assert_eq!(client.get_commitments(), vec![&env, commitment]);
+    assert_eq!(token_client.balance(&sponsor), 0);
+    assert_eq!(client.get_balance(), FIXED_AMOUNT);
+}
+
+#[test]
+fn test_deposit_encrypted_note() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 2));
+
+    // The ciphertext is stored by leaf index and published with the deposit
+    let commitment = BytesN::from_array(&env, &[1u8; 32]);
+    let encrypted_note = Bytes::from_array(&env, &[0xab; 184]);
+    let (leaf_index, new_root) = client.deposit(
+        &alice,
+        &commitment,
+        &FIXED_AMOUNT,
+        &Some(encrypted_note.clone()),
+    );
+    assert_eq!(
+        env.events().all(),
+        [DepositEvent {
+            leaf_index,
+            commitment: commitment.clone(),
+            new_root,
+            path: insertion_path(&env, core::slice::from_ref(&commitment)),
+            encrypted_note: Some(encrypted_note.clone()),
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(client.get_encrypted_note(&leaf_index), Some(encrypted_note));
+
+    let too_large = Some(Bytes::from_array(&env, &[0xab; 257]));
+    let other = BytesN::from_array(&env, &[2u8; 32]);
+    let result = client.try_deposit(&alice, &other, &FIXED_AMOUNT, &too_large);
+    assert_eq!(result, Err(Ok(Error::EncryptedNoteTooLarge)));
+
+    let (leaf_index, _) = client.deposit(&alice, &other, &FIXED_AMOUNT, &None);
+    assert_eq!(client.get_encrypted_note(&leaf_index), None);
+}
+
+#[test]
+fn test_deposit_from_allowance() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let vault = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 2));
+
+    // Without an allowance the pull fails and nothing is recorded
+    let commitment = BytesN::from_array(&env, &[1u8; 32]);
+    assert!(client
+        .try_deposit_from(&vault, &alice, &commitment, &FIXED_AMOUNT)
+        .is_err());
+    assert_eq!(client.get_commitment_count(), 0);
+
+    // Alice approves the vault once, and the vault deposits for her with only its own signature
+    token_client.approve(&alice, &vault, &FIXED_AMOUNT);
+    let (leaf_index, new_root) = client.deposit_from(&vault, &alice, &commitment, &FIXED_AMOUNT);
+    assert_eq!(env.auths().len(), 1);
+    assert_eq!(env.auths()[0].0, vault);
+    assert_eq!(
+        env.events().all(),
+        [DepositEvent {
+            leaf_index,
+            commitment: commitment.clone(),
+            new_root,
+            path: insertion_path(&env, &[commitment]),
+            encrypted_note: None,
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(token_client.balance(&alice), FIXED_AMOUNT);
+    assert_eq!(token_client.allowance(&alice, &vault), 0);
+    assert_eq!(client.get_balance(), FIXED_AMOUNT);
+
+    // The allowance is used up
+    let result = client.try_deposit_from(
+        &vault,
+        &alice,
+        &BytesN::from_array(&env, &[2u8; 32]),
+        &FIXED_AMOUNT,
+    );
+    assert!(result.is_err());
+    assert_eq!(client.get_commitment_count(), 1);
+}
+
+#[test]
+fn test_deposit_batch() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 3));
+
+    let commitments = vec![
+        &env,
+        BytesN::from_array(&env, &[1u8; 32]),
+        BytesN::from_array(&env, &[2u8; 32]),
+        BytesN::from_array(&env, &[3u8; 32]),
+    ];
+    let leaf_indices = client.deposit_batch(&alice, &commitments, &FIXED_AMOUNT);
+    assert_eq!(leaf_indices, vec![&env, 0, 1, 2]);
+    assert_eq!(env.events().all().events().len(), 3);
+
+    assert_eq!(client.get_commitments(), commitments);
+    assert_eq!(token_client.balance(&alice), 0);
+    assert_eq!(client.get_balance(), FIXED_AMOUNT * 3);
+    assert_eq!(client.get_pool_stats().total_deposited, FIXED_AMOUNT * 3);
+
+    // The per-commitment amount is checked like a single deposit, and an empty batch is a no-op
+    let result = client.try_deposit_batch(&alice, &commitments, &500000000);
+    assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+    assert_eq!(
+        client.deposit_batch(&alice, &vec![&env], &FIXED_AMOUNT),
+        vec![&env]
+    );
+}
+
+#[test]
+fn test_deposit_fee() {
+    let env = Env::default();
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let treasury = Address::generate(&env);
+    let fee = 1_000_000i128;
+    env.mock_all_auths();
+    token_client.mint(&alice, &((FIXED_AMOUNT + fee) * 3));
+
+    assert_eq!(client.get_deposit_fee(), 0);
+    let result = client.try_set_deposit_fee(&alice, &fee);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    let result = client.try_set_deposit_fee(&admin, &-1);
+    assert_eq!(result, Err(Ok(Error::InvalidFee)));
+
+    client.set_deposit_fee(&admin, &fee);
+    assert_eq!(client.get_deposit_fee(), fee);
+
+    // The note is worth the denomination, so the depositor pays the fee on top of it
+    let commitment = BytesN::from_array(&env, &[1u8; 32]);
+    let result = client.try_deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+    client.deposit(&alice, &commitment, &(FIXED_AMOUNT + fee), &None);
+    assert_eq!(client.get_total_fees(), fee);
+    assert_eq!(client.get_balance(), FIXED_AMOUNT + fee);
+
+    // Batches pay the fee once per commitment
+    let commitments = vec![
+        &env,
+        BytesN::from_array(&env, &[2u8; 32]),
+        BytesN::from_array(&env, &[3u8; 32]),
+    ];
+    client.deposit_batch(&alice, &commitments, &(FIXED_AMOUNT + fee));
+    // One `FeesCollected` for the batch and a `DepositEvent` per commitment
+    assert_eq!(env.events().all().events().len(), 3);
+    assert_eq!(client.get_total_fees(), fee * 3);
+    assert_eq!(client.get_balance(), (FIXED_AMOUNT + fee) * 3);
+    assert_eq!(client.get_pool_stats().total_deposited, FIXED_AMOUNT * 3);
+    assert_eq!(token_client.balance(&alice), 0);
+
+    // Fees are paid out on their own, leaving the notes' funds in the pool
+    let result = client.try_withdraw_fees(&alice, &treasury);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    assert_eq!(client.withdraw_fees(&admin, &treasury), fee * 3);
+    assert_eq!(
+        env.events().all(),
+        [FeesWithdrawn {
+            to: treasury.clone(),
+            amount: fee * 3,
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(token_client.balance(&treasury), fee * 3);
+    assert_eq!(client.get_balance(), FIXED_AMOUNT * 3);
+    assert_eq!(client.withdraw_fees(&admin, &treasury), 0);
+    assert_eq!(client.get_total_fees(), fee * 3);
+}
+
+#[test]
+fn test_deposit_commitment_outside_field() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 2));
+
+    // The modulus itself and anything above it are rejected before any funds move
+    for commitment in [SNARK_SCALAR_FIELD, [0xffu8; 32]] {
+        let result = client.try_deposit(
+            &alice,
+            &BytesN::from_array(&env, &commitment),
+            &FIXED_AMOUNT,
+            &None,
+        );
+        assert_eq!(result, Err(Ok(Error::InvalidFieldElement)));
+    }
+    let mut batch = vec![&env, BytesN::from_array(&env, &[1u8; 32])];
+    batch.push_back(BytesN::from_array(&env, &SNARK_SCALAR_FIELD));
+    let result = client.try_deposit_batch(&alice, &batch, &FIXED_AMOUNT);
+    assert_eq!(result, Err(Ok(Error::InvalidFieldElement)));
+    assert_eq!(client.get_commitment_count(), 0);
+    assert_eq!(token_client.balance(&alice), FIXED_AMOUNT * 2);
+
+    // The largest field element is a valid commitment
+    let mut largest = SNARK_SCALAR_FIELD;
+    largest[31] -= 1;
+    let largest = BytesN::from_array(&env, &largest);
+    let (leaf_index, _) = client.deposit(&alice, &largest, &FIXED_AMOUNT, &None);
+    assert_eq!(client.get_leaf(&leaf_index), Some(largest));
+}
+
+#[test]
+fn test_deposit_duplicate_commitment() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &2000000000);
+
+    let commitment = BytesN::from_array(&env, &[1u8; 32]);
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    let root = client.get_merkle_root();
+
+    let result = client.try_deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    assert_eq!(result, Err(Ok(Error::DuplicateCommitment)));
+
+    // The failed deposit leaves the tree and balances untouched
+    assert_eq!(client.get_commitment_count(), 1);
+    assert_eq!(client.get_merkle_root(), root);
+    assert_eq!(token_client.balance(&alice), 1000000000);
+    assert_eq!(token_client.balance(&contract_id), 1000000000);
+}
+
+#[test]
+fn test_reentrancy_lock() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &2000000000);
+
+    // The lock is released after every call
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[1u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    env.as_contract(&contract_id, || {
+        assert!(!env.storage().temporary().has(&LOCK_KEY));
+    });
+
+    // While the lock is held, as it is during a token transfer, the pool rejects reentry
+    env.as_contract(&contract_id, || {
+        env.storage().temporary().set(&LOCK_KEY, &true);
+    });
+    let commitment = BytesN::from_array(&env, &[2u8; 32]);
+    let result = client.try_deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    assert_eq!(result, Err(Ok(Error::Reentrancy)));
+    let result = client.try_deposit_batch(&alice, &vec![&env, commitment], &FIXED_AMOUNT);
+    assert_eq!(result, Err(Ok(Error::Reentrancy)));
+    let result = client.try_withdraw(
+        &alice,
+        &alice,
+        &0,
+        &init_proof(&env),
+        &init_pub_signals(&env),
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::Reentrancy)));
+    assert_eq!(client.get_commitment_count(), 1);
+    assert_eq!(token_client.balance(&alice), 1000000000);
+}
+
+#[test]
+fn test_storage_ttl() {
+    let env = Env::default();
+    let (_token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    env.mock_all_auths();
+    let commitment = sample_commitment(&env);
+    deposit_fixed(&client, &alice);
+    env.as_contract(&contract_id, || {
+        assert_eq!(env.storage().instance().get_ttl(), INSTANCE_BUMP_AMOUNT);
+        assert_eq!(
+            env.storage()
+                .persistent()
+                .get_ttl(&(COMMITMENT_KEY, commitment.clone())),
+            PERSISTENT_BUMP_AMOUNT
+        );
+    });
+
+    let pub_signals = init_pub_signals(&env);
+    client.withdraw(&bob, &bob, &0, &init_proof(&env), &pub_signals, &None);
+    let nullifier = client.parse_public_signals(&pub_signals).nullifier_hash;
+    env.as_contract(&contract_id, || {
+        assert_eq!(env.storage().instance().get_ttl(), INSTANCE_BUMP_AMOUNT);
+        assert_eq!(
+            env.storage().persistent().get_ttl(&(NULL_KEY, nullifier)),
+            PERSISTENT_BUMP_AMOUNT
+        );
+    });
+
+    // The admin can top up an idle pool
+    let result = client.try_bump_ttl(&alice, &(90 * DAY_IN_LEDGERS));
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    client.bump_ttl(&admin, &(90 * DAY_IN_LEDGERS));
+    env.as_contract(&contract_id, || {
+        assert_eq!(env.storage().instance().get_ttl(), 90 * DAY_IN_LEDGERS);
+    });
+
+    // It extends the tree and the root history along with the instance
+    client.bump_ttl(&admin, &(200 * DAY_IN_LEDGERS));
+    env.as_contract(&contract_id, || {
+        let persistent = env.storage().persistent();
+        assert_eq!(env.storage().instance().get_ttl(), 200 * DAY_IN_LEDGERS);
+        assert_eq!(persistent.get_ttl(&TREE_ROOT_KEY), 200 * DAY_IN_LEDGERS);
+        assert_eq!(persistent.get_ttl(&TREE_FRONTIER_KEY), 200 * DAY_IN_LEDGERS);
+        assert_eq!(persistent.get_ttl(&LEAF_COUNT_KEY), 200 * DAY_IN_LEDGERS);
+        assert_eq!(
+            persistent.get_ttl(&(TREE_LEAVES_KEY, 0u32)),
+            200 * DAY_IN_LEDGERS
+        );
+        assert_eq!(persistent.get_ttl(&ROOT_INDEX_KEY), 200 * DAY_IN_LEDGERS);
+        assert_eq!(persistent.get_ttl(&(ROOTS_KEY, 0u32)), 200 * DAY_IN_LEDGERS);
+        assert_eq!(
+            persistent.get_ttl(&(ROOT_LEDGER_KEY, 0u32)),
+            200 * DAY_IN_LEDGERS
+        );
+        assert_eq!(
+            persistent.get_ttl(&(COMMITMENT_KEY, commitment.clone())),
+            PERSISTENT_BUMP_AMOUNT
+        );
+    });
+}
+
+#[test]
+fn test_tree_storage_pages() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 70));
+
+    // Fill the first page and spill into the second
+    let mut commitments = vec![&env];
+    for i in 1..=(LEAF_PAGE_SIZE + 2) {
+        commitments.push_back(BytesN::from_array(&env, &[i as u8; 32]));
+    }
+    client.deposit_batch(&alice, &commitments.slice(..LEAF_PAGE_SIZE), &FIXED_AMOUNT);
+    client.deposit_batch(&alice, &commitments.slice(LEAF_PAGE_SIZE..), &FIXED_AMOUNT);
+
+    assert_eq!(client.get_commitments(), commitments);
+    assert_eq!(client.get_commitment_count(), LEAF_PAGE_SIZE + 2);
+    assert_eq!(
+        client.get_leaf(&(LEAF_PAGE_SIZE - 1)),
+        commitments.get(LEAF_PAGE_SIZE - 1)
+    );
+    assert_eq!(
+        client.get_leaf(&(LEAF_PAGE_SIZE + 1)),
+        commitments.get(LEAF_PAGE_SIZE + 1)
+    );
+    assert_eq!(client.get_leaf(&(LEAF_PAGE_SIZE + 2)), None);
+
+    // The tree reloaded from its pages has the root of the same leaves inserted in one go
+    let mut tree = LeanIMT::new(&env, TREE_DEPTH);
+    for commitment in commitments.iter() {
+        tree.insert(commitment).unwrap();
+    }
+    assert_eq!(client.get_merkle_root(), tree.get_root());
+    assert_eq!(
+        client.get_merkle_proof(&(LEAF_PAGE_SIZE + 1)).len(),
+        TREE_DEPTH
+    );
+
+    // Only the config stays in instance storage
+    env.as_contract(&contract_id, || {
+        assert!(!env.storage().instance().has(&TREE_LEAVES_KEY));
+        assert!(!env.storage().instance().has(&TREE_ROOT_KEY));
+        assert!(env.storage().persistent().has(&(TREE_LEAVES_KEY, 1u32)));
+        assert!(!env.storage().persistent().has(&(TREE_LEAVES_KEY, 2u32)));
+    });
+}
+
+#[test]
+fn test_deposit_reads_only_the_last_leaf_page() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 200));
+
+    let commitment = |i: u32| {
+        let mut bytes = [0u8; 32];
+        bytes[28..].copy_from_slice(&i.to_be_bytes());
+        BytesN::from_array(&env, &bytes)
+    };
+    let mut reference = LeanIMT::new(&env, TREE_DEPTH);
+
+    client.deposit(&alice, &commitment(1), &FIXED_AMOUNT, &None);
+    reference.insert(commitment(1)).unwrap();
+    client.deposit(&alice, &commitment(2), &FIXED_AMOUNT, &None);
+    reference.insert(commitment(2)).unwrap();
+    let first_page_reads = env.cost_estimate().resources().memory_read_entries;
+
+    // Fill two pages; the next deposit opens a third
+    for range in [
+        3..=LEAF_PAGE_SIZE,
+        (LEAF_PAGE_SIZE + 1)..=(2 * LEAF_PAGE_SIZE),
+    ] {
+        let mut commitments = vec![&env];
+        for i in range {
+            commitments.push_back(commitment(i));
+            reference.insert(commitment(i)).unwrap();
+        }
+        client.deposit_batch(&alice, &commitments, &FIXED_AMOUNT);
+    }
+
+    let index = 2 * LEAF_PAGE_SIZE + 1;
+    client.deposit(&alice, &commitment(index), &FIXED_AMOUNT, &None);
+    reference.insert(commitment(index)).unwrap();
+    let next = commitment(index + 1);
+    client.deposit(&alice, &next, &FIXED_AMOUNT, &None);
+    reference.insert(next.clone()).unwrap();
+
+    // The deposit touched the same entries as one into the first page: its own page, not the
+    // full ones before it
+    assert_eq!(
+        env.cost_estimate().resources().memory_read_entries,
+        first_page_reads
+    );
+    assert_eq!(client.get_merkle_root(), reference.get_root());
+    assert_eq!(client.get_tree_nodes(), reference.get_frontier());
+    assert_eq!(client.get_leaf(&index), Some(next));
+    assert_eq!(client.get_commitments(), reference.get_leaves().clone());
+}
+
+#[test]
+fn test_get_state() {
+    let env = Env::default();
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &1000000000);
+
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[1u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    let association_root = BytesN::from_array(&env, &[7u8; 32]);
+    client.set_association_root(&admin, &association_root);
+    client.pause(&admin);
+
+    assert_eq!(
+        client.get_state(),
+        PoolState {
+            merkle_root: client.get_merkle_root(),
+            commitment_count: 1,
+            merkle_depth: TREE_DEPTH,
+            remaining_capacity: (1 << TREE_DEPTH) - 1,
+            association_root,
+            paused: true,
+        }
+    );
+}
+
+#[test]
+fn test_get_config() {
+    let env = Env::default();
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let config = client.get_config();
+    assert_eq!(config.token, token_id);
+    assert_eq!(config.admin, admin);
+    assert_eq!(config.tree_depth, TREE_DEPTH);
+    assert_eq!(config.fixed_amount, Some(FIXED_AMOUNT));
+
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, admin) =
+        setup_test_environment_with_verifier(&env, false, verifier_id.clone());
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    assert_eq!(
+        client.get_config(),
+        PoolConfig {
+            token: token_id,
+            admin,
+            groth16_verifier: verifier_id,
+            tree_depth: TREE_DEPTH,
+            fixed_amount: None,
+        }
+    );
+}
+
+#[test]
+fn test_leaf_lookups() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &3000000000);
+
+    let missing = BytesN::from_array(&env, &[9u8; 32]);
+    assert_eq!(client.get_leaf(&0), None);
+    assert_eq!(client.get_leaf_index(&missing), None);
+
+    for i in 1..=3u8 {
+        client.deposit(
+            &alice,
+            &BytesN::from_array(&env, &[i; 32]),
+            &FIXED_AMOUNT,
+            &None,
+        );
+    }
+
+    for i in 0..3u32 {
+        let commitment = BytesN::from_array(&env, &[i as u8 + 1; 32]);
+        assert_eq!(client.get_leaf(&i), Some(commitment.clone()));
+        assert_eq!(client.get_leaf_index(&commitment), Some(i));
+    }
+    assert_eq!(client.get_leaf(&3), None);
+    assert_eq!(client.get_leaf_index(&missing), None);
+}
+
+#[test]
+fn test_get_commitments_paged() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 70));
+    assert_eq!(client.get_commitments_paged(&0, &10).len(), 0);
+
+    // 70 leaves span two storage pages of 64
+    let mut commitments = vec![&env];
+    for i in 0..70u32 {
+        let mut commitment = [0u8; 32];
+        commitment[28..].copy_from_slice(&(i + 1).to_be_bytes());
+        commitments.push_back(BytesN::from_array(&env, &commitment));
+    }
+    client.deposit_batch(&alice, &commitments, &FIXED_AMOUNT);
+
+    assert_eq!(
+        client.get_commitments_paged(&0, &10),
+        commitments.slice(0..10)
+    );
+    assert_eq!(
+        client.get_commitments_paged(&60, &10),
+        commitments.slice(60..70)
+    );
+    assert_eq!(client.get_commitments_paged(&0, &1000), commitments);
+    assert_eq!(
+        client.get_commitments_paged(&65, &u32::MAX),
+        commitments.slice(65..70)
+    );
+
+    // Paging through in steps rebuilds every leaf
+    let mut synced = vec![&env];
+    let mut start = 0;
+    loop {
+        let page = client.get_commitments_paged(&start, &16);
+        if page.is_empty() {
+            break;
+        }
+        start += page.len();
+        synced.append(&page);
+    }
+    assert_eq!(synced, client.get_commitments());
+
+    assert_eq!(client.get_commitments_paged(&70, &10).len(), 0);
+    assert_eq!(client.get_commitments_paged(&u32::MAX, &10).len(), 0);
+    assert_eq!(client.get_commitments_paged(&5, &0).len(), 0);
+}
+
+#[test]
+fn test_deposit_invalid_amount() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &1000000000);
+
+    let commitment = BytesN::from_array(&env, &[1u8; 32]);
+
+    // Fixed-denomination pools only accept their denomination
+    assert!(client.is_fixed_denomination());
+    assert_eq!(client.get_denomination(), Some(FIXED_AMOUNT));
+    let result = client.try_deposit(&alice, &commitment, &500000000, &None);
+    assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+    let result = client.try_deposit(&alice, &commitment, &0, &None);
+    assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+
+    assert_eq!(token_client.balance(&alice), 1000000000);
+    assert_eq!(client.get_commitment_count(), 0);
+}
+
+#[test]
+fn test_variable_amount_deposit_and_withdraw() {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, admin) =
+        setup_test_environment_with_verifier(&env, false, verifier_id);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &1500000000);
+
+    assert!(!client.is_fixed_denomination());
+    assert_eq!(client.get_denomination(), None);
+    let label = BytesN::from_array(&env, &[1u8; 32]);
+    let precommitment = BytesN::from_array(&env, &[2u8; 32]);
+    let result = client.try_deposit_note(&alice, &label, &precommitment, &-1);
+    assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+
+    // Nothing would tie a caller-chosen commitment to the amount paid for it
+    let commitment = BytesN::from_array(&env, &[3u8; 32]);
+    let result = client.try_deposit(&alice, &commitment, &1000000000, &None);
+    assert_eq!(result, Err(Ok(Error::UnboundCommitment)));
+    assert_eq!(token_client.balance(&alice), 1500000000);
+
+    // A note worth 1_000_000_000 and a second, smaller one share the pool
+    client.deposit_note(&alice, &label, &precommitment, &1000000000);
+    let other_precommitment = BytesN::from_array(&env, &[4u8; 32]);
+    let (_, root) = client.deposit_note(&alice, &label, &other_precommitment, &500000000);
+    assert_eq!(token_client.balance(&contract_id), 1500000000);
+
+    let association_root = BytesN::from_array(
+        &env,
+        &[
+            0x5d, 0x58, 0x26, 0xf9, 0xc9, 0x18, 0x7b, 0xdb, 0x21, 0x3f, 0x01, 0xde, 0xd6, 0xd2,
+            0x30, 0xe9, 0xf1, 0xab, 0x65, 0x3b, 0x5b, 0xee, 0x60, 0x36, 0x50, 0x4e, 0x82, 0xbc,
+            0x07, 0x16, 0xba, 0xa2,
+        ],
+    );
+    client.set_association_root(&admin, &association_root);
+
+    // The payout is the withdrawnValue public signal, not the whole pool balance
+    client.withdraw(
+        &bob,
+        &bob,
+        &0,
+        &init_proof(&env),
+        &with_state_root(&env, &init_pub_signals(&env), &root),
+        &None,
+    );
+    assert_eq!(token_client.balance(&bob), 1000000000);
+    assert_eq!(token_client.balance(&contract_id), 500000000);
+}
+
+#[test]
+fn test_withdraw_insufficient_balance() {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, admin) =
+        setup_test_environment_with_verifier(&env, false, verifier_id);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    // The mock verifier accepts a withdrawnValue above anything deposited, so the root is known
+    // but the contract can't cover the payout. A value above what notes hold never settles for
+    // what is left
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &600000000);
+    let label = BytesN::from_array(&env, &[1u8; 32]);
+    client.deposit_note(
+        &alice,
+        &label,
+        &BytesN::from_array(&env, &[2u8; 32]),
+        &500000000,
+    );
+    let (_, root) = client.deposit_note(
+        &alice,
+        &label,
+        &BytesN::from_array(&env, &[3u8; 32]),
+        &100000000,
+    );
+
+    // Set association root to match the proof
+    let association_root = BytesN::from_array(
+        &env,
+        &[
+            0x5d, 0x58, 0x26, 0xf9, 0xc9, 0x18, 0x7b, 0xdb, 0x21, 0x3f, 0x01, 0xde, 0xd6, 0xd2,
+            0x30, 0xe9, 0xf1, 0xab, 0x65, 0x3b, 0x5b, 0xee, 0x60, 0x36, 0x50, 0x4e, 0x82, 0xbc,
+            0x07, 0x16, 0xba, 0xa2,
+        ],
+    );
+    env.mock_all_auths();
+    client.set_association_root(&admin, &association_root);
+
+    let bob = Address::generate(&env);
+    let proof = init_proof(&env);
+    let pub_signals = with_state_root(&env, &init_pub_signals(&env), &root);
+
+    // Attempt to withdraw more than the contract holds
+    env.mock_all_auths();
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::InsufficientBalance)));
+
+    // Ensure nullifier was not stored when withdrawal failed
+    assert_eq!(client.get_nullifiers().len(), 0);
+}
+
+#[test]
+fn test_withdraw_last_note_settles_pool() {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, admin) =
+        setup_test_environment_with_verifier(&env, true, verifier_id);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 2));
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[1u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[2u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    let root = client.get_merkle_root();
+    let note = |nullifier: u8| {
+        pub_signals_for_note(&env, &BytesN::from_array(&env, &[nullifier; 32]), &root)
+    };
+    let proof = init_proof(&env);
+    assert_eq!(client.get_unspent_value(), FIXED_AMOUNT * 2);
+
+    // Each note pays its proven amount, and the dust sent to the pool stays behind
+    token_client.mint(&contract_id, &7);
+    client.withdraw(&bob, &bob, &0, &proof, &note(1), &None);
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT);
+    assert_eq!(client.get_unspent_value(), FIXED_AMOUNT);
+
+    client.withdraw(&bob, &bob, &0, &proof, &note(2), &None);
+    assert_eq!(
+        env.events().all(),
+        [WithdrawEvent {
+            nullifier: BytesN::from_array(&env, &[2u8; 32]),
+            to: bob.clone(),
+            amount: FIXED_AMOUNT,
+            relayer: bob.clone(),
+            fee: 0,
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT * 2);
+    assert_eq!(token_client.balance(&contract_id), 7);
+    assert_eq!(client.get_unspent_value(), 0);
+    client.sweep(&admin, &token_id, &admin, &7);
+
+    // A last note the pool is short for pays out what is left rather than nothing
+    token_client.mint(&alice, &FIXED_AMOUNT);
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[3u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    let root = client.get_merkle_root();
+    token_client.transfer(&contract_id, &alice, &3);
+    let pub_signals = pub_signals_for_note(&env, &BytesN::from_array(&env, &[3u8; 32]), &root);
+    client.withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT * 3 - 3);
+    assert_eq!(token_client.balance(&contract_id), 0);
+
+    // Every note is spent, so nothing is left to settle against
+    let pub_signals = pub_signals_for_note(&env, &BytesN::from_array(&env, &[4u8; 32]), &root);
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::InsufficientBalance)));
+}
+
+#[test]
+fn test_withdraw_last_note_leaves_surplus() {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, admin) =
+        setup_test_environment_with_verifier(&env, true, verifier_id);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let treasury = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 3));
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[1u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    let proof = init_proof(&env);
+    let note = |nullifier: u8| {
+        pub_signals_for_note(
+            &env,
+            &BytesN::from_array(&env, &[nullifier; 32]),
+            &client.get_merkle_root(),
+        )
+    };
+
+    // Tokens sent to the pool directly don't go to the last withdrawer
+    token_client.mint(&contract_id, &FIXED_AMOUNT);
+    client.withdraw(&bob, &bob, &0, &proof, &note(1), &None);
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT);
+    assert_eq!(token_client.balance(&contract_id), FIXED_AMOUNT);
+    assert_eq!(client.get_unspent_value(), 0);
+
+    // The surplus is the admin's to sweep, but nothing beyond it
+    let result = client.try_sweep(&admin, &token_id, &treasury, &(FIXED_AMOUNT + 1));
+    assert_eq!(result, Err(Ok(Error::CannotSweepPoolToken)));
+    client.sweep(&admin, &token_id, &treasury, &FIXED_AMOUNT);
+    assert_eq!(token_client.balance(&treasury), FIXED_AMOUNT);
+    assert_eq!(token_client.balance(&contract_id), 0);
+
+    // While another note holds value, a shortfall fails rather than settling for what is left
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[2u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[3u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    token_client.transfer(&contract_id, &alice, &(FIXED_AMOUNT + 1));
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &note(2), &None);
+    assert_eq!(result, Err(Ok(Error::InsufficientBalance)));
+}
+
+#[test]
+fn test_withdraw_unknown_state_root() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 32));
+
+    let proof = init_proof(&env);
+    let pub_signals = init_pub_signals(&env);
+
+    // Nothing has been deposited, so the proof's root was never produced here
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::UnknownStateRoot)));
+
+    let commitment = sample_commitment(&env);
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+
+    // Later deposits push the proof's root out of the window of recent roots
+    for i in 0..ROOT_HISTORY_SIZE {
+        let mut other = [0u8; 32];
+        other[28..].copy_from_slice(&(i + 1).to_be_bytes());
+        client.deposit(
+            &alice,
+            &BytesN::from_array(&env, &other),
+            &FIXED_AMOUNT,
+            &None,
+        );
+    }
+
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::UnknownStateRoot)));
+    assert_eq!(token_client.balance(&bob), 0);
+}
+
+#[test]
+fn test_withdraw_delay() {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, admin) =
+        setup_test_environment_with_verifier(&env, true, verifier_id);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &2000000000);
+    assert_eq!(client.get_withdraw_delay(), 0);
+
+    let result = client.try_set_withdraw_delay(&alice, &100);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    client.set_withdraw_delay(&admin, &100);
+    assert_eq!(client.get_withdraw_delay(), 100);
+
+    let commitment = sample_commitment(&env);
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    let deposited_at = env.ledger().sequence();
+
+    // The proof's root was recorded by the deposit, so it has to age before it is accepted
+    let proof = init_proof(&env);
+    let pub_signals = init_pub_signals(&env);
+    env.ledger()
+        .with_mut(|l| l.sequence_number = deposited_at + 99);
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::WithdrawTooEarly)));
+    assert!(!client.verify_proof(&proof, &pub_signals));
+
+    // Later deposits don't make the older root any younger
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[1u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    env.ledger()
+        .with_mut(|l| l.sequence_number = deposited_at + 100);
+    client.withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT);
+}
+
+#[test]
+fn test_withdraw_limits() {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, admin) =
+        setup_test_environment_with_verifier(&env, true, verifier_id);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 4));
+    assert_eq!(client.get_min_reserve(), 0);
+    assert_eq!(client.get_withdraw_volume_limit(), (0, 0));
+
+    let result = client.try_set_min_reserve(&alice, &FIXED_AMOUNT);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    let result = client.try_set_min_reserve(&admin, &-1);
+    assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+    let result = client.try_set_withdraw_volume_limit(&alice, &FIXED_AMOUNT, &100);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    let result = client.try_set_withdraw_volume_limit(&admin, &FIXED_AMOUNT, &0);
+    assert_eq!(result, Err(Ok(Error::InvalidConfiguration)));
+
+    for leaf in 1..=4u8 {
+        client.deposit(
+            &alice,
+            &BytesN::from_array(&env, &[leaf; 32]),
+            &FIXED_AMOUNT,
+            &None,
+        );
+    }
+    let root = client.get_merkle_root();
+    let note = |nullifier: u8| {
+        pub_signals_for_note(&env, &BytesN::from_array(&env, &[nullifier; 32]), &root)
+    };
+    let proof = init_proof(&env);
+
+    // One note per window of 100 ledgers
+    client.set_withdraw_volume_limit(&admin, &FIXED_AMOUNT, &100);
+    client.withdraw(&bob, &bob, &0, &proof, &note(1), &None);
+    assert_eq!(client.get_withdrawn_in_window(), FIXED_AMOUNT);
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &note(2), &None);
+    assert_eq!(result, Err(Ok(Error::WithdrawVolumeExceeded)));
+    assert_eq!(
+        client.can_withdraw(&proof, &note(2)),
+        WithdrawCheck::WithdrawVolumeExceeded
+    );
+
+    env.ledger()
+        .with_mut(|l| l.sequence_number = (l.sequence_number / 100 + 1) * 100);
+    assert_eq!(client.get_withdrawn_in_window(), 0);
+    client.withdraw(&bob, &bob, &0, &proof, &note(2), &None);
+    client.set_withdraw_volume_limit(&admin, &0, &0);
+
+    // The last note would take the pool below its reserve
+    client.set_min_reserve(&admin, &FIXED_AMOUNT);
+    client.withdraw(&bob, &bob, &0, &proof, &note(3), &None);
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &note(4), &None);
+    assert_eq!(result, Err(Ok(Error::BelowMinReserve)));
+    assert_eq!(
+        client.can_withdraw(&proof, &note(4)),
+        WithdrawCheck::BelowMinReserve
+    );
+
+    client.set_min_reserve(&admin, &0);
+    client.withdraw(&bob, &bob, &0, &proof, &note(4), &None);
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT * 4);
+}
+
+#[test]
+fn test_root_history_ring() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 35));
+
+    assert_eq!(client.get_root_history().len(), 0);
+    assert!(!client.is_known_root(&client.get_merkle_root()));
+    assert!(!client.is_current_root(&client.get_merkle_root()));
+
+    let mut roots = vec![&env];
+    for i in 0..35u32 {
+        let mut commitment = [0u8; 32];
+        commitment[28..].copy_from_slice(&(i + 1).to_be_bytes());
+        client.deposit(
+            &alice,
+            &BytesN::from_array(&env, &commitment),
+            &FIXED_AMOUNT,
+            &None,
+        );
+        roots.push_back(client.get_merkle_root());
+    }
+
+    // Only the last ROOT_HISTORY_SIZE roots are kept, oldest first
+    let history = client.get_root_history();
+    assert_eq!(history, roots.slice(5..));
+    assert_eq!(history.len(), ROOT_HISTORY_SIZE);
+    assert_eq!(history.last().unwrap(), client.get_merkle_root());
+
+    for root in roots.slice(..5).iter() {
+        assert!(!client.is_known_root(&root));
+        assert_eq!(client.get_leaf_count_at_root(&root), None);
+    }
+    for (offset, root) in history.iter().enumerate() {
+        assert!(client.is_known_root(&root));
+        assert_eq!(
+            client.is_current_root(&root),
+            offset as u32 == ROOT_HISTORY_SIZE - 1
+        );
+        assert_eq!(
+            client.get_leaf_count_at_root(&root),
+            Some(offset as u32 + 6)
+        );
+    }
+}
+
+#[test]
+fn test_reuse_nullifier() {
+    let env = Env::default();
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+
+    // Mint tokens to alice for the deposit
+    env.mock_all_auths();
+    deposit_fixed(&client, &alice);
+
+    // Set association root to match the proof
+    let association_root = BytesN::from_array(
+        &env,
+        &[
+            0x5d, 0x58, 0x26, 0xf9, 0xc9, 0x18, 0x7b, 0xdb, 0x21, 0x3f, 0x01, 0xde, 0xd6, 0xd2,
+            0x30, 0xe9, 0xf1, 0xab, 0x65, 0x3b, 0x5b, 0xee, 0x60, 0x36, 0x50, 0x4e, 0x82, 0xbc,
+            0x07, 0x16, 0xba, 0xa2,
+        ],
+    );
+    env.mock_all_auths();
+    client.set_association_root(&admin, &association_root);
+
+    // First withdraw - should succeed
+    let proof = init_proof(&env);
+    let pub_signals = init_pub_signals(&env);
+    env.mock_all_auths();
+    client.withdraw(&bob, &bob, &0, &proof, &pub_signals, &None); // Should succeed
+
+    // Verify the nullifier was stored
+    let nullifiers = client.get_nullifiers();
+    assert_eq!(nullifiers.len(), 1);
+    assert!(client.is_nullifier_used(&nullifiers.get(0).unwrap()));
+
+    // Attempt to reuse nullifier - should fail even though contract has no balance
+    // The balance check comes first, so we need to add balance to reach the nullifier check
+    env.mock_all_auths();
+    token_client.mint(&contract_id, &1000000000); // Add balance directly to contract
+
+    // Now try to withdraw again with the same proof
+    env.mock_all_auths();
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::NullifierUsed)));
+}
+
+#[test]
+fn test_end_to_end_withdrawal() {
+    let env = Env::default();
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    env.mock_all_auths();
+    let (leaf_index, state_root) = deposit_fixed(&client, &alice);
+    assert_eq!(leaf_index, 0);
+
+    // The association root the proof was generated against
+    let association_root = BytesN::from_array(
+        &env,
+        &[
+            0x5d, 0x58, 0x26, 0xf9, 0xc9, 0x18, 0x7b, 0xdb, 0x21, 0x3f, 0x01, 0xde, 0xd6, 0xd2,
+            0x30, 0xe9, 0xf1, 0xab, 0x65, 0x3b, 0x5b, 0xee, 0x60, 0x36, 0x50, 0x4e, 0x82, 0xbc,
+            0x07, 0x16, 0xba, 0xa2,
+        ],
+    );
+    client.set_association_root(&admin, &association_root);
+
+    // Build the public signals from the pool's own state, in the circuit's output order: they
+    // must be exactly the ones the proof was generated for
+    let nullifier_hash = init_nullifier_hash(&env);
+    let pub_signals = PublicSignals {
+        pub_signals: Vec::from_array(
+            &env,
+            [
+                Fr::from_bytes(nullifier_hash.clone()),
+                Fr::from_u256(U256::from_u128(&env, FIXED_AMOUNT as u128)),
+                Fr::from_bytes(state_root),
+                Fr::from_bytes(client.get_association_root()),
+            ],
+        ),
+    }
+    .to_bytes(&env);
+    assert_eq!(pub_signals, init_pub_signals(&env));
+
+    let proof = init_proof(&env);
+    assert!(!client.is_spent(&nullifier_hash));
+    let receipt = client.withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(
+        receipt,
+        WithdrawReceipt {
+            nullifier_hash: nullifier_hash.clone(),
+            amount: FIXED_AMOUNT,
+            to: bob.clone(),
+            ledger: env.ledger().sequence(),
+        }
+    );
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT);
+    assert_eq!(token_client.balance(&contract_id), 0);
+    assert!(client.is_spent(&nullifier_hash));
+    assert_eq!(client.get_nullifiers(), vec![&env, nullifier_hash]);
+
+    // Even with the pool refilled, the same note can't be withdrawn twice
+    token_client.mint(&contract_id, &FIXED_AMOUNT);
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::NullifierUsed)));
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT);
+    assert_eq!(token_client.balance(&contract_id), FIXED_AMOUNT);
+}
+
+#[test]
+fn test_is_nullifier_used() {
+    let env = Env::default();
+    let (_token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+
+    env.mock_all_auths();
+    deposit_fixed(&client, &alice);
+
+    let association_root = BytesN::from_array(
+        &env,
+        &[
+            0x5d, 0x58, 0x26, 0xf9, 0xc9, 0x18, 0x7b, 0xdb, 0x21, 0x3f, 0x01, 0xde, 0xd6, 0xd2,
+            0x30, 0xe9, 0xf1, 0xab, 0x65, 0x3b, 0x5b, 0xee, 0x60, 0x36, 0x50, 0x4e, 0x82, 0xbc,
+            0x07, 0x16, 0xba, 0xa2,
+        ],
+    );
+    client.set_association_root(&admin, &association_root);
+
+    let pub_signals = init_pub_signals(&env);
+    let nullifier = PublicSignals::from_bytes(&env, &pub_signals)
+        .pub_signals
+        .get(0)
+        .unwrap()
+        .to_bytes();
+    let unrelated_nullifier = BytesN::from_array(&env, &[7u8; 32]);
+
+    // Nothing is spent before the withdrawal
+    assert!(!client.is_nullifier_used(&nullifier));
+    assert!(!client.is_nullifier_used(&unrelated_nullifier));
+
+    client.withdraw(&bob, &bob, &0, &init_proof(&env), &pub_signals, &None);
+
+    // Only the spent nullifier is marked, and it is still enumerable
+    assert!(client.is_nullifier_used(&nullifier));
+    assert!(!client.is_nullifier_used(&unrelated_nullifier));
+    assert!(client.is_spent(&nullifier));
+    assert!(!client.is_spent(&unrelated_nullifier));
+    assert_eq!(client.get_nullifiers(), vec![&env, nullifier]);
+}
+
+#[test]
+fn test_contract_initialization() {
+    let env = Env::default();
+    let (_token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+
+    // Test that contract initializes correctly
+    let merkle_root = client.get_merkle_root();
+    let merkle_depth = client.get_merkle_depth();
+    let commitment_count = client.get_commitment_count();
+    let commitments = client.get_commitments();
+    let nullifiers = client.get_nullifiers();
+
+    // Verify initial state
+    assert_eq!(merkle_depth, 20);
+    assert_eq!(commitment_count, 0);
+    assert_eq!(commitments.len(), 0);
+    assert_eq!(nullifiers.len(), 0);
+    assert_eq!(client.remaining_capacity(), 1 << 20);
+    assert!(!client.is_full());
+
+    // Merkle root should be initialized (not all zeros)
+    assert_ne!(merkle_root, BytesN::from_array(&env, &[0u8; 32]));
+
+    // The deployed verification key can be read back
+    assert_eq!(client.get_verification_key(), init_vk(&env));
+}
+
+#[test]
+fn test_configurable_tree_depth() {
+    let env = Env::default();
+    env.cost_estimate().budget().reset_unlimited();
+    env.mock_all_auths();
+
+    let token_admin = Address::generate(&env);
+    let token_id = env.register(MockToken, ());
+    let token_client = MockTokenClient::new(&env, &token_id);
+    token_client.initialize(
+        &token_admin,
+        &7u32,
+        &String::from_str(&env, "Test Token"),
+        &String::from_str(&env, "TEST"),
+    );
+
+    // A depth-2 pool holds exactly four commitments
+    let contract_id = env.register(
+        PrivacyPoolsContract,
+        (
+            init_vk(&env),
+            token_id.clone(),
+            Address::generate(&env),
+            env.register(groth16_verifier_wasm::WASM, ()),
+            pool_params(Some(FIXED_AMOUNT), 2),
+            open_access(),
+        ),
+    );
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    assert_eq!(client.get_merkle_depth(), 2);
+    assert_eq!(client.get_effective_depth(), 0);
+    assert_eq!(client.remaining_capacity(), 4);
+
+    // The effective depth only grows with the leaves: 1 leaf needs 0 levels, 2 need 1, 3 need 2
+    let alice = Address::generate(&env);
+    token_client.mint(&alice, &(FIXED_AMOUNT * 5));
+    for i in 1..=3u8 {
+        let next_leaf_index = client.next_leaf_index();
+        let (leaf_index, _) = client.deposit(
+            &alice,
+            &BytesN::from_array(&env, &[i; 32]),
+            &FIXED_AMOUNT,
+            &None,
+        );
+        assert_eq!(leaf_index, (i - 1) as u32);
+        assert_eq!(leaf_index, next_leaf_index);
+        assert_eq!(client.get_effective_depth(), [0, 1, 2][leaf_index as usize]);
+    }
+
+    // A batch that doesn't fit is rejected as a whole
+    let batch = vec![
+        &env,
+        BytesN::from_array(&env, &[4u8; 32]),
+        BytesN::from_array(&env, &[5u8; 32]),
+    ];
+    let result = client.try_deposit_batch(&alice, &batch, &FIXED_AMOUNT);
+    assert_eq!(result, Err(Ok(Error::TreeAtCapacity)));
+    assert_eq!(client.get_commitment_count(), 3);
+    assert_eq!(client.next_leaf_index(), 3);
+    assert_eq!(client.get_leaf_index(&batch.get(0).unwrap()), None);
+    assert_eq!(client.remaining_capacity(), 1);
+    assert_eq!(token_client.balance(&alice), FIXED_AMOUNT * 2);
+
+    let (leaf_index, new_root) = client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[4u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    assert_eq!(leaf_index, 3);
+    assert_eq!(new_root, client.get_merkle_root());
+    assert_eq!(client.remaining_capacity(), 0);
+    assert!(client.is_full());
+
+    let result = client.try_deposit(
+        &alice,
+        &BytesN::from_array(&env, &[5u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::TreeAtCapacity)));
+    assert_eq!(client.get_commitment_count(), 4);
+
+    // The commitment index matches the leaves after the rejected deposits
+    for i in 0..4u32 {
+        let commitment = client.get_leaf(&i).unwrap();
+        assert_eq!(client.get_leaf_index(&commitment), Some(i));
+    }
+    assert_eq!(
+        client.get_leaf_index(&BytesN::from_array(&env, &[5u8; 32])),
+        None
+    );
+}
+
+// Per-transaction limits of the Stellar network
+const TX_MAX_INSTRUCTIONS: u64 = 100_000_000;
+const TX_MAX_MEMORY_BYTES: u64 = 41_943_040;
+
+/// Runs `f` on a fresh budget and returns the (cpu instructions, memory bytes) it used
+fn measure<T>(env: &Env, f: impl FnOnce() -> T) -> (u64, u64) {
+    env.cost_estimate().budget().reset_unlimited();
+    f();
+    let budget = env.cost_estimate().budget();
+    (budget.cpu_instruction_cost(), budget.memory_bytes_cost())
+}
+
+// Reports the cost of deposit and withdraw, and fails once either no longer fits in a
+// transaction. Run with `cargo test -p opaque test_operation_budget -- --nocapture` for the
+// numbers. The pool runs as native code here, so the figures are a lower bound on the Wasm cost
+#[test]
+fn test_operation_budget() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    // A deposit hashes one path, so its cost grows with the tree depth
+    for depth in [8u32, 12, 16, 20, 24, 32] {
+        env.cost_estimate().budget().reset_unlimited();
+        let token_id = env.register(MockToken, ());
+        let token_client = MockTokenClient::new(&env, &token_id);
+        token_client.initialize(
+            &Address::generate(&env),
+            &7u32,
+            &String::from_str(&env, "Test Token"),
+            &String::from_str(&env, "TEST"),
+        );
+        let contract_id = env.register(
+            PrivacyPoolsContract,
+            (
+                init_vk(&env),
+                token_id,
+                Address::generate(&env),
+                env.register(MockVerifier, ()),
+                pool_params(Some(FIXED_AMOUNT), depth),
+                open_access(),
+            ),
+        );
+        let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+        let alice = Address::generate(&env);
+        token_client.mint(&alice, &FIXED_AMOUNT);
+
+        // Lay out three full pages of leaves and one more, so the deposit lands in a tree of
+        // several pages and shows whether it reads more than the last. Only the leaves are
+        // written, so the root doesn't match them, which doesn't change the cost
+        let filled = 3 * LEAF_PAGE_SIZE + 1;
+        env.as_contract(&contract_id, || {
+            let leaf = BytesN::from_array(&env, &[1u8; 32]);
+            for page in 0..filled.div_ceil(LEAF_PAGE_SIZE) {
+                let mut leaves = vec![&env];
+                for _ in (page * LEAF_PAGE_SIZE)..((page + 1) * LEAF_PAGE_SIZE).min(filled) {
+                    leaves.push_back(leaf.clone());
+                }
+                env.storage()
+                    .persistent()
+                    .set(&(TREE_LEAVES_KEY, page), &leaves);
+            }
+            env.storage().persistent().set(&LEAF_COUNT_KEY, &filled);
+        });
+        let (cpu, memory) = measure(&env, || {
+            client.deposit(
+                &alice,
+                &BytesN::from_array(&env, &[2u8; 32]),
+                &FIXED_AMOUNT,
+                &None,
+            )
+        });
+        std::println!(
+            "deposit  depth {depth:>2}: {cpu:>11} cpu instructions, {memory:>9} memory bytes"
+        );
+        assert!(
+            cpu <= TX_MAX_INSTRUCTIONS,
+            "deposit at depth {depth} uses {cpu} instructions"
+        );
+        assert!(
+            memory <= TX_MAX_MEMORY_BYTES,
+            "deposit at depth {depth} uses {memory} bytes"
+        );
+    }
+
+    // A withdrawal is dominated by the Groth16 pairing check, which only depends on the number
+    // of public signals, so the bundled depth-20 proof is representative of every depth
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    token_client.mint(&alice, &FIXED_AMOUNT);
+    let commitment = sample_commitment(&env);
+    let association_root = BytesN::from_array(
+        &env,
+        &[
+            0x5d, 0x58, 0x26, 0xf9, 0xc9, 0x18, 0x7b, 0xdb, 0x21, 0x3f, 0x01, 0xde, 0xd6, 0xd2,
+            0x30, 0xe9, 0xf1, 0xab, 0x65, 0x3b, 0x5b, 0xee, 0x60, 0x36, 0x50, 0x4e, 0x82, 0xbc,
+            0x07, 0x16, 0xba, 0xa2,
+        ],
+    );
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    client.set_association_root(&admin, &association_root);
+
+    let (cpu, memory) = measure(&env, || {
+        client.withdraw(
+            &bob,
+            &bob,
+            &0,
+            &init_proof(&env),
+            &init_pub_signals(&env),
+            &None,
+        )
+    });
+    std::println!("withdraw depth 20: {cpu:>11} cpu instructions, {memory:>9} memory bytes");
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT);
+    assert!(
+        cpu <= TX_MAX_INSTRUCTIONS,
+        "withdraw uses {cpu} instructions"
+    );
+    assert!(
+        memory <= TX_MAX_MEMORY_BYTES,
+        "withdraw uses {memory} bytes"
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_constructor_rejects_zero_tree_depth() {
+    let env = Env::default();
+    env.register(
+        PrivacyPoolsContract,
+        (
+            init_vk(&env),
+            Address::generate(&env),
+            Address::generate(&env),
+            Address::generate(&env),
+            pool_params(Some(FIXED_AMOUNT), 0),
+            open_access(),
+        ),
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_constructor_rejects_non_token() {
+    let env = Env::default();
+
+    // An account, or any contract without the SEP-41 metadata functions, isn't a token
+    env.register(
+        PrivacyPoolsContract,
+        (
+            init_vk(&env),
+            Address::generate(&env),
+            Address::generate(&env),
+            Address::generate(&env),
+            pool_params(Some(FIXED_AMOUNT), TREE_DEPTH),
+            open_access(),
+        ),
+    );
+}
+
+// A token the constructor can read decimals from, so it gets to its final checks
+fn register_token(env: &Env) -> Address {
+    let token_id = env.register(MockToken, ());
+    MockTokenClient::new(env, &token_id).initialize(
+        &Address::generate(env),
+        &7u32,
+        &String::from_str(env, "Test Token"),
+        &String::from_str(env, "TEST"),
+    );
+    token_id
+}
+
+#[test]
+#[should_panic]
+fn test_constructor_rejects_empty_verification_key() {
+    let env = Env::default();
+    let token_id = register_token(&env);
+    env.register(
+        PrivacyPoolsContract,
+        (
+            Bytes::new(&env),
+            token_id,
+            Address::generate(&env),
+            Address::generate(&env),
+            pool_params(Some(FIXED_AMOUNT), TREE_DEPTH),
+            open_access(),
+        ),
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_constructor_rejects_admin_as_verifier() {
+    let env = Env::default();
+    let token_id = register_token(&env);
+    let admin = Address::generate(&env);
+    env.register(
+        PrivacyPoolsContract,
+        (
+            init_vk(&env),
+            token_id,
+            admin.clone(),
+            admin,
+            pool_params(Some(FIXED_AMOUNT), TREE_DEPTH),
+            open_access(),
+        ),
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_constructor_rejects_issuer_without_denomination() {
+    let env = Env::default();
+    let token_id = register_token(&env);
+    // A signed variable-amount deposit would be unbound, and unsigned ones need a signature
+    env.register(
+        PrivacyPoolsContract,
+        (
+            init_vk(&env),
+            token_id,
+            Address::generate(&env),
+            Address::generate(&env),
+            pool_params(None, TREE_DEPTH),
+            AccessConfig {
+                deposit_issuer: Some(BytesN::from_array(&env, &[7u8; 32])),
+                allow_admin_nullifier_ops: false,
+            },
+        ),
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_constructor_rejects_other_poseidon_params() {
+    let env = Env::default();
+    let token_id = register_token(&env);
+    // A circuit hashing with another parameter set could never prove against the pool's roots
+    env.register(
+        PrivacyPoolsContract,
+        (
+            init_vk(&env),
+            token_id,
+            Address::generate(&env),
+            Address::generate(&env),
+            PoolParams {
+                poseidon_params_id: poseidon::PARAMS_ID + 1,
+                ..pool_params(Some(FIXED_AMOUNT), TREE_DEPTH)
+            },
+            open_access(),
+        ),
+    );
+}
+
+#[test]
+fn test_merkle_root_matches_circuit() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+
+    let alice = Address::generate(&env);
+
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    env.mock_all_auths();
+    token_client.mint(&alice, &2000000000);
+
+    // Commitment used by the bundled proof
+    let commitment = sample_commitment(&env);
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+
+    // The root must equal the stateRoot public signal the circuit produced for this leaf
+    let pub_signals = PublicSignals::from_bytes(&env, &init_pub_signals(&env));
+    let state_root = pub_signals.pub_signals.get(2).unwrap().to_bytes();
+    assert_eq!(client.get_merkle_root(), state_root);
+
+    // A second leaf exercises hashing against a non-empty left sibling
+    let second_commitment = BytesN::from_array(
+        &env,
+        &[
+            0x3b, 0x8f, 0x71, 0x2b, 0x68, 0x8c, 0x70, 0xd8, 0x4a, 0x91, 0xa1, 0x09, 0x45, 0x84,
+            0x25, 0x44, 0xe2, 0x4c, 0xed, 0xb7, 0xe9, 0x23, 0x84, 0x07, 0x14, 0xc6, 0x78, 0x48,
+            0x49, 0x40, 0x60, 0xbd,
+        ],
+    );
+    client.deposit(&alice, &second_commitment, &FIXED_AMOUNT, &None);
+
+    let expected_root = BytesN::from_array(
+        &env,
+        &[
+            0x41, 0x13, 0x38, 0x1e, 0x28, 0x23, 0x6e, 0x54, 0xce, 0x7f, 0x14, 0xe7, 0x12, 0x79,
+            0x5f, 0x7f, 0x0f, 0x1f, 0xa7, 0x1d, 0x6b, 0xe7, 0x03, 0x70, 0x98, 0xbc, 0xd5, 0x15,
+            0x23, 0x3f, 0x0c, 0x80,
+        ],
+    );
+    assert_eq!(client.get_merkle_root(), expected_root);
+    assert_eq!(client.get_commitment_count(), 2);
+}
+
+// Notes in deposit order: the bundled proof's note, the note of circuits/build/test_input.json,
+// the note of circuits/build/sample_input.json and `compute_commitment(1000000000, 1, 4, 5)`
+const CIRCUIT_TREE_LEAVES: [[u8; 32]; 4] = [
+    [
+        0x10, 0xcb, 0x63, 0x1d, 0x17, 0x4a, 0x98, 0xb2, 0x44, 0x0b, 0x68, 0xd2, 0xe5, 0x7d, 0xa2,
+        0xae, 0x9a, 0x13, 0xf7, 0xd1, 0xcc, 0xcb, 0x1f, 0x41, 0xa1, 0xdd, 0x3d, 0x69, 0xa2, 0x2f,
+        0xaa, 0xe9,
+    ],
+    [
+        0x3b, 0x8f, 0x71, 0x2b, 0x68, 0x8c, 0x70, 0xd8, 0x4a, 0x91, 0xa1, 0x09, 0x45, 0x84, 0x25,
+        0x44, 0xe2, 0x4c, 0xed, 0xb7, 0xe9, 0x23, 0x84, 0x07, 0x14, 0xc6, 0x78, 0x48, 0x49, 0x40,
+        0x60, 0xbd,
+    ],
+    [
+        0x4d, 0x00, 0xa9, 0xaa, 0x48, 0x11, 0x74, 0xab, 0xeb, 0x36, 0x0c, 0xef, 0x72, 0xcf, 0x86,
+        0x91, 0x17, 0x69, 0x92, 0xa1, 0xc9, 0xe0, 0xc2, 0x92, 0x53, 0xeb, 0xc4, 0xda, 0x67, 0xc4,
+        0x32, 0xbd,
+    ],
+    [
+        0x3e, 0x30, 0x6f, 0x8e, 0x77, 0xdc, 0x49, 0x34, 0x2e, 0x64, 0xb8, 0x33, 0x95, 0xaa, 0x19,
+        0x71, 0xfb, 0xf6, 0xbd, 0xd3, 0x1c, 0x13, 0x13, 0x2a, 0x30, 0xf6, 0xa2, 0xc4, 0x77, 0x21,
+        0xbd, 0xbb,
+    ],
+];
+
+// The stateRoot the circuit's `MerkleProof(20)` computes after each of the leaves above is
+// inserted: Poseidon255(2) from circuits/poseidon255_constants.circom at every level, with empty
+// subtrees hashed up from zero leaves. Computed independently of the contract's tree code.
+const CIRCUIT_TREE_ROOTS: [[u8; 32]; 4] = [
+    [
+        0x4a, 0x4f, 0x11, 0x8a, 0x44, 0xf7, 0xd0, 0x73, 0xe8, 0x8b, 0xae, 0x54, 0xe6, 0x20, 0x6d,
+        0xd2, 0x48, 0x97, 0xa5, 0x43, 0x48, 0xb9, 0xf2, 0xc8, 0xeb, 0x70, 0x7d, 0x26, 0xf4, 0x4e,
+        0x32, 0xbc,
+    ],
+    [
+        0x41, 0x13, 0x38, 0x1e, 0x28, 0x23, 0x6e, 0x54, 0xce, 0x7f, 0x14, 0xe7, 0x12, 0x79, 0x5f,
+        0x7f, 0x0f, 0x1f, 0xa7, 0x1d, 0x6b, 0xe7, 0x03, 0x70, 0x98, 0xbc, 0xd5, 0x15, 0x23, 0x3f,
+        0x0c, 0x80,
+    ],
+    [
+        0x0e, 0x99, 0xe4, 0x94, 0xf1, 0x72, 0x7c, 0x49, 0x3e, 0xc3, 0xfd, 0x1e, 0x98, 0x4e, 0xe2,
+        0xaa, 0x12, 0x59, 0x97, 0x26, 0x0a, 0xbe, 0xc9, 0x4b, 0xff, 0x8e, 0xf2, 0x1f, 0xd6, 0xbf,
+        0x94, 0x71,
+    ],
+    [
+        0x44, 0x61, 0x61, 0x1e, 0x58, 0xb4, 0x84, 0xd3, 0x35, 0xca, 0x8c, 0xca, 0xea, 0x1e, 0x56,
+        0x20, 0x0a, 0x8b, 0xb6, 0xfc, 0x25, 0xbb, 0xc1, 0xf4, 0x62, 0x09, 0x38, 0x4a, 0x60, 0x68,
+        0x56, 0xc2,
+    ],
+];
+
+#[test]
+fn test_merkle_roots_match_circuit_vectors() {
+    let env = Env::default();
+    env.cost_estimate().budget().reset_unlimited();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 4));
+
+    for (leaf, root) in CIRCUIT_TREE_LEAVES.iter().zip(CIRCUIT_TREE_ROOTS.iter()) {
+        client.deposit(
+            &alice,
+            &BytesN::from_array(&env, leaf),
+            &FIXED_AMOUNT,
+            &None,
+        );
+        assert_eq!(client.get_merkle_root(), BytesN::from_array(&env, root));
+    }
+
+    // Every leaf's path, hashed the way the circuit's MerkleProof template does, gives the root
+    let poseidon = poseidon::Poseidon255::new(&env, 3);
+    let root = BytesN::from_array(&env, &CIRCUIT_TREE_ROOTS[3]);
+    for (leaf_index, leaf) in CIRCUIT_TREE_LEAVES.iter().enumerate() {
+        let path = client.get_merkle_proof(&(leaf_index as u32));
+        let mut node = Fr::from_bytes(BytesN::from_array(&env, leaf));
+        for (level, sibling) in path.iter().enumerate() {
+            let sibling = Fr::from_bytes(sibling);
+            node = if (leaf_index >> level) & 1 == 0 {
+                poseidon.hash_two(&env, &node, &sibling)
+            } else {
+                poseidon.hash_two(&env, &sibling, &node)
+            };
+        }
+        assert_eq!(node.to_bytes(), root);
+    }
+}
+
+#[test]
+fn test_incremental_root_matches_full_tree() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+
+    let alice = Address::generate(&env);
+
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    env.mock_all_auths();
+    token_client.mint(&alice, &3000000000);
+
+    let mut third = [0u8; 32];
+    third[31] = 42;
+    let commitments = vec![
+        &env,
+        sample_commitment(&env),
+        BytesN::from_array(
+            &env,
+            &[
+                0x3b, 0x8f, 0x71, 0x2b, 0x68, 0x8c, 0x70, 0xd8, 0x4a, 0x91, 0xa1, 0x09, 0x45, 0x84,
+                0x25, 0x44, 0xe2, 0x4c, 0xed, 0xb7, 0xe9, 0x23, 0x84, 0x07, 0x14, 0xc6, 0x78, 0x48,
+                0x49, 0x40, 0x60, 0xbd,
+            ],
+        ),
+        BytesN::from_array(&env, &third),
+    ];
+
+    // Each deposit reloads the tree from storage; the root must match a tree that
+    // was built in memory without ever being reloaded
+    let mut reference = LeanIMT::new(&env, 20);
+    for commitment in commitments.iter() {
+        client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+        reference.insert(commitment).unwrap();
+        assert_eq!(client.get_merkle_root(), reference.get_root());
+    }
+
+    let expected_root = BytesN::from_array(
+        &env,
+        &[
+            0x08, 0x85, 0x7d, 0xa0, 0x94, 0x22, 0x0b, 0xba, 0x31, 0xfb, 0x27, 0xc0, 0x72, 0x12,
+            0xd0, 0xd8, 0x92, 0x71, 0x51, 0x2e, 0x40, 0x6c, 0xa5, 0x34, 0xb8, 0xd8, 0xbd, 0xc2,
+            0x20, 0x0f, 0x8d, 0xc1,
+        ],
+    );
+    assert_eq!(client.get_merkle_root(), expected_root);
+}
+
+#[test]
+fn test_get_merkle_proof() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &3000000000);
+
+    assert_eq!(
+        client.try_get_merkle_proof(&0),
+        Err(Ok(Error::InvalidLeafIndex))
+    );
+
+    let mut reference = LeanIMT::new(&env, 20);
+    for i in 1..=3u8 {
+        let commitment = BytesN::from_array(&env, &[i; 32]);
+        client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+        reference.insert(commitment).unwrap();
+    }
+
+    for leaf_index in 0..3u32 {
+        let path = client.get_merkle_proof(&leaf_index);
+        assert_eq!(path.len(), 20);
+
+        let (expected, _) = reference.generate_proof(leaf_index).unwrap();
+        for (sibling, expected_sibling) in path.iter().zip(expected.iter()) {
+            assert_eq!(sibling, expected_sibling.to_bytes());
+        }
+    }
+
+    // Leaves 0 and 1 are each other's first sibling
+    let path = client.get_merkle_proof(&0);
+    assert_eq!(path.get(0).unwrap(), BytesN::from_array(&env, &[2u8; 32]));
+    let path = client.get_merkle_proof(&1);
+    assert_eq!(path.get(0).unwrap(), BytesN::from_array(&env, &[1u8; 32]));
+
+    assert_eq!(
+        client.try_get_merkle_proof(&3),
+        Err(Ok(Error::InvalidLeafIndex))
+    );
+}
+
+#[test]
+fn test_get_root_proof() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &3000000000);
+    for i in 1..=3u8 {
+        let commitment = BytesN::from_array(&env, &[i; 32]);
+        client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    }
+    env.ledger().with_mut(|ledger| ledger.sequence_number = 42);
+
+    // A leaf, the empty fourth leaf, the pair of the first two leaves and the right half of
+    // the tree all hash up to the root
+    let poseidon = poseidon::Poseidon255::new(&env, 3);
+    for (level, index) in [(0, 2), (0, 3), (1, 0), (19, 1)] {
+        let proof = client.get_root_proof(&level, &index);
+        assert_eq!(proof.root, client.get_merkle_root());
+        assert_eq!(proof.leaf_count, 3);
+        assert_eq!(proof.ledger, 42);
+        assert_eq!(proof.siblings.len(), 20 - level);
+
+        let mut node = Fr::from_bytes(proof.node);
+        for (offset, sibling) in proof.siblings.iter().enumerate() {
+            let sibling = Fr::from_bytes(sibling);
+            node = if (index >> offset) & 1 == 0 {
+                poseidon.hash_two(&env, &node, &sibling)
+            } else {
+                poseidon.hash_two(&env, &sibling, &node)
+            };
+        }
+        assert_eq!(node.to_bytes(), proof.root);
+    }
+
+    let proof = client.get_root_proof(&0, &2);
+    assert_eq!(proof.node, BytesN::from_array(&env, &[3u8; 32]));
+    assert_eq!(proof.siblings, client.get_merkle_proof(&2));
+    let proof = client.get_root_proof(&20, &0);
+    assert_eq!(proof.node, client.get_merkle_root());
+    assert!(proof.siblings.is_empty());
+
+    for (level, index) in [(21, 0), (20, 1), (19, 2)] {
+        let result = client.try_get_root_proof(&level, &index);
+        assert_eq!(result, Err(Ok(Error::InvalidLeafIndex)));
+    }
+}
+
+#[test]
+fn test_version() {
+    let env = Env::default();
+    let (_token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+
+    assert_eq!(client.version(), CONTRACT_VERSION);
+}
+
+#[test]
+fn test_get_tree_nodes() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &4000000000);
+
+    let mut reference = LeanIMT::new(&env, 20);
+    assert_eq!(client.get_tree_nodes(), reference.get_frontier());
+
+    for i in 1..=3u8 {
+        let commitment = BytesN::from_array(&env, &[i; 32]);
+        client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+        reference.insert(commitment).unwrap();
+        assert_eq!(client.get_tree_nodes(), reference.get_frontier());
+    }
+
+    // An off-chain copy rebuilt from the exported state tracks the next deposit
+    let mut rebuilt = LeanIMT::from_storage_with_frontier(
+        &env,
+        client.get_commitments(),
+        client.get_merkle_depth(),
+        client.get_merkle_root(),
+        client.get_tree_nodes(),
+    );
+    let commitment = BytesN::from_array(&env, &[4u8; 32]);
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    rebuilt.insert(commitment).unwrap();
+    assert_eq!(rebuilt.get_root(), client.get_merkle_root());
+    assert_eq!(rebuilt.get_frontier(), client.get_tree_nodes());
+}
+
+#[test]
+fn test_withdraw_without_association_set() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+
+    // Create test addresses
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    // Mint tokens to alice
+    env.mock_all_auths();
+    token_client.mint(&alice, &1000000000);
+
+    // Test initial balance
+    assert_eq!(client.get_balance(), 0);
+    assert_eq!(token_client.balance(&alice), 1000000000);
+
+    // Test deposit - use the same commitment as in our proof
+    let commitment = sample_commitment(&env);
+
+    // Mock authentication for alice
+    env.mock_all_auths();
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+
+    // Check commitments
+    let commitments = client.get_commitments();
+    assert_eq!(commitments.len(), 1);
+    assert_eq!(commitments.get(0).unwrap(), commitment);
+
+    // Check balances after deposit
+    assert_eq!(token_client.balance(&alice), 0); // Alice's balance should be 0
+    assert_eq!(token_client.balance(&contract_id), 1000000000); // Contract should have the tokens
+
+    // Verify no association set is configured
+    assert_eq!(client.has_association_set(), false);
+
+    // Verify state before withdrawal attempt
+    assert_eq!(token_client.balance(&bob), 0); // Bob should have 0
+    assert_eq!(token_client.balance(&contract_id), 1000000000); // Contract should have tokens
+    assert_eq!(client.get_nullifiers().len(), 0); // No nullifiers should be stored
+
+    // Test withdraw with no association set configured
+    // The association root check is skipped until the admin configures a set
+    let proof = init_proof(&env);
+    let pub_signals = init_pub_signals(&env);
+
+    env.mock_all_auths();
+    client.withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+
+    assert_eq!(token_client.balance(&bob), 1000000000);
+    assert_eq!(token_client.balance(&contract_id), 0);
+    assert_eq!(client.get_nullifiers().len(), 1);
+}
+
+#[test]
+fn test_withdraw_association_root_mismatch() {
+    let env = Env::default();
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+
+    // Create test addresses
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    // Mint tokens to alice
+    env.mock_all_auths();
+    token_client.mint(&alice, &1000000000);
+
+    // Test initial balance
+    assert_eq!(client.get_balance(), 0);
+    assert_eq!(token_client.balance(&alice), 1000000000);
+
+    // Test deposit - use the same commitment as in our proof
+    let commitment = sample_commitment(&env);
+
+    // Mock authentication for alice
+    env.mock_all_auths();
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+
+    // Check commitments
+    let commitments = client.get_commitments();
+    assert_eq!(commitments.len(), 1);
+    assert_eq!(commitments.get(0).unwrap(), commitment);
+
+    // Check balances after deposit
+    assert_eq!(token_client.balance(&alice), 0); // Alice's balance should be 0
+    assert_eq!(token_client.balance(&contract_id), 1000000000); // Contract should have the tokens
+
+    // Set an incorrect association root (different from the one in the proof)
+    let incorrect_association_root = BytesN::from_array(
+        &env,
+        &[
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0xff,
+        ],
+    );
+    env.mock_all_auths();
+    client.set_association_root(&admin, &incorrect_association_root);
+
+    // Verify association set is configured
+    assert_eq!(client.has_association_set(), true);
+
+    // Test withdraw with proof that has a different association root
+    let proof = init_proof(&env);
+    let pub_signals = init_pub_signals(&env); // This has the correct association root for the proof
+
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::AssociationRootMismatch)));
+
+    // Check that balances are unchanged (withdrawal failed)
+    assert_eq!(token_client.balance(&bob), 0); // Bob should still have 0
+    assert_eq!(token_client.balance(&contract_id), 1000000000); // Contract should still have tokens
+
+    // Check that no nullifier was stored when withdrawal failed
+    let nullifiers = client.get_nullifiers();
+    assert_eq!(nullifiers.len(), 0);
+}
+
+#[test]
+fn test_withdraw_whitelisted_recipient() {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, admin) =
+        setup_test_environment_with_verifier(&env, true, verifier_id);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let exchange = Address::generate(&env);
+    env.mock_all_auths();
+    deposit_fixed(&client, &alice);
+
+    // The proof's association root is not the configured one
+    client.set_association_root(&admin, &BytesN::from_array(&env, &[0xffu8; 32]));
+
+    let result = client.try_add_whitelisted_recipient(&alice, &exchange);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    assert!(!client.is_whitelisted_recipient(&exchange));
+    client.add_whitelisted_recipient(&admin, &exchange);
+    assert_eq!(
+        env.events().all(),
+        [RecipientWhitelisted {
+            recipient: exchange.clone(),
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert!(client.is_whitelisted_recipient(&exchange));
+
+    // Only a proof bound to the whitelisted recipient skips the check
+    let proof = init_proof(&env);
+    let nullifier_hash = init_nullifier_hash(&env);
+    let result = client.try_withdraw(
+        &exchange,
+        &exchange,
+        &0,
+        &proof,
+        &init_pub_signals(&env),
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::AssociationRootMismatch)));
+    let bob_signals = init_pub_signals_with_context(
+        &env,
+        client.get_withdraw_context(&bob, &bob, &0, &nullifier_hash),
+    );
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &bob_signals, &None);
+    assert_eq!(result, Err(Ok(Error::AssociationRootMismatch)));
+
+    let pub_signals = init_pub_signals_with_context(
+        &env,
+        client.get_withdraw_context(&exchange, &exchange, &0, &nullifier_hash),
+    );
+    client.withdraw(&exchange, &exchange, &0, &proof, &pub_signals, &None);
+    let association_root = client.parse_public_signals(&pub_signals).association_root;
+    assert_eq!(
+        env.events().all(),
+        [
+            AssociationBypassed {
+                nullifier: nullifier_hash.clone(),
+                recipient: exchange.clone(),
+                association_root,
+            }
+            .to_xdr(&env, &contract_id),
+            WithdrawEvent {
+                nullifier: nullifier_hash,
+                to: exchange.clone(),
+                amount: FIXED_AMOUNT,
+                relayer: exchange.clone(),
+                fee: 0,
+            }
+            .to_xdr(&env, &contract_id)
+        ]
+    );
+    assert_eq!(token_client.balance(&exchange), FIXED_AMOUNT);
+
+    client.remove_whitelisted_recipient(&admin, &exchange);
+    assert_eq!(
+        env.events().all(),
+        [RecipientUnwhitelisted {
+            recipient: exchange.clone(),
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert!(!client.is_whitelisted_recipient(&exchange));
+}
+
+#[test]
+fn test_association_root_history() {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, admin) =
+        setup_test_environment_with_verifier(&env, true, verifier_id);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    env.mock_all_auths();
+    deposit_fixed(&client, &alice);
+
+    let pub_signals = init_pub_signals(&env);
+    let proof_root = client.parse_public_signals(&pub_signals).association_root;
+    let zero_root = BytesN::from_array(&env, &[0u8; 32]);
+    assert!(!client.is_known_association_root(&proof_root));
+    assert!(!client.is_known_association_root(&zero_root));
+    assert_eq!(client.get_association_root_history().len(), 0);
+    assert_eq!(client.get_association_root_updated_at(), None);
+
+    // The proof was built against the first set; the admin replaces it right after
+    client.set_association_root(&admin, &proof_root);
+    env.ledger().with_mut(|l| l.sequence_number += 1);
+    let newer_root = BytesN::from_array(&env, &[0xffu8; 32]);
+    client.set_association_root(&admin, &newer_root);
+    assert_eq!(client.get_association_root(), newer_root);
+    assert_eq!(
+        client.get_association_root_history(),
+        vec![&env, proof_root.clone(), newer_root.clone()]
+    );
+    assert_eq!(
+        client.get_association_root_updated_at(),
+        Some(env.ledger().sequence())
+    );
+    assert!(client.is_known_association_root(&proof_root));
+    assert!(client.is_known_association_root(&newer_root));
+    assert!(!client.is_known_association_root(&zero_root));
+
+    client.withdraw(&bob, &bob, &0, &init_proof(&env), &pub_signals, &None);
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT);
+
+    // Once enough newer sets are recorded, the old root falls out of the ring
+    for i in 0..ROOT_HISTORY_SIZE {
+        client.set_association_root(&admin, &BytesN::from_array(&env, &[i as u8 + 1; 32]));
+    }
+    assert!(!client.is_known_association_root(&proof_root));
+    assert!(!client.is_known_association_root(&newer_root));
+    let history = client.get_association_root_history();
+    assert_eq!(history.len(), ROOT_HISTORY_SIZE);
+    assert_eq!(history.last().unwrap(), client.get_association_root());
+}
+
+#[test]
+fn test_association_labels() {
+    let env = Env::default();
+    let (_token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    env.mock_all_auths();
+
+    let labels = [
+        BytesN::from_array(&env, &[1u8; 32]),
+        BytesN::from_array(&env, &[2u8; 32]),
+        BytesN::from_array(&env, &[3u8; 32]),
+        BytesN::from_array(&env, &[4u8; 32]),
+    ];
+    let expected_tree = |labels: &[BytesN<32>]| {
+        let mut tree = LeanIMT::new(&env, ASSOCIATION_TREE_DEPTH);
+        for label in labels {
+            tree.insert(label.clone()).unwrap();
+        }
+        tree
+    };
+
+    let root = client.add_label(&admin, &labels[0]);
+    assert_eq!(
+        env.events().all(),
+        [LabelAdded {
+            label: labels[0].clone(),
+            association_root: root.clone(),
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    let root = client.add_label(&admin, &labels[1]);
+    assert_eq!(root, expected_tree(&labels[..2]).get_root());
+    assert_eq!(client.get_association_root(), root);
+    assert_eq!(
+        client.get_labels(),
+        vec![&env, labels[0].clone(), labels[1].clone()]
+    );
+
+    // The label proof is the path the circuit's labelSiblings input expects
+    let (siblings, _) = expected_tree(&labels[..2]).generate_proof(1).unwrap();
+    let proof = client.get_label_proof(&1);
+    assert_eq!(proof.len(), ASSOCIATION_TREE_DEPTH);
+    for (level, sibling) in siblings.iter().enumerate() {
+        assert_eq!(proof.get(level as u32).unwrap(), sibling.to_bytes());
+    }
+    assert_eq!(
+        client.try_get_label_proof(&2),
+        Err(Ok(Error::InvalidLeafIndex))
+    );
+
+    let result = client.try_add_label(&admin, &labels[1]);
+    assert_eq!(result, Err(Ok(Error::LabelExists)));
+    let result = client.try_add_label(&Address::generate(&env), &labels[2]);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+
+    // The association tree holds 2^depth labels
+    client.add_label(&admin, &labels[2]);
+    client.add_label(&admin, &labels[3]);
+    let result = client.try_add_label(&admin, &BytesN::from_array(&env, &[5u8; 32]));
+    assert_eq!(result, Err(Ok(Error::TreeAtCapacity)));
+
+    // Removing a label rebuilds the tree; proofs against the old root stay valid for a while
+    let full_root = client.get_association_root();
+    let root = client.remove_label(&admin, &labels[1]);
+    assert_eq!(
+        client.get_labels(),
+        vec![
+            &env,
+            labels[0].clone(),
+            labels[2].clone(),
+            labels[3].clone()
+        ]
+    );
+    assert_eq!(
+        root,
+        expected_tree(&[labels[0].clone(), labels[2].clone(), labels[3].clone()]).get_root()
+    );
+    assert!(client.is_known_association_root(&full_root));
+    let result = client.try_remove_label(&admin, &labels[1]);
+    assert_eq!(result, Err(Ok(Error::UnknownLabel)));
+}
+
+#[test]
+fn test_set_association_root_non_admin() {
+    let env = Env::default();
+    let (_token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+
+    // Create a non-admin user
+    let non_admin = Address::generate(&env);
+
+    // Create a test association root
+    let association_root = BytesN::from_array(
+        &env,
+        &[
+            0x5d, 0x58, 0x26, 0xf9, 0xc9, 0x18, 0x7b, 0xdb, 0x21, 0x3f, 0x01, 0xde, 0xd6, 0xd2,
+            0x30, 0xe9, 0xf1, 0xab, 0x65, 0x3b, 0x5b, 0xee, 0x60, 0x36, 0x50, 0x4e, 0x82, 0xbc,
+            0x07, 0x16, 0xba, 0xa2,
+        ],
+    );
+
+    // Mock authentication for the non-admin user
+    env.mock_all_auths();
+
+    // Attempt to call set_association_root with non-admin should return error
+    let result = client.try_set_association_root(&non_admin, &association_root);
+
+    // Verify that the call returned an error
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+
+    // Verify that no association root was set (should still be zero)
+    let stored_root = client.get_association_root();
+    let zero_root = BytesN::from_array(&env, &[0u8; 32]);
+    assert_eq!(
+        stored_root, zero_root,
+        "Association root should not be set by non-admin"
+    );
+
+    // Verify that has_association_set returns false
+    assert_eq!(
+        client.has_association_set(),
+        false,
+        "Should not have association set after failed non-admin call"
+    );
+}
+
+#[test]
+fn test_transfer_admin() {
+    let env = Env::default();
+    let (_token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+
+    let new_admin = Address::generate(&env);
+    env.mock_all_auths();
+
+    client.transfer_admin(&admin, &new_admin);
+    assert_eq!(client.get_admin(), new_admin);
+    assert_eq!(
+        env.events().all(),
+        [AdminTransferred {
+            old_admin: admin.clone(),
+            new_admin: new_admin.clone(),
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+
+    // The previous admin has lost its rights
+    let association_root = BytesN::from_array(&env, &[1u8; 32]);
+    let result = client.try_set_association_root(&admin, &association_root);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    client.set_association_root(&new_admin, &association_root);
+    assert_eq!(client.get_association_root(), association_root);
+}
+
+#[test]
+fn test_transfer_admin_non_admin() {
+    let env = Env::default();
+    let (_token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+
+    let non_admin = Address::generate(&env);
+    env.mock_all_auths();
+
+    let result = client.try_transfer_admin(&non_admin, &non_admin);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    assert_eq!(client.get_admin(), admin);
+}
+
+#[test]
+fn test_multisig_admin() {
+    let env = Env::default();
+    let (_token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    env.mock_all_auths();
+
+    assert_eq!(client.get_admins(), vec![&env, admin.clone()]);
+    assert_eq!(client.get_admin_threshold(), 1);
+
+    // With a threshold of 1 the single admin executes its own proposal
+    let second = Address::generate(&env);
+    let third = Address::generate(&env);
+    let admins = vec![&env, admin.clone(), second.clone(), third.clone()];
+    let proposal_id = client.propose(&admin, &AdminAction::SetAdmins(admins.clone(), 2));
+    client.execute(&admin, &proposal_id);
+    assert_eq!(
+        env.events().all(),
+        [ProposalExecuted {
+            proposal_id: proposal_id.clone(),
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(client.get_admins(), admins);
+    assert_eq!(client.get_admin_threshold(), 2);
+
+    // A single admin can no longer act alone
+    let association_root = BytesN::from_array(&env, &[1u8; 32]);
+    let result = client.try_set_association_root(&admin, &association_root);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    assert_eq!(
+        client.try_transfer_admin(&admin, &Address::generate(&env)),
+        Err(Ok(Error::OnlyAdmin))
+    );
+
+    let action = AdminAction::SetAssociationRoot(association_root.clone());
+    let proposal_id = client.propose(&admin, &action);
+    assert_eq!(
+        env.events().all(),
+        [ProposalCreated {
+            proposal_id: proposal_id.clone(),
+            proposer: admin.clone(),
+            action: action.clone(),
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(
+        client.try_execute(&admin, &proposal_id),
+        Err(Ok(Error::ThresholdNotMet))
+    );
+
+    let outsider = Address::generate(&env);
+    assert_eq!(
+        client.try_approve(&outsider, &proposal_id),
+        Err(Ok(Error::OnlyAdmin))
+    );
+    assert_eq!(
+        client.try_approve(&admin, &proposal_id),
+        Err(Ok(Error::AlreadyApproved))
+    );
+    client.approve(&second, &proposal_id);
+    assert_eq!(
+        client.get_proposal(&proposal_id),
+        Some(Proposal {
+            action,
+            approvals: vec![&env, admin.clone(), second.clone()],
+        })
+    );
+
+    client.execute(&third, &proposal_id);
+    assert_eq!(client.get_association_root(), association_root);
+    assert_eq!(client.get_proposal(&proposal_id), None);
+    assert_eq!(
+        client.try_execute(&third, &proposal_id),
+        Err(Ok(Error::UnknownProposal))
+    );
+
+    // The threshold can't exceed the number of admins
+    let proposal_id = client.propose(
+        &admin,
+        &AdminAction::SetAdmins(vec![&env, admin.clone()], 2),
+    );
+    client.approve(&second, &proposal_id);
+    assert_eq!(
+        client.try_execute(&admin, &proposal_id),
+        Err(Ok(Error::InvalidConfiguration))
+    );
+}
+
+#[test]
+fn test_multisig_blocks_direct_admin_methods() {
+    let env = Env::default();
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    env.mock_all_auths();
+
+    let second = Address::generate(&env);
+    let admins = vec![&env, admin.clone(), second.clone()];
+    let proposal_id = client.propose(&admin, &AdminAction::SetAdmins(admins, 2));
+    client.execute(&admin, &proposal_id);
+
+    // Every admin-only method refuses a single admin once the threshold is above 1
+    let only_admin = Err(Ok(Error::OnlyAdmin));
+    let other = Address::generate(&env);
+    let value = BytesN::from_array(&env, &[1u8; 32]);
+    let vk_bytes = init_vk(&env);
+    for caller in [&admin, &second] {
+        assert_eq!(client.try_set_proof_versions(caller, &1, &1), only_admin);
+        assert_eq!(client.try_set_deposit_fee(caller, &1), only_admin);
+        assert_eq!(
+            client.try_withdraw_fees(caller, &other),
+            Err(Ok(Error::OnlyAdmin))
+        );
+        assert_eq!(client.try_set_deposit_issuer(caller, &value), only_admin);
+        assert_eq!(client.try_set_withdraw_delay(caller, &1), only_admin);
+        assert_eq!(client.try_set_min_reserve(caller, &1), only_admin);
+        assert_eq!(
+            client.try_set_withdraw_volume_limit(caller, &1, &1),
+            only_admin
+        );
+        assert_eq!(client.try_set_denomination(caller, &None), only_admin);
+        assert_eq!(client.try_remove_nullifier(caller, &value), only_admin);
+        assert_eq!(client.try_set_token(caller, &token_id), only_admin);
+        assert_eq!(client.try_sweep(caller, &token_id, &other, &1), only_admin);
+        assert_eq!(client.try_set_association_root(caller, &value), only_admin);
+        assert_eq!(
+            client.try_add_label(caller, &value),
+            Err(Ok(Error::OnlyAdmin))
+        );
+        assert_eq!(
+            client.try_remove_label(caller, &value),
+            Err(Ok(Error::OnlyAdmin))
+        );
+        assert_eq!(
+            client.try_add_whitelisted_recipient(caller, &other),
+            only_admin
+        );
+        assert_eq!(
+            client.try_remove_whitelisted_recipient(caller, &other),
+            only_admin
+        );
+        assert_eq!(client.try_transfer_admin(caller, &other), only_admin);
+        assert_eq!(client.try_bump_ttl(caller, &1000), only_admin);
+        assert_eq!(client.try_pause(caller), only_admin);
+        assert_eq!(client.try_unpause(caller), only_admin);
+        assert_eq!(
+            client.try_set_verification_key(caller, &vk_bytes),
+            only_admin
+        );
+        assert_eq!(client.try_apply_verification_key(caller), only_admin);
+        assert_eq!(client.try_cancel_verification_key(caller), only_admin);
+        assert_eq!(client.try_set_groth16_verifier(caller, &other), only_admin);
+        assert_eq!(client.try_apply_groth16_verifier(caller), only_admin);
+        assert_eq!(client.try_cancel_groth16_verifier(caller), only_admin);
+        assert_eq!(client.try_rebuild_tree(caller), only_admin);
+        assert_eq!(client.try_announce_emergency(caller), only_admin);
+        assert_eq!(client.try_cancel_emergency(caller), only_admin);
+        assert_eq!(client.try_upgrade(caller, &value), only_admin);
+    }
+
+    // bump_ttl goes through a proposal like everything else
+    let proposal_id = client.propose(&admin, &AdminAction::BumpTtl(1000));
+    client.approve(&second, &proposal_id);
+    client.execute(&second, &proposal_id);
+    assert_eq!(client.get_proposal(&proposal_id), None);
+}
+
+#[test]
+fn test_set_token() {
+    let env = Env::default();
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+
+    let new_token_id = env.register(MockToken, ());
+    let result = client.try_set_token(&alice, &new_token_id);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+
+    // The new token must answer the metadata calls, and its decimals replace the old ones
+    let result = client.try_set_token(&admin, &new_token_id);
+    assert_eq!(result, Err(Ok(Error::InvalidToken)));
+    let new_token_client = MockTokenClient::new(&env, &new_token_id);
+    new_token_client.initialize(
+        &Address::generate(&env),
+        &6u32,
+        &String::from_str(&env, "New Token"),
+        &String::from_str(&env, "NEW"),
+    );
+    assert_eq!(client.get_token_decimals(), 7);
+
+    // A stray balance of the old token blocks the change
+    token_client.mint(&contract_id, &1);
+    let result = client.try_set_token(&admin, &new_token_id);
+    assert_eq!(result, Err(Ok(Error::PoolNotEmpty)));
+    token_client.transfer(&contract_id, &alice, &1);
+
+    client.set_token(&admin, &new_token_id);
+    assert_eq!(
+        env.events().all(),
+        [TokenChanged {
+            old_token: token_id.clone(),
+            new_token: new_token_id.clone(),
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(client.get_token(), new_token_id);
+    assert_eq!(client.get_token_decimals(), 6);
+
+    // Deposits now go to the new token, after which it is locked in
+    new_token_client.mint(&alice, &FIXED_AMOUNT);
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[1u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    assert_eq!(client.get_balance(), FIXED_AMOUNT);
+
+    let result = client.try_set_token(&admin, &token_id);
+    assert_eq!(result, Err(Ok(Error::PoolNotEmpty)));
+}
+
+#[test]
+fn test_set_denomination() {
+    let env = Env::default();
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    assert_eq!(client.get_denomination(), Some(FIXED_AMOUNT));
+
+    let result = client.try_set_denomination(&alice, &Some(FIXED_AMOUNT * 10));
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    let result = client.try_set_denomination(&admin, &Some(0));
+    assert_eq!(result, Err(Ok(Error::InvalidConfiguration)));
+
+    client.set_denomination(&admin, &Some(FIXED_AMOUNT * 10));
+    assert_eq!(
+        env.events().all(),
+        [DenominationChanged {
+            old_denomination: Some(FIXED_AMOUNT),
+            new_denomination: Some(FIXED_AMOUNT * 10),
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(client.get_denomination(), Some(FIXED_AMOUNT * 10));
+
+    // Deposits now have to be the new amount, after which it is locked in
+    token_client.mint(&alice, &(FIXED_AMOUNT * 10));
+    let commitment = BytesN::from_array(&env, &[1u8; 32]);
+    let result = client.try_deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+    client.deposit(&alice, &commitment, &(FIXED_AMOUNT * 10), &None);
+
+    let result = client.try_set_denomination(&admin, &None);
+    assert_eq!(result, Err(Ok(Error::PoolNotEmpty)));
+}
+
+#[test]
+fn test_remove_nullifier() {
+    let env = Env::default();
+    let (token_id, default_id, admin) = setup_test_environment(&env);
+    env.mock_all_auths();
+
+    let nullifier = |byte: u8| BytesN::from_array(&env, &[byte; 32]);
+    let spend = |contract_id: &Address, nullifier: &BytesN<32>| {
+        env.as_contract(contract_id, || {
+            PrivacyPoolsContract::store_nullifier(&env, nullifier, FIXED_AMOUNT)
+        });
+    };
+
+    // Off unless the pool was deployed with it
+    let client = PrivacyPoolsContractClient::new(&env, &default_id);
+    assert!(!client.is_nullifier_removal_allowed());
+    spend(&default_id, &nullifier(1));
+    let result = client.try_remove_nullifier(&admin, &nullifier(1));
+    assert_eq!(result, Err(Ok(Error::NullifierOpsDisabled)));
+    assert!(client.is_nullifier_used(&nullifier(1)));
+
+    let contract_id = env.register(
+        PrivacyPoolsContract,
+        (
+            init_vk(&env),
+            token_id,
+            admin.clone(),
+            env.register(MockVerifier, ()),
+            pool_params(Some(FIXED_AMOUNT), TREE_DEPTH),
+            AccessConfig {
+                deposit_issuer: None,
+                allow_admin_nullifier_ops: true,
+            },
+        ),
+    );
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    assert!(client.is_nullifier_removal_allowed());
+    for byte in 1..=4 {
+        spend(&contract_id, &nullifier(byte));
+    }
+
+    let non_admin = Address::generate(&env);
+    let result = client.try_remove_nullifier(&non_admin, &nullifier(2));
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    let result = client.try_remove_nullifier(&admin, &nullifier(5));
+    assert_eq!(result, Err(Ok(Error::UnknownNullifier)));
+
+    // The last nullifier takes the removed one's place
+    client.remove_nullifier(&admin, &nullifier(2));
+    assert_eq!(
+        env.events().all(),
+        [NullifierRemoved {
+            nullifier: nullifier(2),
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert!(!client.is_nullifier_used(&nullifier(2)));
+    assert_eq!(client.get_nullifier_count(), 3);
+    assert_eq!(client.get_unspent_value(), FIXED_AMOUNT);
+    assert_eq!(
+        client.get_nullifiers(),
+        vec![&env, nullifier(1), nullifier(4), nullifier(3)]
+    );
+
+    // The moved nullifier can be removed from its new place, and the last one from the end
+    client.remove_nullifier(&admin, &nullifier(4));
+    assert_eq!(
+        client.get_nullifiers(),
+        vec![&env, nullifier(1), nullifier(3)]
+    );
+    client.remove_nullifier(&admin, &nullifier(3));
+    assert_eq!(client.get_nullifiers(), vec![&env, nullifier(1)]);
+    assert_eq!(client.get_unspent_value(), FIXED_AMOUNT * 3);
+    let result = client.try_remove_nullifier(&admin, &nullifier(3));
+    assert_eq!(result, Err(Ok(Error::UnknownNullifier)));
+
+    // A removed nullifier's note can be spent again
+    spend(&contract_id, &nullifier(2));
+    assert!(client.is_nullifier_used(&nullifier(2)));
+}
+
+#[test]
+fn test_pause_blocks_deposit_and_withdraw() {
+    let env = Env::default();
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &1000000000);
+
+    let commitment = sample_commitment(&env);
+    let association_root = BytesN::from_array(
+        &env,
+        &[
+            0x5d, 0x58, 0x26, 0xf9, 0xc9, 0x18, 0x7b, 0xdb, 0x21, 0x3f, 0x01, 0xde, 0xd6, 0xd2,
+            0x30, 0xe9, 0xf1, 0xab, 0x65, 0x3b, 0x5b, 0xee, 0x60, 0x36, 0x50, 0x4e, 0x82, 0xbc,
+            0x07, 0x16, 0xba, 0xa2,
+        ],
+    );
+    client.set_association_root(&admin, &association_root);
+
+    assert!(!client.is_paused());
+    client.pause(&admin);
+    assert!(client.is_paused());
+
+    let result = client.try_deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    assert_eq!(result, Err(Ok(Error::Paused)));
+    assert_eq!(token_client.balance(&alice), 1000000000);
+
+    client.unpause(&admin);
+    assert!(!client.is_paused());
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+
+    client.pause(&admin);
+    let proof = init_proof(&env);
+    let pub_signals = init_pub_signals(&env);
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::Paused)));
+    assert_eq!(client.get_nullifiers().len(), 0);
+
+    client.unpause(&admin);
+    client.withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(token_client.balance(&bob), 1000000000);
+}
+
+#[test]
+fn test_pause_non_admin() {
+    let env = Env::default();
+    let (_token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+
+    let non_admin = Address::generate(&env);
+    env.mock_all_auths();
+
+    assert_eq!(client.try_pause(&non_admin), Err(Ok(Error::OnlyAdmin)));
+    assert!(!client.is_paused());
+
+    client.pause(&admin);
+    assert_eq!(client.try_unpause(&non_admin), Err(Ok(Error::OnlyAdmin)));
+    assert!(client.is_paused());
+}
+
+#[test]
+fn test_withdraw_after_association_root_cleared() {
+    let env = Env::default();
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+
+    // Create test addresses
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    // Mint tokens to alice
+    env.mock_all_auths();
+    deposit_fixed(&client, &alice);
+
+    // An association set that doesn't match the proof blocks the withdrawal
+    let other_root = BytesN::from_array(&env, &[0x11u8; 32]);
+    client.set_association_root(&admin, &other_root);
+    assert!(client.has_association_set());
+
+    let proof = init_proof(&env);
+    let pub_signals = init_pub_signals(&env);
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(result, Err(Ok(Error::AssociationRootMismatch)));
+
+    // Clearing the association set turns the check off again
+    client.set_association_root(&admin, &BytesN::from_array(&env, &[0u8; 32]));
+    assert!(!client.has_association_set());
+
+    client.withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(token_client.balance(&bob), 1000000000);
+}
+
+#[test]
+fn test_set_verification_key_timelock() {
+    let env = Env::default();
+    let (_token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    env.mock_all_auths();
+
+    let non_admin = Address::generate(&env);
+    // Any key with the serialized layout can be proposed; flip a bit of alpha to tell them apart
+    let mut new_vk = init_vk(&env);
+    new_vk.set(0, new_vk.get(0).unwrap() ^ 1);
+
+    let result = client.try_set_verification_key(&non_admin, &init_vk(&env));
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    let result = client.try_set_verification_key(&admin, &Bytes::from_array(&env, &[1, 2, 3]));
+    assert_eq!(result, Err(Ok(Error::InvalidVerificationKey)));
+    let result = client.try_apply_verification_key(&admin);
+    assert_eq!(result, Err(Ok(Error::NoPendingVerificationKey)));
+
+    // A proposed key is published but doesn't replace the current one until the delay passes
+    client.set_verification_key(&admin, &new_vk);
+    let effective_at = env.ledger().timestamp() + VK_TIMELOCK;
+    assert_eq!(
+        env.events().all(),
+        [VerificationKeyProposed {
+            vk_hash: env.crypto().sha256(&new_vk).into(),
+            effective_at,
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(
+        client.get_pending_verification_key(),
+        Some((new_vk.clone(), effective_at))
+    );
+    assert_eq!(client.get_verification_key(), init_vk(&env));
+
+    env.ledger()
+        .with_mut(|ledger| ledger.timestamp = effective_at - 1);
+    let result = client.try_apply_verification_key(&admin);
+    assert_eq!(result, Err(Ok(Error::TimelockActive)));
+
+    env.ledger()
+        .with_mut(|ledger| ledger.timestamp = effective_at);
+    client.apply_verification_key(&admin);
+    assert_eq!(client.get_verification_key(), new_vk);
+    assert_eq!(client.get_pending_verification_key(), None);
+
+    // A pending key can be withdrawn before it applies
+    client.set_verification_key(&admin, &init_vk(&env));
+    client.cancel_verification_key(&admin);
+    assert_eq!(client.get_pending_verification_key(), None);
+    assert_eq!(client.get_verification_key(), new_vk);
+}
+
+#[test]
+fn test_set_groth16_verifier_timelock() {
+    let env = Env::default();
+    let (_token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    env.mock_all_auths();
+
+    let non_admin = Address::generate(&env);
+    let old_verifier = client.get_groth16_verifier();
+    let new_verifier = env.register(MockVerifier, ());
+
+    let result = client.try_set_groth16_verifier(&non_admin, &new_verifier);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    let result = client.try_apply_groth16_verifier(&admin);
+    assert_eq!(result, Err(Ok(Error::NoPendingVerifier)));
+
+    // A proposed verifier is published but only replaces the current one after the delay
+    client.set_groth16_verifier(&admin, &new_verifier);
+    let effective_at = env.ledger().timestamp() + VK_TIMELOCK;
+    assert_eq!(
+        env.events().all(),
+        [VerifierProposed {
+            verifier: new_verifier.clone(),
+            effective_at,
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(
+        client.get_pending_groth16_verifier(),
+        Some((new_verifier.clone(), effective_at))
+    );
+    assert_eq!(client.get_groth16_verifier(), old_verifier);
+
+    env.ledger()
+        .with_mut(|ledger| ledger.timestamp = effective_at - 1);
+    let result = client.try_apply_groth16_verifier(&admin);
+    assert_eq!(result, Err(Ok(Error::TimelockActive)));
+
+    env.ledger()
+        .with_mut(|ledger| ledger.timestamp = effective_at);
+    client.apply_groth16_verifier(&admin);
+    assert_eq!(
+        env.events().all(),
+        [VerifierUpdated {
+            old_verifier: old_verifier.clone(),
+            new_verifier: new_verifier.clone(),
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(client.get_groth16_verifier(), new_verifier);
+    assert_eq!(client.get_pending_groth16_verifier(), None);
+
+    // A pending verifier can be withdrawn before it applies
+    client.set_groth16_verifier(&admin, &old_verifier);
+    client.cancel_groth16_verifier(&admin);
+    assert_eq!(client.get_pending_groth16_verifier(), None);
+    assert_eq!(client.get_groth16_verifier(), new_verifier);
+}
+
+#[test]
+fn test_rebuild_tree() {
+    let env = Env::default();
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+    env.mock_all_auths();
+
+    let alice = Address::generate(&env);
+    token_client.mint(&alice, &(FIXED_AMOUNT * 3));
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[1u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[2u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    let root = client.get_merkle_root();
+
+    // A root left behind by the SHA256 tree of older deployments
+    let stale_root = BytesN::from_array(&env, &[7u8; 32]);
+    env.as_contract(&contract_id, || {
+        env.storage().persistent().set(&TREE_ROOT_KEY, &stale_root);
+    });
+
+    let result = client.try_rebuild_tree(&Address::generate(&env));
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+
+    client.rebuild_tree(&admin);
+    assert_eq!(
+        env.events().all(),
+        [TreeRebuilt {
+            old_root: stale_root,
+            new_root: root.clone(),
+            leaf_count: 2,
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(client.get_merkle_root(), root);
+    assert!(client.is_known_root(&root));
+
+    // The rebuilt tree keeps accepting deposits
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[3u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    assert_eq!(client.get_commitment_count(), 3);
+
+    let result = client.try_rebuild_tree(&admin);
+    assert_eq!(result, Err(Ok(Error::TreeAlreadyRebuilt)));
+
+    // A change of Poseidon parameters takes another rebuild, once
+    env.as_contract(&contract_id, || {
+        env.storage()
+            .instance()
+            .set(&POSEIDON_PARAMS_KEY, &(poseidon::PARAMS_ID + 1));
+    });
+    client.rebuild_tree(&admin);
+    assert_eq!(client.get_poseidon_params_id(), poseidon::PARAMS_ID);
+    let result = client.try_rebuild_tree(&admin);
+    assert_eq!(result, Err(Ok(Error::TreeAlreadyRebuilt)));
+}
+
+/// Registers a pool and rewrites its storage into the layout of the original contract, with
+/// three leaves, one of them spent, and a SHA256 root. The pool holds none of the token
+fn setup_legacy_pool(env: &Env) -> (Address, Address, Address, Vec<BytesN<32>>) {
+    let (token_id, contract_id, admin) = setup_test_environment(env);
+    let leaves = vec![
+        env,
+        BytesN::from_array(env, &[1u8; 32]),
+        BytesN::from_array(env, &[2u8; 32]),
+        BytesN::from_array(env, &[3u8; 32]),
+    ];
+    env.as_contract(&contract_id, || {
+        let instance = env.storage().instance();
+        for key in [
+            ADMINS_KEY,
+            ADMIN_THRESHOLD_KEY,
+            TOKEN_DECIMALS_KEY,
+            DENOMINATION_KEY,
+            ROOT_HISTORY_SIZE_KEY,
+            POSEIDON_PARAMS_KEY,
+        ] {
+            instance.remove(&key);
+        }
+        for key in [
+            LEAF_COUNT_KEY,
+            TREE_DEPTH_KEY,
+            TREE_ROOT_KEY,
+            TREE_FRONTIER_KEY,
+        ] {
+            env.storage().persistent().remove(&key);
+        }
+
+        instance.set(&LEGACY_ADMIN_KEY, &admin);
+        instance.set(&TREE_LEAVES_KEY, &leaves);
+        instance.set(&TREE_DEPTH_KEY, &8u32);
+        instance.set(&TREE_ROOT_KEY, &BytesN::from_array(env, &[9u8; 32]));
+        instance.set(&NULL_KEY, &vec![env, BytesN::from_array(env, &[4u8; 32])]);
+    });
+    (token_id, contract_id, admin, leaves)
+}
+
+#[test]
+fn test_rebuild_tree_migrates_legacy_storage() {
+    let env = Env::default();
+    let (token_id, contract_id, admin, leaves) = setup_legacy_pool(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+    env.mock_all_auths();
+    token_client.mint(&contract_id, &(FIXED_AMOUNT * 2));
+
+    // The original single admin is still recognised before the migration
+    assert_eq!(client.get_admins(), vec![&env, admin.clone()]);
+    let result = client.try_rebuild_tree(&Address::generate(&env));
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+
+    let mut tree = LeanIMT::new(&env, 8);
+    for leaf in leaves.iter() {
+        tree.insert(leaf).unwrap();
+    }
+    let root = tree.get_root();
+
+    client.rebuild_tree(&admin);
+    assert_eq!(
+        env.events().all(),
+        [TreeRebuilt {
+            old_root: BytesN::from_array(&env, &[9u8; 32]),
+            new_root: root.clone(),
+            leaf_count: 3,
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(client.get_merkle_root(), root);
+    assert_eq!(client.get_merkle_depth(), 8);
+    assert_eq!(client.get_commitments(), leaves);
+    assert_eq!(client.get_leaf_index(&leaves.get(2).unwrap()), Some(2));
+    assert_eq!(
+        client.get_nullifiers(),
+        vec![&env, BytesN::from_array(&env, &[4u8; 32])]
+    );
+    assert_eq!(client.get_unspent_value(), FIXED_AMOUNT * 2);
+    assert_eq!(client.get_denomination(), Some(FIXED_AMOUNT));
+    assert_eq!(client.get_token_decimals(), 7);
+    env.as_contract(&contract_id, || {
+        let instance = env.storage().instance();
+        assert_eq!(instance.get(&ADMINS_KEY), Some(vec![&env, admin.clone()]));
+        assert!(!instance.has(&LEGACY_ADMIN_KEY));
+        assert!(!instance.has(&TREE_LEAVES_KEY));
+        assert!(!instance.has(&TREE_ROOT_KEY));
+        assert!(!instance.has(&NULL_KEY));
+    });
+
+    // The migrated pool takes deposits on top of the old leaves
+    let alice = Address::generate(&env);
+    token_client.mint(&alice, &FIXED_AMOUNT);
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[5u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    assert_eq!(client.get_commitment_count(), 4);
+    assert_eq!(client.get_unspent_value(), FIXED_AMOUNT * 3);
+
+    let result = client.try_rebuild_tree(&admin);
+    assert_eq!(result, Err(Ok(Error::TreeAlreadyRebuilt)));
+}
+
+#[test]
+fn test_upgrade() {
+    let env = Env::default();
+    let (_token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    env.mock_all_auths();
+
+    let wasm_hash = env
+        .deployer()
+        .upload_contract_wasm(groth16_verifier_wasm::WASM);
+    let result = client.try_upgrade(&Address::generate(&env), &wasm_hash);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+
+    client.upgrade(&admin, &wasm_hash);
+    assert_eq!(
+        env.events().all(),
+        [ContractUpgraded {
+            wasm_hash: wasm_hash.clone(),
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+
+    // The pool now runs the uploaded code, which has none of its functions
+    assert!(client.try_get_commitment_count().is_err());
+}
+
+#[test]
+fn test_poseidon_params_id() {
+    let env = Env::default();
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+    env.mock_all_auths();
+    assert_eq!(client.get_poseidon_params_id(), poseidon::PARAMS_ID);
+
+    // Pools from before the id was stored were hashed with the first parameter set
+    env.as_contract(&contract_id, || {
+        env.storage().instance().remove(&POSEIDON_PARAMS_KEY);
+    });
+    assert_eq!(client.get_poseidon_params_id(), 1);
+
+    // A tree hashed with other parameters takes no deposits until it is rebuilt
+    let alice = Address::generate(&env);
+    token_client.mint(&alice, &2000000000);
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[1u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    env.as_contract(&contract_id, || {
+        env.storage()
+            .instance()
+            .set(&POSEIDON_PARAMS_KEY, &(poseidon::PARAMS_ID + 1));
+    });
+    let commitment = BytesN::from_array(&env, &[2u8; 32]);
+    let result = client.try_deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    assert_eq!(result, Err(Ok(Error::PoseidonParamsMismatch)));
+
+    client.rebuild_tree(&admin);
+    assert_eq!(client.get_poseidon_params_id(), poseidon::PARAMS_ID);
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    assert_eq!(client.get_commitment_count(), 2);
+}
+
+#[test]
+fn test_sweep() {
+    let env = Env::default();
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+    env.mock_all_auths();
+
+    let stray_id = env.register(MockToken, ());
+    let stray_client = MockTokenClient::new(&env, &stray_id);
+    stray_client.initialize(
+        &Address::generate(&env),
+        &7u32,
+        &String::from_str(&env, "Stray Token"),
+        &String::from_str(&env, "STRAY"),
+    );
+    let recipient = Address::generate(&env);
+    stray_client.mint(&contract_id, &500);
+    token_client.mint(&contract_id, &500);
+
+    // Only the pool token beyond what backs notes can be swept
+    let alice = Address::generate(&env);
+    token_client.mint(&alice, &FIXED_AMOUNT);
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[1u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+
+    let result = client.try_sweep(&recipient, &stray_id, &recipient, &500);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    let result = client.try_sweep(&admin, &token_id, &recipient, &501);
+    assert_eq!(result, Err(Ok(Error::CannotSweepPoolToken)));
+    let result = client.try_sweep(&admin, &stray_id, &recipient, &0);
+    assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+
+    client.sweep(&admin, &stray_id, &recipient, &500);
+    assert_eq!(
+        env.events().all(),
+        [TokenSwept {
+            token: stray_id.clone(),
+            to: recipient.clone(),
+            amount: 500,
+        }
+        .to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(stray_client.balance(&recipient), 500);
+    assert_eq!(stray_client.balance(&contract_id), 0);
+    assert_eq!(client.get_balance(), FIXED_AMOUNT + 500);
+}
+
+#[test]
+fn test_compute_commitment() {
+    let env = Env::default();
+    let (_token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+
+    let field = |byte: u8| {
+        let mut bytes = [0u8; 32];
+        bytes[31] = byte;
+        BytesN::from_array(&env, &bytes)
+    };
+
+    // The note of circuits/build/sample_input.json
+    let commitment = client.compute_commitment(&1000000000, &field(1), &field(2), &field(3));
+    assert_eq!(
+        commitment,
+        BytesN::from_array(
+            &env,
+            &[
+                0x4d, 0x00, 0xa9, 0xaa, 0x48, 0x11, 0x74, 0xab, 0xeb, 0x36, 0x0c, 0xef, 0x72, 0xcf,
+                0x86, 0x91, 0x17, 0x69, 0x92, 0xa1, 0xc9, 0xe0, 0xc2, 0x92, 0x53, 0xeb, 0xc4, 0xda,
+                0x67, 0xc4, 0x32, 0xbd,
+            ],
+        )
+    );
+
+    let result = client.try_compute_commitment(&-1, &field(1), &field(2), &field(3));
+    assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+    let outside = BytesN::from_array(&env, &SNARK_SCALAR_FIELD);
+    let result = client.try_compute_commitment(&1000000000, &field(1), &outside, &field(3));
+    assert_eq!(result, Err(Ok(Error::InvalidFieldElement)));
+}
+
+#[test]
+fn test_deposit_note_binds_value() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 2));
+
+    let field = |byte: u8| {
+        let mut bytes = [0u8; 32];
+        bytes[31] = byte;
+        BytesN::from_array(&env, &bytes)
+    };
+    let precommitment = poseidon::Poseidon255::new(&env, 3)
+        .hash_two(&env, &Fr::from_bytes(field(2)), &Fr::from_bytes(field(3)))
+        .to_bytes();
+
+    // Under-paying the denomination is rejected, whichever way the deposit is made
+    let result = client.try_deposit_note(&alice, &field(1), &precommitment, &(FIXED_AMOUNT / 2));
+    assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+    let full_note = client.compute_commitment(&FIXED_AMOUNT, &field(1), &field(2), &field(3));
+    let result = client.try_deposit(&alice, &full_note, &(FIXED_AMOUNT / 2), &None);
+    assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+    assert_eq!(client.get_commitment_count(), 0);
+
+    // The stored commitment is for the value the pool received
+    let (leaf_index, _root) = client.deposit_note(&alice, &field(1), &precommitment, &FIXED_AMOUNT);
+    assert_eq!(client.get_leaf(&leaf_index), Some(full_note.clone()));
+    assert_eq!(token_client.balance(&contract_id), FIXED_AMOUNT);
+
+    let outside = BytesN::from_array(&env, &SNARK_SCALAR_FIELD);
+    let result = client.try_deposit_note(&alice, &field(1), &outside, &FIXED_AMOUNT);
+    assert_eq!(result, Err(Ok(Error::InvalidFieldElement)));
+
+    // Without a denomination, paying less commits to less rather than minting the full note
+    let (token_id, contract_id, _admin) = setup_test_environment_with_mode(&env, false);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    MockTokenClient::new(&env, &token_id).mint(&alice, &FIXED_AMOUNT);
+    let (leaf_index, _root) =
+        client.deposit_note(&alice, &field(1), &precommitment, &(FIXED_AMOUNT / 2));
+    let stored = client.get_leaf(&leaf_index).unwrap();
+    assert_ne!(stored, full_note);
+    assert_eq!(
+        stored,
+        client.compute_commitment(&(FIXED_AMOUNT / 2), &field(1), &field(2), &field(3))
+    );
+}
+
+#[test]
+fn test_mismatched_deposits_rejected() {
+    let env = Env::default();
+    let alice = Address::generate(&env);
+    let vault = Address::generate(&env);
+    env.mock_all_auths();
+
+    let field = |byte: u8| {
+        let mut bytes = [0u8; 32];
+        bytes[31] = byte;
+        BytesN::from_array(&env, &bytes)
+    };
+
+    for fixed_denomination in [true, false] {
+        let (token_id, contract_id, _admin) =
+            setup_test_environment_with_mode(&env, fixed_denomination);
+        let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+        let token_client = MockTokenClient::new(&env, &token_id);
+        token_client.mint(&alice, &FIXED_AMOUNT);
+        token_client.approve(&alice, &vault, &FIXED_AMOUNT);
+
+        // A note claiming the full amount, paid for with half of it, through every path that
+        // takes a caller-chosen commitment
+        let note = client.compute_commitment(&FIXED_AMOUNT, &field(1), &field(2), &field(3));
+        let paid = FIXED_AMOUNT / 2;
+        let expected = if fixed_denomination {
+            Error::InvalidAmount
+        } else {
+            Error::UnboundCommitment
+        };
+        let result = client.try_deposit(&alice, &note, &paid, &None);
+        assert_eq!(result, Err(Ok(expected)));
+        let result = client.try_deposit_from(&vault, &alice, &note, &paid);
+        assert_eq!(result, Err(Ok(expected)));
+        let result = client.try_deposit_for(&alice, &note, &paid);
+        assert_eq!(result, Err(Ok(expected)));
+        let result = client.try_deposit_batch(&alice, &vec![&env, note.clone()], &paid);
+        assert_eq!(result, Err(Ok(expected)));
+        // Without an issuer the signature is ignored
+        let signature = BytesN::from_array(&env, &[0u8; 64]);
+        let result = client.try_deposit_signed(&alice, &note, &paid, &signature);
+        assert_eq!(result, Err(Ok(expected)));
+
+        assert_eq!(client.get_commitment_count(), 0);
+        assert_eq!(client.get_leaf_index(&note), None);
+        assert_eq!(token_client.balance(&alice), FIXED_AMOUNT);
+    }
+}
+
+#[test]
+fn test_deposit_issuer_signature() {
+    let env = Env::default();
+    let (token_id, permissionless_id, admin) = setup_test_environment(&env);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let issuer = SigningKey::from_bytes(&[7u8; 32]);
+    let issuer_key = BytesN::from_array(&env, &issuer.verifying_key().to_bytes());
+    let contract_id = env.register(
+        PrivacyPoolsContract,
+        (
+            init_vk(&env),
+            token_id.clone(),
+            admin.clone(),
+            env.register(MockVerifier, ()),
+            pool_params(Some(FIXED_AMOUNT), TREE_DEPTH),
+            AccessConfig {
+                deposit_issuer: Some(issuer_key.clone()),
+                allow_admin_nullifier_ops: false,
+            },
+        ),
+    );
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    assert_eq!(client.get_deposit_issuer(), Some(issuer_key));
+
+    // Dropping the denomination would leave no way in
+    env.mock_all_auths();
+    let result = client.try_set_denomination(&admin, &None);
+    assert_eq!(result, Err(Ok(Error::InvalidConfiguration)));
+    assert_eq!(client.get_denomination(), Some(FIXED_AMOUNT));
+
+    let alice = Address::generate(&env);
+    token_client.mint(&alice, &(FIXED_AMOUNT * 3));
+
+    let commitment = BytesN::from_array(&env, &[1u8; 32]);
+    let sign = |key: &SigningKey, commitment: &BytesN<32>| {
+        BytesN::from_array(&env, &key.sign(&commitment.to_array()).to_bytes())
+    };
+
+    // Unsigned deposits are turned away at the door
+    let result = client.try_deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    assert_eq!(result, Err(Ok(Error::IssuerSignatureRequired)));
+    let batch = vec![&env, commitment.clone()];
+    let result = client.try_deposit_batch(&alice, &batch, &FIXED_AMOUNT);
+    assert_eq!(result, Err(Ok(Error::IssuerSignatureRequired)));
+
+    // Only the issuer's signature over this very commitment is accepted
+    let other = SigningKey::from_bytes(&[8u8; 32]);
+    let signature = sign(&other, &commitment);
+    let result = client.try_deposit_signed(&alice, &commitment, &FIXED_AMOUNT, &signature);
+    assert!(result.is_err());
+    let signature = sign(&issuer, &BytesN::from_array(&env, &[2u8; 32]));
+    let result = client.try_deposit_signed(&alice, &commitment, &FIXED_AMOUNT, &signature);
+    assert!(result.is_err());
+    assert_eq!(client.get_commitment_count(), 0);
+
+    let signature = sign(&issuer, &commitment);
+    client.deposit_signed(&alice, &commitment, &FIXED_AMOUNT, &signature);
+    assert_eq!(client.get_commitments(), vec![&env, commitment.clone()]);
+
+    // Rotating the key invalidates signatures from the old one
+    let other_key = BytesN::from_array(&env, &other.verifying_key().to_bytes());
+    let non_admin = Address::generate(&env);
+    let result = client.try_set_deposit_issuer(&non_admin, &other_key);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    client.set_deposit_issuer(&admin, &other_key);
+    let next = BytesN::from_array(&env, &[3u8; 32]);
+    let signature = sign(&issuer, &next);
+    let result = client.try_deposit_signed(&alice, &next, &FIXED_AMOUNT, &signature);
+    assert!(result.is_err());
+    client.deposit_signed(&alice, &next, &FIXED_AMOUNT, &sign(&other, &next));
+
+    // Permissionless pools have no issuer and can't be given one
+    let client = PrivacyPoolsContractClient::new(&env, &permissionless_id);
+    assert_eq!(client.get_deposit_issuer(), None);
+    let result = client.try_set_deposit_issuer(&admin, &other_key);
+    assert_eq!(result, Err(Ok(Error::InvalidConfiguration)));
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+}
+
+#[test]
+fn test_compute_nullifier_hash() {
+    let env = Env::default();
+    let (_token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+
+    // The nullifier of circuits/build/sample_input.json
+    let mut nullifier = [0u8; 32];
+    nullifier[31] = 2;
+    let nullifier_hash = client.compute_nullifier_hash(&BytesN::from_array(&env, &nullifier));
+    assert_eq!(
+        nullifier_hash,
+        BytesN::from_array(
+            &env,
+            &[
+                0x43, 0x60, 0x64, 0x94, 0xef, 0xf4, 0x69, 0x53, 0xfa, 0x8e, 0x88, 0xc5, 0xf1, 0x34,
+                0xd4, 0xd3, 0xbc, 0x05, 0x7a, 0xbc, 0xa6, 0xcf, 0x80, 0x56, 0x41, 0xac, 0x16, 0xc9,
+                0x6e, 0x74, 0x3f, 0x23,
+            ],
+        )
+    );
+
+    let result = client.try_compute_nullifier_hash(&BytesN::from_array(&env, &SNARK_SCALAR_FIELD));
+    assert_eq!(result, Err(Ok(Error::InvalidFieldElement)));
+}
+
+#[test]
+fn test_self_test() {
+    let env = Env::default();
+    let (_token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
 
-    // Test initial balance
-    assert_eq!(client.get_balance(), 0);
-    assert_eq!(token_client.balance(&alice), 1000000000);
+    // The bundled proof is the one the tests build
+    assert_eq!(Bytes::from_array(&env, &self_test::PROOF), init_proof(&env));
+    assert_eq!(
+        Bytes::from_array(&env, &self_test::PUB_SIGNALS),
+        init_pub_signals(&env)
+    );
+    assert!(client.self_test());
 
-    // Test deposit - use the same commitment as in our proof
-    let commitment = BytesN::from_array(
-        &env,
-        &[
-            0x10, 0xcb, 0x63, 0x1d, 0x17, 0x4a, 0x98, 0xb2, 0x44, 0x0b, 0x68, 0xd2, 0xe5, 0x7d,
-            0xa2, 0xae, 0x9a, 0x13, 0xf7, 0xd1, 0xcc, 0xcb, 0x1f, 0x41, 0xa1, 0xdd, 0x3d, 0x69,
-            0xa2, 0x2f, 0xaa, 0xe9,
-        ],
+    // A pool pointed at a contract that isn't a verifier fails it
+    let not_a_verifier = env.register(MockToken, ());
+    let (_token_id, contract_id, _admin) =
+        setup_test_environment_with_verifier(&env, true, not_a_verifier);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    assert!(!client.self_test());
+}
+
+#[test]
+fn test_native_verify() {
+    let env = Env::default();
+    let (_token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+
+    let to_vec = |bytes: &Bytes| bytes.iter().collect::<std::vec::Vec<u8>>();
+    let vk = to_vec(&init_vk(&env));
+    let proof = to_vec(&init_proof(&env));
+    let pub_signals = to_vec(&init_pub_signals(&env));
+
+    // The native check agrees with the groth16_verifier contract on the same bytes
+    assert_eq!(
+        zk_verifier::native::verify(&vk, &proof, &pub_signals),
+        Ok(true)
     );
+    assert!(client.self_test());
 
-    // Mock authentication for alice
+    let mut tampered = pub_signals.clone();
+    tampered[4 + 32 + 31] ^= 1; // withdrawn value
+    assert_eq!(
+        zk_verifier::native::verify(&vk, &proof, &tampered),
+        Ok(false)
+    );
+    assert_eq!(
+        zk_verifier::native::verify(&vk, &proof, &pub_signals[..100]),
+        Ok(false)
+    );
+    assert_eq!(
+        zk_verifier::native::verify(&vk[..100], &proof, &pub_signals),
+        Err(zk_verifier::Groth16Error::MalformedVerifyingKey)
+    );
+}
+
+#[test]
+fn test_missing_configuration() {
+    let env = Env::default();
+    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
     env.mock_all_auths();
-    client.deposit(&alice, &commitment);
 
-    // Check commitments
-    let commitments = client.get_commitments();
-    assert_eq!(commitments.len(), 1);
-    assert_eq!(commitments.get(0).unwrap(), commitment);
+    let alice = Address::generate(&env);
+    token_client.mint(&alice, &1000000000);
 
-    // Check balances after deposit
-    assert_eq!(token_client.balance(&alice), 0); // Alice's balance should be 0
-    assert_eq!(token_client.balance(&contract_id), 1000000000); // Contract should have the tokens
+    // A partial upgrade could leave the configuration incomplete
+    env.as_contract(&contract_id, || {
+        env.storage().instance().remove(&TOKEN_KEY);
+        env.storage().instance().remove(&VK_KEY);
+    });
 
-    // Set an incorrect association root (different from the one in the proof)
-    let incorrect_association_root = BytesN::from_array(
-        &env,
-        &[
-            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
-            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
-            0xff, 0xff, 0xff, 0xff,
-        ],
+    assert_eq!(client.try_get_token(), Err(Ok(Error::NotInitialized)));
+    assert_eq!(client.get_balance(), 0);
+    assert_eq!(
+        client.try_get_verification_key(),
+        Err(Ok(Error::NotInitialized))
+    );
+    let commitment = BytesN::from_array(&env, &[1u8; 32]);
+    let result = client.try_deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    assert_eq!(result, Err(Ok(Error::NotInitialized)));
+    assert!(!client.self_test());
+
+    // The plain getters keep their types: empty lists, and a typed error for the admin
+    assert_eq!(client.get_commitments().len(), 0);
+    assert_eq!(client.get_nullifiers().len(), 0);
+    env.as_contract(&contract_id, || {
+        env.storage().instance().remove(&ADMINS_KEY);
+    });
+    assert_eq!(
+        client.try_get_admin(),
+        Err(Ok(soroban_sdk::Error::from_contract_error(
+            Error::NotInitialized as u32
+        )))
     );
+}
+
+#[test]
+fn test_emergency_refund() {
+    let env = Env::default();
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
     env.mock_all_auths();
-    let set_result = client.set_association_root(&admin, &incorrect_association_root);
+    token_client.mint(&alice, &(FIXED_AMOUNT * 2));
+
+    // A note built the same way as circuits/commitment.circom
+    let field = |byte: u8| {
+        let mut bytes = [0u8; 32];
+        bytes[31] = byte;
+        BytesN::from_array(&env, &bytes)
+    };
+    let (label, nullifier, secret) = (field(1), field(2), field(3));
+    let mut value_bytes = [0u8; 32];
+    value_bytes[16..].copy_from_slice(&FIXED_AMOUNT.to_be_bytes());
+    let poseidon = poseidon::Poseidon255::new(&env, 3);
+    let value_label = poseidon.hash_two(
+        &env,
+        &Fr::from_bytes(BytesN::from_array(&env, &value_bytes)),
+        &Fr::from_bytes(label.clone()),
+    );
+    let precommitment = poseidon.hash_two(
+        &env,
+        &Fr::from_bytes(nullifier.clone()),
+        &Fr::from_bytes(secret.clone()),
+    );
+    let commitment = poseidon
+        .hash_two(&env, &value_label, &precommitment)
+        .to_bytes();
+    let nullifier_hash = poseidon::Poseidon255::new(&env, 2)
+        .hash(&env, &Fr::from_bytes(nullifier.clone()))
+        .to_bytes();
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+
+    // A note claiming ten times the denomination it was paid with
+    let inflated_value = FIXED_AMOUNT * 10;
+    let inflated = client.compute_commitment(&inflated_value, &label, &field(5), &secret);
+    client.deposit(&alice, &inflated, &FIXED_AMOUNT, &None);
+
+    let claim = client.get_refund_claim(&commitment, &bob);
+    let result = client.try_request_refund(&claim);
+    assert_eq!(result, Err(Ok(Error::EmergencyNotActive)));
+    let result = client.try_announce_emergency(&bob);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+
+    // An announcement can be cancelled until refunds open
+    client.announce_emergency(&admin);
+    let active_at = env.ledger().timestamp() + EMERGENCY_TIMELOCK;
+    assert_eq!(
+        env.events().all(),
+        [EmergencyAnnounced { active_at }.to_xdr(&env, &contract_id)]
+    );
+    assert_eq!(client.get_emergency_active_at(), Some(active_at));
+    client.cancel_emergency(&admin);
+    assert_eq!(client.get_emergency_active_at(), None);
+
+    client.announce_emergency(&admin);
+    let active_at = env.ledger().timestamp() + EMERGENCY_TIMELOCK;
+    env.ledger()
+        .with_mut(|ledger| ledger.timestamp = active_at - 1);
+    let result = client.try_request_refund(&claim);
+    assert_eq!(result, Err(Ok(Error::EmergencyNotActive)));
+
+    env.ledger().with_mut(|ledger| ledger.timestamp = active_at);
+    assert!(client.is_emergency_active());
+    let result = client.try_cancel_emergency(&admin);
+    assert_eq!(result, Err(Ok(Error::TimelockActive)));
+
+    // The reveal is only accepted for a claim that is old enough
+    let result = client.try_emergency_refund(&bob, &FIXED_AMOUNT, &label, &nullifier, &secret);
+    assert_eq!(result, Err(Ok(Error::RefundNotReady)));
+    client.request_refund(&claim);
+    client.request_refund(&client.get_refund_claim(&inflated, &bob));
+    let result = client.try_emergency_refund(&bob, &FIXED_AMOUNT, &label, &nullifier, &secret);
+    assert_eq!(result, Err(Ok(Error::RefundNotReady)));
+
+    env.ledger()
+        .with_mut(|ledger| ledger.timestamp = active_at + REFUND_CLAIM_DELAY);
+    let result = client.try_emergency_refund(&bob, &FIXED_AMOUNT, &label, &nullifier, &field(4));
+    assert_eq!(result, Err(Ok(Error::UnknownCommitment)));
+    let result = client.try_emergency_refund(&alice, &FIXED_AMOUNT, &label, &nullifier, &secret);
+    assert_eq!(result, Err(Ok(Error::RefundNotReady)));
+
+    // A fixed pool only refunds the denomination, like a withdrawal
+    let result = client.try_emergency_refund(&bob, &inflated_value, &label, &field(5), &secret);
+    assert_eq!(result, Err(Ok(Error::AmountMismatch)));
+
+    client.emergency_refund(&bob, &FIXED_AMOUNT, &label, &nullifier, &secret);
     assert_eq!(
-        set_result,
-        vec![&env, String::from_str(&env, SUCCESS_ASSOCIATION_ROOT_SET)]
+        env.events().all(),
+        [EmergencyRefund {
+            commitment: commitment.clone(),
+            nullifier: nullifier_hash.clone(),
+            to: bob.clone(),
+            amount: FIXED_AMOUNT,
+        }
+        .to_xdr(&env, &contract_id)]
     );
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT);
+    assert!(client.is_nullifier_used(&nullifier_hash));
 
-    // Verify association set is configured
-    assert_eq!(client.has_association_set(), true);
+    let result = client.try_emergency_refund(&bob, &FIXED_AMOUNT, &label, &nullifier, &secret);
+    assert_eq!(result, Err(Ok(Error::NullifierUsed)));
+}
 
-    // Test withdraw with proof that has a different association root
-    let proof = init_proof(&env);
-    let pub_signals = init_pub_signals(&env); // This has the correct association root for the proof
+#[test]
+fn test_proof_versions() {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, admin) =
+        setup_test_environment_with_verifier(&env, true, verifier_id);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    env.mock_all_auths();
+    deposit_fixed(&client, &alice);
 
-    let result = client.withdraw(&bob, &proof, &pub_signals);
+    // Version 1 is the legacy layout behind a version byte
+    let legacy = init_pub_signals(&env);
+    let mut versioned = Bytes::from_array(&env, &[1]);
+    versioned.append(&legacy);
+    assert_eq!(client.get_proof_versions(), (0, 1));
     assert_eq!(
-        result,
-        vec![
-            &env,
-            String::from_str(&env, "Association set root mismatch")
-        ]
+        client.parse_public_signals(&versioned),
+        client.parse_public_signals(&legacy)
     );
+    let mut unknown = Bytes::from_array(&env, &[2]);
+    unknown.append(&legacy);
+    let result = client.try_parse_public_signals(&unknown);
+    assert_eq!(result, Err(Ok(Error::UnsupportedProofVersion)));
+
+    let result = client.try_set_proof_versions(&bob, &1, &1);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    for (min_version, max_version) in [(1, 0), (0, 2)] {
+        let result = client.try_set_proof_versions(&admin, &min_version, &max_version);
+        assert_eq!(result, Err(Ok(Error::InvalidConfiguration)));
+    }
 
-    // Check that balances are unchanged (withdrawal failed)
-    assert_eq!(token_client.balance(&bob), 0); // Bob should still have 0
-    assert_eq!(token_client.balance(&contract_id), 1000000000); // Contract should still have tokens
+    // Once version 0 is deprecated only versioned signals are accepted
+    client.set_proof_versions(&admin, &1, &1);
+    let proof = init_proof(&env);
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &legacy, &None);
+    assert_eq!(result, Err(Ok(Error::UnsupportedProofVersion)));
+    assert!(!client.verify_proof(&proof, &legacy));
+    assert!(client.verify_proof(&proof, &versioned));
 
-    // Check that no nullifier was stored when withdrawal failed
-    let nullifiers = client.get_nullifiers();
-    assert_eq!(nullifiers.len(), 0);
+    client.withdraw(&bob, &bob, &0, &proof, &versioned, &None);
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT);
 }
 
 #[test]
-fn test_set_association_root_non_admin() {
+fn test_parse_public_signals() {
     let env = Env::default();
     let (_token_id, contract_id, _admin) = setup_test_environment(&env);
     let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    env.mock_all_auths();
 
-    // Create a non-admin user
-    let non_admin = Address::generate(&env);
+    let pub_signals = init_pub_signals(&env);
+    let expected = PublicSignals::from_bytes(&env, &pub_signals).pub_signals;
+    let signals = client.parse_public_signals(&pub_signals);
+    assert_eq!(signals.nullifier_hash, expected.get(0).unwrap().to_bytes());
+    assert_eq!(signals.withdrawn_value, expected.get(1).unwrap().to_bytes());
+    assert_eq!(signals.state_root, expected.get(2).unwrap().to_bytes());
+    assert_eq!(
+        signals.association_root,
+        expected.get(3).unwrap().to_bytes()
+    );
+    assert_eq!(signals.context, None);
+    assert_eq!(signals.withdrawn_amount(), Some(FIXED_AMOUNT));
+
+    let context = BytesN::from_array(&env, &[7u8; 32]);
+    let signals =
+        client.parse_public_signals(&init_pub_signals_with_context(&env, context.clone()));
+    assert_eq!(signals.context, Some(context));
+
+    // Truncated, padded and miscounted signals are all rejected
+    let mut padded = pub_signals.clone();
+    padded.push_back(0);
+    let mut miscounted = pub_signals.clone();
+    miscounted.set(3, 3);
+    for malformed in [
+        Bytes::new(&env),
+        pub_signals.slice(..pub_signals.len() - 1),
+        padded,
+        miscounted,
+    ] {
+        let result = client.try_parse_public_signals(&malformed);
+        assert_eq!(result, Err(Ok(Error::MalformedPublicSignals)));
+    }
 
-    // Create a test association root
-    let association_root = BytesN::from_array(
-        &env,
-        &[
-            0x5d, 0x58, 0x26, 0xf9, 0xc9, 0x18, 0x7b, 0xdb, 0x21, 0x3f, 0x01, 0xde, 0xd6, 0xd2,
-            0x30, 0xe9, 0xf1, 0xab, 0x65, 0x3b, 0x5b, 0xee, 0x60, 0x36, 0x50, 0x4e, 0x82, 0xbc,
-            0x07, 0x16, 0xba, 0xa2,
-        ],
+    let bob = Address::generate(&env);
+    let result = client.try_withdraw(
+        &bob,
+        &bob,
+        &0,
+        &init_proof(&env),
+        &pub_signals.slice(..100),
+        &None,
     );
+    assert_eq!(result, Err(Ok(Error::MalformedPublicSignals)));
+}
 
-    // Mock authentication for the non-admin user
+#[test]
+fn test_public_signals_canonical_encoding() {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, _admin) =
+        setup_test_environment_with_verifier(&env, true, verifier_id);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
+
+    // The bundled proof's signals and the nullifier hash of circuits/build/sample_input.json
+    // round-trip through parsing unchanged
+    let bundled = Bytes::from_array(&env, &self_test::PUB_SIGNALS);
+    let mut nullifier_hash = [0u8; 32];
+    nullifier_hash.copy_from_slice(&self_test::PUB_SIGNALS[4..36]);
+    assert_eq!(
+        client.parse_public_signals(&bundled).nullifier_hash,
+        BytesN::from_array(&env, &nullifier_hash)
+    );
+    let mut nullifier = [0u8; 32];
+    nullifier[31] = 2;
+    let sample_hash = client.compute_nullifier_hash(&BytesN::from_array(&env, &nullifier));
+    let mut signals = PublicSignals::from_bytes(&env, &init_pub_signals(&env));
+    signals
+        .pub_signals
+        .set(0, Fr::from_bytes(sample_hash.clone()));
+    let parsed = client.parse_public_signals(&signals.to_bytes(&env));
+    assert_eq!(parsed.nullifier_hash, sample_hash);
+
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
     env.mock_all_auths();
+    token_client.mint(&alice, &(FIXED_AMOUNT * 2));
+    let commitment = sample_commitment(&env);
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[1u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
 
-    // Attempt to call set_association_root with non-admin should return error
-    let result = client.set_association_root(&non_admin, &association_root);
+    // nullifier_hash + modulus is the same field element to the verifier, so it would spend
+    // the note a second time under a different nullifier
+    let mut aliased = nullifier_hash;
+    let mut carry = 0u16;
+    for i in (0..32).rev() {
+        let sum = aliased[i] as u16 + SNARK_SCALAR_FIELD[i] as u16 + carry;
+        aliased[i] = sum as u8;
+        carry = sum >> 8;
+    }
+    assert_eq!(carry, 0);
+    let pub_signals = init_pub_signals(&env);
+    let mut aliased_signals = pub_signals.clone();
+    aliased_signals.copy_from_slice(4, &aliased);
 
-    // Verify that the call returned an error message
-    assert_eq!(result, vec![&env, String::from_str(&env, ERROR_ONLY_ADMIN)]);
+    let proof = init_proof(&env);
+    let result = client.try_parse_public_signals(&aliased_signals);
+    assert_eq!(result, Err(Ok(Error::MalformedPublicSignals)));
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &aliased_signals, &None);
+    assert_eq!(result, Err(Ok(Error::MalformedPublicSignals)));
+
+    client.withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    let result = client.try_withdraw(&bob, &bob, &0, &proof, &aliased_signals, &None);
+    assert_eq!(result, Err(Ok(Error::MalformedPublicSignals)));
+    assert_eq!(token_client.balance(&bob), FIXED_AMOUNT);
+}
 
-    // Verify that no association root was set (should still be zero)
-    let stored_root = client.get_association_root();
-    let zero_root = BytesN::from_array(&env, &[0u8; 32]);
-    assert_eq!(
-        stored_root, zero_root,
-        "Association root should not be set by non-admin"
-    );
+#[test]
+fn test_public_signal_offsets() {
+    let env = Env::default();
+    let (_token_id, contract_id, _admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
 
-    // Verify that has_association_set returns false
+    // Give every signal a distinct value, so reading any field from the wrong slot shows up
+    let mut pub_signals = Bytes::from_array(&env, &6u32.to_be_bytes());
+    for index in 0..6u8 {
+        let mut value = [0u8; 32];
+        value[1] = index + 1;
+        value[31] = 0xa0 + index;
+        pub_signals.append(&Bytes::from_array(&env, &value));
+    }
+    let at_offset = |index: u32| {
+        let start = 4 + index * 32;
+        let mut value = [0u8; 32];
+        pub_signals
+            .slice(start..start + 32)
+            .copy_into_slice(&mut value);
+        BytesN::from_array(&env, &value)
+    };
+
+    let signals = client.parse_public_signals(&pub_signals);
+    assert_eq!(signals.nullifier_hash, at_offset(0));
+    assert_eq!(signals.withdrawn_value, at_offset(1));
+    assert_eq!(signals.state_root, at_offset(2));
+    assert_eq!(signals.association_root, at_offset(3));
+    assert_eq!(signals.context, Some(at_offset(4)));
+    assert_eq!(signals.change_commitment, Some(at_offset(5)));
+
+    // The verifier reads the same bytes through zk_verifier's layout, so both agree on every
+    // field, with or without a version byte
+    let verified = PublicSignals::from_bytes(&env, &pub_signals).pub_signals;
+    assert_eq!(signals.nullifier_hash, verified.get(0).unwrap().to_bytes());
+    assert_eq!(signals.withdrawn_value, verified.get(1).unwrap().to_bytes());
+    assert_eq!(signals.state_root, verified.get(2).unwrap().to_bytes());
     assert_eq!(
-        client.has_association_set(),
-        false,
-        "Should not have association set after failed non-admin call"
+        signals.association_root,
+        verified.get(3).unwrap().to_bytes()
     );
+    let mut versioned = Bytes::from_array(&env, &[1u8]);
+    versioned.append(&pub_signals);
+    assert_eq!(client.parse_public_signals(&versioned), signals);
 }
 
 #[test]
-#[should_panic(expected = "Association root must be set before withdrawal")]
-fn test_withdraw_requires_association_root() {
+fn test_verify_proof() {
     let env = Env::default();
-    let (token_id, contract_id, _admin) = setup_test_environment(&env);
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
 
-    // Create test addresses
     let alice = Address::generate(&env);
     let bob = Address::generate(&env);
+    env.mock_all_auths();
+    token_client.mint(&alice, &1000000000);
+
+    let proof = init_proof(&env);
+    let pub_signals = init_pub_signals(&env);
+
+    // Nothing has been deposited yet, so the proof's root is unknown
+    assert!(!client.verify_proof(&proof, &pub_signals));
+
+    let commitment = sample_commitment(&env);
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    client.set_association_root(&admin, &BytesN::from_array(&env, &[1u8; 32]));
+    assert!(!client.verify_proof(&proof, &pub_signals));
+
+    let association_root = BytesN::from_array(
+        &env,
+        &[
+            0x5d, 0x58, 0x26, 0xf9, 0xc9, 0x18, 0x7b, 0xdb, 0x21, 0x3f, 0x01, 0xde, 0xd6, 0xd2,
+            0x30, 0xe9, 0xf1, 0xab, 0x65, 0x3b, 0x5b, 0xee, 0x60, 0x36, 0x50, 0x4e, 0x82, 0xbc,
+            0x07, 0x16, 0xba, 0xa2,
+        ],
+    );
+    client.set_association_root(&admin, &association_root);
+    assert!(client.verify_proof(&proof, &pub_signals));
+    assert!(!client.verify_proof(&proof, &pub_signals.slice(..100)));
+
+    // Verifying doesn't spend the nullifier, so the withdrawal still goes through
+    assert_eq!(client.get_nullifiers().len(), 0);
+    client.withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert!(!client.verify_proof(&proof, &pub_signals));
+}
 
+#[test]
+fn test_can_withdraw() {
+    let env = Env::default();
+    let (token_id, contract_id, admin) = setup_test_environment(&env);
     let client = PrivacyPoolsContractClient::new(&env, &contract_id);
     let token_client = MockTokenClient::new(&env, &token_id);
 
-    // Mint tokens to alice
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
     env.mock_all_auths();
-    token_client.mint(&alice, &1000000000);
+    token_client.mint(&alice, &(FIXED_AMOUNT * 2));
 
-    // Test deposit
-    let commitment = BytesN::from_array(
+    let proof = init_proof(&env);
+    let pub_signals = init_pub_signals(&env);
+    assert_eq!(
+        client.can_withdraw(&proof, &pub_signals),
+        WithdrawCheck::UnknownStateRoot
+    );
+
+    let commitment = sample_commitment(&env);
+    client.deposit(&alice, &commitment, &FIXED_AMOUNT, &None);
+    client.deposit(
+        &alice,
+        &BytesN::from_array(&env, &[1u8; 32]),
+        &FIXED_AMOUNT,
+        &None,
+    );
+    client.set_association_root(&admin, &BytesN::from_array(&env, &[1u8; 32]));
+    assert_eq!(
+        client.can_withdraw(&proof, &pub_signals),
+        WithdrawCheck::AssociationRootMismatch
+    );
+
+    let association_root = BytesN::from_array(
         &env,
         &[
-            0x10, 0xcb, 0x63, 0x1d, 0x17, 0x4a, 0x98, 0xb2, 0x44, 0x0b, 0x68, 0xd2, 0xe5, 0x7d,
-            0xa2, 0xae, 0x9a, 0x13, 0xf7, 0xd1, 0xcc, 0xcb, 0x1f, 0x41, 0xa1, 0xdd, 0x3d, 0x69,
-            0xa2, 0x2f, 0xaa, 0xe9,
+            0x5d, 0x58, 0x26, 0xf9, 0xc9, 0x18, 0x7b, 0xdb, 0x21, 0x3f, 0x01, 0xde, 0xd6, 0xd2,
+            0x30, 0xe9, 0xf1, 0xab, 0x65, 0x3b, 0x5b, 0xee, 0x60, 0x36, 0x50, 0x4e, 0x82, 0xbc,
+            0x07, 0x16, 0xba, 0xa2,
         ],
     );
+    client.set_association_root(&admin, &association_root);
+    assert_eq!(client.can_withdraw(&proof, &pub_signals), WithdrawCheck::Ok);
+    assert_eq!(
+        client.can_withdraw(&proof, &pub_signals.slice(..100)),
+        WithdrawCheck::MalformedPublicSignals
+    );
 
-    // Mock authentication for alice
-    env.mock_all_auths();
-    client.deposit(&alice, &commitment);
+    client.pause(&admin);
+    assert_eq!(
+        client.can_withdraw(&proof, &pub_signals),
+        WithdrawCheck::Paused
+    );
+    client.unpause(&admin);
 
-    // Check balances after deposit
-    assert_eq!(token_client.balance(&alice), 0); // Alice's balance should be 0
-    assert_eq!(token_client.balance(&contract_id), 1000000000); // Contract should have the tokens
+    // The pool can't pay the note once some of its balance is gone
+    token_client.transfer(&contract_id, &alice, &(FIXED_AMOUNT + 1));
+    assert_eq!(
+        client.can_withdraw(&proof, &pub_signals),
+        WithdrawCheck::InsufficientBalance
+    );
+    token_client.transfer(&alice, &contract_id, &(FIXED_AMOUNT + 1));
 
-    // Verify no association set is configured
-    assert_eq!(client.has_association_set(), false);
+    // Checking doesn't spend the nullifier
+    assert_eq!(client.get_nullifiers().len(), 0);
+    client.withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+    assert_eq!(
+        client.can_withdraw(&proof, &pub_signals),
+        WithdrawCheck::NullifierUsed
+    );
+}
 
-    // Verify state before withdrawal attempt
-    assert_eq!(token_client.balance(&bob), 0); // Bob should have 0
-    assert_eq!(token_client.balance(&contract_id), 1000000000); // Contract should have tokens
-    assert_eq!(client.get_nullifiers().len(), 0); // No nullifiers should be stored
+// Property tests: seeded random sequences of deposits and withdrawals, with the pool's
+// invariants checked after every step. To cover a new invariant, add it to
+// `check_pool_invariants`; to cover a new operation, add a `PoolStep` variant.
 
-    // Attempt to withdraw without setting association root - this should panic
-    let proof = init_proof(&env);
-    let pub_signals = init_pub_signals(&env);
+// xorshift64, so every failing sequence can be replayed from its seed
+struct TestRng(u64);
+
+impl TestRng {
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum PoolStep {
+    Deposit,
+    Withdraw,
+    // Resubmits a nullifier that has already been spent
+    ReplayWithdraw,
+    // Submits the same proof twice in one ledger, as competing relayers would
+    RacingWithdraws,
+}
+
+const POOL_STEPS: [PoolStep; 4] = [
+    PoolStep::Deposit,
+    PoolStep::Withdraw,
+    PoolStep::ReplayWithdraw,
+    PoolStep::RacingWithdraws,
+];
+
+// What the harness expects the pool to hold, tracked independently of the contract
+struct PoolModel {
+    balance: i128,
+    deposits: u32,
+    spent: Vec<BytesN<32>>,
+}
+
+// The bundled signals with their nullifier and state root replaced, for the mock verifier
+fn pub_signals_for_note(env: &Env, nullifier_hash: &BytesN<32>, state_root: &BytesN<32>) -> Bytes {
+    let mut pub_signals = PublicSignals::from_bytes(env, &init_pub_signals(env));
+    pub_signals
+        .pub_signals
+        .set(0, Fr::from_bytes(nullifier_hash.clone()));
+    pub_signals
+        .pub_signals
+        .set(2, Fr::from_bytes(state_root.clone()));
+    pub_signals.to_bytes(env)
+}
+
+fn check_pool_invariants(
+    client: &PrivacyPoolsContractClient,
+    token_client: &MockTokenClient,
+    contract_id: &Address,
+    model: &PoolModel,
+) {
+    // The pool holds exactly what was deposited and not withdrawn, which is never negative
+    let balance = token_client.balance(contract_id);
+    assert!(balance >= 0);
+    assert_eq!(balance, model.balance);
+    assert_eq!(client.get_balance(), model.balance);
+    assert_eq!(client.get_commitment_count(), model.deposits);
+
+    // Nothing reaches the pool outside a deposit here, so the lifetime totals account for it
+    let stats = client.get_pool_stats();
+    assert_eq!(stats.total_deposited, model.deposits as i128 * FIXED_AMOUNT);
+    assert_eq!(stats.total_deposited - stats.total_withdrawn, model.balance);
+
+    // Every accepted nullifier is recorded once, in the order it was spent
+    assert_eq!(client.get_nullifiers(), model.spent);
+    for (index, nullifier) in model.spent.iter().enumerate() {
+        assert!(client.is_nullifier_used(&nullifier));
+        assert_eq!(model.spent.first_index_of(&nullifier), Some(index as u32));
+    }
+}
+
+fn run_pool_sequence(seed: u64, steps: u32) {
+    let env = Env::default();
+    let verifier_id = env.register(MockVerifier, ());
+    let (token_id, contract_id, _admin) =
+        setup_test_environment_with_verifier(&env, true, verifier_id);
+    let client = PrivacyPoolsContractClient::new(&env, &contract_id);
+    let token_client = MockTokenClient::new(&env, &token_id);
 
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
     env.mock_all_auths();
-    client.withdraw(&bob, &proof, &pub_signals);
-}
\ No newline at end of file
+
+    let mut rng = TestRng(seed);
+    let mut model = PoolModel {
+        balance: 0,
+        deposits: 0,
+        spent: vec![&env],
+    };
+    let proof = init_proof(&env);
+    let mut next_note = 1u32;
+    let mut fresh_nullifier = |env: &Env| {
+        let mut bytes = [0u8; 32];
+        bytes[28..].copy_from_slice(&next_note.to_be_bytes());
+        next_note += 1;
+        BytesN::from_array(env, &bytes)
+    };
+
+    for _ in 0..steps {
+        let step = POOL_STEPS[rng.below(POOL_STEPS.len() as u64) as usize];
+        let root = client.get_merkle_root();
+        match step {
+            PoolStep::Deposit => {
+                let mut commitment = [0u8; 32];
+                commitment[24..].copy_from_slice(&(seed ^ model.deposits as u64).to_be_bytes());
+                commitment[0] = 1;
+                token_client.mint(&alice, &FIXED_AMOUNT);
+                client.deposit(
+                    &alice,
+                    &BytesN::from_array(&env, &commitment),
+                    &FIXED_AMOUNT,
+                    &None,
+                );
+                model.balance += FIXED_AMOUNT;
+                model.deposits += 1;
+            }
+            PoolStep::Withdraw | PoolStep::RacingWithdraws => {
+                let nullifier = fresh_nullifier(&env);
+                let pub_signals = pub_signals_for_note(&env, &nullifier, &root);
+                let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+                // The empty tree's root is never in the history, so nothing can be proven yet
+                if model.deposits == 0 {
+                    assert_eq!(result, Err(Ok(Error::UnknownStateRoot)));
+                    continue;
+                }
+                if model.balance < FIXED_AMOUNT {
+                    assert_eq!(result, Err(Ok(Error::InsufficientBalance)));
+                    continue;
+                }
+                assert_eq!(result.unwrap().unwrap().amount, FIXED_AMOUNT);
+                model.balance -= FIXED_AMOUNT;
+                model.spent.push_back(nullifier);
+
+                if let PoolStep::RacingWithdraws = step {
+                    let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+                    assert_eq!(result, Err(Ok(Error::NullifierUsed)));
+                }
+            }
+            PoolStep::ReplayWithdraw => {
+                if model.spent.is_empty() {
+                    continue;
+                }
+                let index = rng.below(model.spent.len() as u64) as u32;
+                let nullifier = model.spent.get(index).unwrap();
+                let pub_signals = pub_signals_for_note(&env, &nullifier, &root);
+                let result = client.try_withdraw(&bob, &bob, &0, &proof, &pub_signals, &None);
+                assert_eq!(result, Err(Ok(Error::NullifierUsed)));
+            }
+        }
+
+        check_pool_invariants(&client, &token_client, &contract_id, &model);
+    }
+}
+
+#[test]
+fn test_random_sequences_keep_pool_invariants() {
+    for seed in [0x9e37_79b9_7f4a_7c15, 0x2545_f491_4f6c_dd1d, 7] {
+        run_pool_sequence(seed, 24);
+    }
+}
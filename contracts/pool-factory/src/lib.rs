@@ -0,0 +1,360 @@
+#![no_std]
+
+use soroban_sdk::{
+    contract, contracterror, contractevent, contractimpl, symbol_short, vec, xdr::ToXdr, Address,
+    Bytes, BytesN, Env, Symbol, Vec,
+};
+
+#[cfg(test)]
+mod test;
+
+mod opaque_pool {
+    soroban_sdk::contractimport!(file = "../../target/wasm32v1-none/release/opaque.wasm");
+}
+
+// Contract errors
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    OnlyAdmin = 1,
+    PoolExists = 2,
+    UnknownToken = 3,
+    InvalidDenominations = 4,
+    UnknownTier = 5,
+    DemoBuild = 6,
+}
+
+// Contract events
+#[contractevent(topics = ["pool_deployed"], data_format = "vec")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PoolDeployed {
+    pub token: Address,
+    pub pool: Address,
+}
+
+#[contractevent(topics = ["tier_deployed"], data_format = "vec")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TierDeployed {
+    pub token: Address,
+    pub denomination: i128,
+    pub pool: Address,
+}
+
+// Storage keys
+const ADMIN_KEY: Symbol = symbol_short!("admin");
+const POOL_WASM_KEY: Symbol = symbol_short!("wasm");
+const GROTH16_VERIFIER_KEY: Symbol = symbol_short!("g16v");
+const TOKENS_KEY: Symbol = symbol_short!("tokens");
+const POOL_KEY: Symbol = symbol_short!("pool");
+const DENOMINATIONS_KEY: Symbol = symbol_short!("denoms");
+const TIER_POOLS_KEY: Symbol = symbol_short!("tiers");
+
+const POSEIDON_PARAMS_ID: u32 = 1; // Parameter set of the bundled withdrawal circuit
+
+/// Deploys one privacy pool per token
+///
+/// Each pool is an independent `opaque` contract with its own tree, nullifiers and root history,
+/// so every asset keeps a separate anonymity set and the pool contract stays single-token.
+#[contract]
+pub struct PoolFactory;
+
+#[contractimpl]
+impl PoolFactory {
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `admin` - The factory admin, also set as admin of every deployed pool
+    /// * `pool_wasm_hash` - Hash of the uploaded `opaque` pool wasm
+    /// * `groth16_verifier` - The verifier contract shared by all pools
+    pub fn __constructor(
+        env: &Env,
+        admin: Address,
+        pool_wasm_hash: BytesN<32>,
+        groth16_verifier: Address,
+    ) {
+        env.storage().instance().set(&ADMIN_KEY, &admin);
+        env.storage()
+            .instance()
+            .set(&POOL_WASM_KEY, &pool_wasm_hash);
+        env.storage()
+            .instance()
+            .set(&GROTH16_VERIFIER_KEY, &groth16_verifier);
+    }
+
+    /// Deploys a privacy pool for `token`
+    ///
+    /// The pool address is derived from the token address, so it is deterministic and there is
+    /// at most one pool per token.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `token` - The token the pool holds
+    /// * `vk_bytes` - The serialized verification key for the withdrawal circuit
+    /// * `denomination` - The amount every deposit and withdrawal must be, or `None` for a pool
+    ///   that accepts any amount
+    /// * `root_history_size` - Number of recent roots withdrawals may reference
+    /// * `tree_depth` - State tree depth of the withdrawal circuit
+    ///
+    /// # Returns
+    ///
+    /// * The address of the new pool
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::PoolExists)` if a pool for `token` was already deployed
+    /// * `Err(Error::DemoBuild)` if the pool wasm was built with the `demo` feature
+    pub fn deploy_pool(
+        env: &Env,
+        caller: Address,
+        token: Address,
+        vk_bytes: Bytes,
+        denomination: Option<i128>,
+        root_history_size: u32,
+        tree_depth: u32,
+    ) -> Result<Address, Error> {
+        caller.require_auth();
+
+        let admin: Address = env.storage().instance().get(&ADMIN_KEY).unwrap();
+        if caller != admin {
+            return Err(Error::OnlyAdmin);
+        }
+
+        let pool_key = (POOL_KEY, token.clone());
+        if env.storage().persistent().has(&pool_key) {
+            return Err(Error::PoolExists);
+        }
+
+        let wasm_hash: BytesN<32> = env.storage().instance().get(&POOL_WASM_KEY).unwrap();
+        let groth16_verifier: Address =
+            env.storage().instance().get(&GROTH16_VERIFIER_KEY).unwrap();
+        let salt = env.crypto().sha256(&token.clone().to_xdr(env));
+        let pool = env.deployer().with_current_contract(salt).deploy_v2(
+            wasm_hash,
+            (
+                vk_bytes,
+                token.clone(),
+                admin,
+                groth16_verifier,
+                opaque_pool::PoolParams {
+                    denomination,
+                    tree_depth,
+                    root_history_size,
+                    poseidon_params_id: POSEIDON_PARAMS_ID,
+                },
+                Self::permissionless(),
+            ),
+        );
+        Self::require_verifying_pool(env, &pool)?;
+
+        env.storage().persistent().set(&pool_key, &pool);
+        Self::add_supported_token(env, &token);
+
+        PoolDeployed {
+            token,
+            pool: pool.clone(),
+        }
+        .publish(env);
+
+        Ok(pool)
+    }
+
+    /// Deploys one fixed-denomination pool per tier for `token`, e.g. 1, 10 and 100 units
+    ///
+    /// Each tier is an independent pool with its own tree, root history and nullifiers, so a
+    /// withdrawal proves membership in the tier it exits and every note in a tier looks the
+    /// same. Depositors pick a tier with `get_tier_pool(token, tier)` and deposit its
+    /// denomination there. Tier pools sit alongside the pool `deploy_pool` creates, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `env` - The Soroban environment
+    /// * `caller` - The address of the caller (must be authenticated and be the admin)
+    /// * `token` - The token the pools hold
+    /// * `vk_bytes` - The serialized verification key for the withdrawal circuit
+    /// * `denominations` - The tiers' amounts, in increasing order
+    /// * `root_history_size` - Number of recent roots withdrawals may reference
+    /// * `tree_depth` - State tree depth of the withdrawal circuit
+    ///
+    /// # Returns
+    ///
+    /// * The addresses of the new pools, in the order of `denominations`
+    /// * `Err(Error::OnlyAdmin)` if the caller is not the admin
+    /// * `Err(Error::PoolExists)` if tiers were already deployed for `token`
+    /// * `Err(Error::DemoBuild)` if the pool wasm was built with the `demo` feature
+    /// * `Err(Error::InvalidDenominations)` if `denominations` is empty, or not positive and
+    ///   strictly increasing
+    ///
+    /// # Events
+    ///
+    /// * Publishes `TierDeployed` for each pool
+    pub fn deploy_tiered_pools(
+        env: &Env,
+        caller: Address,
+        token: Address,
+        vk_bytes: Bytes,
+        denominations: Vec<i128>,
+        root_history_size: u32,
+        tree_depth: u32,
+    ) -> Result<Vec<Address>, Error> {
+        caller.require_auth();
+
+        let admin: Address = env.storage().instance().get(&ADMIN_KEY).unwrap();
+        if caller != admin {
+            return Err(Error::OnlyAdmin);
+        }
+
+        let denominations_key = (DENOMINATIONS_KEY, token.clone());
+        if env.storage().persistent().has(&denominations_key) {
+            return Err(Error::PoolExists);
+        }
+
+        let mut previous = 0;
+        for denomination in denominations.iter() {
+            if denomination <= previous {
+                return Err(Error::InvalidDenominations);
+            }
+            previous = denomination;
+        }
+        if denominations.is_empty() {
+            return Err(Error::InvalidDenominations);
+        }
+
+        let wasm_hash: BytesN<32> = env.storage().instance().get(&POOL_WASM_KEY).unwrap();
+        let groth16_verifier: Address =
+            env.storage().instance().get(&GROTH16_VERIFIER_KEY).unwrap();
+        let mut pools = vec![env];
+        for denomination in denominations.iter() {
+            let salt = env
+                .crypto()
+                .sha256(&(token.clone(), denomination).to_xdr(env));
+            let pool = env.deployer().with_current_contract(salt).deploy_v2(
+                wasm_hash.clone(),
+                (
+                    vk_bytes.clone(),
+                    token.clone(),
+                    admin.clone(),
+                    groth16_verifier.clone(),
+                    opaque_pool::PoolParams {
+                        denomination: Some(denomination),
+                        tree_depth,
+                        root_history_size,
+                        poseidon_params_id: POSEIDON_PARAMS_ID,
+                    },
+                    Self::permissionless(),
+                ),
+            );
+            Self::require_verifying_pool(env, &pool)?;
+
+            TierDeployed {
+                token: token.clone(),
+                denomination,
+                pool: pool.clone(),
+            }
+            .publish(env);
+            pools.push_back(pool);
+        }
+
+        env.storage()
+            .persistent()
+            .set(&denominations_key, &denominations);
+        env.storage()
+            .persistent()
+            .set(&(TIER_POOLS_KEY, token.clone()), &pools);
+        Self::add_supported_token(env, &token);
+
+        Ok(pools)
+    }
+
+    /// Gets the tier amounts deployed for `token`, in increasing order
+    ///
+    /// # Returns
+    ///
+    /// * The denominations, empty if `token` has no tiered pools
+    pub fn get_denominations(env: &Env, token: Address) -> Vec<i128> {
+        env.storage()
+            .persistent()
+            .get(&(DENOMINATIONS_KEY, token))
+            .unwrap_or(vec![env])
+    }
+
+    /// Gets the pool of a tier, indexed like `get_denominations`
+    ///
+    /// # Returns
+    ///
+    /// * The pool address
+    /// * `Err(Error::UnknownToken)` if no tiers exist for `token`
+    /// * `Err(Error::UnknownTier)` if `tier` is out of range
+    pub fn get_tier_pool(env: &Env, token: Address, tier: u32) -> Result<Address, Error> {
+        let pools: Vec<Address> = env
+            .storage()
+            .persistent()
+            .get(&(TIER_POOLS_KEY, token))
+            .ok_or(Error::UnknownToken)?;
+        pools.get(tier).ok_or(Error::UnknownTier)
+    }
+
+    /// The access options of every factory pool: no deposit issuer and no admin nullifier
+    /// removal, so they stay permissionless
+    fn permissionless() -> opaque_pool::AccessConfig {
+        opaque_pool::AccessConfig {
+            deposit_issuer: None,
+            allow_admin_nullifier_ops: false,
+        }
+    }
+
+    /// Fails if `pool` is a `demo` build, which pays out withdrawals without verifying their
+    /// proofs; the failed call rolls the deployment back
+    fn require_verifying_pool(env: &Env, pool: &Address) -> Result<(), Error> {
+        if opaque_pool::Client::new(env, pool).is_demo_build() {
+            return Err(Error::DemoBuild);
+        }
+        Ok(())
+    }
+
+    /// Records `token` in `supported_tokens` the first time one of its pools is deployed
+    fn add_supported_token(env: &Env, token: &Address) {
+        let mut tokens = Self::supported_tokens(env);
+        if !tokens.contains(token) {
+            tokens.push_back(token.clone());
+            env.storage().instance().set(&TOKENS_KEY, &tokens);
+        }
+    }
+
+    /// Gets every token that has a pool, in deployment order
+    pub fn supported_tokens(env: &Env) -> Vec<Address> {
+        env.storage()
+            .instance()
+            .get(&TOKENS_KEY)
+            .unwrap_or(vec![env])
+    }
+
+    /// Gets the pool deployed for `token`
+    ///
+    /// # Returns
+    ///
+    /// * The pool address
+    /// * `Err(Error::UnknownToken)` if no pool exists for `token`
+    pub fn get_pool(env: &Env, token: Address) -> Result<Address, Error> {
+        env.storage()
+            .persistent()
+            .get(&(POOL_KEY, token))
+            .ok_or(Error::UnknownToken)
+    }
+
+    /// Gets the balance held by the pool for `token`
+    ///
+    /// # Returns
+    ///
+    /// * The pool's balance of `token`
+    /// * `Err(Error::UnknownToken)` if no pool exists for `token`
+    pub fn get_balance(env: &Env, token: Address) -> Result<i128, Error> {
+        let pool = Self::get_pool(env, token)?;
+        Ok(opaque_pool::Client::new(env, &pool).get_balance())
+    }
+
+    /// Gets the factory admin
+    pub fn get_admin(env: &Env) -> Address {
+        env.storage().instance().get(&ADMIN_KEY).unwrap()
+    }
+}
@@ -0,0 +1,166 @@
+#![cfg(test)]
+use super::*;
+use soroban_sdk::testutils::Address as TestAddress;
+use soroban_sdk::{token, vec, Address, Bytes, Env};
+
+fn setup_factory(env: &Env) -> (Address, Address) {
+    env.cost_estimate().budget().reset_unlimited();
+
+    let pool_wasm_hash = env.deployer().upload_contract_wasm(opaque_pool::WASM);
+    let admin = Address::generate(env);
+    let groth16_verifier = Address::generate(env);
+    let factory_id = env.register(
+        PoolFactory,
+        (admin.clone(), pool_wasm_hash, groth16_verifier),
+    );
+
+    (factory_id, admin)
+}
+
+// A verification key with the layout pools check at deployment: alpha, beta, gamma, delta and
+// one IC point. The points are never used, since these tests don't withdraw
+fn test_vk(env: &Env) -> Bytes {
+    let mut vk = Bytes::from_array(env, &[0u8; 96 + 3 * 192]);
+    vk.extend_from_array(&1u32.to_be_bytes());
+    vk.extend_from_array(&[0u8; 96]);
+    vk
+}
+
+fn deploy(env: &Env, client: &PoolFactoryClient, admin: &Address, token: &Address) -> Address {
+    client.deploy_pool(
+        admin,
+        token,
+        &test_vk(env),
+        &Some(1_000_000_000),
+        &30u32,
+        &20u32,
+    )
+}
+
+#[test]
+fn test_deploy_pool_per_token() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (factory_id, admin) = setup_factory(&env);
+    let client = PoolFactoryClient::new(&env, &factory_id);
+
+    let xlm = env
+        .register_stellar_asset_contract_v2(Address::generate(&env))
+        .address();
+    let usdc = env
+        .register_stellar_asset_contract_v2(Address::generate(&env))
+        .address();
+
+    assert_eq!(client.supported_tokens().len(), 0);
+
+    let xlm_pool = deploy(&env, &client, &admin, &xlm);
+    let usdc_pool = deploy(&env, &client, &admin, &usdc);
+    assert_ne!(xlm_pool, usdc_pool);
+
+    assert_eq!(
+        client.supported_tokens(),
+        vec![&env, xlm.clone(), usdc.clone()]
+    );
+    assert_eq!(client.get_pool(&xlm), xlm_pool);
+    assert_eq!(client.get_pool(&usdc), usdc_pool);
+
+    // Each pool is configured for its own token and administered by the factory admin
+    let pool_client = opaque_pool::Client::new(&env, &usdc_pool);
+    assert_eq!(pool_client.get_admin(), admin);
+    assert_eq!(pool_client.get_merkle_depth(), 20);
+
+    // Balances are tracked per token
+    token::StellarAssetClient::new(&env, &usdc).mint(&usdc_pool, &500);
+    assert_eq!(client.get_balance(&usdc), 500);
+    assert_eq!(client.get_balance(&xlm), 0);
+}
+
+#[test]
+fn test_deploy_pool_errors() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (factory_id, admin) = setup_factory(&env);
+    let client = PoolFactoryClient::new(&env, &factory_id);
+
+    let token = env
+        .register_stellar_asset_contract_v2(Address::generate(&env))
+        .address();
+    let non_admin = Address::generate(&env);
+
+    let result = client.try_deploy_pool(&non_admin, &token, &test_vk(&env), &None, &30, &20);
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    assert_eq!(client.try_get_pool(&token), Err(Ok(Error::UnknownToken)));
+    assert_eq!(client.try_get_balance(&token), Err(Ok(Error::UnknownToken)));
+
+    deploy(&env, &client, &admin, &token);
+    let result = client.try_deploy_pool(&admin, &token, &test_vk(&env), &None, &30, &20);
+    assert_eq!(result, Err(Ok(Error::PoolExists)));
+    assert_eq!(client.supported_tokens().len(), 1);
+}
+
+#[test]
+fn test_deploy_tiered_pools() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (factory_id, admin) = setup_factory(&env);
+    let client = PoolFactoryClient::new(&env, &factory_id);
+
+    let token = env
+        .register_stellar_asset_contract_v2(Address::generate(&env))
+        .address();
+    let denominations = vec![&env, 10_000_000i128, 100_000_000, 1_000_000_000];
+
+    let non_admin = Address::generate(&env);
+    let result = client.try_deploy_tiered_pools(
+        &non_admin,
+        &token,
+        &test_vk(&env),
+        &denominations,
+        &30,
+        &20,
+    );
+    assert_eq!(result, Err(Ok(Error::OnlyAdmin)));
+    for invalid in [
+        vec![&env],
+        vec![&env, 0i128, 10],
+        vec![&env, 100i128, 10],
+        vec![&env, 10i128, 10],
+    ] {
+        let result =
+            client.try_deploy_tiered_pools(&admin, &token, &test_vk(&env), &invalid, &30, &20);
+        assert_eq!(result, Err(Ok(Error::InvalidDenominations)));
+    }
+    assert_eq!(client.get_denominations(&token).len(), 0);
+    assert_eq!(
+        client.try_get_tier_pool(&token, &0),
+        Err(Ok(Error::UnknownToken))
+    );
+
+    let pools =
+        client.deploy_tiered_pools(&admin, &token, &test_vk(&env), &denominations, &30, &20);
+    assert_eq!(pools.len(), 3);
+    assert_eq!(client.get_denominations(&token), denominations);
+    assert_eq!(client.supported_tokens(), vec![&env, token.clone()]);
+
+    // Every tier is a separate pool that only takes its own denomination
+    for (tier, denomination) in denominations.iter().enumerate() {
+        let pool = client.get_tier_pool(&token, &(tier as u32));
+        assert_eq!(pool, pools.get(tier as u32).unwrap());
+        let pool_client = opaque_pool::Client::new(&env, &pool);
+        assert_eq!(pool_client.get_denomination(), Some(denomination));
+        assert_eq!(pool_client.get_commitment_count(), 0);
+    }
+    assert_eq!(
+        client.try_get_tier_pool(&token, &3),
+        Err(Ok(Error::UnknownTier))
+    );
+
+    let result =
+        client.try_deploy_tiered_pools(&admin, &token, &test_vk(&env), &denominations, &30, &20);
+    assert_eq!(result, Err(Ok(Error::PoolExists)));
+
+    // The regular pool for the token can still be deployed next to the tiers
+    let pool = deploy(&env, &client, &admin, &token);
+    assert!(!pools.contains(&pool));
+    assert_eq!(client.supported_tokens().len(), 1);
+}
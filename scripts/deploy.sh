@@ -58,7 +58,9 @@ stellar contract deploy \
   --vk_bytes $VK_HEX \
   --token_address $XLM_SAC \
   --admin $ADMIN_ADDRESS \
-  --groth16_verifier $GROTH16_VERIFIER_ID
+  --groth16_verifier $GROTH16_VERIFIER_ID \
+  --params '{"denomination": "1000000000", "tree_depth": 20, "root_history_size": 30, "poseidon_params_id": 1}' \
+  --access '{"deposit_issuer": null, "allow_admin_nullifier_ops": false}'
 
 OPAQUE_CONTRACT_ID=$(stellar contract alias show opaque --network $NETWORK)
 
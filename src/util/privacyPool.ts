@@ -98,6 +98,7 @@ export async function handleDeposit(
     const tx = await client.deposit({
       from: fromAddress,
       commitment: commitmentBuffer,
+      amount: value,
     });
 
     // 6. Sign and submit transaction using the wallet's signTransaction directly
@@ -107,20 +108,24 @@ export async function handleDeposit(
     console.log("[Deposit] Transaction result:", result);
 
     // 7. Get leaf index from result - handle XDR parsing issues gracefully
+    // The contract returns (leaf_index, new_root)
     let leafIndex = 0;
     try {
       if (result.result) {
         // Try different ways to extract the leaf index
         if (typeof result.result === "number") {
           leafIndex = result.result;
+        } else if (Array.isArray(result.result)) {
+          leafIndex = Number(result.result[0]);
         } else if (
           typeof result.result === "object" &&
           "isOk" in result.result &&
           typeof result.result.isOk === "function"
         ) {
-          leafIndex = result.result.isOk()
-            ? (result.result as { unwrap: () => number }).unwrap()
-            : 0;
+          const deposited = result.result.isOk()
+            ? (result.result as { unwrap: () => [number, unknown] }).unwrap()
+            : undefined;
+          leafIndex = deposited ? Number(deposited[0]) : 0;
         } else if (
           typeof result.result === "object" &&
           "value" in result.result